@@ -1,131 +1,433 @@
-use crate::tokens::Token;
+use crate::diagnostics::Span;
+use crate::tokens::{Token, TokenType};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Identifies a statement or expression. Facts about a node, such as where it
+/// was parsed from (see [`Spans`]) or the scope the resolver binds it to, are
+/// kept in tables keyed by its id rather than in the node. Ids are unique
+/// across every parse in the process, so resolutions from earlier REPL lines
+/// stay valid while later ones are added.
+pub type NodeId = usize;
+
+/// Where each node starts in the source, from [`crate::parser::Parser::spans`].
+pub type Spans = HashMap<NodeId, Span>;
+
+pub fn next_node_id() -> NodeId {
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
 
 #[derive(Debug, Clone)]
 pub enum Stmt {
     Expr {
+        id: NodeId,
         expr: Box<Expr>,
     },
     Print {
+        id: NodeId,
         expr: Box<Expr>,
     },
     Var {
+        id: NodeId,
         name: Token,
+        /// The type after the name, e.g. `Number` in `var n: Number = 1;`.
+        /// Checked by [`crate::typecheck`] and ignored when running.
+        ty: Option<Token>,
         expr: Option<Box<Expr>>,
     },
     Block {
+        id: NodeId,
         stmts: Vec<Box<Stmt>>,
     },
     If {
+        id: NodeId,
         condition: Box<Expr>,
         then_branch: Box<Stmt>,
         else_branch: Option<Box<Stmt>>,
     },
     While {
+        id: NodeId,
         condition: Box<Expr>,
         body: Box<Stmt>,
     },
+    For {
+        id: NodeId,
+        initializer: Option<Box<Stmt>>,
+        condition: Option<Box<Expr>>,
+        increment: Option<Box<Expr>>,
+        body: Box<Stmt>,
+    },
     Function {
+        id: NodeId,
         name: Token,
         params: Vec<Token>,
+        /// The type annotating each parameter, if it has one.
+        param_types: Vec<Option<Token>>,
+        /// The type after the parameters, e.g. `Number` in
+        /// `fun add(a, b): Number`.
+        return_type: Option<Token>,
         body: Vec<Box<Stmt>>,
+        /// The `///` comments just before the declaration, one line each.
+        doc: Option<String>,
     },
     Return {
+        id: NodeId,
+        keyword: Token,
+        value: Option<Box<Expr>>,
+    },
+    Yield {
+        id: NodeId,
         keyword: Token,
         value: Option<Box<Expr>>,
     },
     Class {
+        id: NodeId,
         name: Token,
         methods: Vec<Box<Stmt>>,
+        /// `class var` declarations, as `Var` statements.
+        statics: Vec<Box<Stmt>>,
         superclass: Option<Box<Expr>>,
+        doc: Option<String>,
+    },
+    Test {
+        id: NodeId,
+        name: Token,
+        body: Vec<Box<Stmt>>,
     },
 }
 
 #[derive(Debug, Clone)]
 pub enum Expr {
     Binary {
+        id: NodeId,
         left: Box<Expr>,
         operator: Token,
         right: Box<Expr>,
     },
     Unary {
+        id: NodeId,
         operator: Token,
         right: Box<Expr>,
     },
     Grouping {
+        id: NodeId,
         expr: Box<Expr>,
     },
     Literal {
+        id: NodeId,
         value: Token,
     },
     Variable {
+        id: NodeId,
         name: Token,
     },
     Assignment {
+        id: NodeId,
         name: Token,
         value: Box<Expr>,
     },
     Logical {
+        id: NodeId,
         left: Box<Expr>,
         operator: Token,
         right: Box<Expr>,
     },
     Call {
+        id: NodeId,
         callee: Box<Expr>,
         paren: Token,
         arguments: Vec<Box<Expr>>,
     },
     Get {
+        id: NodeId,
         object: Box<Expr>,
         name: Token,
     },
     Set {
+        id: NodeId,
         object: Box<Expr>,
         name: Token,
         value: Box<Expr>,
     },
     This {
+        id: NodeId,
         keyword: Token,
     },
+    List {
+        id: NodeId,
+        bracket: Token,
+        elements: Vec<Box<Expr>>,
+    },
+    Index {
+        id: NodeId,
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+    },
+    IndexSet {
+        id: NodeId,
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    },
     Super {
+        id: NodeId,
         keyword: Token,
         method: Token,
     },
+    Match {
+        id: NodeId,
+        keyword: Token,
+        subject: Box<Expr>,
+        arms: Vec<MatchArm>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub body: Box<Expr>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    /// `_` matches any value without binding it.
+    Wildcard,
+    /// Matches values equal to a number, string, boolean or `nil` literal.
+    Literal { value: Token },
+    /// Matches any value and binds it to `name` for the arm's body.
+    Binding { name: Token },
+    /// `Point(x, y)` matches instances of `Point` or its subclasses. Each
+    /// sub-pattern is matched against the field named after the parameter of
+    /// `init` in the same position.
+    Class {
+        class: Box<Expr>,
+        paren: Token,
+        fields: Vec<Pattern>,
+    },
+}
+
+impl Stmt {
+    pub fn id(&self) -> NodeId {
+        match self {
+            Stmt::Expr { id, .. }
+            | Stmt::Print { id, .. }
+            | Stmt::Var { id, .. }
+            | Stmt::Block { id, .. }
+            | Stmt::If { id, .. }
+            | Stmt::While { id, .. }
+            | Stmt::For { id, .. }
+            | Stmt::Function { id, .. }
+            | Stmt::Return { id, .. }
+            | Stmt::Yield { id, .. }
+            | Stmt::Class { id, .. }
+            | Stmt::Test { id, .. } => *id,
+        }
+    }
 }
 
 impl Expr {
-    pub fn to_string(&self) -> String {
-        match &self {
+    pub fn id(&self) -> NodeId {
+        match self {
+            Expr::Binary { id, .. }
+            | Expr::Unary { id, .. }
+            | Expr::Grouping { id, .. }
+            | Expr::Literal { id, .. }
+            | Expr::Variable { id, .. }
+            | Expr::Assignment { id, .. }
+            | Expr::Logical { id, .. }
+            | Expr::Call { id, .. }
+            | Expr::Get { id, .. }
+            | Expr::Set { id, .. }
+            | Expr::This { id, .. }
+            | Expr::List { id, .. }
+            | Expr::Index { id, .. }
+            | Expr::IndexSet { id, .. }
+            | Expr::Super { id, .. }
+            | Expr::Match { id, .. } => *id,
+        }
+    }
+
+    /// The expression written as Lox source, e.g. `head.next` or `items[0]`,
+    /// for error messages that point at the part of a line that went wrong.
+    pub fn source(&self) -> String {
+        let list = |exprs: &[Box<Expr>]| {
+            let sources: Vec<String> = exprs.iter().map(|expr| expr.source()).collect();
+            sources.join(", ")
+        };
+        match self {
             Expr::Binary {
                 left,
                 operator,
                 right,
+                ..
             }
             | Expr::Logical {
                 left,
                 operator,
                 right,
-            } => format!("({} {operator} {})", left.to_string(), right.to_string()),
-            Expr::Unary { operator, right } => format!("({operator} {})", right.to_string()),
-            Expr::Grouping { expr } => format!("(group {})", expr.to_string()),
-            Expr::Literal { value } => format!("{value}"),
-            Expr::Variable { name } => format!("{name}"),
-            Expr::Assignment { name, value } => format!("{name} = {} ", value.to_string()),
+                ..
+            } => format!("{} {} {}", left.source(), operator.lexeme, right.source()),
+            Expr::Unary {
+                operator, right, ..
+            } => format!("{}{}", operator.lexeme, right.source()),
+            Expr::Grouping { expr, .. } => format!("({})", expr.source()),
+            Expr::Literal { value, .. } => value.lexeme.to_string(),
+            Expr::Variable { name, .. } => name.lexeme.to_string(),
+            Expr::Assignment { name, value, .. } => format!("{} = {}", name.lexeme, value.source()),
+            Expr::Call {
+                callee, arguments, ..
+            } => format!("{}({})", callee.source(), list(arguments)),
+            Expr::Get { object, name, .. } => format!("{}.{}", object.source(), name.lexeme),
+            Expr::Set {
+                object,
+                name,
+                value,
+                ..
+            } => format!("{}.{} = {}", object.source(), name.lexeme, value.source()),
+            Expr::This { keyword, .. } => keyword.lexeme.to_string(),
+            Expr::List { elements, .. } => format!("[{}]", list(elements)),
+            Expr::Index { object, index, .. } => format!("{}[{}]", object.source(), index.source()),
+            Expr::IndexSet {
+                object,
+                index,
+                value,
+                ..
+            } => format!(
+                "{}[{}] = {}",
+                object.source(),
+                index.source(),
+                value.source()
+            ),
+            Expr::Super {
+                keyword, method, ..
+            } => format!("{}.{}", keyword.lexeme, method.lexeme),
+            Expr::Match { subject, .. } => format!("match {} {{ ... }}", subject.source()),
+        }
+    }
+}
+
+impl Expr {
+    /// Moves a child expression out of its parent, leaving `nil` behind.
+    /// `Expr` implements `Drop`, so its fields can't be moved out by a pattern.
+    pub fn take(expr: &mut Box<Expr>) -> Box<Expr> {
+        let placeholder = Expr::Literal {
+            id: 0,
+            value: Token::new(TokenType::Nil, String::from("nil"), 0),
+        };
+        std::mem::replace(expr, Box::new(placeholder))
+    }
+
+    /// Takes the left operand of a binary or logical expression, if it is one
+    /// too.
+    fn take_chained_left(&mut self) -> Option<Box<Expr>> {
+        match self {
+            Expr::Binary { left, .. } | Expr::Logical { left, .. }
+                if matches!(**left, Expr::Binary { .. } | Expr::Logical { .. }) =>
+            {
+                Some(Expr::take(left))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Long chains like `1 + 1 + ... + 1` parse into left-leaning trees as deep as
+/// the chain is long, so they're unlinked one operand at a time instead of
+/// recursively.
+impl Drop for Expr {
+    fn drop(&mut self) {
+        let mut next = self.take_chained_left();
+        while let Some(mut expr) = next {
+            next = expr.take_chained_left();
+        }
+    }
+}
+
+impl std::fmt::Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Pattern::Wildcard => write!(f, "_"),
+            Pattern::Literal { value } => write!(f, "{value}"),
+            Pattern::Binding { name } => write!(f, "{name}"),
+            Pattern::Class { class, fields, .. } => {
+                write!(f, "{class}( ")?;
+                for field in fields {
+                    write!(f, "{field} ")?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let s = match &self {
+            Expr::Binary {
+                left,
+                operator,
+                right,
+                ..
+            }
+            | Expr::Logical {
+                left,
+                operator,
+                right,
+                ..
+            } => format!("({} {operator} {})", left, right),
+            Expr::Unary {
+                operator, right, ..
+            } => format!("({operator} {})", right),
+            Expr::Grouping { expr, .. } => format!("(group {})", expr),
+            Expr::Literal { value, .. } => format!("{value}"),
+            Expr::Variable { name, .. } => format!("{name}"),
+            Expr::Assignment { name, value, .. } => format!("{name} = {} ", value),
             Expr::Call {
                 callee, arguments, ..
             } => {
-                let mut s = format!("{}( ", callee.to_string());
+                let mut s = format!("{}( ", callee);
                 for arg in arguments {
-                    s = format!("{s}{} ", arg.to_string());
+                    s = format!("{s}{} ", arg);
                 }
                 s = format!("{s})");
                 s
             }
-            Expr::Get { object, .. } => format!("(get {})", object.to_string()),
-            Expr::Set { object, value, .. } => {
-                format!("(set {} <- {})", object.to_string(), value.to_string())
+            Expr::Get { object, name, .. } => format!("(get {object}.{name})"),
+            Expr::Set {
+                object,
+                name,
+                value,
+                ..
+            } => format!("(set {object}.{name} <- {value})"),
+            Expr::This { keyword, .. } => format!("{keyword}"),
+            Expr::List { elements, .. } => {
+                let mut s = String::from("[ ");
+                for element in elements {
+                    s = format!("{s}{element} ");
+                }
+                format!("{s}]")
             }
-            Expr::This { keyword } => format!("{keyword}"),
-            Expr::Super { keyword, method } => format!("{keyword}.{method}"),
-        }
+            Expr::Index { object, index, .. } => format!("(index {object} {index})"),
+            Expr::IndexSet {
+                object,
+                index,
+                value,
+                ..
+            } => format!("(set {object}[{index}] <- {value})"),
+            Expr::Super {
+                keyword, method, ..
+            } => format!("{keyword}.{method}"),
+            Expr::Match { subject, arms, .. } => {
+                let mut s = format!("(match {subject} ");
+                for arm in arms {
+                    s = format!("{s}({} => {}) ", arm.pattern, arm.body);
+                }
+                format!("{s})")
+            }
+        };
+        write!(f, "{s}")
     }
 }
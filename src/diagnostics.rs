@@ -0,0 +1,169 @@
+use crate::error::Phase;
+use crate::json;
+use crate::sync::{Rc, RefCell};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// Where in the source a diagnostic points to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub line: usize,
+    /// 1-based, when known.
+    pub column: Option<usize>,
+}
+
+/// An edit that fixes the problem a diagnostic reports, which editors can
+/// offer to apply.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suggestion {
+    /// Where `insert` goes: just before this position.
+    pub span: Span,
+    pub insert: String,
+}
+
+/// A problem found while running a program, in a form tools can inspect
+/// rather than just print.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub phase: Phase,
+    /// The script the problem is in, set when several scripts are run in
+    /// one session so it's clear which one it came from.
+    pub file: Option<String>,
+    /// `None` for problems that aren't tied to the source, e.g. an unreadable file.
+    pub span: Option<Span>,
+    pub message: String,
+    pub suggestion: Option<Suggestion>,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match (&self.file, self.span) {
+            (Some(file), Some(span)) => write!(f, "[{file} line {}] ", span.line)?,
+            (Some(file), None) => write!(f, "[{file}] ")?,
+            (None, Some(span)) => write!(f, "[line {}] ", span.line)?,
+            (None, None) => (),
+        }
+        let severity = match self.severity {
+            Severity::Error => "Error",
+            Severity::Warning => "Warning",
+        };
+        write!(f, "{severity}: {}", self.message)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(
+                f,
+                "\n  help: insert `{}` at line {}",
+                suggestion.insert, suggestion.span.line
+            )?;
+            if let Some(column) = suggestion.span.column {
+                write!(f, ", column {column}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Receives the diagnostics [`crate::Lox`] reports. The default,
+/// [`StderrSink`], prints them; embedders can collect them instead, e.g. into a
+/// shared `Rc<RefCell<Vec<Diagnostic>>>` they keep a clone of.
+pub trait DiagnosticSink {
+    fn report(&mut self, diagnostic: Diagnostic);
+}
+
+pub struct StderrSink;
+
+impl DiagnosticSink for StderrSink {
+    fn report(&mut self, diagnostic: Diagnostic) {
+        eprintln!("{diagnostic}");
+    }
+}
+
+/// Prints each diagnostic to stderr as a JSON object on its own line, for
+/// editors and CI tools to parse.
+pub struct JsonSink {
+    /// The file being run, or `None` when the code didn't come from a file.
+    /// Diagnostics that name their own file use that instead.
+    file: Option<String>,
+}
+
+impl JsonSink {
+    pub fn new(file: Option<String>) -> Self {
+        JsonSink { file }
+    }
+}
+
+impl DiagnosticSink for JsonSink {
+    fn report(&mut self, diagnostic: Diagnostic) {
+        let optional = |value: Option<usize>| match value {
+            Some(value) => value.to_string(),
+            None => String::from("null"),
+        };
+        let severity = match diagnostic.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let file = match diagnostic.file.as_ref().or(self.file.as_ref()) {
+            Some(file) => json::string(file),
+            None => String::from("null"),
+        };
+        let suggestion = match &diagnostic.suggestion {
+            Some(suggestion) => format!(
+                "{{\"line\":{},\"column\":{},\"insert\":{}}}",
+                suggestion.span.line,
+                optional(suggestion.span.column),
+                json::string(&suggestion.insert)
+            ),
+            None => String::from("null"),
+        };
+        eprintln!(
+            "{{\"severity\":\"{severity}\",\"file\":{file},\"line\":{},\"column\":{},\"code\":\"{}\",\"message\":{},\"suggestion\":{suggestion}}}",
+            optional(diagnostic.span.map(|span| span.line)),
+            optional(diagnostic.span.and_then(|span| span.column)),
+            diagnostic.phase,
+            json::string(&diagnostic.message)
+        );
+    }
+}
+
+/// The candidate most likely to be a misspelling of `name`, if any is close
+/// enough. Ties go to the alphabetically first candidate.
+pub fn closest_name<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let max_distance = (name.chars().count() / 3).max(1);
+    candidates
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// The Levenshtein distance between `a` and `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+impl DiagnosticSink for Vec<Diagnostic> {
+    fn report(&mut self, diagnostic: Diagnostic) {
+        self.push(diagnostic);
+    }
+}
+
+impl DiagnosticSink for Rc<RefCell<Vec<Diagnostic>>> {
+    fn report(&mut self, diagnostic: Diagnostic) {
+        self.borrow_mut().push(diagnostic);
+    }
+}
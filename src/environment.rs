@@ -1,14 +1,28 @@
 use crate::error::LoxError;
 use crate::interpreter::Types;
+use crate::sync::{Rc, RefCell};
 use crate::tokens::Token;
-use std::cell::RefCell;
 use std::collections::HashMap;
-use std::rc::Rc;
 
 #[derive(Debug)]
 pub struct Environment {
     pub parent: Option<Rc<RefCell<Environment>>>,
+    /// Globals, which are looked up by name since they can be used before
+    /// they're defined. Only the outermost environment has any.
     pub values: HashMap<String, Types>,
+    /// Locals, indexed by the slot the resolver gave them. They're defined in
+    /// the same order they're declared, so the indices line up.
+    slots: Vec<Types>,
+}
+
+/// A copy of an environment's bindings, taken with [`Environment::snapshot`].
+///
+/// Only the bindings are copied: values such as instances and lists are shared
+/// with the live environment, so mutating their contents isn't undone by a restore.
+#[derive(Debug, Clone)]
+pub struct EnvironmentSnapshot {
+    values: HashMap<String, Types>,
+    slots: Vec<Types>,
 }
 
 impl Environment {
@@ -16,6 +30,7 @@ impl Environment {
         Rc::new(RefCell::new(Environment {
             parent: None,
             values: HashMap::new(),
+            slots: vec![],
         }))
     }
 
@@ -23,26 +38,95 @@ impl Environment {
         Rc::new(RefCell::new(Environment {
             parent: Some(parent.clone()),
             values: HashMap::new(),
+            slots: vec![],
         }))
     }
 
-    pub fn get_parent(this: &Rc<RefCell<Self>>, depth: usize) -> Rc<RefCell<Self>> {
+    /// Creates a sibling of `this` holding copies of its bindings, so closures
+    /// that captured `this` keep seeing the old values after the copy is changed.
+    pub fn fork(this: &Rc<RefCell<Self>>) -> Rc<RefCell<Self>> {
+        let this = this.borrow();
+        Rc::new(RefCell::new(Environment {
+            parent: this.parent.clone(),
+            values: this.values.clone(),
+            slots: this.slots.clone(),
+        }))
+    }
+
+    pub fn get_parent(this: &Rc<RefCell<Self>>, depth: usize) -> Option<Rc<RefCell<Self>>> {
         if depth == 0 {
-            return this.clone();
+            Some(this.clone())
         } else {
-            Environment::get_parent(&this.borrow().parent.as_ref().unwrap(), depth - 1)
+            Environment::get_parent(this.borrow().parent.as_ref()?, depth - 1)
+        }
+    }
+
+    pub fn snapshot(&self) -> EnvironmentSnapshot {
+        EnvironmentSnapshot {
+            values: self.values.clone(),
+            slots: self.slots.clone(),
         }
     }
 
+    /// Resets the bindings to those in `snapshot`, dropping any defined since.
+    /// The environment is updated in place, so closures that captured it see the change.
+    pub fn restore(&mut self, snapshot: &EnvironmentSnapshot) {
+        self.values = snapshot.values.clone();
+        self.slots = snapshot.slots.clone();
+    }
+
+    pub(crate) fn slots(&self) -> &[Types] {
+        &self.slots
+    }
+
+    /// Defines a global by name or, in a local scope, the next local slot.
     pub fn define(&mut self, name: String, value: Types) {
-        self.values.insert(name, value);
+        if self.parent.is_none() {
+            self.values.insert(name, value);
+        } else {
+            self.slots.push(value);
+        }
+    }
+
+    /// Defines the next local slot, as [`Environment::define`] does in any
+    /// environment but the outermost.
+    pub(crate) fn define_local(&mut self, value: Types) {
+        self.slots.push(value);
     }
 
+    /// Empties an environment nothing else refers to any more, so it can be
+    /// used again as a child of `parent` without allocating a new one.
+    pub(crate) fn reuse(&mut self, parent: &Rc<RefCell<Self>>) {
+        self.parent = Some(parent.clone());
+        self.values.clear();
+        self.slots.clear();
+    }
+
+    /// Names of the globals visible from here. Locals aren't included, since
+    /// they're only known by slot.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.values.keys().cloned().collect();
+        if let Some(parent) = &self.parent {
+            names.extend(parent.borrow().names());
+        }
+        names
+    }
+
+    /// Whether a global called `name` is visible from here.
+    pub fn contains(&self, name: &str) -> bool {
+        self.values.contains_key(name)
+            || self
+                .parent
+                .as_ref()
+                .is_some_and(|parent| parent.borrow().contains(name))
+    }
+
+    /// Reads a global.
     pub fn get(&self, token: &Token) -> Result<Types, LoxError> {
-        if self.values.contains_key(&token.lexeme) {
-            Ok(self.values.get(&token.lexeme).unwrap().clone())
-        } else if self.parent.is_some() {
-            self.parent.as_ref().unwrap().borrow().get(token)
+        if let Some(value) = self.values.get(token.lexeme.as_str()) {
+            Ok(value.clone())
+        } else if let Some(parent) = &self.parent {
+            parent.borrow().get(token)
         } else {
             LoxError::new_runtime(
                 token.line,
@@ -51,32 +135,33 @@ impl Environment {
         }
     }
 
-    pub fn get_at(&self, token: &Token, depth: usize) -> Result<Types, LoxError> {
+    /// Reads the local in `slot` of the environment `depth` levels up.
+    pub fn get_at(&self, token: &Token, depth: usize, slot: usize) -> Result<Types, LoxError> {
         if depth == 0 {
-            self.get(token)
-        } else {
-            if let Some(parent) = &self.parent {
-                parent.borrow().get_at(token, depth - 1)
-            } else {
-                LoxError::new_runtime(
+            match self.slots.get(slot) {
+                Some(value) => Ok(value.clone()),
+                None => LoxError::new_runtime(
                     token.line,
-                    format!("Bad depth. Looking for depth {depth}, but no parent found."),
-                )
+                    format!("Failed to get undefined variable `{}`.", token.lexeme),
+                ),
             }
+        } else if let Some(parent) = &self.parent {
+            parent.borrow().get_at(token, depth - 1, slot)
+        } else {
+            LoxError::new_runtime(
+                token.line,
+                format!("Bad depth. Looking for depth {depth}, but no parent found."),
+            )
         }
     }
 
+    /// Assigns a global.
     pub fn set(&mut self, token: &Token, value: Types) -> Result<(), LoxError> {
-        if self.values.contains_key(&token.lexeme) {
-            *self.values.get_mut(&token.lexeme).unwrap() = value;
-            Ok(())
-        } else if self.parent.is_some() {
-            self.parent
-                .as_mut()
-                .unwrap()
-                .borrow_mut()
-                .set(token, value)?;
+        if let Some(slot) = self.values.get_mut(token.lexeme.as_str()) {
+            *slot = value;
             Ok(())
+        } else if let Some(parent) = &self.parent {
+            parent.borrow_mut().set(token, value)
         } else {
             LoxError::new_runtime(
                 token.line,
@@ -85,18 +170,32 @@ impl Environment {
         }
     }
 
-    pub fn set_at(&mut self, token: &Token, value: Types, depth: usize) -> Result<(), LoxError> {
+    /// Assigns the local in `slot` of the environment `depth` levels up.
+    pub fn set_at(
+        &mut self,
+        token: &Token,
+        value: Types,
+        depth: usize,
+        slot: usize,
+    ) -> Result<(), LoxError> {
         if depth == 0 {
-            self.set(token, value)
-        } else {
-            if let Some(parent) = &self.parent {
-                parent.borrow_mut().set_at(token, value, depth - 1)
-            } else {
-                LoxError::new_runtime(
+            match self.slots.get_mut(slot) {
+                Some(local) => {
+                    *local = value;
+                    Ok(())
+                }
+                None => LoxError::new_runtime(
                     token.line,
-                    format!("Bad depth. Looking for depth {depth}, but no parent found."),
-                )
+                    format!("Failed to set undefined variable: `{}`.", token.lexeme),
+                ),
             }
+        } else if let Some(parent) = &self.parent {
+            parent.borrow_mut().set_at(token, value, depth - 1, slot)
+        } else {
+            LoxError::new_runtime(
+                token.line,
+                format!("Bad depth. Looking for depth {depth}, but no parent found."),
+            )
         }
     }
 }
@@ -1,72 +1,338 @@
+use crate::diagnostics::{Diagnostic, DiagnosticSink, Severity, Span, StderrSink, Suggestion};
 use crate::interpreter::Types;
+
 #[derive(Debug)]
 pub struct LoxErrorContainer {
     line: usize,
     message: String,
+    /// Set when the error was caused by the input ending early, e.g. an
+    /// unclosed `{` or a dangling operator, rather than by a malformed token.
+    at_eof: bool,
+    suggestion: Option<Suggestion>,
 }
 
 impl LoxErrorContainer {
-    pub fn report(&self) {
-        eprintln!("[line {}] Error: {}", self.line, self.message);
+    pub(crate) fn new(line: usize, message: String) -> Self {
+        LoxErrorContainer {
+            line,
+            message,
+            at_eof: false,
+            suggestion: None,
+        }
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn suggestion(&self) -> Option<&Suggestion> {
+        self.suggestion.as_ref()
+    }
+}
+
+impl std::fmt::Display for LoxErrorContainer {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "[line {}] Error: {}", self.line, self.message)
+    }
+}
+
+/// The stage of running a program that an error came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Scan,
+    Parse,
+    Resolve,
+    /// Mismatches with type annotations, see [`crate::typecheck`].
+    Type,
+    Runtime,
+    Io,
+}
+
+impl std::fmt::Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            Phase::Scan => "scan",
+            Phase::Parse => "parse",
+            Phase::Resolve => "resolve",
+            Phase::Type => "type",
+            Phase::Runtime => "runtime",
+            Phase::Io => "io",
+        };
+        write!(f, "{name}")
     }
 }
 
+/// Which set of process exit codes to report errors with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitCodes {
+    /// loxrt's own codes: one per phase, from 1 to 6.
+    Loxrt,
+    /// The `sysexits.h` values used by the book's reference implementations:
+    /// 65 for compile errors, 70 for runtime errors, 66 for unreadable input
+    /// and 73 for output that can't be written.
+    Sysexits,
+}
+
+/// What was being done to the file a [`LoxError::IoError`] is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoOperation {
+    Read,
+    Write,
+}
+
+impl std::fmt::Display for IoOperation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            IoOperation::Read => write!(f, "read"),
+            IoOperation::Write => write!(f, "write"),
+        }
+    }
+}
+
+#[derive(Debug)]
 pub enum LoxError {
     ScannerError(LoxErrorContainer),
     ParserErrors(Vec<LoxErrorContainer>),
     ResolutionError(LoxErrorContainer),
+    TypeErrors(Vec<LoxErrorContainer>),
     RuntimeError(LoxErrorContainer),
     ReturnError(Types),
+    IoError {
+        path: String,
+        operation: IoOperation,
+        error: std::io::Error,
+    },
+    /// The script was stopped through its [`crate::interpreter::CancelHandle`].
+    Interrupted,
 }
 
 impl LoxError {
     pub fn new_scanner<T>(line: usize, message: String) -> Result<T, Self> {
-        Err(LoxError::ScannerError(LoxErrorContainer { line, message }))
+        Err(LoxError::ScannerError(LoxErrorContainer::new(
+            line, message,
+        )))
+    }
+    pub fn new_scanner_at_eof<T>(line: usize, message: String) -> Result<T, Self> {
+        Err(LoxError::ScannerError(LoxErrorContainer {
+            at_eof: true,
+            ..LoxErrorContainer::new(line, message)
+        }))
     }
     pub fn new_parser<T>(line: usize, message: String) -> Result<T, Self> {
+        Err(LoxError::ParserErrors(vec![LoxErrorContainer::new(
+            line, message,
+        )]))
+    }
+    pub fn new_parser_at_eof<T>(line: usize, message: String) -> Result<T, Self> {
         Err(LoxError::ParserErrors(vec![LoxErrorContainer {
-            line,
-            message,
+            at_eof: true,
+            ..LoxErrorContainer::new(line, message)
         }]))
     }
     pub fn new_runtime<T>(line: usize, message: String) -> Result<T, Self> {
-        Err(LoxError::RuntimeError(LoxErrorContainer { line, message }))
+        Err(LoxError::RuntimeError(LoxErrorContainer::new(
+            line, message,
+        )))
     }
     pub fn new_resolution<T>(line: usize, message: String) -> Result<T, Self> {
-        Err(LoxError::ResolutionError(LoxErrorContainer {
-            line,
-            message,
-        }))
+        Err(LoxError::ResolutionError(LoxErrorContainer::new(
+            line, message,
+        )))
+    }
+    pub fn new_types<T>(errors: Vec<LoxErrorContainer>) -> Result<T, Self> {
+        Err(LoxError::TypeErrors(errors))
     }
     pub fn new_return<T>(value: Types) -> Result<T, Self> {
         Err(LoxError::ReturnError(value))
     }
+    pub fn new_io<T>(path: &str, error: std::io::Error) -> Result<T, Self> {
+        Err(LoxError::IoError {
+            path: String::from(path),
+            operation: IoOperation::Read,
+            error,
+        })
+    }
+
+    /// Attaches an edit that fixes the error. For parser errors it goes on the last one.
+    pub fn with_suggestion(mut self, suggestion: Suggestion) -> Self {
+        match &mut self {
+            LoxError::ScannerError(e)
+            | LoxError::RuntimeError(e)
+            | LoxError::ResolutionError(e) => e.suggestion = Some(suggestion),
+            LoxError::ParserErrors(es) | LoxError::TypeErrors(es) => {
+                if let Some(e) = es.last_mut() {
+                    e.suggestion = Some(suggestion);
+                }
+            }
+            LoxError::ReturnError(_) | LoxError::IoError { .. } | LoxError::Interrupted => (),
+        }
+        self
+    }
+
+    pub fn phase(&self) -> Phase {
+        match self {
+            LoxError::ScannerError(_) => Phase::Scan,
+            LoxError::ParserErrors(_) => Phase::Parse,
+            LoxError::ResolutionError(_) => Phase::Resolve,
+            LoxError::TypeErrors(_) => Phase::Type,
+            LoxError::RuntimeError(_) | LoxError::ReturnError(_) | LoxError::Interrupted => {
+                Phase::Runtime
+            }
+            LoxError::IoError { .. } => Phase::Io,
+        }
+    }
+
+    /// The line the error was found on. For parser and type errors this is the
+    /// line of the first one.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            LoxError::ScannerError(e)
+            | LoxError::RuntimeError(e)
+            | LoxError::ResolutionError(e) => Some(e.line),
+            LoxError::ParserErrors(es) | LoxError::TypeErrors(es) => es.first().map(|e| e.line),
+            LoxError::ReturnError(_) | LoxError::IoError { .. } | LoxError::Interrupted => None,
+        }
+    }
+
+    /// The error's message without the line prefix. For parser and type errors
+    /// this is the message of the first one.
+    pub fn message(&self) -> String {
+        match self {
+            LoxError::ScannerError(e)
+            | LoxError::RuntimeError(e)
+            | LoxError::ResolutionError(e) => e.message.clone(),
+            LoxError::ParserErrors(es) | LoxError::TypeErrors(es) => {
+                es.first().map(|e| e.message.clone()).unwrap_or_default()
+            }
+            LoxError::ReturnError(_) => String::from("Can't return from top-level code."),
+            LoxError::IoError {
+                path,
+                operation,
+                error,
+            } => format!("Failed to {operation} `{path}`: {error}"),
+            LoxError::Interrupted => String::from("Interrupted."),
+        }
+    }
+
+    pub fn code(&self, codes: ExitCodes) -> i32 {
+        if codes == ExitCodes::Sysexits {
+            return match self {
+                LoxError::ScannerError(_)
+                | LoxError::ParserErrors(_)
+                | LoxError::ResolutionError(_)
+                | LoxError::TypeErrors(_) => 65,
+                LoxError::RuntimeError(_) | LoxError::ReturnError(_) => 70,
+                LoxError::IoError {
+                    operation: IoOperation::Read,
+                    ..
+                } => 66,
+                LoxError::IoError {
+                    operation: IoOperation::Write,
+                    ..
+                } => 73,
+                // 128 plus SIGINT, as shells report processes stopped by Ctrl-C.
+                LoxError::Interrupted => 130,
+            };
+        }
 
-    fn code(&self) -> i32 {
         match self {
             LoxError::ScannerError(_) => 1,
             LoxError::ParserErrors(_) => 2,
-            LoxError::RuntimeError(_) => 3,
+            LoxError::RuntimeError(_) | LoxError::ReturnError(_) => 3,
             LoxError::ResolutionError(_) => 4,
-            LoxError::ReturnError(_) => panic!("Shouldn't try to exit on a return error"),
+            LoxError::IoError { .. } => 5,
+            LoxError::TypeErrors(_) => 6,
+            LoxError::Interrupted => 130,
+        }
+    }
+
+    /// Whether the source failed only because it ended too early, meaning more
+    /// input could still make it valid. Used by the REPL to ask for more lines.
+    pub fn is_incomplete_input(&self) -> bool {
+        match self {
+            LoxError::ScannerError(e) => e.at_eof,
+            LoxError::ParserErrors(es) => !es.is_empty() && es.iter().all(|e| e.at_eof),
+            _ => false,
+        }
+    }
+
+    /// The error as diagnostics, one for each parser or type error.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let diagnostic =
+            |line: Option<usize>, message: String, suggestion: Option<&Suggestion>| Diagnostic {
+                severity: Severity::Error,
+                phase: self.phase(),
+                file: None,
+                span: line.map(|line| Span { line, column: None }),
+                message,
+                suggestion: suggestion.cloned(),
+            };
+        match self {
+            LoxError::ParserErrors(es) | LoxError::TypeErrors(es) => es
+                .iter()
+                .map(|e| diagnostic(Some(e.line), e.message.clone(), e.suggestion()))
+                .collect(),
+            LoxError::ScannerError(e)
+            | LoxError::RuntimeError(e)
+            | LoxError::ResolutionError(e) => {
+                vec![diagnostic(Some(e.line), e.message.clone(), e.suggestion())]
+            }
+            _ => vec![diagnostic(self.line(), self.message(), None)],
+        }
+    }
+
+    pub fn report_to(&self, sink: &mut dyn DiagnosticSink) {
+        for diagnostic in self.diagnostics() {
+            sink.report(diagnostic);
         }
     }
 
     pub fn report(&self) {
+        self.report_to(&mut StderrSink)
+    }
+
+    pub fn exit(&self) {
+        self.exit_with(ExitCodes::Loxrt)
+    }
+
+    pub fn exit_with(&self, codes: ExitCodes) {
+        std::process::exit(self.code(codes))
+    }
+}
+
+impl std::fmt::Display for LoxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             LoxError::ScannerError(e)
             | LoxError::RuntimeError(e)
-            | LoxError::ResolutionError(e) => e.report(),
-            LoxError::ParserErrors(es) => {
-                for e in es {
-                    e.report()
+            | LoxError::ResolutionError(e) => write!(f, "{e}"),
+            LoxError::ParserErrors(es) | LoxError::TypeErrors(es) => {
+                for (i, e) in es.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{e}")?;
                 }
+                Ok(())
+            }
+            // A return that escaped every function; the resolver should reject
+            // these, but report it rather than abort if one slips through.
+            LoxError::ReturnError(_) | LoxError::IoError { .. } | LoxError::Interrupted => {
+                write!(f, "Error: {}", self.message())
             }
-            LoxError::ReturnError(_) => panic!("Shouldn't be reporting return errors."),
         }
     }
+}
 
-    pub fn exit(&self) {
-        std::process::exit(self.code())
+impl std::error::Error for LoxError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoxError::IoError { error, .. } => Some(error),
+            _ => None,
+        }
     }
 }
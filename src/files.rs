@@ -0,0 +1,326 @@
+//! Natives for working with the host's files and paths. Those that touch the
+//! filesystem need [`Permission::Read`] or [`Permission::Write`].
+
+use crate::environment::Environment;
+use crate::error::LoxError;
+use crate::host::LoxObject;
+use crate::interpreter::{Interpreter, Types};
+use crate::memory;
+use crate::natives::{invalid_input, string_arg, NativeBody, NativeFunction};
+use crate::permissions::Permission;
+use crate::sync::{Rc, RefCell};
+use crate::trace::TraceValue;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::Path;
+
+pub(crate) fn define_globals(env: &mut Environment) {
+    let natives: &[(&str, usize, NativeBody, &str)] = &[
+        ("open", 2, open, "open(path, mode) opens a File for reading (\"r\"), writing (\"w\"), appending (\"a\") or, with \"r+\", \"w+\" or \"a+\", both, with readLine(), write(s), seek(n) and close() methods."),
+        ("listDir", 1, list_dir, "listDir(path) lists the names of the entries of the directory at path, sorted."),
+        ("exists", 1, exists, "exists(path) is true if there's a file or directory at path."),
+        ("isDir", 1, is_dir, "isDir(path) is true if there's a directory at path."),
+        ("joinPath", 2, join_path, "joinPath(a, b) returns the path b relative to the directory a, or b if it's absolute."),
+        ("basename", 1, basename, "basename(path) returns the last component of path, or nil if it ends in \"..\" or is a root."),
+        ("dirname", 1, dirname, "dirname(path) returns path without its last component, or nil if it's a root."),
+    ];
+    for (name, airity, func, doc) in natives {
+        env.define(
+            String::from(*name),
+            NativeFunction::new(name, *airity, doc, *func),
+        );
+    }
+}
+
+/// `open(path, mode)` opens the file at `path` and returns a `File` to read
+/// or write it a piece at a time. `mode` is one of `"r"`, `"w"` and `"a"`, as
+/// for C's `fopen`, optionally followed by `+` to both read and write.
+fn open(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let line = interpreter.line();
+    let path = string_arg(line, &arguments[0])?;
+    let mode = string_arg(line, &arguments[1])?;
+    let mut options = OpenOptions::new();
+    let (reads, writes) = match mode.as_str() {
+        "r" => (true, false),
+        "w" | "a" => (false, true),
+        "r+" | "w+" | "a+" => (true, true),
+        _ => {
+            return LoxError::new_runtime(
+                line,
+                format!("Expected mode to be \"r\", \"w\", \"a\", \"r+\", \"w+\" or \"a+\" but found \"{mode}\""),
+            )
+        }
+    };
+    if reads {
+        interpreter.require(Permission::Read)?;
+    }
+    if writes {
+        interpreter.require(Permission::Write)?;
+    }
+    match &mode[..1] {
+        "r" => options.read(true).write(writes),
+        "w" => options.read(reads).write(true).create(true).truncate(true),
+        _ => options.read(reads).append(true).create(true),
+    };
+
+    // A replayed script doesn't touch the file, its inputs are in the trace.
+    let mut file = None;
+    interpreter.input("open", || match options.open(&*path) {
+        Ok(opened) => {
+            file = Some(BufReader::new(opened));
+            Ok(vec![])
+        }
+        Err(e) => LoxError::new_runtime(line, format!("Failed to open `{path}`: {e}")),
+    })?;
+    interpreter.allocate(File::SIZE)?;
+    Ok(Types::host(Rc::new(RefCell::new(File {
+        file,
+        reads,
+        writes,
+        closed: false,
+    }))))
+}
+
+/// `listDir(path)` lists the names of the entries of the directory at
+/// `path`, sorted, without `.` and `..`.
+fn list_dir(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let line = interpreter.line();
+    interpreter.require(Permission::Read)?;
+    let path = string_arg(line, &arguments[0])?;
+    let names = interpreter.input("listDir", || {
+        let entries = fs::read_dir(&*path)
+            .and_then(|entries| entries.collect::<Result<Vec<_>, _>>())
+            .or_else(|e| LoxError::new_runtime(line, format!("Failed to list `{path}`: {e}")))?;
+        let mut names: Vec<String> = entries
+            .iter()
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        Ok(names.into_iter().map(TraceValue::String).collect())
+    })?;
+    let mut list = Vec::with_capacity(names.len());
+    for name in names {
+        let TraceValue::String(name) = name else {
+            return invalid_input(line, "listDir");
+        };
+        interpreter.allocate(memory::string_size(name.len()))?;
+        list.push(Types::String(Rc::from(name)));
+    }
+    interpreter.allocate(memory::list_size(list.len()))?;
+    Ok(Types::List(Rc::new(RefCell::new(list))))
+}
+
+/// `exists(path)` is true if there's a file or directory at `path`.
+fn exists(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    path_test(interpreter, &arguments[0], "exists", Path::exists)
+}
+
+/// `isDir(path)` is true if there's a directory at `path`.
+fn is_dir(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    path_test(interpreter, &arguments[0], "isDir", Path::is_dir)
+}
+
+/// Checks something about the file at `path` for the native `native`. The
+/// answer is recorded as 1 or 0, since traces don't have booleans.
+fn path_test(
+    interpreter: &mut Interpreter,
+    path: &Types,
+    native: &str,
+    test: fn(&Path) -> bool,
+) -> Result<Types, LoxError> {
+    let line = interpreter.line();
+    interpreter.require(Permission::Read)?;
+    let path = string_arg(line, path)?;
+    let input = interpreter.input(native, || {
+        let answer = if test(Path::new(&*path)) { 1.0 } else { 0.0 };
+        Ok(vec![TraceValue::Number(answer)])
+    })?;
+    match input.as_slice() {
+        [TraceValue::Number(answer)] => Ok(Types::Bool(*answer != 0.0)),
+        _ => invalid_input(line, native),
+    }
+}
+
+/// `joinPath(a, b)` returns the path `b` relative to the directory `a`, or
+/// `b` itself if it's absolute. Paths are only worked out, not looked up, so
+/// this and the other path natives don't need a permission.
+fn join_path(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let line = interpreter.line();
+    let a = string_arg(line, &arguments[0])?;
+    let b = string_arg(line, &arguments[1])?;
+    path_result(interpreter, Some(&Path::new(&*a).join(&*b)))
+}
+
+/// `basename(path)` returns the last component of `path`, e.g. `"b.lox"` for
+/// `"a/b.lox"`, or nil if it ends in `..` or is a root.
+fn basename(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let path = string_arg(interpreter.line(), &arguments[0])?;
+    let name = Path::new(&*path).file_name().map(Path::new);
+    path_result(interpreter, name)
+}
+
+/// `dirname(path)` returns `path` without its last component, e.g. `"a"` for
+/// `"a/b.lox"` and `""` for `"b.lox"`, or nil if it's a root.
+fn dirname(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let path = string_arg(interpreter.line(), &arguments[0])?;
+    path_result(interpreter, Path::new(&*path).parent())
+}
+
+/// Returns a path worked out by a native as a string, or nil for `None`.
+fn path_result(interpreter: &mut Interpreter, path: Option<&Path>) -> Result<Types, LoxError> {
+    let Some(path) = path else {
+        return Ok(Types::Nil);
+    };
+    let path = path.to_string_lossy();
+    interpreter.allocate(memory::string_size(path.len()))?;
+    Ok(Types::String(Rc::new(path.into_owned())))
+}
+
+/// A file opened by `open`. Reads go through a buffer, so lines can be read
+/// one at a time without reading the whole file.
+struct File {
+    /// `None` while replaying a trace.
+    file: Option<BufReader<fs::File>>,
+    reads: bool,
+    writes: bool,
+    closed: bool,
+}
+
+impl File {
+    /// The handle plus the buffer it reads through.
+    const SIZE: usize = 64 + 8 * 1024;
+
+    /// `readLine()` returns the next line without its line ending, or nil at
+    /// the end of the file.
+    fn read_line(&mut self, interpreter: &mut Interpreter) -> Result<Types, LoxError> {
+        let line = interpreter.line();
+        if !self.reads {
+            return LoxError::new_runtime(
+                line,
+                String::from("The file wasn't opened for reading."),
+            );
+        }
+        let file = &mut self.file;
+        let input = interpreter.input("readLine", || {
+            let Some(file) = file else { return Ok(vec![]) };
+            let mut text = String::new();
+            match file.read_line(&mut text) {
+                Ok(0) => Ok(vec![TraceValue::Nil]),
+                Ok(_) => {
+                    if text.ends_with('\n') {
+                        text.pop();
+                        if text.ends_with('\r') {
+                            text.pop();
+                        }
+                    }
+                    Ok(vec![TraceValue::String(text)])
+                }
+                Err(e) => LoxError::new_runtime(line, format!("Failed to read the file: {e}")),
+            }
+        })?;
+        match input.as_slice() {
+            [TraceValue::String(text)] => {
+                interpreter.allocate(memory::string_size(text.len()))?;
+                Ok(Types::String(Rc::new(text.clone())))
+            }
+            [TraceValue::Nil] => Ok(Types::Nil),
+            _ => invalid_input(line, "readLine"),
+        }
+    }
+
+    /// `write(s)` writes `s`, converted to a string, where the last read or
+    /// write left off, or at the end in `"a"` mode.
+    fn write(&mut self, interpreter: &mut Interpreter, value: &Types) -> Result<Types, LoxError> {
+        let line = interpreter.line();
+        if !self.writes {
+            return LoxError::new_runtime(
+                line,
+                String::from("The file wasn't opened for writing."),
+            );
+        }
+        let text = match value {
+            Types::String(s) => s.to_string(),
+            other => other.to_string(),
+        };
+        let file = &mut self.file;
+        interpreter.input("write", || {
+            if let Some(file) = file {
+                // Reading fills the buffer past where it got to. Seeking by
+                // nothing discards the buffer and moves the handle back there,
+                // which `stream_position` wouldn't.
+                #[allow(clippy::seek_from_current)]
+                let rewound = file.seek(SeekFrom::Current(0));
+                rewound
+                    .and_then(|_| file.get_mut().write_all(text.as_bytes()))
+                    .or_else(|e| {
+                        LoxError::new_runtime(line, format!("Failed to write the file: {e}"))
+                    })?;
+            }
+            Ok(vec![])
+        })?;
+        Ok(Types::Nil)
+    }
+
+    /// `seek(n)` moves to `n` bytes from the start of the file.
+    fn seek(&mut self, interpreter: &mut Interpreter, value: &Types) -> Result<Types, LoxError> {
+        let line = interpreter.line();
+        let offset = match value {
+            Types::Number(n) if *n >= 0.0 && n.fract() == 0.0 => *n as u64,
+            other => {
+                return LoxError::new_runtime(
+                    line,
+                    format!("Expected a whole number of bytes but found {other}"),
+                )
+            }
+        };
+        let file = &mut self.file;
+        interpreter.input("seek", || {
+            if let Some(file) = file {
+                file.seek(SeekFrom::Start(offset)).or_else(|e| {
+                    LoxError::new_runtime(line, format!("Failed to seek in the file: {e}"))
+                })?;
+            }
+            Ok(vec![])
+        })?;
+        Ok(Types::Nil)
+    }
+}
+
+impl LoxObject for File {
+    fn class_name(&self) -> &str {
+        "File"
+    }
+
+    fn method_airity(&self, name: &str) -> Option<usize> {
+        match name {
+            "readLine" | "close" => Some(0),
+            "write" | "seek" => Some(1),
+            _ => None,
+        }
+    }
+
+    fn call_method(
+        &mut self,
+        interpreter: &mut Interpreter,
+        name: &str,
+        arguments: Vec<Types>,
+    ) -> Result<Types, LoxError> {
+        if name == "close" {
+            self.file = None;
+            self.closed = true;
+            return Ok(Types::Nil);
+        }
+        if self.closed {
+            return LoxError::new_runtime(interpreter.line(), String::from("The file is closed."));
+        }
+        match name {
+            "readLine" => self.read_line(interpreter),
+            "write" => self.write(interpreter, &arguments[0]),
+            _ => self.seek(interpreter, &arguments[0]),
+        }
+    }
+
+    fn size(&self) -> usize {
+        File::SIZE
+    }
+}
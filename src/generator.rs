@@ -0,0 +1,326 @@
+use crate::ast::Stmt;
+use crate::environment::Environment;
+use crate::error::LoxError;
+use crate::interpreter::{Interpreter, Types};
+use crate::sync::{Rc, RefCell};
+use crate::tokens::Lexeme;
+
+/// The paused call of a function whose body contains `yield`. Calling such a
+/// function returns a generator instead of running the body, and each
+/// `next(generator)` runs it until the following `yield`.
+///
+/// Statements that can't reach a `yield` run on the interpreter as usual. The
+/// ones that can are tracked as frames, so the body can pick up where it left
+/// off.
+pub struct Generator {
+    name: Lexeme,
+    /// The function's body, shared with its declaration rather than copied.
+    body: Rc<[Box<Stmt>]>,
+    state: State,
+}
+
+/// A cooperative task queued by `spawn`. Tasks take turns running until their
+/// next `yield`, see [`Interpreter::run_tasks`].
+pub enum Task {
+    /// A function that hasn't been called yet.
+    Start(Types),
+    Resume(Rc<RefCell<Generator>>),
+}
+
+enum State {
+    Suspended(Vec<Frame>),
+    Running,
+    Done,
+}
+
+/// Where the generator is in a statement that can reach a `yield`. Frames
+/// don't hold the statements: the first one runs the body, and each of the
+/// others runs part of the statement the one before it is at, see [`code`].
+enum Frame {
+    /// Runs statements one after another: the body, a block's statements, a
+    /// branch of an `if`, or a loop's body.
+    Block {
+        next: usize,
+        env: Rc<RefCell<Environment>>,
+        /// Set when running the `else` branch of an `if`.
+        else_branch: bool,
+    },
+    While {
+        env: Rc<RefCell<Environment>>,
+    },
+    For {
+        env: Rc<RefCell<Environment>>,
+        started: bool,
+    },
+}
+
+/// What the innermost frame runs.
+enum Code<'a> {
+    Stmts(&'a [Box<Stmt>]),
+    /// A `while` or `for` statement.
+    Loop(&'a Stmt),
+}
+
+/// Whether a function with this body is a generator.
+pub fn is_generator(body: &[Box<Stmt>]) -> bool {
+    body.iter().any(|stmt| contains_yield(stmt))
+}
+
+pub(crate) fn contains_yield(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::Yield { .. } => true,
+        Stmt::Block { stmts, .. } => is_generator(stmts),
+        Stmt::If {
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            contains_yield(then_branch)
+                || else_branch
+                    .as_ref()
+                    .is_some_and(|branch| contains_yield(branch))
+        }
+        Stmt::While { body, .. } | Stmt::For { body, .. } => contains_yield(body),
+        // A `yield` in a nested function belongs to that function.
+        _ => false,
+    }
+}
+
+impl Generator {
+    pub fn new(name: Lexeme, body: Rc<[Box<Stmt>]>, env: Rc<RefCell<Environment>>) -> Self {
+        Generator {
+            name,
+            body,
+            state: State::Suspended(vec![Frame::Block {
+                next: 0,
+                env,
+                else_branch: false,
+            }]),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    /// The environments the generator's frames keep alive while it's suspended.
+    pub(crate) fn environments(&self) -> impl Iterator<Item = &Rc<RefCell<Environment>>> {
+        let frames = match &self.state {
+            State::Suspended(frames) => frames.as_slice(),
+            State::Running | State::Done => &[],
+        };
+        frames.iter().map(|frame| match frame {
+            Frame::Block { env, .. } | Frame::While { env } | Frame::For { env, .. } => env,
+        })
+    }
+
+    pub fn is_done(&self) -> bool {
+        matches!(self.state, State::Done)
+    }
+
+    /// Runs the generator to its next `yield` and returns the yielded value,
+    /// or nil once the body has finished.
+    pub fn resume(
+        this: &Rc<RefCell<Generator>>,
+        interpreter: &mut Interpreter,
+    ) -> Result<Types, LoxError> {
+        let state = std::mem::replace(&mut this.borrow_mut().state, State::Running);
+        let mut frames = match state {
+            State::Suspended(frames) => frames,
+            State::Running => {
+                return LoxError::new_runtime(
+                    interpreter.line(),
+                    String::from("Generator is already running."),
+                )
+            }
+            State::Done => {
+                this.borrow_mut().state = State::Done;
+                return Ok(Types::Nil);
+            }
+        };
+
+        let body = this.borrow().body.clone();
+        let result = interpreter.away(|interpreter| {
+            interpreter.off_frame(|interpreter| run(&body, &mut frames, interpreter))
+        });
+
+        let (state, result) = match result {
+            Ok(Some(value)) => (State::Suspended(frames), Ok(value)),
+            Ok(None) | Err(LoxError::ReturnError(_)) => (State::Done, Ok(Types::Nil)),
+            Err(e) => (State::Done, Err(e)),
+        };
+        this.borrow_mut().state = state;
+        result
+    }
+}
+
+/// What the innermost of `frames` runs, found by following them from `body`.
+fn code<'a>(body: &'a [Box<Stmt>], frames: &[Frame]) -> Code<'a> {
+    let mut code = Code::Stmts(body);
+    for (outer, inner) in frames.iter().zip(frames.iter().skip(1)) {
+        code = match (code, outer, inner) {
+            (Code::Stmts(stmts), Frame::Block { next, .. }, inner) => {
+                // The statement the outer frame last started.
+                let stmt = &*stmts[next - 1];
+                match (stmt, inner) {
+                    (Stmt::Block { stmts, .. }, _) => Code::Stmts(stmts),
+                    (
+                        Stmt::If {
+                            else_branch: Some(branch),
+                            ..
+                        },
+                        Frame::Block {
+                            else_branch: true, ..
+                        },
+                    )
+                    | (
+                        Stmt::If {
+                            then_branch: branch,
+                            ..
+                        },
+                        _,
+                    ) => Code::Stmts(std::slice::from_ref(branch)),
+                    _ => Code::Loop(stmt),
+                }
+            }
+            (Code::Loop(Stmt::While { body, .. } | Stmt::For { body, .. }), _, _) => {
+                Code::Stmts(std::slice::from_ref(body))
+            }
+            _ => unreachable!("Frames are only pushed for the code they run."),
+        };
+    }
+    code
+}
+
+/// Runs frames until a `yield`, returning its value, or until they run out.
+fn run(
+    body: &[Box<Stmt>],
+    frames: &mut Vec<Frame>,
+    interpreter: &mut Interpreter,
+) -> Result<Option<Types>, LoxError> {
+    loop {
+        let code = code(body, frames);
+        let Some(frame) = frames.last_mut() else {
+            return Ok(None);
+        };
+        let body_frame = |env: &Rc<RefCell<Environment>>| Frame::Block {
+            next: 0,
+            env: env.clone(),
+            else_branch: false,
+        };
+        match (frame, code) {
+            (Frame::Block { next, env, .. }, Code::Stmts(stmts)) => match stmts.get(*next) {
+                Some(stmt) => {
+                    let env = env.clone();
+                    *next += 1;
+                    interpreter.environment = env.clone();
+                    if let Some(value) = start(stmt, env, frames, interpreter)? {
+                        return Ok(Some(value));
+                    }
+                }
+                None => {
+                    frames.pop();
+                }
+            },
+            (Frame::While { env }, Code::Loop(Stmt::While { condition, .. })) => {
+                interpreter.environment = env.clone();
+                if interpreter.evaulate(condition)?.is_truty() {
+                    let body = body_frame(env);
+                    frames.push(body);
+                } else {
+                    frames.pop();
+                }
+            }
+            (
+                Frame::For { env, started },
+                Code::Loop(Stmt::For {
+                    condition,
+                    increment,
+                    ..
+                }),
+            ) => {
+                // Mirrors `Interpreter::execute_for`: each iteration gets a
+                // copy of the loop variables made before the increment.
+                if *started {
+                    *env = Environment::fork(env);
+                    interpreter.environment = env.clone();
+                    if let Some(increment) = increment {
+                        interpreter.evaulate(increment)?;
+                    }
+                }
+                *started = true;
+                interpreter.environment = env.clone();
+                let go = match condition {
+                    Some(condition) => interpreter.evaulate(condition)?.is_truty(),
+                    None => true,
+                };
+                if go {
+                    let body = body_frame(env);
+                    frames.push(body);
+                } else {
+                    frames.pop();
+                }
+            }
+            _ => unreachable!("Frames are only pushed for the code they run."),
+        }
+    }
+}
+
+/// Starts running `stmt` in `env`, pushing a frame if it can reach a `yield`.
+fn start(
+    stmt: &Stmt,
+    env: Rc<RefCell<Environment>>,
+    frames: &mut Vec<Frame>,
+    interpreter: &mut Interpreter,
+) -> Result<Option<Types>, LoxError> {
+    if !contains_yield(stmt) {
+        interpreter.execute(stmt)?;
+        return Ok(None);
+    }
+
+    match stmt {
+        Stmt::Yield { value, .. } => {
+            let value = match value {
+                Some(value) => interpreter.evaulate(value)?,
+                None => Types::Nil,
+            };
+            return Ok(Some(value));
+        }
+        Stmt::Block { .. } => frames.push(Frame::Block {
+            next: 0,
+            env: Environment::new_child(&env),
+            else_branch: false,
+        }),
+        Stmt::If {
+            condition,
+            else_branch,
+            ..
+        } => {
+            let else_branch = match interpreter.evaulate(condition)?.is_truty() {
+                true => Some(false),
+                false => else_branch.as_ref().map(|_| true),
+            };
+            if let Some(else_branch) = else_branch {
+                frames.push(Frame::Block {
+                    next: 0,
+                    env,
+                    else_branch,
+                });
+            }
+        }
+        Stmt::While { .. } => frames.push(Frame::While { env }),
+        Stmt::For { initializer, .. } => {
+            let loop_env = Environment::new_child(&env);
+            interpreter.environment = loop_env.clone();
+            if let Some(initializer) = initializer {
+                interpreter.execute(initializer)?;
+            }
+            frames.push(Frame::For {
+                env: Environment::fork(&loop_env),
+                started: false,
+            });
+        }
+        stmt => interpreter.execute(stmt)?,
+    }
+    Ok(None)
+}
@@ -0,0 +1,24 @@
+use crate::ast::Stmt;
+use crate::error::LoxError;
+use crate::interpreter::Types;
+use crate::sync::Shareable;
+
+/// Callbacks the interpreter makes as it runs a program, for tools such as
+/// tracers, profilers, debuggers and coverage reports. Set them with
+/// [`crate::interpreter::Interpreter::set_hooks`]; every callback does nothing
+/// by default.
+///
+/// Like [`crate::diagnostics::DiagnosticSink`], the interpreter owns the hooks,
+/// so a host that wants to look at what they gathered can keep a shared handle
+/// to it, e.g. an `Rc<RefCell<_>>` the hooks also hold.
+pub trait InterpreterHooks: Shareable {
+    /// Called before each statement runs, including those in function bodies.
+    fn before_statement(&mut self, _stmt: &Stmt) {}
+
+    /// Called before a call from Lox code, once its arguments are evaluated.
+    /// `name` is the function's, class's or native's name.
+    fn before_call(&mut self, _name: &str, _arguments: &[Types]) {}
+
+    /// Called once when a program stops because of `error`.
+    fn on_error(&mut self, _error: &LoxError) {}
+}
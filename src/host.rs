@@ -0,0 +1,142 @@
+use crate::error::LoxError;
+use crate::interpreter::{Interpreter, Types};
+use crate::natives::NativeFunction;
+use crate::sync::{Rc, RefCell, Shareable};
+use crate::tokens::Token;
+use std::ops::Deref;
+
+/// A Rust value that Lox code uses like an instance: it reads and sets its
+/// properties and calls its methods, which run Rust code. Wrap one with
+/// [`Types::host`] to hand it to a script, e.g. as a global.
+///
+/// The host keeps its own handle to the object, so it sees every change the
+/// script makes. The object is borrowed mutably while one of its methods runs;
+/// if the method makes the script use the object again, e.g. by calling back
+/// into it, that use is a runtime error.
+pub trait LoxObject: Shareable {
+    /// The name Lox code sees as the object's class, e.g. when it's printed.
+    fn class_name(&self) -> &str;
+
+    /// The value of the property `name`, or `None` if there isn't one.
+    /// Properties are looked up before methods.
+    fn get(&self, _name: &str) -> Option<Types> {
+        None
+    }
+
+    /// Sets the property `name`, or returns why it can't be set, which the
+    /// script gets as a runtime error.
+    fn set(&mut self, name: &str, _value: Types) -> Result<(), String> {
+        Err(format!(
+            "Can't set `{name}` on instance of <class {}>.",
+            self.class_name()
+        ))
+    }
+
+    /// The number of arguments the method `name` takes, or `None` if there's no
+    /// such method.
+    fn method_airity(&self, _name: &str) -> Option<usize> {
+        None
+    }
+
+    /// Runs the method `name`, which [`LoxObject::method_airity`] says exists,
+    /// with as many arguments as it takes.
+    fn call_method(
+        &mut self,
+        interpreter: &mut Interpreter,
+        name: &str,
+        _arguments: Vec<Types>,
+    ) -> Result<Types, LoxError> {
+        LoxError::new_runtime(interpreter.line(), format!("Undefined property `{name}`."))
+    }
+
+    /// Roughly how many bytes the object keeps alive, which count towards
+    /// [`crate::interpreter::InterpreterOptions::memory_limit`].
+    fn size(&self) -> usize {
+        0
+    }
+}
+
+/// A host object as [`Types::Host`] holds it. The host's handle to the
+/// object is a fat pointer, so values keep it behind another `Rc` to stay two
+/// words. It derefs to the object's `RefCell`.
+pub struct HostObject {
+    pub(crate) object: Rc<RefCell<dyn LoxObject>>,
+    /// Read when the object is wrapped, so it can be printed while one of its
+    /// methods runs.
+    class_name: String,
+}
+
+impl HostObject {
+    pub(crate) fn new(object: Rc<RefCell<dyn LoxObject>>) -> Self {
+        let class_name = object.borrow().class_name().to_string();
+        HostObject { object, class_name }
+    }
+
+    pub fn class_name(&self) -> &str {
+        &self.class_name
+    }
+}
+
+impl Deref for HostObject {
+    type Target = RefCell<dyn LoxObject>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.object
+    }
+}
+
+/// The error for using a host object while one of its methods runs.
+fn in_use<T>(line: usize, object: &HostObject) -> Result<T, LoxError> {
+    LoxError::new_runtime(
+        line,
+        format!(
+            "Can't use instance of <class {}> while one of its methods is running.",
+            object.class_name()
+        ),
+    )
+}
+
+/// Reads the property or method `name` of a host object.
+pub(crate) fn get(object: &Rc<HostObject>, name: &Token) -> Result<Types, LoxError> {
+    let Ok(borrowed) = object.try_borrow() else {
+        return in_use(name.line, object);
+    };
+    if let Some(value) = borrowed.get(name.lexeme.as_str()) {
+        return Ok(value);
+    }
+    match borrowed.method_airity(&name.lexeme) {
+        Some(airity) => {
+            // The method stays bound to the object it was read from.
+            let object = object.clone();
+            let method = name.lexeme.to_string();
+            Ok(NativeFunction::new(
+                &name.lexeme,
+                airity,
+                "",
+                move |interpreter, arguments| match object.try_borrow_mut() {
+                    Ok(mut borrowed) => borrowed.call_method(interpreter, &method, arguments),
+                    Err(_) => in_use(interpreter.line(), &object),
+                },
+            ))
+        }
+        None => LoxError::new_runtime(
+            name.line,
+            format!(
+                "Instance of <class {}> doesn't have a field `{}`",
+                borrowed.class_name(),
+                name.lexeme
+            ),
+        ),
+    }
+}
+
+/// Sets the property `name` of a host object.
+pub(crate) fn set(object: &HostObject, name: &Token, value: Types) -> Result<(), LoxError> {
+    let Ok(mut borrowed) = object.try_borrow_mut() else {
+        return in_use(name.line, object);
+    };
+    match borrowed.set(&name.lexeme, value) {
+        Ok(()) => Ok(()),
+        Err(message) => LoxError::new_runtime(name.line, message),
+    }
+}
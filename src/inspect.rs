@@ -0,0 +1,243 @@
+use crate::interpreter::{Callable, Types};
+use crate::sync::Rc;
+
+/// How deeply `inspect` looks into nested instances and lists by default.
+pub const DEFAULT_DEPTH: usize = 4;
+
+/// Renders `value` showing its contents: the fields of instances, one per
+/// line and indented, the elements of lists, and the airity of functions.
+/// Instances and lists nested more than `max_depth` levels down are elided,
+/// and ones nested in themselves are shown as `<cycle>`.
+pub fn inspect(value: &Types, max_depth: usize) -> String {
+    let mut inspector = Inspector {
+        out: String::new(),
+        max_depth,
+        ancestors: vec![],
+    };
+    inspector.value(value, 0);
+    inspector.out
+}
+
+struct Inspector {
+    out: String,
+    max_depth: usize,
+    /// The lists and instances containing the value being rendered.
+    ancestors: Vec<*const ()>,
+}
+
+impl Inspector {
+    fn value(&mut self, value: &Types, depth: usize) {
+        let ptr = match value {
+            Types::List(list) => Rc::as_ptr(list) as *const (),
+            Types::ClassInstance(instance) => Rc::as_ptr(instance) as *const (),
+            _ => {
+                self.scalar(value);
+                return;
+            }
+        };
+        if self.ancestors.contains(&ptr) {
+            self.out.push_str("<cycle>");
+            return;
+        }
+        self.ancestors.push(ptr);
+        match value {
+            Types::List(list) => self.list(&list.borrow(), depth),
+            Types::ClassInstance(instance) => {
+                let instance = instance.borrow();
+                let fields: Vec<(String, Types)> = instance
+                    .field_names()
+                    .into_iter()
+                    .filter_map(|name| Some((name.clone(), instance.get_field(&name)?)))
+                    .collect();
+                self.instance(instance.class().as_ref().name(), &fields, depth);
+            }
+            _ => (),
+        }
+        self.ancestors.pop();
+    }
+
+    fn scalar(&mut self, value: &Types) {
+        let rendered = match value {
+            Types::String(s) => format!("{:?}", &**s),
+            Types::Callable(function) => format!("<fn {}/{}>", function.name(), function.airity()),
+            Types::NativeFunc(function) => {
+                format!("<native fn {}/{}>", function.name(), function.airity())
+            }
+            other => other.to_string(),
+        };
+        self.out.push_str(&rendered);
+    }
+
+    fn list(&mut self, elements: &[Types], depth: usize) {
+        if elements.is_empty() {
+            self.out.push_str("[]");
+        } else if depth >= self.max_depth {
+            self.out.push_str("[...]");
+        } else if elements.iter().all(is_scalar) {
+            self.out.push('[');
+            for (i, element) in elements.iter().enumerate() {
+                if i > 0 {
+                    self.out.push_str(", ");
+                }
+                self.scalar(element);
+            }
+            self.out.push(']');
+        } else {
+            self.out.push_str("[\n");
+            for element in elements {
+                self.indent(depth + 1);
+                self.value(element, depth + 1);
+                self.out.push_str(",\n");
+            }
+            self.indent(depth);
+            self.out.push(']');
+        }
+    }
+
+    fn instance(&mut self, class: &str, fields: &[(String, Types)], depth: usize) {
+        self.out.push_str(class);
+        if fields.is_empty() {
+            self.out.push_str(" {}");
+        } else if depth >= self.max_depth {
+            self.out.push_str(" {...}");
+        } else {
+            self.out.push_str(" {\n");
+            for (name, value) in fields {
+                self.indent(depth + 1);
+                self.out.push_str(name);
+                self.out.push_str(": ");
+                self.value(value, depth + 1);
+                self.out.push_str(",\n");
+            }
+            self.indent(depth);
+            self.out.push('}');
+        }
+    }
+
+    fn indent(&mut self, depth: usize) {
+        self.out.push_str(&"  ".repeat(depth));
+    }
+}
+
+/// Describes what kind of value `value` is without showing the value itself,
+/// e.g. `function fun add(a, b)`. Instances are described by their class and
+/// the names of their fields and methods, one per line.
+pub fn type_of(value: &Types) -> String {
+    match value {
+        Types::Number(_) => String::from("number"),
+        Types::String(_) => String::from("string"),
+        Types::Bool(_) => String::from("bool"),
+        Types::Nil | Types::Uninitialized => String::from("nil"),
+        Types::Callable(function) => format!("function {}", function.signature()),
+        Types::NativeFunc(function) => {
+            format!("native function {}/{}", function.name(), function.airity())
+        }
+        Types::Class(class) => {
+            let mut described = format!("class {}", class.name());
+            if let Some(superclass) = class.superclass() {
+                described.push_str(&format!(" < {}", superclass.name()));
+            }
+            described.push_str(&format!("\n  methods: {}", names(class.method_names())));
+            described
+        }
+        Types::ClassInstance(instance) => {
+            let instance = instance.borrow();
+            let class = instance.class();
+            format!(
+                "instance of {}\n  fields: {}\n  methods: {}",
+                class.name(),
+                names(instance.field_names()),
+                names(class.method_names())
+            )
+        }
+        Types::List(list) => match list.borrow().len() {
+            1 => String::from("list of 1 element"),
+            len => format!("list of {len} elements"),
+        },
+        Types::Generator(_) => String::from("generator"),
+        Types::Host(object) => format!("host object {}", object.class_name()),
+        #[cfg(feature = "threads")]
+        Types::Channel(_) => String::from("channel"),
+    }
+}
+
+fn names(names: Vec<String>) -> String {
+    if names.is_empty() {
+        String::from("(none)")
+    } else {
+        names.join(", ")
+    }
+}
+
+/// Where `actual` and `expected` first differ when compared like
+/// [`Types::deep_eq`] does, as the path to the values that differ, e.g.
+/// `.items[1]`. The path is empty if they differ outright, e.g. a list and a
+/// number, and `None` if they're equal.
+pub fn difference(actual: &Types, expected: &Types) -> Option<String> {
+    let mut path = String::new();
+    differs(actual, expected, &mut path, &mut vec![]).then_some(path)
+}
+
+/// Whether `a` and `b` differ, leaving the path to the difference in `path`.
+/// `comparing` holds the pairs of lists and instances being compared further
+/// up, as for [`Types::deep_eq`], so cycles don't recurse forever.
+fn differs(
+    a: &Types,
+    b: &Types,
+    path: &mut String,
+    comparing: &mut Vec<(*const (), *const ())>,
+) -> bool {
+    let pair = match (a, b) {
+        (Types::List(l1), Types::List(l2)) => {
+            (Rc::as_ptr(l1) as *const (), Rc::as_ptr(l2) as *const ())
+        }
+        (Types::ClassInstance(i1), Types::ClassInstance(i2)) => {
+            (Rc::as_ptr(i1) as *const (), Rc::as_ptr(i2) as *const ())
+        }
+        _ => return !a.deep_eq(b),
+    };
+    if pair.0 == pair.1 || comparing.contains(&pair) {
+        return false;
+    }
+    // The elements or fields to compare, with the path to each.
+    let children: Vec<(String, Option<Types>, Option<Types>)> = match (a, b) {
+        (Types::List(l1), Types::List(l2)) => {
+            let (l1, l2) = (l1.borrow(), l2.borrow());
+            (0..l1.len().max(l2.len()))
+                .map(|i| (format!("[{i}]"), l1.get(i).cloned(), l2.get(i).cloned()))
+                .collect()
+        }
+        (Types::ClassInstance(i1), Types::ClassInstance(i2)) => {
+            let (i1, i2) = (i1.borrow(), i2.borrow());
+            if !Rc::ptr_eq(i1.class(), i2.class()) {
+                return true;
+            }
+            let mut names = i1.field_names();
+            names.extend(i2.field_names());
+            names.sort();
+            names.dedup();
+            names
+                .into_iter()
+                .map(|name| (format!(".{name}"), i1.get_field(&name), i2.get_field(&name)))
+                .collect()
+        }
+        _ => unreachable!(),
+    };
+    comparing.push(pair);
+    let len = path.len();
+    let differ = children.iter().any(|(child, a, b)| {
+        path.truncate(len);
+        path.push_str(child);
+        match (a, b) {
+            (Some(a), Some(b)) => differs(a, b, path, comparing),
+            _ => true,
+        }
+    });
+    comparing.pop();
+    differ
+}
+
+/// Whether a value is shown on one line whatever it contains.
+fn is_scalar(value: &Types) -> bool {
+    !matches!(value, Types::List(_) | Types::ClassInstance(_))
+}
@@ -1,104 +1,264 @@
-use crate::ast::{Expr, Stmt};
-use crate::environment::Environment;
+use crate::ast::{next_node_id, Expr, MatchArm, NodeId, Pattern, Stmt};
+use crate::diagnostics;
+use crate::environment::{Environment, EnvironmentSnapshot};
 use crate::error::LoxError;
-use crate::tokens::{Token, TokenType};
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::rc::Rc;
-use std::time::SystemTime;
+use crate::generator::{self, Generator, Task};
+use crate::hooks::InterpreterHooks;
+use crate::host::{self, HostObject, LoxObject};
+use crate::json;
+use crate::memory::{self, LiveSize};
+use crate::natives::{self, NativeFunction};
+use crate::permissions::{Permission, Permissions};
+use crate::primitives;
+use crate::sync::{Rc, RefCell, Shareable};
+use crate::tokens::{Lexeme, Token, TokenType};
+use crate::trace::{Trace, TraceEntry, TraceValue};
+use smallvec::SmallVec;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
-pub trait Callable {
+pub trait Callable: Shareable {
     fn airity(&self) -> usize;
     fn call(&self, interpreter: &mut Interpreter, arguments: Vec<Types>)
         -> Result<Types, LoxError>;
     fn to_string(&self) -> String;
-}
-
-impl<F> Callable for F
-where
-    F: Fn() -> Result<Types, LoxError>,
-{
-    fn airity(&self) -> usize {
-        0
-    }
-
-    fn call(
-        &self,
-        _interpreter: &mut Interpreter,
-        _arguments: Vec<Types>,
-    ) -> Result<Types, LoxError> {
-        self()
-    }
 
-    fn to_string(&self) -> String {
-        String::from("<native function>")
+    /// The name the callable was declared with, e.g. for [`InterpreterHooks::before_call`].
+    fn name(&self) -> String {
+        self.to_string()
     }
 }
 
-#[derive(Debug, Clone)]
+/// A function declared in Lox. Copying one, or binding a method to an
+/// instance, only copies the two pointers.
+#[derive(Clone)]
 pub struct LoxFunction {
+    /// Shared by every copy of the function, so copies compare equal.
+    declaration: Rc<FunctionDeclaration>,
+    closure: Rc<RefCell<Environment>>,
+}
+
+/// What doesn't change between the functions a declaration makes.
+struct FunctionDeclaration {
     name: Token,
     params: Vec<Token>,
-    body: Vec<Box<Stmt>>,
-    closure: Rc<RefCell<Environment>>,
+    /// From the `///` comments before the declaration, see `help()`.
+    doc: Option<String>,
+    /// Shared with the generators calls make, so they don't copy it.
+    body: Rc<[Box<Stmt>]>,
     is_initializer: bool,
+    is_generator: bool,
+    on_frame: bool,
+}
+
+impl FunctionDeclaration {
+    fn new(
+        name: Token,
+        params: Vec<Token>,
+        body: Vec<Box<Stmt>>,
+        is_initializer: bool,
+        doc: Option<String>,
+    ) -> Self {
+        FunctionDeclaration {
+            name,
+            params,
+            doc,
+            is_generator: generator::is_generator(&body),
+            on_frame: runs_on_frame(&body),
+            body: Rc::from(body),
+            is_initializer,
+        }
+    }
+}
+
+// The closure is left out: it usually holds the function itself.
+impl std::fmt::Debug for LoxFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let params: Vec<&str> = self
+            .declaration
+            .params
+            .iter()
+            .map(|param| param.lexeme.as_str())
+            .collect();
+        f.debug_struct("LoxFunction")
+            .field("name", &self.declaration.name.lexeme)
+            .field("params", &params)
+            .finish()
+    }
+}
+
+/// Whether a function with this body can keep its locals on the interpreter's
+/// frame stack: it never suspends, and declares nothing that could capture them.
+pub(crate) fn runs_on_frame(body: &[Box<Stmt>]) -> bool {
+    !generator::is_generator(body) && !body.iter().any(|stmt| declares_closure(stmt))
+}
+
+fn declares_closure(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::Function { .. } | Stmt::Class { .. } => true,
+        Stmt::Block { stmts, .. } | Stmt::Test { body: stmts, .. } => {
+            stmts.iter().any(|stmt| declares_closure(stmt))
+        }
+        Stmt::If {
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            declares_closure(then_branch)
+                || else_branch
+                    .as_ref()
+                    .is_some_and(|branch| declares_closure(branch))
+        }
+        Stmt::While { body, .. } => declares_closure(body),
+        Stmt::For {
+            initializer, body, ..
+        } => {
+            initializer
+                .as_ref()
+                .is_some_and(|initializer| declares_closure(initializer))
+                || declares_closure(body)
+        }
+        _ => false,
+    }
 }
 
 impl LoxFunction {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(
         name: Token,
         params: Vec<Token>,
         body: Vec<Box<Stmt>>,
         closure: Rc<RefCell<Environment>>,
         is_initializer: bool,
+        doc: Option<String>,
     ) -> Types {
-        Types::Callable(LoxFunction {
-            name,
-            params,
-            body,
+        let declaration = FunctionDeclaration::new(name, params, body, is_initializer, doc);
+        Types::Callable(Rc::new(LoxFunction {
+            declaration: Rc::new(declaration),
             closure,
-            is_initializer,
-        })
+        }))
+    }
+
+    /// The function's declaration, which can be run again away from its closure.
+    #[cfg(feature = "threads")]
+    pub(crate) fn declaration(&self) -> Stmt {
+        Stmt::Function {
+            id: next_node_id(),
+            name: self.declaration.name.clone(),
+            params: self.declaration.params.clone(),
+            param_types: vec![None; self.declaration.params.len()],
+            return_type: None,
+            body: self.declaration.body.to_vec(),
+            doc: self.declaration.doc.clone(),
+        }
+    }
+
+    /// Whether `self` and `other` are copies of the same function. Each
+    /// declaration run and each binding of a method makes a new function.
+    pub fn same_as(&self, other: &LoxFunction) -> bool {
+        Rc::ptr_eq(&self.declaration, &other.declaration)
+            && Rc::ptr_eq(&self.closure, &other.closure)
+    }
+
+    pub(crate) fn closure(&self) -> &Rc<RefCell<Environment>> {
+        &self.closure
+    }
+
+    pub fn doc(&self) -> Option<&str> {
+        self.declaration.doc.as_deref()
+    }
+
+    /// The declaration's first line, e.g. `fun add(a, b)`.
+    pub fn signature(&self) -> String {
+        let params: Vec<&str> = self
+            .declaration
+            .params
+            .iter()
+            .map(|param| param.lexeme.as_str())
+            .collect();
+        format!(
+            "fun {}({})",
+            self.declaration.name.lexeme,
+            params.join(", ")
+        )
     }
 
-    pub fn bind(&self, instance: Types) -> Types {
+    pub fn bind(&self, instance: Types) -> Rc<LoxFunction> {
         let env = Environment::new_child(&self.closure);
         env.borrow_mut().define(String::from("this"), instance);
-        LoxFunction::new(
-            self.name.clone(),
-            self.params.clone(),
-            self.body.clone(),
-            env,
-            self.is_initializer,
-        )
+        Rc::new(LoxFunction {
+            declaration: self.declaration.clone(),
+            closure: env,
+        })
     }
 }
 
 impl Callable for LoxFunction {
     fn airity(&self) -> usize {
-        self.params.len()
+        self.declaration.params.len()
     }
 
     fn call(
         &self,
         interpreter: &mut Interpreter,
-        mut arguments: Vec<Types>,
+        arguments: Vec<Types>,
     ) -> Result<Types, LoxError> {
-        let env = Environment::new_child(&self.closure);
-        arguments
-            .drain(..)
-            .enumerate()
-            .for_each(|(i, arg)| env.borrow_mut().define(self.params[i].lexeme.clone(), arg));
-        match interpreter.execute_block(&self.body, env) {
+        self.call_with(interpreter, arguments)
+    }
+
+    fn to_string(&self) -> String {
+        format!("<fn {}>", self.declaration.name.lexeme)
+    }
+
+    fn name(&self) -> String {
+        self.declaration.name.lexeme.to_string()
+    }
+}
+
+impl LoxFunction {
+    /// Calls the function with `arguments`, which needn't be collected into a
+    /// `Vec` first as they must be for [`Callable::call`].
+    fn call_with(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: impl IntoIterator<Item = Types>,
+    ) -> Result<Types, LoxError> {
+        let result = if self.declaration.on_frame {
+            interpreter.execute_on_frame(&self.declaration.body, &self.closure, arguments)
+        } else {
+            let env = interpreter.new_environment(&self.closure)?;
+            arguments
+                .into_iter()
+                .for_each(|arg| env.borrow_mut().define_local(arg));
+            if self.declaration.is_generator {
+                let generator = Generator::new(
+                    self.declaration.name.lexeme.clone(),
+                    self.declaration.body.clone(),
+                    env,
+                );
+                return Ok(Types::Generator(Rc::new(RefCell::new(generator))));
+            }
+            let result = interpreter.off_frame(|interpreter| {
+                interpreter.execute_block(&self.declaration.body, env.clone())
+            });
+            interpreter.recycle_environment(env);
+            result
+        };
+        match result {
             Err(LoxError::ReturnError(typ)) => {
-                if self.is_initializer && typ == Types::Nil {
+                if self.declaration.is_initializer && typ == Types::Nil {
                     Ok(self.closure.borrow().get_at(
                         &Token {
-                            lexeme: String::from("this"),
+                            lexeme: Lexeme::from("this"),
                             line: 0,
-                            tok_typ: TokenType::Identifier(String::from("this")),
+                            tok_typ: TokenType::Identifier,
+                            column: 0,
                         },
                         0,
+                        0,
                     )?)
                 } else {
                     Ok(typ)
@@ -106,14 +266,16 @@ impl Callable for LoxFunction {
             }
             Err(e) => Err(e),
             _ => {
-                if self.is_initializer {
+                if self.declaration.is_initializer {
                     Ok(self.closure.borrow().get_at(
                         &Token {
-                            lexeme: String::from("this"),
+                            lexeme: Lexeme::from("this"),
                             line: 0,
-                            tok_typ: TokenType::Identifier(String::from("this")),
+                            tok_typ: TokenType::Identifier,
+                            column: 0,
                         },
                         0,
+                        0,
                     )?)
                 } else {
                     Ok(Types::Nil)
@@ -121,37 +283,121 @@ impl Callable for LoxFunction {
             }
         }
     }
+}
 
-    fn to_string(&self) -> String {
-        format!("<fn {}>", self.name.lexeme)
+#[derive(Clone)]
+pub struct LoxClassInstance {
+    base: Rc<LoxClass>,
+    /// Which of `values` each field is in.
+    shape: Rc<Shape>,
+    values: Vec<Types>,
+    mutability: Mutability,
+}
+
+/// The layout of an instance's fields: the slot of its values each one is in.
+/// Instances of a class given the same fields in the same order share a
+/// shape, found by following transitions from the class's empty shape, so a
+/// property read can remember a field's slot for every instance with the
+/// shape it last saw rather than looking the name up.
+#[derive(Debug, Default)]
+pub(crate) struct Shape {
+    /// The fields in slot order.
+    names: Vec<String>,
+    slots: HashMap<String, usize>,
+    /// The shapes made by adding each field to this one.
+    transitions: RefCell<HashMap<String, Rc<Shape>>>,
+}
+
+impl Shape {
+    fn slot(&self, name: &str) -> Option<usize> {
+        self.slots.get(name).copied()
+    }
+
+    /// The shape with `name` added as the last slot, shared with every other
+    /// instance that had this shape when `name` was added.
+    fn with_field(self: &Rc<Self>, name: &str) -> Rc<Shape> {
+        if let Some(next) = self.transitions.borrow().get(name) {
+            return next.clone();
+        }
+        let mut names = self.names.clone();
+        names.push(String::from(name));
+        let mut slots = self.slots.clone();
+        slots.insert(String::from(name), self.names.len());
+        let next = Rc::new(Shape {
+            names,
+            slots,
+            transitions: RefCell::default(),
+        });
+        self.transitions
+            .borrow_mut()
+            .insert(String::from(name), next.clone());
+        next
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct LoxClassInstance {
-    base: LoxClass,
-    fields: HashMap<String, Types>,
+impl std::fmt::Debug for LoxClassInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        format_acyclic(self as *const Self as *const (), f, |f| {
+            let mut debug = f.debug_struct(&self.base.name);
+            for name in self.field_names() {
+                debug.field(&name, &self.get_field(&name).unwrap());
+            }
+            debug.finish()
+        })
+    }
+}
+
+thread_local! {
+    /// The lists and instances being formatted on this thread, so one that
+    /// contains itself is shown as `<cycle>` rather than recursing forever.
+    static FORMATTING: std::cell::RefCell<Vec<*const ()>> = const { std::cell::RefCell::new(Vec::new()) };
+}
+
+/// Formats the list or instance at `ptr` with `write`, unless it's already
+/// being formatted further up.
+fn format_acyclic(
+    ptr: *const (),
+    f: &mut std::fmt::Formatter,
+    write: impl FnOnce(&mut std::fmt::Formatter) -> std::fmt::Result,
+) -> std::fmt::Result {
+    if FORMATTING.with(|formatting| formatting.borrow().contains(&ptr)) {
+        return write!(f, "<cycle>");
+    }
+    FORMATTING.with(|formatting| formatting.borrow_mut().push(ptr));
+    let result = write(f);
+    FORMATTING.with(|formatting| formatting.borrow_mut().pop());
+    result
+}
+
+/// Which changes Lox code may still make to an instance's fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mutability {
+    Open,
+    /// Existing fields can be updated, but none can be added or removed.
+    Sealed,
+    /// No field can be added, removed or updated.
+    Frozen,
 }
 
 impl LoxClassInstance {
-    pub fn new(base: LoxClass) -> Self {
+    pub fn new(base: Rc<LoxClass>) -> Self {
         LoxClassInstance {
+            shape: base.shape.clone(),
             base,
-            fields: HashMap::new(),
+            values: vec![],
+            mutability: Mutability::Open,
         }
     }
 
     pub fn get(this: &Rc<RefCell<Self>>, field: &Token) -> Result<Types, LoxError> {
-        if this.borrow().fields.contains_key(&field.lexeme) {
-            return Ok(this.borrow().fields.get(&field.lexeme).unwrap().clone());
+        if let Some(value) = this.borrow().get_field(&field.lexeme) {
+            return Ok(value);
         }
-        if this.borrow().base.methods.contains_key(&field.lexeme) {
-            if let Types::Callable(method) = this.borrow().base.methods.get(&field.lexeme).unwrap()
-            {
-                return Ok(method.bind(Types::ClassInstance(this.clone())));
-            } else {
-                unreachable!();
-            }
+        let method = this.borrow().base.find_method(&field.lexeme);
+        if let Some(Types::Callable(method)) = method {
+            return Ok(Types::Callable(
+                method.bind(Types::ClassInstance(this.clone())),
+            ));
         }
 
         LoxError::new_runtime(
@@ -164,12 +410,11 @@ impl LoxClassInstance {
         )
     }
 
+    /// The field called `field`. Methods are shared by every instance of the
+    /// class, so they can't be borrowed mutably through one instance.
     pub fn get_mut(&mut self, field: &Token) -> Result<&mut Types, LoxError> {
-        if self.fields.contains_key(&field.lexeme) {
-            return Ok(self.fields.get_mut(&field.lexeme).unwrap());
-        }
-        if self.base.methods.contains_key(&field.lexeme) {
-            return Ok(self.base.methods.get_mut(&field.lexeme).unwrap());
+        if let Some(slot) = self.shape.slot(&field.lexeme) {
+            return Ok(&mut self.values[slot]);
         }
 
         LoxError::new_runtime(
@@ -182,51 +427,261 @@ impl LoxClassInstance {
         )
     }
 
-    pub fn set_property(&mut self, field: &Token, value: Types) {
-        self.fields.insert(field.lexeme.clone(), value);
+    pub fn set_property(&mut self, field: &Token, value: Types) -> Result<(), LoxError> {
+        match self.mutability {
+            Mutability::Frozen => LoxError::new_runtime(
+                field.line,
+                format!("Can't set field `{}` on a frozen instance.", field.lexeme),
+            ),
+            Mutability::Sealed if !self.has_field(&field.lexeme) => LoxError::new_runtime(
+                field.line,
+                format!("Can't add field `{}` to a sealed instance.", field.lexeme),
+            ),
+            _ => {
+                self.set_field(&field.lexeme, value);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn mutability(&self) -> Mutability {
+        self.mutability
+    }
+
+    /// Restricts future changes to the instance. An instance can only become
+    /// more restricted: sealing a frozen instance leaves it frozen.
+    pub fn restrict(&mut self, mutability: Mutability) {
+        if self.mutability == Mutability::Open || mutability == Mutability::Frozen {
+            self.mutability = mutability;
+        }
+    }
+
+    pub fn class(&self) -> &Rc<LoxClass> {
+        &self.base
+    }
+
+    /// Names of the fields currently set on the instance, in sorted order.
+    pub fn field_names(&self) -> Vec<String> {
+        let mut names = self.shape.names.clone();
+        names.sort();
+        names
+    }
+
+    /// The fields' names and values, in the order they were added.
+    pub(crate) fn fields(&self) -> impl Iterator<Item = (&String, &Types)> {
+        self.shape.names.iter().zip(&self.values)
+    }
+
+    pub(crate) fn field_count(&self) -> usize {
+        self.values.len()
+    }
+
+    pub(crate) fn shape(&self) -> &Rc<Shape> {
+        &self.shape
+    }
+
+    /// The value in `slot`, which must be a slot of the instance's shape.
+    pub(crate) fn slot(&self, slot: usize) -> &Types {
+        &self.values[slot]
+    }
+
+    /// The value of the field `name`, without looking at methods.
+    pub fn get_field(&self, name: &str) -> Option<Types> {
+        let slot = self.shape.slot(name)?;
+        Some(self.values[slot].clone())
+    }
+
+    pub fn has_field(&self, name: &str) -> bool {
+        self.shape.slot(name).is_some()
+    }
+
+    /// Removes the field `name`. The other fields are added again from the
+    /// class's empty shape, so the instance shares the shape of instances
+    /// that never had it.
+    pub fn remove_field(&mut self, name: &str) -> Option<Types> {
+        let removed = self.shape.slot(name)?;
+        let mut shape = self.base.shape.clone();
+        for field in self.shape.names.iter().filter(|field| *field != name) {
+            shape = shape.with_field(field);
+        }
+        self.shape = shape;
+        Some(self.values.remove(removed))
+    }
+
+    pub fn set_field(&mut self, name: &str, value: Types) {
+        match self.shape.slot(name) {
+            Some(slot) => self.values[slot] = value,
+            None => {
+                self.shape = self.shape.with_field(name);
+                self.values.push(value);
+            }
+        }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct LoxClass {
     name: String,
+    /// The methods declared in the class itself.
     methods: HashMap<String, Types>,
-    superclass: Option<Box<LoxClass>>,
+    /// Every method callable on instances, including inherited ones that
+    /// aren't overridden, so finding one doesn't walk the superclasses.
+    all_methods: HashMap<String, Types>,
+    /// Fields declared with `class var`, shared by the class and its subclasses.
+    statics: RefCell<HashMap<String, Types>>,
+    superclass: Option<Rc<LoxClass>>,
+    doc: Option<String>,
+    /// The shape of its instances before any field is set.
+    shape: Rc<Shape>,
+}
+
+impl Clone for LoxClass {
+    /// Copies the class. The copy's instances get shapes of their own, since
+    /// a shape is taken to belong to one class.
+    fn clone(&self) -> Self {
+        LoxClass {
+            name: self.name.clone(),
+            methods: self.methods.clone(),
+            all_methods: self.all_methods.clone(),
+            statics: RefCell::new(self.statics.borrow().clone()),
+            superclass: self.superclass.clone(),
+            doc: self.doc.clone(),
+            shape: Rc::default(),
+        }
+    }
 }
 
 impl LoxClass {
     pub fn new(
         name: String,
         methods: HashMap<String, Types>,
-        superclass: Option<Box<LoxClass>>,
+        superclass: Option<Rc<LoxClass>>,
+        doc: Option<String>,
     ) -> Self {
+        let mut all_methods = match &superclass {
+            Some(superclass) => superclass.all_methods.clone(),
+            None => HashMap::new(),
+        };
+        all_methods.extend(methods.clone());
         LoxClass {
             name,
             methods,
+            all_methods,
+            statics: RefCell::new(HashMap::new()),
             superclass,
+            doc,
+            shape: Rc::default(),
         }
     }
 
-    fn new_instance(&self) -> Types {
-        Types::ClassInstance(Rc::new(RefCell::new(LoxClassInstance::new(self.clone()))))
+    pub fn doc(&self) -> Option<&str> {
+        self.doc.as_deref()
+    }
+
+    pub fn define_static(&self, name: String, value: Types) {
+        self.statics.borrow_mut().insert(name, value);
+    }
+
+    /// Reads a static field declared on the class or one of its superclasses.
+    pub fn get_static(&self, field: &Token) -> Result<Types, LoxError> {
+        match self.static_owner(&field.lexeme) {
+            Some(owner) => Ok(owner.statics.borrow()[field.lexeme.as_str()].clone()),
+            None => self.missing_static(field),
+        }
     }
 
-    fn find_method(&self, method: &String) -> Option<Types> {
-        if let Some(method) = self.methods.get(method) {
-            return Some(method.clone());
+    /// Updates a static field declared on the class or one of its superclasses.
+    /// New static fields can only be added with `class var`.
+    pub fn set_static(&self, field: &Token, value: Types) -> Result<(), LoxError> {
+        match self.static_owner(&field.lexeme) {
+            Some(owner) => {
+                owner
+                    .statics
+                    .borrow_mut()
+                    .insert(field.lexeme.to_string(), value);
+                Ok(())
+            }
+            None => self.missing_static(field),
         }
+    }
 
-        if let Some(sc) = &self.superclass {
-            sc.find_method(method)
+    fn static_owner(&self, name: &str) -> Option<&LoxClass> {
+        if self.statics.borrow().contains_key(name) {
+            Some(self)
         } else {
-            None
+            self.superclass.as_ref()?.static_owner(name)
+        }
+    }
+
+    fn missing_static<T>(&self, field: &Token) -> Result<T, LoxError> {
+        LoxError::new_runtime(
+            field.line,
+            format!(
+                "Class {} doesn't have a static field `{}`.",
+                self.name, field.lexeme
+            ),
+        )
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn methods(&self) -> &HashMap<String, Types> {
+        &self.methods
+    }
+
+    pub(crate) fn static_values(&self) -> Vec<Types> {
+        self.statics.borrow().values().cloned().collect()
+    }
+
+    pub fn superclass(&self) -> Option<&Rc<LoxClass>> {
+        self.superclass.as_ref()
+    }
+
+    /// Names of every method callable on instances of the class, including
+    /// inherited ones, in sorted order.
+    pub fn method_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.all_methods.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Whether the class or one of its superclasses is called `name`.
+    pub fn is_named_or_inherits(&self, name: &str) -> bool {
+        self.name == name
+            || self
+                .superclass
+                .as_ref()
+                .is_some_and(|superclass| superclass.is_named_or_inherits(name))
+    }
+
+    /// Whether this class is `other` or inherits from it.
+    pub fn is_subclass_of(&self, other: &LoxClass) -> bool {
+        std::ptr::eq(self, other)
+            || self
+                .superclass
+                .as_ref()
+                .is_some_and(|superclass| superclass.is_subclass_of(other))
+    }
+
+    /// The parameters of the class's initializer, which name the fields a
+    /// class pattern destructures.
+    fn init_params(&self) -> Vec<Token> {
+        match self.find_method("init") {
+            Some(Types::Callable(initializer)) => initializer.declaration.params.clone(),
+            _ => vec![],
         }
     }
+
+    fn find_method(&self, method: &str) -> Option<Types> {
+        self.all_methods.get(method).cloned()
+    }
 }
 
-impl Callable for LoxClass {
+impl Callable for Rc<LoxClass> {
     fn airity(&self) -> usize {
-        if let Some(Types::Callable(initializer)) = self.find_method(&String::from("init")) {
+        if let Some(Types::Callable(initializer)) = self.find_method("init") {
             initializer.airity()
         } else {
             0
@@ -238,13 +693,12 @@ impl Callable for LoxClass {
         interpreter: &mut Interpreter,
         arguments: Vec<Types>,
     ) -> Result<Types, LoxError> {
-        let instance = self.new_instance();
-        if let Some(Types::Callable(initializer)) = self.find_method(&String::from("init")) {
-            if let Types::Callable(bound) = initializer.bind(instance) {
-                bound.call(interpreter, arguments)
-            } else {
-                unreachable!()
-            }
+        let instance =
+            Types::ClassInstance(Rc::new(RefCell::new(LoxClassInstance::new(self.clone()))));
+        interpreter.metrics.allocations += 1;
+        interpreter.allocate(memory::INSTANCE)?;
+        if let Some(Types::Callable(initializer)) = self.find_method("init") {
+            initializer.bind(instance).call(interpreter, arguments)
         } else {
             Ok(instance)
         }
@@ -253,18 +707,38 @@ impl Callable for LoxClass {
     fn to_string(&self) -> String {
         format!("<class {}>", self.name)
     }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
 }
 
+/// A Lox value. Values are copied constantly, so no variant holds more than
+/// one pointer: anything bigger lives behind an `Rc`, keeping a value to two
+/// words, a tag and a payload.
 #[derive(Clone)]
 pub enum Types {
     Number(f64),
-    String(String),
+    /// Shared, so copying a string value doesn't copy its contents. An
+    /// `Rc<String>` rather than an `Rc<str>`, whose pointer would carry the
+    /// length too.
+    String(Rc<String>),
     Bool(bool),
-    NativeFunc(Rc<Box<dyn Callable>>),
-    Callable(LoxFunction),
-    Class(LoxClass),
+    NativeFunc(Rc<NativeFunction>),
+    Callable(Rc<LoxFunction>),
+    Class(Rc<LoxClass>),
     ClassInstance(Rc<RefCell<LoxClassInstance>>),
+    List(Rc<RefCell<Vec<Types>>>),
+    Generator(Rc<RefCell<Generator>>),
+    /// A Rust value the host exposes to scripts, see [`LoxObject`].
+    Host(Rc<HostObject>),
+    #[cfg(feature = "threads")]
+    Channel(crate::threads::Channel),
     Nil,
+    /// Held by a variable declared without an initializer until it's assigned,
+    /// when [`InterpreterOptions::uninitialized_errors`] is set. Reading it is
+    /// a runtime error, so Lox code never sees it.
+    Uninitialized,
 }
 
 impl std::fmt::Debug for Types {
@@ -275,16 +749,31 @@ impl std::fmt::Debug for Types {
             Types::Bool(b) => f.debug_tuple("Bool").field(b).finish(),
             Types::NativeFunc(func) => write!(f, "{}", func.to_string()),
             Types::Callable(c) => write!(f, "{}", c.to_string()),
-            Types::Class(name) => f.debug_tuple("Class").field(name).finish(),
-            Types::ClassInstance(instance) => {
-                f.debug_tuple("ClassInstance").field(instance).finish()
-            }
+            Types::Class(class) => f.debug_tuple("Class").field(&class.name).finish(),
+            Types::ClassInstance(instance) => f
+                .debug_tuple("ClassInstance")
+                .field(&*instance.borrow())
+                .finish(),
+            Types::List(list) => format_acyclic(Rc::as_ptr(list) as *const (), f, |f| {
+                f.debug_list().entries(list.borrow().iter()).finish()
+            }),
+            Types::Generator(generator) => write!(f, "<generator {}>", generator.borrow().name()),
+            Types::Host(object) => write!(f, "Host({})", object.class_name()),
+            #[cfg(feature = "threads")]
+            Types::Channel(_) => write!(f, "<channel>"),
             Types::Nil => write!(f, "Nil"),
+            Types::Uninitialized => write!(f, "Uninitialized"),
         }
     }
 }
 
 impl Types {
+    /// Wraps a host object for scripts to use. The caller can keep a clone of
+    /// `object` to see what scripts do with it.
+    pub fn host(object: Rc<RefCell<impl LoxObject + 'static>>) -> Types {
+        Types::Host(Rc::new(HostObject::new(object)))
+    }
+
     pub fn is_truty(&self) -> bool {
         match self {
             Types::Nil => false,
@@ -292,39 +781,130 @@ impl Types {
             _ => true,
         }
     }
-    pub fn number(&self, token: &Token) -> Result<f64, LoxError> {
+
+    pub fn is_nil(&self) -> bool {
+        matches!(self, Types::Nil)
+    }
+
+    /// Like `==`, but instances of the same class are also equal when their
+    /// fields are, rather than only when they're the same instance.
+    pub fn deep_eq(&self, other: &Types) -> bool {
+        structurally_equal(self, other, true, &mut vec![])
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
         match self {
-            Types::Number(f) => Ok(*f),
-            _ => LoxError::new_runtime(token.line, format!("Expected Number but found {self}")),
+            Types::Number(n) => Some(*n),
+            _ => None,
         }
     }
 
-    pub fn bool(&self, token: &Token) -> Result<bool, LoxError> {
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Types::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Types::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&Rc<RefCell<Vec<Types>>>> {
+        match self {
+            Types::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    pub fn as_instance(&self) -> Option<&Rc<RefCell<LoxClassInstance>>> {
+        match self {
+            Types::ClassInstance(instance) => Some(instance),
+            _ => None,
+        }
+    }
+
+    /// The instance, if it's one of the class called `class` or of a subclass of it.
+    pub fn as_instance_of(&self, class: &str) -> Option<&Rc<RefCell<LoxClassInstance>>> {
+        self.as_instance()
+            .filter(|instance| instance.borrow().class().is_named_or_inherits(class))
+    }
+
+    /// The field `name` of an instance, or the property of a host object.
+    /// `None` for other values, and for methods.
+    pub fn get_field(&self, name: &str) -> Option<Types> {
         match self {
-            Types::Bool(b) => Ok(*b),
-            _ => LoxError::new_runtime(token.line, format!("Expected Bool but found {self}")),
+            Types::ClassInstance(instance) => instance.borrow().get_field(name),
+            Types::Host(object) => object.try_borrow().ok()?.get(name),
+            _ => None,
+        }
+    }
+
+    pub fn number(&self, token: &Token) -> Result<f64, LoxError> {
+        match self.as_number() {
+            Some(n) => Ok(n),
+            None => LoxError::new_runtime(token.line, format!("Expected Number but found {self}")),
+        }
+    }
+
+    pub fn bool(&self, token: &Token) -> Result<bool, LoxError> {
+        match self.as_bool() {
+            Some(b) => Ok(b),
+            None => LoxError::new_runtime(token.line, format!("Expected Bool but found {self}")),
         }
     }
 
-    pub fn string(&self, token: &Token) -> Result<String, LoxError> {
+    pub fn string(&self, token: &Token) -> Result<Rc<String>, LoxError> {
         match self {
             Types::String(s) => Ok(s.clone()),
             _ => LoxError::new_runtime(token.line, format!("Expected String but found {self}")),
         }
     }
 
-    pub fn callable(&self, token: &Token) -> Result<Rc<Box<dyn Callable>>, LoxError> {
+    pub fn as_callable(&self) -> Option<Rc<Box<dyn Callable>>> {
         match self {
             Types::Callable(c) => {
-                let trait_obj: Box<dyn Callable> = Box::new(c.clone());
-                Ok(Rc::new(trait_obj))
+                let trait_obj: Box<dyn Callable> = Box::new((**c).clone());
+                Some(Rc::new(trait_obj))
             }
             Types::Class(c) => {
                 let trait_obj: Box<dyn Callable> = Box::new(c.clone());
-                Ok(Rc::new(trait_obj))
+                Some(Rc::new(trait_obj))
+            }
+            Types::NativeFunc(f) => {
+                let trait_obj: Box<dyn Callable> = Box::new(f.clone());
+                Some(Rc::new(trait_obj))
             }
-            Types::NativeFunc(f) => Ok(f.clone()),
-            _ => LoxError::new_runtime(token.line, format!("Expected Callable but found {self}")),
+            _ => None,
+        }
+    }
+
+    /// The value as a callable, without copying it as [`Types::as_callable`] does.
+    fn as_callable_ref(&self) -> Option<&dyn Callable> {
+        match self {
+            Types::Callable(c) => Some(&**c),
+            Types::Class(c) => Some(c),
+            Types::NativeFunc(f) => Some(f),
+            _ => None,
+        }
+    }
+
+    pub fn callable(&self, token: &Token) -> Result<Rc<Box<dyn Callable>>, LoxError> {
+        match self.as_callable() {
+            Some(c) => Ok(c),
+            None => {
+                LoxError::new_runtime(token.line, format!("Expected Callable but found {self}"))
+            }
+        }
+    }
+
+    pub fn list(&self, token: &Token) -> Result<Rc<RefCell<Vec<Types>>>, LoxError> {
+        match self {
+            Types::List(list) => Ok(list.clone()),
+            _ => LoxError::new_runtime(token.line, format!("Expected List but found {self}")),
         }
     }
 
@@ -339,15 +919,88 @@ impl Types {
     }
 }
 
+/// A string value, so hosts needn't know how strings are stored.
+impl From<&str> for Types {
+    fn from(s: &str) -> Self {
+        Types::String(Rc::new(String::from(s)))
+    }
+}
+
+impl From<String> for Types {
+    fn from(s: String) -> Self {
+        Types::String(Rc::new(s))
+    }
+}
+
+/// Lists are equal when their elements are.
 impl PartialEq for Types {
     fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (Types::Nil, Types::Nil) => true,
-            (Types::String(s1), Types::String(s2)) => s1 == s2,
-            (Types::Number(n1), Types::Number(n2)) => n1 == n2,
-            (Types::Bool(b1), Types::Bool(b2)) => b1 == b2,
-            _ => false,
+        structurally_equal(self, other, false, &mut vec![])
+    }
+}
+
+/// Compares lists, and instances too if `deep` is set, by their contents.
+/// `comparing` holds the pairs of lists and instances already being compared
+/// further up: meeting one of them again means both values contain a cycle,
+/// which is taken to be equal so the comparison doesn't recurse forever.
+fn structurally_equal(
+    a: &Types,
+    b: &Types,
+    deep: bool,
+    comparing: &mut Vec<(*const (), *const ())>,
+) -> bool {
+    let pair = match (a, b) {
+        (Types::List(l1), Types::List(l2)) => {
+            (Rc::as_ptr(l1) as *const (), Rc::as_ptr(l2) as *const ())
+        }
+        (Types::ClassInstance(i1), Types::ClassInstance(i2)) if deep => {
+            (Rc::as_ptr(i1) as *const (), Rc::as_ptr(i2) as *const ())
+        }
+        _ => return identical(a, b),
+    };
+    if pair.0 == pair.1 || comparing.contains(&pair) {
+        return true;
+    }
+    comparing.push(pair);
+    let equal = match (a, b) {
+        (Types::List(l1), Types::List(l2)) => {
+            let (l1, l2) = (l1.borrow(), l2.borrow());
+            l1.len() == l2.len()
+                && l1
+                    .iter()
+                    .zip(l2.iter())
+                    .all(|(e1, e2)| structurally_equal(e1, e2, deep, comparing))
+        }
+        (Types::ClassInstance(i1), Types::ClassInstance(i2)) => {
+            let (i1, i2) = (i1.borrow(), i2.borrow());
+            Rc::ptr_eq(&i1.base, &i2.base)
+                && i1.field_count() == i2.field_count()
+                && i1.fields().all(|(name, v1)| {
+                    i2.get_field(name)
+                        .is_some_and(|v2| structurally_equal(v1, &v2, deep, comparing))
+                })
         }
+        _ => false,
+    };
+    comparing.pop();
+    equal
+}
+
+/// Equality for values other than lists and instances compared deeply.
+fn identical(a: &Types, b: &Types) -> bool {
+    match (a, b) {
+        (Types::Nil, Types::Nil) => true,
+        (Types::String(s1), Types::String(s2)) => s1 == s2,
+        (Types::Number(n1), Types::Number(n2)) => n1 == n2,
+        (Types::Bool(b1), Types::Bool(b2)) => b1 == b2,
+        (Types::Callable(f1), Types::Callable(f2)) => f1.same_as(f2),
+        (Types::NativeFunc(f1), Types::NativeFunc(f2)) => Rc::ptr_eq(f1, f2),
+        (Types::Class(c1), Types::Class(c2)) => Rc::ptr_eq(c1, c2),
+        (Types::ClassInstance(i1), Types::ClassInstance(i2)) => Rc::ptr_eq(i1, i2),
+        (Types::Host(o1), Types::Host(o2)) => Rc::ptr_eq(&o1.object, &o2.object),
+        #[cfg(feature = "threads")]
+        (Types::Channel(c1), Types::Channel(c2)) => c1.same_as(c2),
+        _ => false,
     }
 }
 
@@ -363,130 +1016,1082 @@ impl std::fmt::Display for Types {
             }
             Types::Callable(c) => write!(f, "{}", c.to_string()),
             Types::NativeFunc(func) => write!(f, "{}", func.to_string()),
-            Types::Nil => write!(f, "Nil"),
+            Types::List(list) => format_acyclic(Rc::as_ptr(list) as *const (), f, |f| {
+                write!(f, "[")?;
+                for (i, element) in list.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{element}")?;
+                }
+                write!(f, "]")
+            }),
+            Types::Generator(generator) => write!(f, "<generator {}>", generator.borrow().name()),
+            Types::Host(object) => {
+                write!(f, "instance of <class {}>", object.class_name())
+            }
+            #[cfg(feature = "threads")]
+            Types::Channel(_) => write!(f, "<channel>"),
+            Types::Nil | Types::Uninitialized => write!(f, "Nil"),
         }
     }
 }
 
+/// Where a resolved local variable is kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Local {
+    /// In the environment `depth` levels up from where it's used, at `slot`.
+    Env { depth: usize, slot: usize },
+    /// In the current call's frame, at this index from its base.
+    Frame(usize),
+}
+
+/// Saved interpreter state, see [`Interpreter::snapshot`].
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    globals: EnvironmentSnapshot,
+    current: Option<(Rc<RefCell<Environment>>, EnvironmentSnapshot)>,
+}
+
+/// Settings that change how an [`Interpreter`] runs programs. All of them are
+/// off by default, which gives the book's semantics.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InterpreterOptions {
+    /// What natives may do outside the interpreter, such as spawning host
+    /// processes through `exec`. Nothing is allowed by default so embedders
+    /// get a sandboxed interpreter.
+    pub permissions: Permissions,
+    /// Makes `+` with one string operand convert the other one to a string, so
+    /// `"count: " + 3` is `"count: 3"`. Otherwise it's a runtime error.
+    pub stringify_operands: bool,
+    /// Makes division by zero follow IEEE 754, giving infinity or NaN, instead
+    /// of being a runtime error as in the book.
+    pub ieee_division: bool,
+    /// Makes reading a property of nil give nil, so `user.address.city` is nil
+    /// when `user.address` is, rather than a runtime error. Setting a property
+    /// of nil is still an error.
+    pub nil_properties: bool,
+    /// Makes reading a variable declared without an initializer, before
+    /// anything is assigned to it, a runtime error rather than giving nil.
+    /// Strict mode turns this on too.
+    pub uninitialized_errors: bool,
+    /// Makes the resolver's warnings errors, and turns off the lenient
+    /// coercions above.
+    pub strict: bool,
+    /// Roughly how many bytes of strings, lists, instances and environments a
+    /// script may keep alive before it's stopped with a runtime error, so an
+    /// untrusted script can't exhaust the host's memory.
+    pub memory_limit: Option<usize>,
+    /// Measures the live values before every allocation, rather than only when
+    /// the memory limit could have been reached, to make problems with memory
+    /// accounting reproducible. Very slow.
+    pub gc_stress: bool,
+    /// Makes `clock()` start at 0 and step by a millisecond each call, seeds
+    /// `random()` with a fixed value and reports no time spent measuring
+    /// memory, so a script prints the same thing every time it runs.
+    pub deterministic: bool,
+}
+
+impl InterpreterOptions {
+    pub fn stringifies_operands(&self) -> bool {
+        self.stringify_operands && !self.strict
+    }
+
+    pub fn uses_ieee_division(&self) -> bool {
+        self.ieee_division && !self.strict
+    }
+
+    pub fn reads_nil_properties(&self) -> bool {
+        self.nil_properties && !self.strict
+    }
+
+    pub fn errors_on_uninitialized(&self) -> bool {
+        self.uninitialized_errors || self.strict
+    }
+}
+
+/// Where `print` statements write to, see [`Interpreter::set_output`].
+pub trait Output: Write + Shareable {}
+
+impl<T: Write + Shareable> Output for T {}
+
+/// Counts of the work an [`Interpreter`] has done, see [`Interpreter::metrics`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Metrics {
+    pub statements: u64,
+    pub expressions: u64,
+    /// Calls made from Lox code, to functions, classes and natives alike.
+    pub calls: u64,
+    /// Environments created for scopes and calls. Calls that keep their
+    /// locals on the frame don't need one.
+    pub environments: u64,
+    /// Strings, lists, instances, functions and classes created by Lox code.
+    pub allocations: u64,
+}
+
+/// Stops a running script from another thread, e.g. a GUI's stop button or a
+/// Ctrl-C handler. The interpreter checks it before each statement and fails
+/// with [`LoxError::Interrupted`] once it's cancelled. Clones share the flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancelHandle(Arc<AtomicBool>);
+
+impl CancelHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Clears a cancellation, reporting whether there was one. The interpreter
+    /// does this when it stops, so the next run isn't interrupted too.
+    fn take(&self) -> bool {
+        self.is_cancelled() && self.0.swap(false, Ordering::Relaxed)
+    }
+}
+
+/// What memory accounting has found, see [`Interpreter::gc_stats`].
+///
+/// Values are freed by reference counting as soon as nothing refers to them,
+/// so loxrt never collects garbage itself. A collection here is a pass that
+/// measures the live values, which runs when the memory limit could have been
+/// reached, or before every allocation with [`InterpreterOptions::gc_stress`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GcStats {
+    pub collections: u64,
+    pub bytes_live: usize,
+    pub objects_live: usize,
+    /// The time spent in collections.
+    pub pause: Duration,
+}
+
 pub struct Interpreter {
     pub global_env: Rc<RefCell<Environment>>,
     pub environment: Rc<RefCell<Environment>>,
-    locals: HashMap<String, usize>,
+    locals: HashMap<NodeId, Local>,
+    /// For global references, a local with a similar name, to suggest if the global doesn't exist.
+    similar_locals: HashMap<NodeId, String>,
+    /// For each `Expr::Get`, what it last read, see [`Interpreter::get_property`].
+    property_caches: HashMap<NodeId, PropertyCache>,
+    /// The parts shared by every function each `Stmt::Function` makes, see
+    /// [`Interpreter::function`].
+    declarations: HashMap<NodeId, Rc<FunctionDeclaration>>,
+    /// Environments of finished calls, see [`Interpreter::new_environment`].
+    spare_environments: Vec<Rc<RefCell<Environment>>>,
+    /// The environments of the blocks and calls waiting for the current one to
+    /// finish, innermost last. Nothing else reaches them while it runs, so
+    /// memory accounting counts them from here.
+    callers: Vec<Rc<RefCell<Environment>>>,
+    /// Locals of calls that run on the frame, see [`runs_on_frame`].
+    frame: Vec<Types>,
+    /// Where the current call's locals start in `frame`.
+    frame_base: usize,
+    /// Set while running a call whose locals are on the frame.
+    on_frame: bool,
+    line: usize,
+    options: InterpreterOptions,
+    output: Box<dyn Output>,
+    tasks: VecDeque<Task>,
+    running_tasks: bool,
+    trace: Tracing,
+    hooks: Option<Box<dyn InterpreterHooks>>,
+    metrics: Metrics,
+    /// Bytes of live values found the last time they were measured, see [`Interpreter::allocate`].
+    live_bytes: usize,
+    /// Bytes allocated since then.
+    allocated_bytes: usize,
+    collections: u64,
+    gc_pause: Duration,
+    cancel: CancelHandle,
+    /// Where threads started by `spawnThread` leave the diagnostics of the
+    /// errors they fail with, see [`Interpreter::take_thread_errors`].
+    #[cfg(feature = "threads")]
+    thread_errors: crate::threads::ThreadErrors,
+    /// Threads started by `spawnThread` that haven't been waited for yet.
+    #[cfg(feature = "threads")]
+    threads: Vec<std::thread::JoinHandle<()>>,
+    /// Calls of `clock()` so far, for the virtual clock of deterministic mode.
+    clock_ticks: u64,
+    /// The state of `random()`'s generator, seeded on its first call.
+    random_state: Option<u64>,
+    /// The names of the globals defined before any script ran, i.e. the
+    /// natives and what the preludes define, see [`Interpreter::mark_library`].
+    library: HashSet<String>,
+}
+
+/// What an `Expr::Get` found the last time it read from an instance with
+/// `shape`. A shape only belongs to one class, whose methods never change once
+/// it's declared, so this is right for any instance with the same shape.
+/// Holding on to the shape keeps another from being allocated at its address.
+struct PropertyCache {
+    shape: Rc<Shape>,
+    property: Property,
+}
+
+enum Property {
+    /// The field in this slot of the instance's values.
+    Field(usize),
+    /// A method of the class, with no field of the same name to shadow it.
+    Method(Rc<LoxFunction>),
+}
+
+/// The arguments of a call expression. Most calls have only a few, which
+/// are kept inline.
+type Arguments = SmallVec<[Types; 4]>;
+
+/// How many environments of finished calls are kept to be reused.
+const MAX_SPARE_ENVIRONMENTS: usize = 64;
+
+/// What `random()` is seeded with in deterministic mode.
+const DETERMINISTIC_SEED: u64 = 0x5EED;
+
+/// What happens to the inputs natives get from outside the program.
+enum Tracing {
+    Off,
+    Record(Trace),
+    Replay(Trace),
+}
+
+/// Applies a comparison operator to two numbers, or to two strings, which
+/// compare lexicographically.
+fn compare(left: &Types, right: &Types, operator: &Token) -> Result<Types, LoxError> {
+    let ordering = match (left, right) {
+        (Types::String(left), Types::String(right)) => Some(left.cmp(right)),
+        _ => left.number(operator)?.partial_cmp(&right.number(operator)?),
+    };
+    // NaN compares false with everything.
+    let result = ordering.is_some_and(|ordering| match operator.tok_typ {
+        TokenType::Greater => ordering.is_gt(),
+        TokenType::GreaterEqual => ordering.is_ge(),
+        TokenType::Less => ordering.is_lt(),
+        _ => ordering.is_le(),
+    });
+    Ok(Types::Bool(result))
+}
+
+fn list_index(list: &[Types], index: &Types, token: &Token) -> Result<usize, LoxError> {
+    let n = index.number(token)?;
+    if n.fract() != 0.0 || n < 0.0 || n as usize >= list.len() {
+        return LoxError::new_runtime(
+            token.line,
+            format!(
+                "Index {n} is out of bounds for list of length {}.",
+                list.len()
+            ),
+        );
+    }
+    Ok(n as usize)
 }
 
-fn clock() -> Result<Types, LoxError> {
-    match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-        Ok(n) => Ok(Types::Number(n.as_millis() as f64 / 1000.0)),
-        Err(_) => panic!("SystemTime before UNIX EPOCH!"),
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl Interpreter {
     pub fn new() -> Self {
         let environment = Environment::new();
-        environment.borrow_mut().define(
-            String::from("clock"),
-            Types::NativeFunc(Rc::new(Box::new(clock))),
-        );
+        natives::define_globals(&mut environment.borrow_mut());
+        let library = environment.borrow().values.keys().cloned().collect();
         Interpreter {
             global_env: environment.clone(),
             environment,
             locals: HashMap::new(),
+            similar_locals: HashMap::new(),
+            property_caches: HashMap::new(),
+            declarations: HashMap::new(),
+            spare_environments: vec![],
+            callers: vec![],
+            frame: vec![],
+            frame_base: 0,
+            on_frame: false,
+            line: 0,
+            options: InterpreterOptions::default(),
+            output: Box::new(std::io::stdout()),
+            tasks: VecDeque::new(),
+            running_tasks: false,
+            trace: Tracing::Off,
+            hooks: None,
+            metrics: Metrics::default(),
+            live_bytes: 0,
+            allocated_bytes: 0,
+            collections: 0,
+            gc_pause: Duration::ZERO,
+            cancel: CancelHandle::new(),
+            #[cfg(feature = "threads")]
+            thread_errors: Default::default(),
+            #[cfg(feature = "threads")]
+            threads: vec![],
+            clock_ticks: 0,
+            random_state: None,
+            library,
         }
     }
 
-    pub fn interpret(&mut self, statements: &Vec<Box<Stmt>>) -> Result<(), LoxError> {
-        for stmt in statements {
-            self.execute(&**stmt)?;
+    /// Reads a global variable, e.g. a result computed by a script.
+    pub fn get_global(&self, name: &str) -> Option<Types> {
+        match self.global_env.borrow().values.get(name) {
+            Some(Types::Uninitialized) => Some(Types::Nil),
+            value => value.cloned(),
         }
+    }
+
+    /// Defines or overwrites a global variable, e.g. to pass configuration to a script.
+    pub fn set_global(&mut self, name: &str, value: Types) {
+        self.global_env
+            .borrow_mut()
+            .define(String::from(name), value);
+    }
+
+    /// All global variables, including the native functions.
+    pub fn globals(&self) -> impl Iterator<Item = (String, Types)> {
+        let globals: Vec<(String, Types)> = self
+            .global_env
+            .borrow()
+            .values
+            .iter()
+            .map(|(name, value)| match value {
+                Types::Uninitialized => (name.clone(), Types::Nil),
+                value => (name.clone(), value.clone()),
+            })
+            .collect();
+        globals.into_iter()
+    }
 
+    /// Records the globals defined so far as part of the standard library,
+    /// which every interpreter made the same way has, so
+    /// [`Interpreter::export_globals_json`] leaves their functions and classes
+    /// out without a warning.
+    pub fn mark_library(&mut self) {
+        self.library = self.global_env.borrow().values.keys().cloned().collect();
+    }
+
+    /// Renders the globals holding data, i.e. numbers, strings, booleans, nil
+    /// and lists of them, as a JSON object, so a host can save a session's
+    /// state and restore it with [`Interpreter::import_globals_json`] in
+    /// another process. Also returns a warning for each global left out
+    /// because it holds something else, e.g. a function or an instance.
+    pub fn export_globals_json(&self) -> (String, Vec<String>) {
+        let mut globals: Vec<(String, Types)> = self.globals().collect();
+        globals.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let mut entries = vec![];
+        let mut warnings = vec![];
+        for (name, value) in globals {
+            let library_code = matches!(
+                value,
+                Types::Callable(_) | Types::NativeFunc(_) | Types::Class(_)
+            );
+            if library_code && self.library.contains(&name) {
+                continue;
+            }
+            match json::value(&value) {
+                Ok(value) => entries.push(format!("{}:{value}", json::string(&name))),
+                Err(kind) => warnings.push(format!("`{name}` is {kind}, so it wasn't exported.")),
+            }
+        }
+        (format!("{{{}}}", entries.join(",")), warnings)
+    }
+
+    /// Defines a global for each entry of a JSON object such as
+    /// [`Interpreter::export_globals_json`] renders, replacing any global with
+    /// the same name. Arrays become lists and `null` becomes nil. Nothing is
+    /// defined if the JSON is invalid or holds objects below the top level.
+    pub fn import_globals_json(&mut self, json: &str) -> Result<(), String> {
+        for (name, value) in json::parse_object(json)? {
+            self.set_global(&name, value);
+        }
         Ok(())
     }
 
+    /// Captures the global bindings, and the innermost environment's too when
+    /// `include_current` is set, so they can be reset later with [`Interpreter::restore`].
+    pub fn snapshot(&self, include_current: bool) -> Snapshot {
+        let current = if include_current && !Rc::ptr_eq(&self.environment, &self.global_env) {
+            Some((
+                self.environment.clone(),
+                self.environment.borrow().snapshot(),
+            ))
+        } else {
+            None
+        };
+        Snapshot {
+            globals: self.global_env.borrow().snapshot(),
+            current,
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.global_env.borrow_mut().restore(&snapshot.globals);
+        match &snapshot.current {
+            Some((environment, values)) => {
+                environment.borrow_mut().restore(values);
+                self.environment = environment.clone();
+            }
+            None => self.environment = self.global_env.clone(),
+        }
+    }
+
+    /// Redirects the output of `print` statements, which goes to stdout by default.
+    pub fn set_output(&mut self, output: Box<dyn Output>) {
+        self.output = output;
+    }
+
+    pub fn set_options(&mut self, options: InterpreterOptions) {
+        self.options = options;
+    }
+
+    pub fn options(&self) -> InterpreterOptions {
+        self.options
+    }
+
+    /// Waits for the threads started by `spawnThread` to finish, then returns
+    /// the diagnostics of the errors they failed with since the last call,
+    /// for [`crate::Lox`] to report.
+    pub fn take_thread_errors(&mut self) -> Vec<diagnostics::Diagnostic> {
+        #[cfg(feature = "threads")]
+        {
+            self.join_threads();
+            return std::mem::take(
+                &mut *self.thread_errors.lock().unwrap_or_else(|e| e.into_inner()),
+            );
+        }
+        #[cfg(not(feature = "threads"))]
+        vec![]
+    }
+
+    /// Whether `function` was declared at the top level, so it only needs the
+    /// globals to run.
+    #[cfg(feature = "threads")]
+    pub(crate) fn is_top_level(&self, function: &LoxFunction) -> bool {
+        Rc::ptr_eq(function.closure(), &self.global_env)
+    }
+
+    #[cfg(feature = "threads")]
+    pub(crate) fn add_thread(&mut self, thread: std::thread::JoinHandle<()>) {
+        self.threads.push(thread);
+    }
+
+    /// Waits for the threads started by `spawnThread` to finish. One that
+    /// panicked has nothing to report, since its errors are reported as
+    /// diagnostics instead.
+    #[cfg(feature = "threads")]
+    pub(crate) fn join_threads(&mut self) {
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+
+    #[cfg(feature = "threads")]
+    pub(crate) fn thread_errors(&self) -> crate::threads::ThreadErrors {
+        self.thread_errors.clone()
+    }
+
+    /// Has this interpreter's threads report to where `errors` goes, so errors
+    /// in threads started by other threads still reach the first interpreter.
+    #[cfg(feature = "threads")]
+    pub(crate) fn set_thread_errors(&mut self, errors: crate::threads::ThreadErrors) {
+        self.thread_errors = errors;
+    }
+
+    /// Starts keeping the inputs natives get from outside the program, such
+    /// as the time or the output of processes, to replay them later.
+    pub fn record(&mut self) {
+        self.trace = Tracing::Record(Trace::new());
+    }
+
+    /// Makes natives take their inputs from `trace`, in order, instead of from
+    /// outside the program.
+    pub fn replay(&mut self, trace: Trace) {
+        self.trace = Tracing::Replay(trace);
+    }
+
+    /// Stops recording and returns the inputs recorded so far.
+    pub fn take_trace(&mut self) -> Option<Trace> {
+        match std::mem::replace(&mut self.trace, Tracing::Off) {
+            Tracing::Record(trace) => Some(trace),
+            trace => {
+                self.trace = trace;
+                None
+            }
+        }
+    }
+
+    /// Gets an input for the native `native` through `read`, or from the trace
+    /// being replayed. Natives whose result depends on more than their
+    /// arguments should get it through here.
+    pub fn input(
+        &mut self,
+        native: &str,
+        read: impl FnOnce() -> Result<Vec<TraceValue>, LoxError>,
+    ) -> Result<Vec<TraceValue>, LoxError> {
+        match &mut self.trace {
+            Tracing::Off => read(),
+            Tracing::Record(trace) => {
+                let result = read();
+                // Failed runs are the ones most worth replaying, so failing to
+                // get an input is recorded too.
+                let values = match &result {
+                    Ok(values) => values.clone(),
+                    Err(e @ LoxError::RuntimeError(_)) => vec![TraceValue::Error(e.message())],
+                    Err(_) => return result,
+                };
+                trace.push(TraceEntry {
+                    native: String::from(native),
+                    values,
+                });
+                result
+            }
+            Tracing::Replay(trace) => match trace.pop() {
+                Some(entry) if entry.native == native => match entry.values.as_slice() {
+                    [TraceValue::Error(message)] => {
+                        LoxError::new_runtime(self.line, message.clone())
+                    }
+                    _ => Ok(entry.values),
+                },
+                Some(entry) => LoxError::new_runtime(
+                    self.line,
+                    format!(
+                        "Expected the trace's next input to be for `{native}` but it's for `{}`.",
+                        entry.native
+                    ),
+                ),
+                None => LoxError::new_runtime(
+                    self.line,
+                    format!("The trace has no more inputs for `{native}`."),
+                ),
+            },
+        }
+    }
+
+    /// Makes the interpreter call `hooks` as it runs, replacing any set before.
+    pub fn set_hooks(&mut self, hooks: Box<dyn InterpreterHooks>) {
+        self.hooks = Some(hooks);
+    }
+
+    pub fn take_hooks(&mut self) -> Option<Box<dyn InterpreterHooks>> {
+        self.hooks.take()
+    }
+
+    /// The work done since the interpreter was created or the metrics were
+    /// last reset, e.g. to bill scripts or to track the interpreter's performance.
+    pub fn metrics(&self) -> Metrics {
+        self.metrics
+    }
+
+    pub fn reset_metrics(&mut self) {
+        self.metrics = Metrics::default();
+    }
+
+    /// Accounts for a new value of about `bytes` bytes, failing if it takes the
+    /// script over its memory limit.
+    ///
+    /// Values are freed as soon as nothing refers to them, which the interpreter
+    /// doesn't track, so it only knows how much has been allocated since it last
+    /// measured the live values. Once that could exceed the limit, it measures
+    /// again by walking everything reachable from the globals, the current scope
+    /// and the frame. To keep that from running on every allocation close to the
+    /// limit, it waits until at least an eighth of the limit has been allocated,
+    /// so a script can overshoot by up to that much.
+    pub(crate) fn allocate(&mut self, bytes: usize) -> Result<(), LoxError> {
+        let limit = self.options.memory_limit;
+        if limit.is_none() && !self.options.gc_stress {
+            return Ok(());
+        }
+        self.allocated_bytes += bytes;
+        let due = limit.is_some_and(|limit| {
+            self.live_bytes + self.allocated_bytes > limit && self.allocated_bytes >= limit / 8
+        });
+        if !due && !self.options.gc_stress {
+            return Ok(());
+        }
+
+        let start = Instant::now();
+        let live = self.live_size();
+        self.live_bytes = live.bytes;
+        self.gc_pause += start.elapsed();
+        self.collections += 1;
+        // What was allocated may be in an object that couldn't be measured, so
+        // it's still counted until one can be.
+        if !live.incomplete {
+            self.allocated_bytes = 0;
+        }
+        match limit {
+            Some(limit) if self.live_bytes + self.allocated_bytes > limit => LoxError::new_runtime(
+                self.line,
+                format!("Memory limit of {limit} bytes exceeded."),
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    fn live_size(&self) -> LiveSize {
+        let mut size = LiveSize::default();
+        size.environment(&self.global_env);
+        size.environment(&self.environment);
+        self.callers
+            .iter()
+            .for_each(|environment| size.environment(environment));
+        self.frame.iter().for_each(|value| size.value(value));
+        for task in &self.tasks {
+            match task {
+                Task::Start(func) => size.value(func),
+                Task::Resume(generator) => size.value(&Types::Generator(generator.clone())),
+            }
+        }
+        size
+    }
+
+    /// The approximate size of the values a script can still reach.
+    pub fn live_bytes(&self) -> usize {
+        self.live_size().bytes
+    }
+
+    /// Memory accounting's statistics, with the values live right now.
+    pub fn gc_stats(&self) -> GcStats {
+        let live = self.live_size();
+        GcStats {
+            collections: self.collections,
+            bytes_live: live.bytes,
+            objects_live: live.objects(),
+            pause: if self.options.deterministic {
+                Duration::ZERO
+            } else {
+                self.gc_pause
+            },
+        }
+    }
+
+    /// The time `clock()` gives in deterministic mode, in seconds.
+    pub(crate) fn virtual_clock(&mut self) -> Option<f64> {
+        if !self.options.deterministic {
+            return None;
+        }
+        let time = self.clock_ticks as f64 / 1000.0;
+        self.clock_ticks += 1;
+        Some(time)
+    }
+
+    /// The next number from 0 up to but not including 1 for `random()`.
+    pub(crate) fn next_random(&mut self) -> f64 {
+        let state = self.random_state.get_or_insert_with(|| {
+            if self.options.deterministic {
+                return DETERMINISTIC_SEED;
+            }
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(DETERMINISTIC_SEED, |time| time.as_nanos() as u64)
+        });
+        // SplitMix64, which is good enough for scripts and needs no state
+        // beyond a counter.
+        *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = *state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        // The top 53 bits fill an f64's mantissa exactly.
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A handle that interrupts whatever the interpreter is running.
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.cancel.clone()
+    }
+
+    /// Makes the interpreter stop when `handle` is cancelled, e.g. to stop
+    /// several interpreters with one handle.
+    pub fn set_cancel_handle(&mut self, handle: CancelHandle) {
+        self.cancel = handle;
+    }
+
+    /// Grants `permission` to the scripts this runs, see
+    /// [`InterpreterOptions::permissions`].
+    pub fn grant(&mut self, permission: Permission) {
+        self.options.permissions.grant(permission);
+    }
+
+    /// Fails unless `permission` has been granted, for natives to call before
+    /// doing anything it covers.
+    pub fn require(&self, permission: Permission) -> Result<(), LoxError> {
+        if self.options.permissions.allows(permission) {
+            return Ok(());
+        }
+        LoxError::new_runtime(
+            self.line,
+            format!(
+                "{permission} is disabled. Run with `{}` to enable it.",
+                permission.flag()
+            ),
+        )
+    }
+
+    /// Line of the call currently being evaluated, used by natives to report errors.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Calls `callee` with `arguments` as Lox code would, e.g. for a native that
+    /// takes a callback. Errors are reported at the line of the current call.
+    pub fn call(&mut self, callee: &Types, arguments: Vec<Types>) -> Result<Types, LoxError> {
+        let Some(function) = callee.as_callable_ref() else {
+            return LoxError::new_runtime(
+                self.line,
+                format!("Expected Callable but found {callee}"),
+            );
+        };
+        self.before_call(function, &arguments)?;
+        function.call(self, arguments)
+    }
+
+    /// Calls `callee` with the arguments of a call expression. Lox functions,
+    /// the most common callees, take them without a `Vec` being allocated.
+    fn call_expression(&mut self, callee: &Types, arguments: Arguments) -> Result<Types, LoxError> {
+        match callee {
+            Types::Callable(function) => {
+                self.before_call(&**function, &arguments)?;
+                function.call_with(self, arguments)
+            }
+            _ => self.call(callee, arguments.into_vec()),
+        }
+    }
+
+    /// Checks the number of `arguments` passed to `function` and counts the call.
+    fn before_call(
+        &mut self,
+        function: &dyn Callable,
+        arguments: &[Types],
+    ) -> Result<(), LoxError> {
+        if function.airity() != arguments.len() {
+            return LoxError::new_runtime(
+                self.line,
+                format!(
+                    "Expected {} arguments, but got {}",
+                    function.airity(),
+                    arguments.len()
+                ),
+            );
+        }
+        self.metrics.calls += 1;
+        if let Some(hooks) = &mut self.hooks {
+            hooks.before_call(&function.name(), arguments);
+        }
+        Ok(())
+    }
+
+    /// Queues `task` to run the next time [`Interpreter::run_tasks`] is called.
+    pub fn spawn(&mut self, task: Task) {
+        self.tasks.push_back(task);
+    }
+
+    /// Runs spawned tasks in turn, each until its next `yield`, until all of
+    /// them have finished. Tasks may spawn more tasks while running.
+    pub fn run_tasks(&mut self) -> Result<(), LoxError> {
+        if self.running_tasks {
+            return LoxError::new_runtime(self.line, String::from("Tasks are already running."));
+        }
+        self.running_tasks = true;
+        let result = self.run_task_queue();
+        self.running_tasks = false;
+        result
+    }
+
+    fn run_task_queue(&mut self) -> Result<(), LoxError> {
+        while let Some(task) = self.tasks.pop_front() {
+            let generator = match task {
+                Task::Start(func) => match self.call(&func, vec![])? {
+                    Types::Generator(generator) => generator,
+                    // A function without `yield` finishes in one turn.
+                    _ => continue,
+                },
+                Task::Resume(generator) => generator,
+            };
+            Generator::resume(&generator, self)?;
+            if !generator.borrow().is_done() {
+                self.tasks.push_back(Task::Resume(generator));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn interpret(&mut self, statements: &[Box<Stmt>]) -> Result<(), LoxError> {
+        self.interpret_to_value(statements).map(|_| ())
+    }
+
+    /// Runs `statements` like [`Interpreter::interpret`], returning the value
+    /// of the last one if it's an expression statement, e.g. `1 + 2;`.
+    pub fn interpret_to_value(
+        &mut self,
+        statements: &[Box<Stmt>],
+    ) -> Result<Option<Types>, LoxError> {
+        let mut value = None;
+        for (i, stmt) in statements.iter().enumerate() {
+            let result = match &**stmt {
+                Stmt::Expr { expr, .. } if i + 1 == statements.len() => self
+                    .before_statement(stmt)
+                    .and_then(|_| self.evaulate(expr))
+                    .map(|result| value = Some(result)),
+                _ => self.execute(stmt),
+            };
+            if let Err(e) = result {
+                if let Some(hooks) = &mut self.hooks {
+                    hooks.on_error(&e);
+                }
+                return Err(e);
+            }
+        }
+
+        Ok(value)
+    }
+
     pub fn execute_block(
         &mut self,
-        block: &Vec<Box<Stmt>>,
+        block: &[Box<Stmt>],
         environment: Rc<RefCell<Environment>>,
     ) -> Result<(), LoxError> {
-        let prev = self.environment.clone();
-        self.environment = environment;
-
-        for stmt in block {
-            match self.execute(&**stmt) {
-                Err(e) => {
-                    self.environment = prev;
-                    return Err(e);
-                }
-                _ => (),
+        let prev = std::mem::replace(&mut self.environment, environment);
+        self.callers.push(prev);
+        let result = block.iter().try_for_each(|stmt| self.execute(stmt));
+        self.environment = self.resume_caller();
+        result
+    }
+
+    /// Pushes the current environment onto [`Interpreter::callers`] while `f`
+    /// runs somewhere else, then goes back to it.
+    pub(crate) fn away<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        self.callers.push(self.environment.clone());
+        let result = f(self);
+        self.environment = self.resume_caller();
+        result
+    }
+
+    fn resume_caller(&mut self) -> Rc<RefCell<Environment>> {
+        self.callers
+            .pop()
+            .expect("Every environment pushed onto `callers` is popped once.")
+    }
+
+    /// Runs a function body with `arguments` as the first locals of a new frame.
+    fn execute_on_frame(
+        &mut self,
+        body: &[Box<Stmt>],
+        closure: &Rc<RefCell<Environment>>,
+        arguments: impl IntoIterator<Item = Types>,
+    ) -> Result<(), LoxError> {
+        let prev_environment = std::mem::replace(&mut self.environment, closure.clone());
+        self.callers.push(prev_environment);
+        let prev_base = std::mem::replace(&mut self.frame_base, self.frame.len());
+        let prev_on_frame = std::mem::replace(&mut self.on_frame, true);
+        self.frame.extend(arguments);
+
+        let result = body.iter().try_for_each(|stmt| self.execute(stmt));
+
+        self.frame.truncate(self.frame_base);
+        self.environment = self.resume_caller();
+        self.frame_base = prev_base;
+        self.on_frame = prev_on_frame;
+        result
+    }
+
+    /// A function made by running `declaration`, a `Stmt::Function`, closing
+    /// over the current environment. What doesn't change between runs is only
+    /// copied out of the AST the first time.
+    fn function(&mut self, declaration: &Stmt, is_initializer: bool) -> Types {
+        let shared = match self.declarations.get(&declaration.id()) {
+            Some(shared) => shared.clone(),
+            None => {
+                let Stmt::Function {
+                    id,
+                    name,
+                    params,
+                    body,
+                    doc,
+                    ..
+                } = declaration
+                else {
+                    unreachable!("Only function declarations make functions.")
+                };
+                let shared = Rc::new(FunctionDeclaration::new(
+                    name.clone(),
+                    params.clone(),
+                    body.clone(),
+                    is_initializer,
+                    doc.clone(),
+                ));
+                self.declarations.insert(*id, shared.clone());
+                shared
+            }
+        };
+        Types::Callable(Rc::new(LoxFunction {
+            declaration: shared,
+            closure: self.environment.clone(),
+        }))
+    }
+
+    /// A new child environment of `parent`, reusing one from an earlier call
+    /// if there is one.
+    fn new_environment(
+        &mut self,
+        parent: &Rc<RefCell<Environment>>,
+    ) -> Result<Rc<RefCell<Environment>>, LoxError> {
+        self.metrics.environments += 1;
+        self.allocate(memory::ENVIRONMENT)?;
+        Ok(match self.spare_environments.pop() {
+            Some(env) => {
+                env.borrow_mut().reuse(parent);
+                env
             }
+            None => Environment::new_child(parent),
+        })
+    }
+
+    /// Keeps `env` for [`Interpreter::new_environment`] to reuse, unless
+    /// something such as a closure still refers to it.
+    fn recycle_environment(&mut self, env: Rc<RefCell<Environment>>) {
+        if Rc::strong_count(&env) == 1
+            && Rc::weak_count(&env) == 0
+            && self.spare_environments.len() < MAX_SPARE_ENVIRONMENTS
+        {
+            // Drop the values now rather than whenever it's reused.
+            env.borrow_mut().reuse(&self.global_env);
+            self.spare_environments.push(env);
         }
+    }
+
+    /// Runs `f` with new locals going into environments rather than the
+    /// frame, e.g. for a call that doesn't run on the frame.
+    pub(crate) fn off_frame<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        let prev = std::mem::replace(&mut self.on_frame, false);
+        let result = f(self);
+        self.on_frame = prev;
+        result
+    }
 
-        self.environment = prev;
+    /// Runs `f` in a new scope, which is a new environment unless the current
+    /// call runs on the frame.
+    fn scoped<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T, LoxError>,
+    ) -> Result<T, LoxError> {
+        if self.on_frame {
+            let len = self.frame.len();
+            let result = f(self);
+            self.frame.truncate(len);
+            result
+        } else {
+            let prev = self.environment.clone();
+            self.environment = Environment::new_child(&prev);
+            self.metrics.environments += 1;
+            if let Err(e) = self.allocate(memory::ENVIRONMENT) {
+                self.environment = prev;
+                return Err(e);
+            }
+            let result = f(self);
+            self.environment = prev;
+            result
+        }
+    }
+
+    /// Defines a local, or a global at the top level.
+    fn define(&mut self, name: &Token, value: Types) {
+        if self.on_frame {
+            self.frame.push(value);
+        } else {
+            self.environment
+                .borrow_mut()
+                .define(name.lexeme.to_string(), value);
+        }
+    }
+
+    /// Writes a line to where `print` statements go, see [`Interpreter::set_output`].
+    pub fn write_line(&mut self, line: &str) -> Result<(), LoxError> {
+        match writeln!(self.output, "{line}") {
+            Ok(()) => Ok(()),
+            Err(e) => LoxError::new_runtime(0, format!("Failed to write output: {e}")),
+        }
+    }
+
+    /// Does what's needed before running any statement: stopping if the script
+    /// was cancelled, counting it and calling the hooks.
+    fn before_statement(&mut self, stmt: &Stmt) -> Result<(), LoxError> {
+        if self.cancel.take() {
+            return Err(LoxError::Interrupted);
+        }
+        self.metrics.statements += 1;
+        if let Some(hooks) = &mut self.hooks {
+            hooks.before_statement(stmt);
+        }
         Ok(())
     }
 
     pub fn execute(&mut self, stmt: &Stmt) -> Result<(), LoxError> {
+        self.before_statement(stmt)?;
         match stmt {
-            Stmt::Expr { expr } => {
+            Stmt::Expr { expr, .. } => {
                 self.evaulate(expr)?;
             }
-            Stmt::Print { expr } => {
+            Stmt::Print { expr, .. } => {
                 let s = self.evaulate(expr)?;
-                println!("{s}");
+                self.write_line(&s.to_string())?;
             }
-            Stmt::Var { name, expr } => {
-                let mut value = Types::Nil;
-                if let Some(expr) = expr {
-                    value = self.evaulate(expr)?;
-                }
+            Stmt::Var { name, expr, .. } => {
+                let value = match expr {
+                    Some(expr) => self.evaulate(expr)?,
+                    None if self.options.errors_on_uninitialized() => Types::Uninitialized,
+                    None => Types::Nil,
+                };
 
-                if let TokenType::Identifier(name) = &name.tok_typ {
-                    self.environment.borrow_mut().define(name.clone(), value);
-                } else {
-                    unreachable!()
-                }
+                self.define(name, value);
             }
-            Stmt::Block { stmts } => {
-                let prev = self.environment.clone();
-                self.environment = Environment::new_child(&prev);
-                for stmt in stmts {
-                    if let Err(e) = self.execute(stmt) {
-                        self.environment = prev;
-                        return Err(e);
-                    }
-                }
-
-                self.environment = prev;
+            Stmt::Block { stmts, .. } => {
+                self.scoped(|interpreter| {
+                    stmts.iter().try_for_each(|stmt| interpreter.execute(stmt))
+                })?;
             }
             Stmt::If {
                 condition,
                 then_branch,
                 else_branch,
+                ..
             } => {
                 if self.evaulate(condition)?.is_truty() {
                     self.execute(then_branch)?;
-                } else if else_branch.is_some() {
-                    let branch = else_branch.as_ref().unwrap();
-                    self.execute(&**branch)?;
+                } else if let Some(branch) = else_branch {
+                    self.execute(branch)?;
                 }
             }
-            Stmt::While { condition, body } => {
+            Stmt::While {
+                condition, body, ..
+            } => {
                 while self.evaulate(condition)?.is_truty() {
                     self.execute(body)?;
                 }
             }
-            Stmt::Function { name, params, body } => {
-                let func = LoxFunction::new(
-                    name.clone(),
-                    params.clone(),
-                    body.clone(),
-                    self.environment.clone(),
-                    false,
-                );
+            Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+                ..
+            } => {
+                self.scoped(|interpreter| {
+                    interpreter.execute_for(initializer, condition, increment, body)
+                })?;
+            }
+            Stmt::Function { name, .. } => {
+                let func = self.function(stmt, false);
+                self.metrics.allocations += 1;
+                self.allocate(memory::FUNCTION)?;
                 self.environment
                     .borrow_mut()
-                    .define(name.lexeme.clone(), func);
+                    .define(name.lexeme.to_string(), func);
             }
+            // Tests only run through `Lox::run_tests`.
+            Stmt::Test { .. } => (),
             Stmt::Return { value, .. } => {
                 if let Some(value) = value {
                     let value = self.evaulate(value)?;
@@ -495,14 +2100,34 @@ impl Interpreter {
                     return LoxError::new_return(Types::Nil);
                 }
             }
+            // Generator bodies run their `yield`s in `Generator::resume`, and
+            // the resolver rejects them everywhere else.
+            Stmt::Yield { keyword, .. } => {
+                return LoxError::new_runtime(
+                    keyword.line,
+                    String::from("Can't yield outside a generator."),
+                );
+            }
             Stmt::Class {
                 name,
                 methods,
+                statics,
                 superclass,
+                doc,
+                ..
             } => {
-                self.environment
-                    .borrow_mut()
-                    .define(name.lexeme.clone(), Types::Nil);
+                let mut static_values = vec![];
+                for stmt in statics {
+                    if let Stmt::Var { name, expr, .. } = &**stmt {
+                        let value = match expr {
+                            Some(expr) => self.evaulate(expr)?,
+                            None => Types::Nil,
+                        };
+                        static_values.push((name.lexeme.to_string(), value));
+                    }
+                }
+
+                let enclosing = self.environment.clone();
                 let superclass = match superclass {
                     None => None,
                     Some(superclass) => {
@@ -510,9 +2135,10 @@ impl Interpreter {
                         match &sc {
                             Types::Class(c) => {
                                 let env = Environment::new_child(&self.environment);
+                                self.metrics.environments += 1;
                                 env.borrow_mut().define(String::from("super"), sc.clone());
                                 self.environment = env;
-                                Some(Box::new(c.clone()))
+                                Some(c.clone())
                             }
                             _ => {
                                 return LoxError::new_runtime(
@@ -527,125 +2153,318 @@ impl Interpreter {
                 let mut mtds: HashMap<String, Types> = HashMap::new();
                 for method in methods {
                     match &**method {
-                        Stmt::Function { name, params, body } => {
-                            mtds.insert(
-                                name.lexeme.clone(),
-                                LoxFunction::new(
-                                    name.clone(),
-                                    params.clone(),
-                                    body.clone(),
-                                    self.environment.clone(),
-                                    if name.lexeme == "init" { true } else { false },
-                                ),
+                        Stmt::Function { name, .. } => {
+                            let method = self.function(method, name.lexeme == "init");
+                            mtds.insert(name.lexeme.to_string(), method);
+                        }
+                        _ => {
+                            self.environment = enclosing;
+                            return LoxError::new_runtime(
+                                name.line,
+                                format!("Class `{}` contains a non-method member.", name.lexeme),
                             );
                         }
-                        _ => unreachable!(),
                     }
                 }
 
-                let class =
-                    Types::Class(LoxClass::new(name.lexeme.clone(), mtds, superclass.clone()));
-                if superclass.is_some() {
-                    let prev = self.environment.borrow().parent.as_ref().unwrap().clone();
-                    self.environment = prev;
+                let class = LoxClass::new(name.lexeme.to_string(), mtds, superclass, doc.clone());
+                for (name, value) in static_values {
+                    class.define_static(name, value);
                 }
+                let class = Types::Class(Rc::new(class));
+                self.metrics.allocations += 1;
+                self.environment = enclosing;
+                self.allocate(memory::CLASS)?;
 
-                self.environment.borrow_mut().set(name, class)?;
+                self.environment
+                    .borrow_mut()
+                    .define(name.lexeme.to_string(), class);
             }
         };
 
         Ok(())
     }
 
-    pub fn evaulate(&mut self, expression: &Box<Expr>) -> Result<Types, LoxError> {
-        match **expression {
-            Expr::Binary {
-                ref left,
-                ref operator,
-                ref right,
-            } => {
-                let left = self.evaulate(left)?;
-                let right = self.evaulate(right)?;
+    /// Runs a `for` loop inside the loop's own scope. Each iteration gets a fresh
+    /// copy of the loop variables, made before the increment runs, so closures
+    /// created in the body capture that iteration's values. On the frame nothing
+    /// can capture them, so no copies are needed.
+    fn execute_for(
+        &mut self,
+        initializer: &Option<Box<Stmt>>,
+        condition: &Option<Box<Expr>>,
+        increment: &Option<Box<Expr>>,
+        body: &Stmt,
+    ) -> Result<(), LoxError> {
+        if let Some(initializer) = initializer {
+            self.execute(initializer)?;
+        }
+        let fork = !self.on_frame;
+        if fork {
+            self.environment = Environment::fork(&self.environment);
+            self.metrics.environments += 1;
+            self.allocate(memory::ENVIRONMENT)?;
+        }
 
-                match operator.tok_typ {
-                    TokenType::Minus => Ok(Types::Number(
-                        left.number(&operator)? - right.number(&operator)?,
-                    )),
-                    TokenType::Plus => match (&left, &right) {
-                        (Types::Number(left), Types::Number(right)) => {
-                            Ok(Types::Number(left + right))
-                        }
-                        (Types::String(left), Types::String(right)) => {
-                            Ok(Types::String(format!("{left}{right}")))
-                        }
-                        _ => LoxError::new_runtime(
-                            operator.line,
-                            format!("Invalid operands for operator `+`.\n\tCannot add `{left}` with `{right}`"),
+        loop {
+            if let Some(condition) = condition {
+                if !self.evaulate(condition)?.is_truty() {
+                    return Ok(());
+                }
+            }
+            self.execute(body)?;
+
+            if fork {
+                self.environment = Environment::fork(&self.environment);
+                self.metrics.environments += 1;
+                self.allocate(memory::ENVIRONMENT)?;
+            }
+            if let Some(increment) = increment {
+                self.evaulate(increment)?;
+            }
+        }
+    }
+
+    /// Evaluates the body of the first arm whose pattern matches `subject`,
+    /// in a scope holding the pattern's bindings.
+    fn evaluate_match(
+        &mut self,
+        keyword: &Token,
+        subject: &Expr,
+        arms: &[MatchArm],
+    ) -> Result<Types, LoxError> {
+        let value = self.evaulate(subject)?;
+        for arm in arms {
+            let result = self.scoped(|interpreter| {
+                if interpreter.match_pattern(&arm.pattern, &value)? {
+                    interpreter.evaulate(&arm.body).map(Some)
+                } else {
+                    Ok(None)
+                }
+            })?;
+            if let Some(result) = result {
+                return Ok(result);
+            }
+        }
+        LoxError::new_runtime(keyword.line, format!("No pattern matched {value}."))
+    }
+
+    /// Checks `value` against `pattern`, binding names in the current scope.
+    fn match_pattern(&mut self, pattern: &Pattern, value: &Types) -> Result<bool, LoxError> {
+        match pattern {
+            Pattern::Wildcard => Ok(true),
+            Pattern::Literal { value: literal } => Ok(self.evaulate(&Expr::Literal {
+                id: next_node_id(),
+                value: literal.clone(),
+            })? == *value),
+            Pattern::Binding { name } => {
+                self.define(name, value.clone());
+                Ok(true)
+            }
+            Pattern::Class {
+                class,
+                paren,
+                fields,
+            } => {
+                let class = match self.evaulate(class)? {
+                    Types::Class(class) => class,
+                    other => {
+                        return LoxError::new_runtime(
+                            paren.line,
+                            format!("Can only destructure classes but found {other}."),
+                        )
+                    }
+                };
+                let params = class.init_params();
+                if params.len() != fields.len() {
+                    return LoxError::new_runtime(
+                        paren.line,
+                        format!(
+                            "Pattern for {} has {} fields but its initializer takes {}.",
+                            class.name(),
+                            fields.len(),
+                            params.len()
                         ),
-                    },
-                    TokenType::Slash => Ok(Types::Number(
-                        left.number(&operator)? / right.number(&operator)?,
-                    )),
-                    TokenType::Star => Ok(Types::Number(
-                        left.number(&operator)? * right.number(&operator)?,
-                    )),
-                    TokenType::Greater => Ok(Types::Bool(
-                        left.number(&operator)? > right.number(&operator)?
-                    )),
-                    TokenType::GreaterEqual => Ok(Types::Bool(
-                        left.number(&operator)? >= right.number(&operator)?
-                    )),
-                    TokenType::Less => Ok(Types::Bool(
-                        left.number(&operator)? < right.number(&operator)?
-                    )),
-                    TokenType::LessEqual => Ok(Types::Bool(
-                        left.number(&operator)? <= right.number(&operator)?
-                    )),
-                    TokenType::EqualEqual => Ok(Types::Bool(right == left)),
-                    TokenType::BangEqual => Ok(Types::Bool(right != left)),
-                    _ => LoxError::new_runtime(operator.line, format!("Bad binary operator: {}", operator)),
+                    );
                 }
+                let instance = match value {
+                    Types::ClassInstance(instance)
+                        if instance.borrow().class().is_subclass_of(&class) =>
+                    {
+                        instance.clone()
+                    }
+                    _ => return Ok(false),
+                };
+                for (param, field) in params.iter().zip(fields) {
+                    let field_value = instance.borrow().get_field(&param.lexeme);
+                    match field_value {
+                        Some(field_value) if self.match_pattern(field, &field_value)? => (),
+                        _ => return Ok(false),
+                    }
+                }
+                Ok(true)
             }
+        }
+    }
+
+    /// Evaluates a chain of binary and logical operators such as `a + b + c`,
+    /// which the parser nests to the left, walking down the chain in a loop
+    /// rather than recursing, so long generated chains can't overflow the stack.
+    fn operator_chain(&mut self, expression: &Expr) -> Result<Types, LoxError> {
+        let mut operations = vec![];
+        let mut leftmost = expression;
+        while let Expr::Binary {
+            left,
+            operator,
+            right,
+            ..
+        }
+        | Expr::Logical {
+            left,
+            operator,
+            right,
+            ..
+        } = leftmost
+        {
+            operations.push((operator, right));
+            leftmost = left;
+        }
+        // Count the operators below the first as though each was evaluated on its own.
+        self.metrics.expressions += operations.len() as u64 - 1;
+
+        let mut value = self.evaulate(leftmost)?;
+        for (operator, right) in operations.into_iter().rev() {
+            value = match operator.tok_typ {
+                TokenType::Or if value.is_truty() => value,
+                TokenType::And if !value.is_truty() => value,
+                TokenType::Or | TokenType::And => self.evaulate(right)?,
+                _ => {
+                    let right = self.evaulate(right)?;
+                    self.binary(value, operator, right)?
+                }
+            };
+        }
+        Ok(value)
+    }
+
+    fn binary(&mut self, left: Types, operator: &Token, right: Types) -> Result<Types, LoxError> {
+        match operator.tok_typ {
+            TokenType::Minus => Ok(Types::Number(
+                left.number(operator)? - right.number(operator)?,
+            )),
+            TokenType::Plus => match (&left, &right) {
+                (Types::Number(left), Types::Number(right)) => Ok(Types::Number(left + right)),
+                (Types::String(left), Types::String(right)) => {
+                    let mut concatenated = String::with_capacity(left.len() + right.len());
+                    concatenated.push_str(left);
+                    concatenated.push_str(right);
+                    self.metrics.allocations += 1;
+                    self.allocate(memory::string_size(concatenated.len()))?;
+                    Ok(Types::String(Rc::from(concatenated)))
+                }
+                (Types::String(_), _) | (_, Types::String(_))
+                    if self.options.stringifies_operands() =>
+                {
+                    let concatenated = format!("{left}{right}");
+                    self.metrics.allocations += 1;
+                    self.allocate(memory::string_size(concatenated.len()))?;
+                    Ok(Types::String(Rc::from(concatenated)))
+                }
+                _ => LoxError::new_runtime(
+                    operator.line,
+                    format!(
+                        "Invalid operands for operator `+`.\n\tCannot add `{left}` with `{right}`"
+                    ),
+                ),
+            },
+            TokenType::Slash => {
+                let (left, right) = (left.number(operator)?, right.number(operator)?);
+                if right == 0.0 && !self.options.uses_ieee_division() {
+                    return LoxError::new_runtime(operator.line, String::from("Division by zero."));
+                }
+                Ok(Types::Number(left / right))
+            }
+            TokenType::Star => Ok(Types::Number(
+                left.number(operator)? * right.number(operator)?,
+            )),
+            TokenType::Greater
+            | TokenType::GreaterEqual
+            | TokenType::Less
+            | TokenType::LessEqual => compare(&left, &right, operator),
+            TokenType::EqualEqual => Ok(Types::Bool(right == left)),
+            TokenType::BangEqual => Ok(Types::Bool(right != left)),
+            _ => LoxError::new_runtime(operator.line, format!("Bad binary operator: {}", operator)),
+        }
+    }
+
+    pub fn evaulate(&mut self, expression: &Expr) -> Result<Types, LoxError> {
+        self.metrics.expressions += 1;
+        match *expression {
+            Expr::Binary { .. } | Expr::Logical { .. } => self.operator_chain(expression),
             Expr::Unary {
                 ref operator,
                 ref right,
+                ..
             } => {
                 let right = self.evaulate(right)?;
                 match operator.tok_typ {
                     TokenType::Minus => match right {
-                        Types::Number(n) => return Ok(Types::Number(-n)),
+                        Types::Number(n) => Ok(Types::Number(-n)),
                         _ => LoxError::new_runtime(
                             operator.line,
                             format!("Cannot perform Unary operator `-` on {right}"),
                         ),
                     },
-                    TokenType::Bang => return Ok(Types::Bool(!right.is_truty())),
+                    TokenType::Bang => Ok(Types::Bool(!right.is_truty())),
                     _ => LoxError::new_runtime(
                         operator.line,
                         format!("Bad Unary operator {:?}", operator.tok_typ),
                     ),
                 }
             }
-            Expr::Grouping { ref expr } => self.evaulate(expr),
-            Expr::Literal { ref value } => match &value.tok_typ {
-                TokenType::Str(s) => Ok(Types::String(s.clone())),
+            Expr::Grouping { ref expr, .. } => self.evaulate(expr),
+            Expr::Literal { ref value, .. } => match &value.tok_typ {
+                TokenType::Str(s) => {
+                    self.metrics.allocations += 1;
+                    self.allocate(memory::string_size(s.len()))?;
+                    Ok(Types::String(Rc::new(s.clone())))
+                }
                 TokenType::Number(n) => Ok(Types::Number(*n)),
                 TokenType::False => Ok(Types::Bool(false)),
                 TokenType::True => Ok(Types::Bool(true)),
                 TokenType::Nil => Ok(Types::Nil),
                 _ => LoxError::new_runtime(value.line, format!("Bad Token Literal: {value}")),
             },
-            Expr::Variable { ref name } => Ok(self.lookup_variable(name, &*expression)?),
+            Expr::Variable { id, ref name } => Ok(self.lookup_variable(name, id)?),
             Expr::Assignment {
+                id,
                 name: ref name_tok,
                 ref value,
             } => {
-                let result_val = self.evaulate(&value)?;
-                match self.locals.get(&value.to_string()) {
-                    Some(dist) => {
-                        self.environment
-                            .borrow_mut()
-                            .set_at(name_tok, result_val.clone(), *dist)?
+                let result_val = self.evaulate(value)?;
+                match self.locals.get(&id) {
+                    Some(&Local::Env { depth, slot }) => self.environment.borrow_mut().set_at(
+                        name_tok,
+                        result_val.clone(),
+                        depth,
+                        slot,
+                    )?,
+                    Some(&Local::Frame(index)) => match self.frame.get_mut(self.frame_base + index)
+                    {
+                        Some(local) => *local = result_val.clone(),
+                        None => {
+                            return LoxError::new_runtime(
+                                name_tok.line,
+                                format!("Failed to set undefined variable: `{}`.", name_tok.lexeme),
+                            )
+                        }
+                    },
+                    None if !self.global_env.borrow().contains(name_tok.lexeme.as_str()) => {
+                        return self.undefined_global(
+                            name_tok,
+                            id,
+                            format!("Failed to set undefined variable: `{}`.", name_tok.lexeme),
+                        )
                     }
                     None => self
                         .global_env
@@ -654,65 +2473,39 @@ impl Interpreter {
                 }
                 Ok(result_val)
             }
-            Expr::Logical {
-                ref left,
-                ref operator,
-                ref right,
-            } => {
-                let left = self.evaulate(left)?;
-                match operator.tok_typ {
-                    TokenType::Or => {
-                        if left.is_truty() {
-                            Ok(left)
-                        } else {
-                            Ok(self.evaulate(right)?)
-                        }
-                    }
-                    TokenType::And => {
-                        if !left.is_truty() {
-                            Ok(left)
-                        } else {
-                            Ok(self.evaulate(right)?)
-                        }
-                    }
-                    _ => LoxError::new_runtime(operator.line, format!("Bad operator: {operator}")),
-                }
-            }
             Expr::Call {
                 ref callee,
                 ref arguments,
                 ref paren,
+                ..
             } => {
                 let callee = self.evaulate(callee)?;
-                let mut args = vec![];
+                let mut args = Arguments::new();
                 for arg in arguments {
                     args.push(self.evaulate(arg)?);
                 }
 
-                let function = callee.callable(paren)?;
-
-                if function.airity() != args.len() {
-                    return LoxError::new_runtime(
-                        paren.line,
-                        format!(
-                            "Expected {} arguments, but got {}",
-                            function.airity(),
-                            args.len()
-                        ),
-                    );
-                }
-                Ok(function.call(self, args)?)
+                self.line = paren.line;
+                self.call_expression(&callee, args)
             }
             Expr::Get {
+                id,
                 ref object,
                 ref name,
             } => {
                 let obj = self.evaulate(object)?;
                 match obj {
-                    Types::ClassInstance(instance) => Ok(LoxClassInstance::get(&instance, name)?),
-                    _ => LoxError::new_runtime(
+                    Types::ClassInstance(instance) => self.get_property(id, &instance, name),
+                    Types::Class(class) => class.get_static(name),
+                    Types::Host(object) => host::get(&object, name),
+                    value @ (Types::String(_) | Types::Number(_)) => primitives::get(&value, name),
+                    Types::Nil if self.options.reads_nil_properties() => Ok(Types::Nil),
+                    other => LoxError::new_runtime(
                         name.line,
-                        String::from("Only instance have properties."),
+                        format!(
+                            "Only instances have properties, but `{}` is {other}.",
+                            object.source()
+                        ),
                     ),
                 }
             }
@@ -720,42 +2513,122 @@ impl Interpreter {
                 ref object,
                 ref value,
                 ref name,
+                ..
             } => match self.evaulate(object)? {
                 Types::ClassInstance(instance) => {
-                    instance
-                        .borrow_mut()
-                        .set_property(name, self.evaulate(value)?);
-                    Ok(Types::Nil)
+                    let value = self.evaulate(value)?;
+                    if !instance.borrow().has_field(&name.lexeme) {
+                        self.allocate(memory::FIELD + name.lexeme.len())?;
+                    }
+                    instance.borrow_mut().set_property(name, value.clone())?;
+                    Ok(value)
+                }
+                Types::Class(class) => {
+                    let value = self.evaulate(value)?;
+                    class.set_static(name, value.clone())?;
+                    Ok(value)
+                }
+                Types::Host(object) => {
+                    let value = self.evaulate(value)?;
+                    host::set(&object, name, value.clone())?;
+                    Ok(value)
                 }
-                _ => todo!(),
+                other => LoxError::new_runtime(
+                    name.line,
+                    format!(
+                        "Only instances have fields, but `{}` is {other}.",
+                        object.source()
+                    ),
+                ),
             },
-            Expr::This { ref keyword } => self.lookup_variable(&keyword, &expression),
-            Expr::Super { ref method, .. } => {
-                let dist = self.locals.get(&expression.to_string()).unwrap();
-                let superclass = if let Types::Class(sc) = self.environment.borrow().get_at(
+            Expr::This { id, ref keyword } => self.lookup_variable(keyword, id),
+            Expr::List { ref elements, .. } => {
+                let mut values = vec![];
+                for element in elements {
+                    values.push(self.evaulate(element)?);
+                }
+                self.metrics.allocations += 1;
+                self.allocate(memory::list_size(values.len()))?;
+                Ok(Types::List(Rc::new(RefCell::new(values))))
+            }
+            Expr::Index {
+                ref object,
+                ref bracket,
+                ref index,
+                ..
+            } => {
+                let list = self.evaulate(object)?.list(bracket)?;
+                let index = self.evaulate(index)?;
+                let i = list_index(&list.borrow(), &index, bracket)?;
+                let value = list.borrow()[i].clone();
+                Ok(value)
+            }
+            Expr::IndexSet {
+                ref object,
+                ref bracket,
+                ref index,
+                ref value,
+                ..
+            } => {
+                let list = self.evaulate(object)?.list(bracket)?;
+                let index = self.evaulate(index)?;
+                let value = self.evaulate(value)?;
+                let i = list_index(&list.borrow(), &index, bracket)?;
+                list.borrow_mut()[i] = value.clone();
+                Ok(value)
+            }
+            Expr::Match {
+                ref keyword,
+                ref subject,
+                ref arms,
+                ..
+            } => self.evaluate_match(keyword, subject, arms),
+            Expr::Super {
+                id,
+                ref keyword,
+                ref method,
+            } => {
+                let dist = match self.locals.get(&id) {
+                    Some(&Local::Env { depth, .. }) if depth > 0 => depth,
+                    _ => {
+                        return LoxError::new_runtime(
+                            keyword.line,
+                            String::from("Can't use `super` outside of a subclass method."),
+                        )
+                    }
+                };
+                let superclass = match self.environment.borrow().get_at(
                     &Token {
-                        lexeme: String::from("super"),
-                        line: 0,
-                        tok_typ: TokenType::Identifier(String::from("super")),
+                        lexeme: Lexeme::from("super"),
+                        line: keyword.line,
+                        tok_typ: TokenType::Identifier,
+                        column: 0,
                     },
-                    *dist,
+                    dist,
+                    0,
                 )? {
-                    sc
-                } else {
-                    unreachable!()
+                    Types::Class(sc) => sc,
+                    other => {
+                        return LoxError::new_runtime(
+                            keyword.line,
+                            format!("Expected superclass but found {other}"),
+                        )
+                    }
                 };
 
                 let this = self.environment.borrow().get_at(
                     &Token {
-                        lexeme: String::from("this"),
-                        line: 0,
-                        tok_typ: TokenType::Identifier(String::from("this")),
+                        lexeme: Lexeme::from("this"),
+                        line: keyword.line,
+                        tok_typ: TokenType::Identifier,
+                        column: 0,
                     },
-                    *dist - 1,
+                    dist - 1,
+                    0,
                 )?;
 
                 if let Some(Types::Callable(method)) = superclass.find_method(&method.lexeme) {
-                    Ok(method.bind(this))
+                    Ok(Types::Callable(method.bind(this)))
                 } else {
                     LoxError::new_runtime(
                         method.line,
@@ -766,14 +2639,110 @@ impl Interpreter {
         }
     }
 
-    pub fn resolve(&mut self, expr: &Expr, depth: usize) {
-        self.locals.insert(expr.to_string(), depth);
+    /// Records where the local variable `id` refers to is kept.
+    pub fn resolve(&mut self, id: NodeId, local: Local) {
+        self.locals.insert(id, local);
+    }
+
+    pub fn note_similar_local(&mut self, id: NodeId, name: String) {
+        self.similar_locals.insert(id, name);
+    }
+
+    /// Reports that the global `token` refers to doesn't exist, suggesting a
+    /// similarly named variable if there is one.
+    fn undefined_global<T>(
+        &self,
+        token: &Token,
+        id: NodeId,
+        message: String,
+    ) -> Result<T, LoxError> {
+        let globals = self.global_env.borrow().names();
+        let candidates = globals
+            .iter()
+            .chain(self.similar_locals.get(&id))
+            .map(String::as_str);
+        let message = match diagnostics::closest_name(&token.lexeme, candidates) {
+            Some(name) => format!("{message} Did you mean `{name}`?"),
+            None => message,
+        };
+        LoxError::new_runtime(token.line, message)
+    }
+
+    /// Reads the property `name` of `instance` for the `Expr::Get` with `id`.
+    /// The field's slot or the method it finds is cached for the next instance
+    /// with the same shape read from there, as instances made by the same
+    /// initializer are in a loop, so that only needs to compare the shapes.
+    fn get_property(
+        &mut self,
+        id: NodeId,
+        instance: &Rc<RefCell<LoxClassInstance>>,
+        name: &Token,
+    ) -> Result<Types, LoxError> {
+        let borrowed = instance.borrow();
+        let cached = self
+            .property_caches
+            .get(&id)
+            .filter(|cache| Rc::ptr_eq(&cache.shape, borrowed.shape()));
+        let property = match cached {
+            Some(cache) => &cache.property,
+            None => {
+                let property = match borrowed.shape().slot(&name.lexeme) {
+                    Some(slot) => Property::Field(slot),
+                    None => match borrowed.class().find_method(&name.lexeme) {
+                        Some(Types::Callable(method)) => Property::Method(method),
+                        // Reports the missing field.
+                        _ => {
+                            drop(borrowed);
+                            return LoxClassInstance::get(instance, name);
+                        }
+                    },
+                };
+                let cache = PropertyCache {
+                    shape: borrowed.shape().clone(),
+                    property,
+                };
+                &self
+                    .property_caches
+                    .entry(id)
+                    .insert_entry(cache)
+                    .into_mut()
+                    .property
+            }
+        };
+        match property {
+            Property::Field(slot) => Ok(borrowed.slot(*slot).clone()),
+            Property::Method(method) => Ok(Types::Callable(
+                method.bind(Types::ClassInstance(instance.clone())),
+            )),
+        }
     }
 
-    fn lookup_variable(&self, token: &Token, expr: &Expr) -> Result<Types, LoxError> {
-        match self.locals.get(&expr.to_string()) {
-            Some(dist) => self.environment.borrow().get_at(token, *dist),
+    fn lookup_variable(&self, token: &Token, id: NodeId) -> Result<Types, LoxError> {
+        let value = match self.locals.get(&id) {
+            Some(&Local::Env { depth, slot }) => {
+                self.environment.borrow().get_at(token, depth, slot)
+            }
+            Some(&Local::Frame(index)) => match self.frame.get(self.frame_base + index) {
+                Some(value) => Ok(value.clone()),
+                None => LoxError::new_runtime(
+                    token.line,
+                    format!("Failed to get undefined variable `{}`.", token.lexeme),
+                ),
+            },
+            None if !self.global_env.borrow().contains(token.lexeme.as_str()) => self
+                .undefined_global(
+                    token,
+                    id,
+                    format!("Failed to get undefined variable `{}`.", token.lexeme),
+                ),
             None => self.global_env.borrow().get(token),
+        }?;
+        match value {
+            Types::Uninitialized => LoxError::new_runtime(
+                token.line,
+                format!("Variable `{}` used before assignment.", token.lexeme),
+            ),
+            value => Ok(value),
         }
     }
 }
@@ -0,0 +1,650 @@
+use crate::ast::{Expr, Pattern, Stmt};
+use crate::generator;
+use crate::tokens::{Token, TokenType};
+
+/// Helpers the emitted code calls for the parts of Lox that JavaScript does
+/// differently, e.g. truthiness, `+` and calling classes without `new`.
+const PRELUDE: &str = r#"const $log = console.log;
+class $Object {}
+const $isClass = (v) => typeof v === "function" && v.prototype instanceof $Object;
+const $error = (message) => new Error(message);
+function $str(v) {
+  if (v === null || v === undefined) return "Nil";
+  if (typeof v === "number") {
+    if (v === Infinity) return "inf";
+    if (v === -Infinity) return "-inf";
+    return Object.is(v, -0) ? "-0" : String(v);
+  }
+  if (Array.isArray(v)) return "[" + v.map($str).join(", ") + "]";
+  if ($isClass(v)) return `<class ${v.name}>`;
+  if (typeof v === "function") {
+    return v.$native ? `<native fn ${v.name}>` : `<fn ${v.name.replace(/^(bound )+/, "")}>`;
+  }
+  if (v instanceof $Object) return `instance of <class ${Object.getPrototypeOf(v).constructor.name}>`;
+  if (typeof v === "object") return `<generator ${v.$name}>`;
+  return String(v);
+}
+const $print = (v) => $log($str(v));
+const $truthy = (v) => v !== false && v !== null && v !== undefined;
+const $and = (l, r) => ($truthy(l) ? r() : l);
+const $or = (l, r) => ($truthy(l) ? l : r());
+const $eq = (a, b) => !Array.isArray(a) && (a ?? null) === (b ?? null);
+function $num(v) {
+  if (typeof v !== "number") throw $error(`Expected Number but found ${$str(v)}`);
+  return v;
+}
+function $neg(v) {
+  if (typeof v !== "number") throw $error(`Cannot perform Unary operator \`-\` on ${$str(v)}`);
+  return -v;
+}
+function $add(a, b) {
+  if ((typeof a === "number" && typeof b === "number") || (typeof a === "string" && typeof b === "string")) return a + b;
+  throw $error(`Invalid operands for operator \`+\`.\n\tCannot add \`${$str(a)}\` with \`${$str(b)}\``);
+}
+function $arith(op, a, b) {
+  a = $num(a);
+  b = $num(b);
+  if (op === "-") return a - b;
+  if (op === "*") return a * b;
+  if (b === 0) throw $error("Division by zero.");
+  return a / b;
+}
+function $compare(op, a, b) {
+  if (typeof a !== "string" || typeof b !== "string") {
+    a = $num(a);
+    b = $num(b);
+  }
+  if (op === "<") return a < b;
+  if (op === "<=") return a <= b;
+  if (op === ">") return a > b;
+  return a >= b;
+}
+function $call(f, ...args) {
+  if (typeof f !== "function") throw $error(`Expected Callable but found ${$str(f)}`);
+  const init = $isClass(f) ? f.prototype.init : undefined;
+  const arity = $isClass(f) ? (init ? init.length : 0) : f.length;
+  if (arity !== args.length) throw $error(`Expected ${arity} arguments, but got ${args.length}`);
+  if (!$isClass(f)) return f(...args);
+  const instance = new f();
+  if (init) init.apply(instance, args);
+  return instance;
+}
+function $superclass(c) {
+  if (!$isClass(c)) throw $error("Superclass must be a class");
+  return c;
+}
+function $static(c, name) {
+  for (let owner = c; owner !== $Object; owner = Object.getPrototypeOf(owner)) {
+    if (Object.hasOwn(owner, name)) return owner;
+  }
+  throw $error(`Class ${c.name} doesn't have a static field \`${name}\`.`);
+}
+function $get(o, name) {
+  if ($isClass(o)) return $static(o, name)[name];
+  if (!(o instanceof $Object)) throw $error("Only instances have properties.");
+  if (Object.hasOwn(o, name)) return o[name];
+  if (typeof o[name] === "function") return o[name].bind(o);
+  throw $error(`Instance of <class ${Object.getPrototypeOf(o).constructor.name}> doesn't have a field \`${name}\``);
+}
+function $set(o, name, value) {
+  if ($isClass(o)) return ($static(o, name)[name] = value);
+  if (!(o instanceof $Object)) throw $error("Only instances have fields.");
+  return (o[name] = value);
+}
+function $super(method, self, name) {
+  if (typeof method !== "function") throw $error(`Undefined property \`${name}\`.`);
+  return method.bind(self);
+}
+function $list(v) {
+  if (!Array.isArray(v)) throw $error(`Expected List but found ${$str(v)}`);
+  return v;
+}
+function $at(list, i) {
+  $num(i);
+  if (!Number.isInteger(i) || i < 0 || i >= list.length) {
+    throw $error(`Index ${$str(i)} is out of bounds for list of length ${list.length}.`);
+  }
+  return i;
+}
+const $index = (l, i) => $list(l)[$at(l, i)];
+const $setIndex = (l, i, v) => ($list(l)[$at(l, i)] = v);
+function $fields(v, c, n) {
+  if (!$isClass(c)) throw $error(`Can only destructure classes but found ${$str(c)}.`);
+  const params = c.$params ?? [];
+  if (params.length !== n) {
+    throw $error(`Pattern for ${c.name} has ${n} fields but its initializer takes ${params.length}.`);
+  }
+  if (!(v instanceof c) || !params.every((p) => Object.hasOwn(v, p))) return null;
+  return params.map((p) => v[p]);
+}
+// Names the generators `f` makes, for printing them.
+function $generator(f) {
+  f.prototype.$name = f.name;
+  return f;
+}
+function $native(f) {
+  f.$native = true;
+  return f;
+}
+const clock = $native(function clock() {
+  return Date.now() / 1000;
+});
+const len = $native(function len(v) {
+  if (typeof v === "string") return [...v].length;
+  if (Array.isArray(v)) return v.length;
+  throw $error(`Can't take the length of ${$str(v)}.`);
+});
+const assert = $native(function assert(condition, message) {
+  if (!$truthy(condition)) throw $error(`Assertion failed: ${$str(message)}`);
+  return null;
+});
+const next = $native(function next(g) {
+  const result = g.next();
+  if (result.done) g.$done = true;
+  return result.done ? null : result.value;
+});
+const done = $native(function done(g) {
+  return g.$done === true;
+});
+"#;
+
+/// Words Lox allows as names that JavaScript doesn't, or that would shadow
+/// something the emitted code relies on.
+const RESERVED: &[&str] = &[
+    "arguments",
+    "await",
+    "break",
+    "case",
+    "catch",
+    "const",
+    "constructor",
+    "continue",
+    "debugger",
+    "default",
+    "delete",
+    "do",
+    "enum",
+    "eval",
+    "export",
+    "extends",
+    "finally",
+    "function",
+    "implements",
+    "import",
+    "in",
+    "instanceof",
+    "interface",
+    "let",
+    "new",
+    "package",
+    "private",
+    "protected",
+    "public",
+    "static",
+    "switch",
+    "throw",
+    "try",
+    "typeof",
+    "undefined",
+    "void",
+    "with",
+    "yield",
+];
+
+/// Translates a resolved program to JavaScript that behaves the same way when
+/// run by Node or a browser, printing with `console.log`.
+///
+/// Only the natives `clock`, `len`, `assert`, `next` and `done` are available,
+/// and `test` blocks are left out since only `loxrt test` runs them.
+pub fn emit(statements: &[Box<Stmt>]) -> String {
+    let mut emitter = Emitter {
+        out: String::from(PRELUDE),
+        indent: 1,
+        depth: 0,
+        in_class: false,
+        temps: 0,
+    };
+    emitter.out.push_str("(() => {\n");
+    for stmt in statements {
+        emitter.stmt(stmt);
+    }
+    emitter.out.push_str("})();\n");
+    emitter.out
+}
+
+struct Emitter {
+    out: String,
+    indent: usize,
+    /// How many scopes deep the current statement is, 0 for globals.
+    depth: usize,
+    /// Set inside methods, where nested functions have to keep `this`.
+    in_class: bool,
+    /// The number of temporaries used so far, to name the next one.
+    temps: usize,
+}
+
+/// The JavaScript name for a Lox variable.
+fn name(token: &Token) -> String {
+    if RESERVED.contains(&token.lexeme.as_str()) {
+        format!("{}$", token.lexeme)
+    } else {
+        token.lexeme.to_string()
+    }
+}
+
+fn literal(token: &Token) -> String {
+    match &token.tok_typ {
+        TokenType::Str(s) => format!("{s:?}"),
+        TokenType::Number(n) => format!("{n}"),
+        TokenType::True => String::from("true"),
+        TokenType::False => String::from("false"),
+        _ => String::from("null"),
+    }
+}
+
+impl Emitter {
+    fn line(&mut self, line: &str) {
+        for _ in 0..self.indent {
+            self.out.push_str("  ");
+        }
+        self.out.push_str(line);
+        self.out.push('\n');
+    }
+
+    fn temp(&mut self) -> String {
+        self.temps += 1;
+        format!("$t{}", self.temps)
+    }
+
+    fn declaration(&self) -> &'static str {
+        if self.depth == 0 {
+            "var"
+        } else {
+            "let"
+        }
+    }
+
+    /// Emits `stmts` one level deeper, in a scope of their own.
+    fn block(&mut self, stmts: &[Box<Stmt>]) {
+        self.indent += 1;
+        self.depth += 1;
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+        self.depth -= 1;
+        self.indent -= 1;
+    }
+
+    /// Emits the body of an `if` or a loop, which may or may not be a block.
+    fn body(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Block { stmts, .. } => self.block(stmts),
+            stmt => self.block(std::slice::from_ref(&Box::new(stmt.clone()))),
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expr { expr, .. } => {
+                let expr = self.expr(expr);
+                self.line(&format!("{expr};"));
+            }
+            Stmt::Print { expr, .. } => {
+                let expr = self.expr(expr);
+                self.line(&format!("$print({expr});"));
+            }
+            Stmt::Var {
+                name: var, expr, ..
+            } => {
+                let value = match expr {
+                    Some(expr) => self.expr(expr),
+                    None => String::from("null"),
+                };
+                let declaration = self.declaration();
+                self.line(&format!("{declaration} {} = {value};", name(var)));
+            }
+            Stmt::Block { stmts, .. } => {
+                self.line("{");
+                self.block(stmts);
+                self.line("}");
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                let condition = self.expr(condition);
+                self.line(&format!("if ($truthy({condition})) {{"));
+                self.body(then_branch);
+                if let Some(branch) = else_branch {
+                    self.line("} else {");
+                    self.body(branch);
+                }
+                self.line("}");
+            }
+            Stmt::While {
+                condition, body, ..
+            } => {
+                let condition = self.expr(condition);
+                self.line(&format!("while ($truthy({condition})) {{"));
+                self.body(body);
+                self.line("}");
+            }
+            Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+                ..
+            } => {
+                // Like Lox, `let` in a `for` gives each iteration its own copy
+                // of the loop variable for closures to capture.
+                self.depth += 1;
+                let initializer = match initializer.as_deref() {
+                    Some(Stmt::Var {
+                        name: var, expr, ..
+                    }) => {
+                        let value = match expr {
+                            Some(expr) => self.expr(expr),
+                            None => String::from("null"),
+                        };
+                        format!("let {} = {value}", name(var))
+                    }
+                    Some(Stmt::Expr { expr, .. }) => self.expr(expr),
+                    _ => String::new(),
+                };
+                let condition = match condition {
+                    Some(condition) => format!("$truthy({})", self.expr(condition)),
+                    None => String::new(),
+                };
+                let increment = match increment {
+                    Some(increment) => self.expr(increment),
+                    None => String::new(),
+                };
+                self.line(&format!("for ({initializer}; {condition}; {increment}) {{"));
+                self.body(body);
+                self.line("}");
+                self.depth -= 1;
+            }
+            Stmt::Function {
+                name: function,
+                params,
+                body,
+                ..
+            } => {
+                let params: Vec<String> = params.iter().map(name).collect();
+                let params = params.join(", ");
+                let declaration = self.declaration();
+                let function_name = name(function);
+                // Arrow functions keep the `this` of the method they're in.
+                if generator::is_generator(body) {
+                    self.line(&format!(
+                        "{declaration} {function_name} = $generator(function* {function_name}({params}) {{"
+                    ));
+                } else {
+                    self.line(&format!("{declaration} {function_name} = ({params}) => {{"));
+                }
+                self.block(body);
+                if generator::is_generator(body) && self.in_class {
+                    self.line("}).bind(this);");
+                } else if generator::is_generator(body) {
+                    self.line("});");
+                } else {
+                    self.line("};");
+                }
+            }
+            Stmt::Return { value, .. } => match value {
+                Some(value) => {
+                    let value = self.expr(value);
+                    self.line(&format!("return {value};"));
+                }
+                None => self.line("return;"),
+            },
+            Stmt::Yield { value, .. } => {
+                let value = match value {
+                    Some(value) => self.expr(value),
+                    None => String::from("null"),
+                };
+                self.line(&format!("yield {value};"));
+            }
+            Stmt::Class {
+                name: class,
+                methods,
+                statics,
+                superclass,
+                ..
+            } => self.class(class, methods, statics, superclass.as_deref()),
+            Stmt::Test { name, .. } => {
+                self.line(&format!(
+                    "// test {} only runs with `loxrt test`.",
+                    name.lexeme
+                ));
+            }
+        }
+    }
+
+    fn class(
+        &mut self,
+        class: &Token,
+        methods: &[Box<Stmt>],
+        statics: &[Box<Stmt>],
+        superclass: Option<&Expr>,
+    ) {
+        let superclass = match superclass {
+            Some(superclass) => format!("$superclass({})", self.expr(superclass)),
+            None => String::from("$Object"),
+        };
+        let declaration = self.declaration();
+        let class_name = name(class);
+        self.line(&format!(
+            "{declaration} {class_name} = class {class_name} extends {superclass} {{"
+        ));
+        self.indent += 1;
+        for stmt in statics {
+            if let Stmt::Var {
+                name: field, expr, ..
+            } = &**stmt
+            {
+                let value = match expr {
+                    Some(expr) => self.expr(expr),
+                    None => String::from("null"),
+                };
+                self.line(&format!("static {} = {value};", field.lexeme));
+            }
+        }
+
+        let in_class = std::mem::replace(&mut self.in_class, true);
+        for method in methods {
+            if let Stmt::Function {
+                name: method,
+                params,
+                body,
+                ..
+            } = &**method
+            {
+                let params: Vec<String> = params.iter().map(name).collect();
+                if method.lexeme == "init" {
+                    // Patterns destructure instances by the initializer's parameters.
+                    let fields: Vec<String> = params.iter().map(|p| format!("{p:?}")).collect();
+                    self.line(&format!("static $params = [{}];", fields.join(", ")));
+                }
+                // A method called `constructor` would become the constructor.
+                let key = if method.lexeme == "constructor" {
+                    String::from("[\"constructor\"]")
+                } else {
+                    method.lexeme.to_string()
+                };
+                let is_generator = generator::is_generator(body);
+                let star = if is_generator { "*" } else { "" };
+                self.line(&format!("{star}{key}({}) {{", params.join(", ")));
+                self.block(body);
+                self.line("}");
+                if is_generator {
+                    self.line(&format!(
+                        "static {{ $generator(this.prototype[{:?}]); }}",
+                        method.lexeme
+                    ));
+                }
+            }
+        }
+        self.in_class = in_class;
+        self.indent -= 1;
+        self.line("};");
+    }
+
+    fn expr(&mut self, expr: &Expr) -> String {
+        match expr {
+            Expr::Binary {
+                left,
+                operator,
+                right,
+                ..
+            } => {
+                let (left, right) = (self.expr(left), self.expr(right));
+                match operator.tok_typ {
+                    TokenType::Plus => format!("$add({left}, {right})"),
+                    TokenType::EqualEqual => format!("$eq({left}, {right})"),
+                    TokenType::BangEqual => format!("!$eq({left}, {right})"),
+                    TokenType::Greater
+                    | TokenType::GreaterEqual
+                    | TokenType::Less
+                    | TokenType::LessEqual => {
+                        format!("$compare(\"{}\", {left}, {right})", operator.lexeme)
+                    }
+                    _ => format!("$arith(\"{}\", {left}, {right})", operator.lexeme),
+                }
+            }
+            Expr::Logical {
+                left,
+                operator,
+                right,
+                ..
+            } => {
+                let (left, right) = (self.expr(left), self.expr(right));
+                let helper = match operator.tok_typ {
+                    TokenType::And => "$and",
+                    _ => "$or",
+                };
+                format!("{helper}({left}, () => {right})")
+            }
+            Expr::Unary {
+                operator, right, ..
+            } => {
+                let right = self.expr(right);
+                match operator.tok_typ {
+                    TokenType::Minus => format!("$neg({right})"),
+                    _ => format!("!$truthy({right})"),
+                }
+            }
+            Expr::Grouping { expr, .. } => format!("({})", self.expr(expr)),
+            Expr::Literal { value, .. } => literal(value),
+            Expr::Variable { name: variable, .. } => name(variable),
+            Expr::Assignment {
+                name: variable,
+                value,
+                ..
+            } => format!("({} = {})", name(variable), self.expr(value)),
+            Expr::Call {
+                callee, arguments, ..
+            } => {
+                let mut call = format!("$call({}", self.expr(callee));
+                for argument in arguments {
+                    call.push_str(", ");
+                    call.push_str(&self.expr(argument));
+                }
+                call.push(')');
+                call
+            }
+            Expr::Get { object, name, .. } => {
+                format!("$get({}, {:?})", self.expr(object), name.lexeme)
+            }
+            Expr::Set {
+                object,
+                name,
+                value,
+                ..
+            } => format!(
+                "$set({}, {:?}, {})",
+                self.expr(object),
+                name.lexeme,
+                self.expr(value)
+            ),
+            Expr::This { .. } => String::from("this"),
+            Expr::Super { method, .. } => format!(
+                "$super(super[{:?}], this, {:?})",
+                method.lexeme, method.lexeme
+            ),
+            Expr::List { elements, .. } => {
+                let elements: Vec<String> = elements.iter().map(|e| self.expr(e)).collect();
+                format!("[{}]", elements.join(", "))
+            }
+            Expr::Index { object, index, .. } => {
+                format!("$index({}, {})", self.expr(object), self.expr(index))
+            }
+            Expr::IndexSet {
+                object,
+                index,
+                value,
+                ..
+            } => format!(
+                "$setIndex({}, {}, {})",
+                self.expr(object),
+                self.expr(index),
+                self.expr(value)
+            ),
+            Expr::Match { subject, arms, .. } => {
+                let subject = self.expr(subject);
+                let value = self.temp();
+                let mut code = format!("(({value}) => {{ ");
+                for arm in arms {
+                    let mut bindings = vec![];
+                    let condition = self.pattern(&arm.pattern, &value, &mut bindings);
+                    let body = self.expr(&arm.body);
+                    code.push_str("{ ");
+                    if !bindings.is_empty() {
+                        code.push_str(&format!("let {}; ", bindings.join(", ")));
+                    }
+                    code.push_str(&format!("if ({condition}) return {body}; }} "));
+                }
+                code.push_str(&format!(
+                    "throw $error(`No pattern matched ${{$str({value})}}.`); }})({subject})"
+                ));
+                code
+            }
+        }
+    }
+
+    /// The condition for `value` matching `pattern`, which assigns the
+    /// pattern's bindings as it goes. Names it assigns are added to `bindings`.
+    fn pattern(&mut self, pattern: &Pattern, value: &str, bindings: &mut Vec<String>) -> String {
+        match pattern {
+            Pattern::Wildcard => String::from("true"),
+            Pattern::Literal {
+                value: literal_value,
+            } => {
+                format!("$eq({value}, {})", literal(literal_value))
+            }
+            Pattern::Binding { name: binding } => {
+                let binding = name(binding);
+                let condition = format!("(({binding} = {value}), true)");
+                bindings.push(binding);
+                condition
+            }
+            Pattern::Class { class, fields, .. } => {
+                let class = self.expr(class);
+                let values = self.temp();
+                let mut condition = format!(
+                    "({values} = $fields({value}, {class}, {})) !== null",
+                    fields.len()
+                );
+                bindings.push(values.clone());
+                for (i, field) in fields.iter().enumerate() {
+                    let field = self.pattern(field, &format!("{values}[{i}]"), bindings);
+                    condition.push_str(&format!(" && {field}"));
+                }
+                condition
+            }
+        }
+    }
+}
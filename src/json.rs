@@ -0,0 +1,276 @@
+//! Reading and writing the JSON that [`crate::interpreter::Interpreter::export_globals_json`]
+//! saves globals as. Only what Lox values map to is supported: `null`,
+//! booleans, numbers, strings and arrays, inside one top-level object.
+
+use crate::interpreter::Types;
+use crate::sync::{Rc, RefCell};
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+/// `s` as a JSON string literal.
+pub(crate) fn string(s: &str) -> String {
+    let mut json = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// Renders `value` as JSON, or returns why it can't be, e.g. "a function".
+pub(crate) fn value(value: &Types) -> Result<String, String> {
+    let mut json = String::new();
+    write_value(value, &mut json, &mut vec![])?;
+    Ok(json)
+}
+
+/// `lists` holds the lists `value` is inside, so one containing itself is
+/// caught rather than written forever.
+fn write_value(
+    value: &Types,
+    json: &mut String,
+    lists: &mut Vec<*const RefCell<Vec<Types>>>,
+) -> Result<(), String> {
+    match value {
+        Types::Nil | Types::Uninitialized => json.push_str("null"),
+        Types::Bool(b) => json.push_str(if *b { "true" } else { "false" }),
+        Types::Number(n) if n.is_finite() => json.push_str(&n.to_string()),
+        Types::Number(n) => return Err(format!("{n}, which JSON has no number for")),
+        Types::String(s) => json.push_str(&string(s)),
+        Types::List(list) => {
+            if lists.contains(&Rc::as_ptr(list)) {
+                return Err(String::from("a list that contains itself"));
+            }
+            lists.push(Rc::as_ptr(list));
+            json.push('[');
+            for (i, element) in list.borrow().iter().enumerate() {
+                if i > 0 {
+                    json.push(',');
+                }
+                write_value(element, json, lists)?;
+            }
+            json.push(']');
+            lists.pop();
+        }
+        Types::Callable(_) | Types::NativeFunc(_) => return Err(String::from("a function")),
+        Types::Class(_) => return Err(String::from("a class")),
+        Types::ClassInstance(instance) => {
+            return Err(format!(
+                "an instance of {}",
+                instance.borrow().class().name()
+            ))
+        }
+        Types::Generator(_) => return Err(String::from("a generator")),
+        Types::Host(object) => return Err(format!("a {}", object.class_name())),
+        #[cfg(feature = "threads")]
+        Types::Channel(_) => return Err(String::from("a channel")),
+    }
+    Ok(())
+}
+
+/// Parses a JSON object into its keys and values, in the order they appear.
+pub(crate) fn parse_object(text: &str) -> Result<Vec<(String, Types)>, String> {
+    let mut parser = Parser {
+        text,
+        chars: text.char_indices().peekable(),
+    };
+    parser.expect('{')?;
+    let mut entries = vec![];
+    if !parser.eat('}') {
+        loop {
+            parser.expect('"')?;
+            let key = parser.string()?;
+            parser.expect(':')?;
+            entries.push((key, parser.value()?));
+            if parser.eat('}') {
+                break;
+            }
+            parser.expect(',')?;
+        }
+    }
+    parser.skip_whitespace();
+    match parser.chars.next() {
+        None => Ok(entries),
+        Some((i, _)) => Err(format!("Unexpected text after the object at byte {i}.")),
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self
+            .chars
+            .next_if(|(_, c)| c.is_ascii_whitespace())
+            .is_some()
+        {}
+    }
+
+    /// Skips `c`, after any whitespace, if it's next.
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        self.chars.next_if(|(_, next)| *next == c).is_some()
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("`{c}`")))
+        }
+    }
+
+    /// The error for finding something other than `expected` next.
+    fn unexpected(&mut self, expected: &str) -> String {
+        match self.chars.peek() {
+            Some((i, c)) => format!("Expected {expected} but found `{c}` at byte {i}."),
+            None => format!("Expected {expected} but the JSON ended."),
+        }
+    }
+
+    fn value(&mut self) -> Result<Types, String> {
+        self.skip_whitespace();
+        let Some(&(start, c)) = self.chars.peek() else {
+            return Err(self.unexpected("a value"));
+        };
+        match c {
+            '"' => {
+                self.chars.next();
+                Ok(Types::String(Rc::from(self.string()?)))
+            }
+            '[' => {
+                self.chars.next();
+                let mut elements = vec![];
+                if !self.eat(']') {
+                    loop {
+                        elements.push(self.value()?);
+                        if self.eat(']') {
+                            break;
+                        }
+                        self.expect(',')?;
+                    }
+                }
+                Ok(Types::List(Rc::new(RefCell::new(elements))))
+            }
+            '{' => Err(format!(
+                "Objects are only supported at the top level, found one at byte {start}."
+            )),
+            _ => {
+                let mut end = start;
+                while let Some((i, c)) = self
+                    .chars
+                    .next_if(|(_, c)| c.is_ascii_alphanumeric() || "+-.".contains(*c))
+                {
+                    end = i + c.len_utf8();
+                }
+                match &self.text[start..end] {
+                    "null" => Ok(Types::Nil),
+                    "true" => Ok(Types::Bool(true)),
+                    "false" => Ok(Types::Bool(false)),
+                    "" => Err(self.unexpected("a value")),
+                    // Rust would also parse e.g. `-inf` or `01`, which aren't JSON.
+                    word => match word.parse() {
+                        Ok(n) if is_number(word) => Ok(Types::Number(n)),
+                        _ => Err(format!("Unexpected value `{word}` at byte {start}.")),
+                    },
+                }
+            }
+        }
+    }
+
+    /// Parses the rest of a string after its opening quote.
+    fn string(&mut self) -> Result<String, String> {
+        let mut s = String::new();
+        loop {
+            match self.chars.next() {
+                Some((_, '"')) => return Ok(s),
+                Some((_, '\\')) => match self.chars.next() {
+                    Some((_, '"')) => s.push('"'),
+                    Some((_, '\\')) => s.push('\\'),
+                    Some((_, '/')) => s.push('/'),
+                    Some((_, 'b')) => s.push('\u{8}'),
+                    Some((_, 'f')) => s.push('\u{c}'),
+                    Some((_, 'n')) => s.push('\n'),
+                    Some((_, 'r')) => s.push('\r'),
+                    Some((_, 't')) => s.push('\t'),
+                    Some((i, 'u')) => {
+                        let mut code = self.hex()?;
+                        // Characters outside the BMP are written as surrogate pairs.
+                        if (0xD800..0xDC00).contains(&code) {
+                            let low = match (self.chars.next(), self.chars.next()) {
+                                (Some((_, '\\')), Some((_, 'u'))) => self.hex()?,
+                                _ => 0,
+                            };
+                            if !(0xDC00..0xE000).contains(&low) {
+                                return Err(format!("Unpaired surrogate at byte {i}."));
+                            }
+                            code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                        }
+                        match char::from_u32(code) {
+                            Some(c) => s.push(c),
+                            None => return Err(format!("Invalid escape at byte {i}.")),
+                        }
+                    }
+                    Some((i, c)) => return Err(format!("Invalid escape `\\{c}` at byte {i}.")),
+                    None => return Err(String::from("Unterminated string.")),
+                },
+                Some((_, c)) => s.push(c),
+                None => return Err(String::from("Unterminated string.")),
+            }
+        }
+    }
+
+    /// Parses the four hex digits of a `\u` escape.
+    fn hex(&mut self) -> Result<u32, String> {
+        let mut code = 0;
+        for _ in 0..4 {
+            match self.chars.next().and_then(|(_, c)| c.to_digit(16)) {
+                Some(digit) => code = code * 16 + digit,
+                None => return Err(String::from("Expected four hex digits after `\\u`.")),
+            }
+        }
+        Ok(code)
+    }
+}
+
+/// Whether `word` follows JSON's grammar for numbers: an optional minus, an
+/// integer part without leading zeros, then an optional fraction and exponent.
+fn is_number(word: &str) -> bool {
+    let digits = |s: &str| s.bytes().take_while(u8::is_ascii_digit).count();
+    let rest = word.strip_prefix('-').unwrap_or(word);
+    let rest = match digits(rest) {
+        0 => return false,
+        1 => &rest[1..],
+        _ if rest.starts_with('0') => return false,
+        n => &rest[n..],
+    };
+    let rest = match rest.strip_prefix('.') {
+        Some(fraction) => match digits(fraction) {
+            0 => return false,
+            n => &fraction[n..],
+        },
+        None => rest,
+    };
+    let rest = match rest.strip_prefix(['e', 'E']) {
+        Some(exponent) => {
+            let exponent = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+            match digits(exponent) {
+                0 => return false,
+                n => &exponent[n..],
+            }
+        }
+        None => rest,
+    };
+    rest.is_empty()
+}
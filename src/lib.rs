@@ -0,0 +1,554 @@
+#![allow(clippy::result_large_err, clippy::vec_box)]
+
+pub mod ast;
+pub mod diagnostics;
+pub mod environment;
+pub mod error;
+pub mod files;
+pub mod generator;
+pub mod hooks;
+pub mod host;
+pub mod inspect;
+pub mod interpreter;
+pub mod js;
+mod json;
+mod memory;
+pub mod natives;
+pub mod optimizer;
+pub mod parser;
+pub mod permissions;
+mod primitives;
+pub mod repl;
+pub mod resolver;
+pub mod scanner;
+pub mod sync;
+pub mod testing;
+#[cfg(feature = "threads")]
+pub mod threads;
+pub mod tokens;
+pub mod trace;
+pub mod typecheck;
+pub mod visit;
+pub mod watch;
+
+use ast::Stmt;
+use diagnostics::{Diagnostic, DiagnosticSink, StderrSink};
+use environment::Environment;
+use error::{IoOperation, LoxError};
+use interpreter::{Interpreter, InterpreterOptions, Output, Types};
+use parser::Parser;
+use resolver::Resolver;
+use scanner::Scanner;
+use std::cell::RefCell;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use std::time::Instant;
+use testing::{Captured, FileResults, Summary, TestResult, TestRun};
+use tokens::TokenType;
+use trace::Trace;
+use visit::Transformer;
+
+/// Lox code every interpreter runs before any script, defining the parts of
+/// the standard library written in Lox itself.
+pub const PRELUDE: &str = include_str!("prelude.lox");
+
+pub struct Lox {
+    /// Applied to every interpreter this creates.
+    pub options: InterpreterOptions,
+    /// See [`Parser::max_errors`].
+    pub max_errors: usize,
+    /// See [`Parser::max_depth`].
+    pub max_depth: usize,
+    /// Runs [`optimizer::optimize`] on programs before interpreting them.
+    pub optimize: bool,
+    /// Saves the inputs scripts get from outside the program to this file,
+    /// see [`Interpreter::record`].
+    pub record: Option<String>,
+    /// Feeds scripts the inputs saved in this file instead, see [`Interpreter::replay`].
+    pub replay: Option<String>,
+    /// Only runs the tests whose names contain this.
+    pub test_filter: Option<String>,
+    /// The number of files [`Lox::test_dir`] tests at once, each on its own
+    /// thread. Files are tested one at a time if there are transformers,
+    /// since those can't be shared between threads.
+    pub test_jobs: usize,
+    diagnostics: RefCell<Box<dyn DiagnosticSink>>,
+    /// The script [`Lox::run_files`] got to, which reported diagnostics are
+    /// tagged with.
+    file: RefCell<Option<String>>,
+    /// Run into the globals of every new interpreter, in order.
+    preludes: Vec<String>,
+    transformers: RefCell<Vec<Box<dyn Transformer>>>,
+}
+
+impl Default for Lox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Lox {
+    pub fn new() -> Self {
+        Lox {
+            options: InterpreterOptions::default(),
+            max_errors: parser::DEFAULT_MAX_ERRORS,
+            max_depth: parser::DEFAULT_MAX_DEPTH,
+            optimize: false,
+            record: None,
+            replay: None,
+            test_filter: None,
+            test_jobs: 1,
+            diagnostics: RefCell::new(Box::new(StderrSink)),
+            file: RefCell::new(None),
+            preludes: vec![String::from(PRELUDE)],
+            transformers: RefCell::new(vec![]),
+        }
+    }
+
+    /// Sends reported errors to `sink` instead of printing them to stderr.
+    pub fn set_diagnostic_sink(&mut self, sink: Box<dyn DiagnosticSink>) {
+        self.diagnostics = RefCell::new(sink);
+    }
+
+    /// Rewrites every program with `transformer` once it's parsed, before
+    /// it's resolved. Transformers run in the order they're added.
+    pub fn add_transformer(&mut self, transformer: Box<dyn Transformer>) {
+        self.transformers.get_mut().push(transformer);
+    }
+
+    /// Runs the script at `path` in every new interpreter after [`PRELUDE`],
+    /// so the globals it defines can be used by every script. It's read and
+    /// parsed now, failing with any errors there; errors running it are
+    /// reported when each interpreter is created.
+    pub fn add_prelude(&mut self, path: &str) -> Result<(), LoxError> {
+        let source = read_source(path)?;
+        self.parse(source.clone())?;
+        self.preludes.push(source);
+        Ok(())
+    }
+
+    /// Reports `error` to the diagnostic sink.
+    pub fn report(&self, error: &LoxError) {
+        self.report_all(error.diagnostics());
+    }
+
+    /// Runs the script at `path`, or the one piped to stdin if `path` is `-`,
+    /// returning its value as [`Lox::run`] does.
+    pub fn run_file(&self, path: &str) -> Result<Types, LoxError> {
+        self.run(read_source(path)?)
+    }
+
+    /// Checks the script at `path` for errors without running it.
+    pub fn check_file(&self, path: &str) -> Result<(), LoxError> {
+        self.check(read_source(path)?)
+    }
+
+    /// Scans, parses and resolves `source` without running it, reporting the
+    /// same errors a run would before any code executes.
+    pub fn check(&self, source: String) -> Result<(), LoxError> {
+        let statements = self.parse(source)?;
+        self.interpreter(&statements)?;
+        Ok(())
+    }
+
+    /// Translates `source` to JavaScript, see [`js::emit`]. It's resolved
+    /// first, so it fails with the same errors a run would before any code
+    /// executes.
+    pub fn emit_js(&self, source: String) -> Result<String, LoxError> {
+        let statements = self.parse(source)?;
+        self.interpreter(&statements)?;
+        Ok(js::emit(&statements))
+    }
+
+    pub fn emit_js_file(&self, path: &str) -> Result<String, LoxError> {
+        self.emit_js(read_source(path)?)
+    }
+
+    pub fn test_file(&self, path: &str) -> Result<usize, LoxError> {
+        self.run_tests(read_source(path)?)
+    }
+
+    /// Runs `source` as a script, e.g. code passed on the command line. Its
+    /// value is that of the last statement if it's an expression statement,
+    /// e.g. 3 for `var a = 1; a + 2;`, and nil otherwise.
+    pub fn run(&self, source: String) -> Result<Types, LoxError> {
+        self.run_with_output(source, Box::new(std::io::stdout()))
+    }
+
+    /// Runs `source`, sending everything it prints to `output` instead of stdout.
+    pub fn run_with_output(
+        &self,
+        source: String,
+        output: Box<dyn Output>,
+    ) -> Result<Types, LoxError> {
+        let statements = self.parse(source)?;
+        let mut interpreter = self.interpreter(&statements)?;
+        let statements = self.optimized(statements);
+        interpreter.set_output(output);
+        if let Some(path) = &self.replay {
+            let trace = Trace::parse(&read_source(path)?).or_else(|e| {
+                LoxError::new_io(path, std::io::Error::new(ErrorKind::InvalidData, e))
+            })?;
+            interpreter.replay(trace);
+        }
+        if self.record.is_some() {
+            interpreter.record();
+        }
+        let result = interpreter
+            .interpret_to_value(&statements)
+            .map(|value| value.unwrap_or(Types::Nil));
+        let result = self.finish_threads(&mut interpreter, result);
+        // Keep the trace of failed runs too, since those are the ones to reproduce.
+        if let (Some(path), Some(trace)) = (&self.record, interpreter.take_trace()) {
+            if let Err(e) = fs::write(path, trace.to_string()) {
+                self.report(&LoxError::IoError {
+                    path: path.clone(),
+                    operation: IoOperation::Write,
+                    error: e,
+                });
+            }
+        }
+        result
+    }
+
+    /// Runs every `test "name" { ... }` block in `source`, returning the number
+    /// of failed tests. Each test gets a fresh interpreter, so the top-level code
+    /// is re-run before every test and no state leaks between them.
+    pub fn run_tests(&self, source: String) -> Result<usize, LoxError> {
+        let start = Instant::now();
+        let run = self.test_run(source)?;
+        let (passed, failed) = self.print_tests(run.tests);
+        println!();
+        println!(
+            "{}",
+            Summary {
+                passed,
+                failed,
+                filtered_out: run.filtered_out,
+                elapsed: start.elapsed(),
+            }
+        );
+        Ok(failed)
+    }
+
+    /// Runs the tests in every `*_test.lox` file under `dir`, each file in
+    /// its own interpreters as [`Lox::run_tests`] does, and [`Lox::test_jobs`]
+    /// files at a time. Returns the number of failed tests, counting a file
+    /// that couldn't be run as one.
+    pub fn test_dir(&self, dir: &str) -> Result<usize, LoxError> {
+        let start = Instant::now();
+        let files = testing::test_files(Path::new(dir)).or_else(|e| LoxError::new_io(dir, e))?;
+        let results = if self.test_jobs > 1 && self.transformers.borrow().is_empty() {
+            testing::run_parallel(&files, self.test_jobs, self.test_worker())
+        } else {
+            files
+                .iter()
+                .map(|path| self.test_file_results(path))
+                .collect()
+        };
+
+        let (mut passed, mut failed, mut filtered_out) = (0, 0, 0);
+        for (path, results) in files.iter().zip(results) {
+            self.report_all(results.reported);
+            match results.run {
+                Ok(run) => {
+                    filtered_out += run.filtered_out;
+                    if run.tests.is_empty() {
+                        continue;
+                    }
+                    println!(
+                        "running {} {} in {} ({:.2?})",
+                        run.tests.len(),
+                        if run.tests.len() == 1 {
+                            "test"
+                        } else {
+                            "tests"
+                        },
+                        path.display(),
+                        results.elapsed
+                    );
+                    let (p, f) = self.print_tests(run.tests);
+                    passed += p;
+                    failed += f;
+                }
+                Err(diagnostics) => {
+                    println!("{} ... FAILED", path.display());
+                    self.report_all(diagnostics);
+                    failed += 1;
+                }
+            }
+            println!();
+        }
+        println!(
+            "{}",
+            Summary {
+                passed,
+                failed,
+                filtered_out,
+                elapsed: start.elapsed(),
+            }
+        );
+        Ok(failed)
+    }
+
+    /// Runs the tests in `source` that match [`Lox::test_filter`].
+    fn test_run(&self, source: String) -> Result<TestRun, LoxError> {
+        let mut statements = self.parse(source)?;
+        if self.optimize {
+            self.interpreter(&statements)?;
+            statements = optimizer::optimize(statements);
+        }
+        let mut run = TestRun {
+            tests: vec![],
+            filtered_out: 0,
+        };
+        for stmt in &statements {
+            if let Stmt::Test { name, body, .. } = &**stmt {
+                let name = match &name.tok_typ {
+                    TokenType::Str(name) => name.clone(),
+                    _ => name.lexeme.to_string(),
+                };
+                if self
+                    .test_filter
+                    .as_ref()
+                    .is_some_and(|filter| !name.contains(filter.as_str()))
+                {
+                    run.filtered_out += 1;
+                    continue;
+                }
+
+                let start = Instant::now();
+                let mut interpreter = self.interpreter(&statements)?;
+                let output = Captured::default();
+                interpreter.set_output(Box::new(output.clone()));
+                let result = interpreter.interpret(&statements).and_then(|_| {
+                    let env = Environment::new_child(&interpreter.global_env);
+                    interpreter.execute_block(body, env)
+                });
+                run.tests.push(TestResult {
+                    name,
+                    output: output.take(),
+                    failure: result.err().map(|e| e.diagnostics()),
+                    elapsed: start.elapsed(),
+                });
+            }
+        }
+        Ok(run)
+    }
+
+    fn test_file_results(&self, path: &Path) -> FileResults {
+        let start = Instant::now();
+        let run = fs::read_to_string(path)
+            .or_else(|e| LoxError::new_io(&path.display().to_string(), e))
+            .and_then(|source| self.test_run(source));
+        FileResults {
+            reported: vec![],
+            run: run.map_err(|e| e.diagnostics()),
+            elapsed: start.elapsed(),
+        }
+    }
+
+    /// Tests files like [`Lox::test_file_results`] from another thread, with a
+    /// `Lox` of its own made with this one's settings.
+    fn test_worker(&self) -> impl Fn(&Path) -> FileResults + Sync {
+        let (options, max_errors, max_depth, optimize) =
+            (self.options, self.max_errors, self.max_depth, self.optimize);
+        let preludes = self.preludes.clone();
+        let test_filter = self.test_filter.clone();
+        move |path| {
+            let reported = sync::Rc::new(sync::RefCell::new(vec![]));
+            let lox = Lox {
+                options,
+                max_errors,
+                max_depth,
+                optimize,
+                record: None,
+                replay: None,
+                test_filter: test_filter.clone(),
+                test_jobs: 1,
+                diagnostics: RefCell::new(Box::new(reported.clone())),
+                file: RefCell::new(None),
+                preludes: preludes.clone(),
+                transformers: RefCell::new(vec![]),
+            };
+            let mut results = lox.test_file_results(path);
+            results.reported = std::mem::take(&mut *reported.borrow_mut());
+            results
+        }
+    }
+
+    /// Prints what each test printed and whether it passed, returning the
+    /// number that passed and failed.
+    fn print_tests(&self, tests: Vec<TestResult>) -> (usize, usize) {
+        let (mut passed, mut failed) = (0, 0);
+        for test in tests {
+            // Output is best effort, as it is for print statements.
+            let _ = std::io::stdout().write_all(&test.output);
+            match test.failure {
+                None => {
+                    println!("test {} ... ok", test.name);
+                    passed += 1;
+                }
+                Some(diagnostics) => {
+                    println!("test {} ... FAILED", test.name);
+                    self.report_all(diagnostics);
+                    failed += 1;
+                }
+            }
+        }
+        (passed, failed)
+    }
+
+    fn report_all(&self, diagnostics: Vec<Diagnostic>) {
+        let mut sink = self.diagnostics.borrow_mut();
+        let file = self.file.borrow();
+        for mut diagnostic in diagnostics {
+            if diagnostic.file.is_none() {
+                diagnostic.file = file.clone();
+            }
+            sink.report(diagnostic);
+        }
+    }
+
+    fn parse(&self, source: String) -> Result<Vec<Box<Stmt>>, LoxError> {
+        let scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens()?;
+        let mut parser = Parser::new(tokens);
+        parser.max_errors(self.max_errors);
+        parser.max_depth(self.max_depth);
+        let mut statements = parser.parse()?;
+        for transformer in self.transformers.borrow_mut().iter_mut() {
+            statements = visit::rewrite_stmts(transformer.as_mut(), statements);
+        }
+        Ok(statements)
+    }
+
+    /// Runs `source` against an existing interpreter, keeping whatever globals
+    /// earlier runs defined.
+    pub fn run_in(&self, interpreter: &mut Interpreter, source: String) -> Result<(), LoxError> {
+        self.eval_in(interpreter, source).map(|_| ())
+    }
+
+    /// Runs the script at `path` against an existing interpreter, like
+    /// [`Lox::run_in`], so its globals can be used afterwards.
+    pub fn run_file_in(&self, interpreter: &mut Interpreter, path: &str) -> Result<(), LoxError> {
+        self.run_in(interpreter, read_source(path)?)
+    }
+
+    /// Runs the scripts at `paths` in order in one interpreter, so later
+    /// scripts can use the globals earlier ones define. The first error stops
+    /// the rest from running. Diagnostics reported while a script runs, and
+    /// for its error afterwards, name the script they came from.
+    pub fn run_files(&self, paths: &[String]) -> Result<(), LoxError> {
+        *self.file.borrow_mut() = None;
+        let mut interpreter = self.new_interpreter();
+        for path in paths {
+            *self.file.borrow_mut() = Some(path.clone());
+            self.run_file_in(&mut interpreter, path)?;
+        }
+        *self.file.borrow_mut() = None;
+        Ok(())
+    }
+
+    /// Runs `source` like [`Lox::run_in`], returning the value of its last
+    /// statement if that's an expression statement, as the REPL shows.
+    pub fn eval_in(
+        &self,
+        interpreter: &mut Interpreter,
+        source: String,
+    ) -> Result<Option<Types>, LoxError> {
+        let statements = self.parse(source)?;
+        self.resolve(interpreter, &statements, false)?;
+        let result = interpreter.interpret_to_value(&self.optimized(statements));
+        self.finish_threads(interpreter, result)
+    }
+
+    /// Waits for the threads a script started and reports the errors they
+    /// failed with. If the script itself succeeded, the last of them is
+    /// returned as its error instead, so the run doesn't look like it passed.
+    fn finish_threads<T>(
+        &self,
+        interpreter: &mut Interpreter,
+        result: Result<T, LoxError>,
+    ) -> Result<T, LoxError> {
+        let mut errors = interpreter.take_thread_errors();
+        let last = match result {
+            Ok(_) => errors.pop(),
+            Err(_) => None,
+        };
+        self.report_all(errors);
+        match last {
+            Some(error) => {
+                LoxError::new_runtime(error.span.map_or(0, |span| span.line), error.message)
+            }
+            None => result,
+        }
+    }
+
+    /// Optimizes `statements` if enabled. They must already be resolved.
+    fn optimized(&self, statements: Vec<Box<Stmt>>) -> Vec<Box<Stmt>> {
+        if self.optimize {
+            optimizer::optimize(statements)
+        } else {
+            statements
+        }
+    }
+
+    /// Makes an interpreter with this one's options, having run the preludes.
+    pub fn new_interpreter(&self) -> Interpreter {
+        let mut interpreter = Interpreter::new();
+        // The preludes run with the default options, so they behave the same
+        // whatever the scripts are run with, and don't count towards the
+        // scripts' memory limit or collections.
+        for prelude in &self.preludes {
+            if let Err(e) = self.run_in(&mut interpreter, prelude.clone()) {
+                self.report(&e);
+            }
+        }
+        interpreter.mark_library();
+        interpreter.reset_metrics();
+        interpreter.set_options(self.options);
+        interpreter
+    }
+
+    fn interpreter(&self, statements: &[Box<Stmt>]) -> Result<Interpreter, LoxError> {
+        let mut interpreter = self.new_interpreter();
+        self.resolve(&mut interpreter, statements, true)?;
+        Ok(interpreter)
+    }
+
+    /// Resolves `statements` for `interpreter`, reporting any warnings. In
+    /// strict mode the first warning is returned as an error instead.
+    /// `whole_program` is set unless more code may be run in the interpreter
+    /// later, see [`Resolver::resolve_program`].
+    fn resolve(
+        &self,
+        interpreter: &mut Interpreter,
+        statements: &[Box<Stmt>],
+        whole_program: bool,
+    ) -> Result<(), LoxError> {
+        let mut resolver = Resolver::new(interpreter);
+        if whole_program {
+            resolver.resolve_program(statements)?;
+        } else {
+            resolver.resolve(statements)?;
+        }
+        let mut warnings = resolver.take_warnings();
+        warnings.extend(typecheck::check(interpreter, statements)?);
+        self.report_all(warnings);
+        Ok(())
+    }
+}
+
+fn read_source(path: &str) -> Result<String, LoxError> {
+    let read = if path == "-" {
+        std::io::read_to_string(std::io::stdin())
+    } else {
+        fs::read_to_string(path)
+    };
+    match read {
+        Ok(s) => Ok(s),
+        Err(e) => LoxError::new_io(path, e),
+    }
+}
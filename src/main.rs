@@ -1,54 +1,182 @@
-pub mod ast;
-pub mod environment;
-pub mod error;
-pub mod interpreter;
-pub mod parser;
-pub mod resolver;
-pub mod scanner;
-pub mod tokens;
-
-use error::LoxError;
-use interpreter::Interpreter;
-use parser::Parser;
-use resolver::Resolver;
-use scanner::Scanner;
-use std::fs;
-
-pub struct Lox;
-impl Lox {
-    pub fn new() -> Self {
-        Lox
-    }
+#![allow(clippy::result_large_err)]
 
-    pub fn run_file(&self, path: &str) -> Result<(), LoxError> {
-        let s =
-            fs::read_to_string(path).expect(format!("Failed to read from file: {}", path).as_str());
-        self.run(s)
-    }
+use lox::diagnostics::JsonSink;
+use lox::error::{ExitCodes, LoxError};
+use lox::interpreter::Types;
+use lox::permissions::{Permission, Permissions};
+use lox::repl::Repl;
+use lox::watch::Watcher;
+use lox::Lox;
+use std::path::Path;
 
-    fn run(&self, source: String) -> Result<(), LoxError> {
-        let scanner = Scanner::new(source);
-        let tokens = scanner.scan_tokens()?;
-        let mut parser = Parser::new(tokens);
-        let statements = parser.parse()?;
+fn main() {
+    let mut lox = Lox::new();
+    let mut paths = vec![];
+    let mut code = None;
+    let mut test = false;
+    let mut check = false;
+    let mut emit_js = false;
+    let mut codes = ExitCodes::Loxrt;
+    let mut json = false;
+    let mut watch = false;
+    let mut keep_globals = false;
+    let mut preludes = vec![];
+    let mut args = std::env::args().skip(1).enumerate();
+    while let Some((i, arg)) = args.next() {
+        match arg.as_str() {
+            // The old name of `--allow-run`, kept as an alias of it.
+            "--allow-exec" => lox.options.permissions.grant(Permission::Process),
+            "--allow-all" => lox.options.permissions = Permissions::all(),
+            "--sysexits" => codes = ExitCodes::Sysexits,
+            "--check" => check = true,
+            "--opt" => lox.optimize = true,
+            "--stringify-operands" => lox.options.stringify_operands = true,
+            "--ieee-division" => lox.options.ieee_division = true,
+            "--nil-properties" => lox.options.nil_properties = true,
+            "--uninitialized-errors" => lox.options.uninitialized_errors = true,
+            "--strict" => lox.options.strict = true,
+            "--gc-stress" => lox.options.gc_stress = true,
+            "--deterministic" => lox.options.deterministic = true,
+            "--watch" => watch = true,
+            "--keep-globals" => keep_globals = true,
+            "--error-format=json" => json = true,
+            "--error-format=human" => json = false,
+            _ if arg.starts_with("--record=") => {
+                lox.record = Some(String::from(&arg["--record=".len()..]))
+            }
+            _ if arg.starts_with("--replay=") => {
+                lox.replay = Some(String::from(&arg["--replay=".len()..]))
+            }
+            _ if arg.starts_with("--prelude=") => {
+                preludes.push(String::from(&arg["--prelude=".len()..]))
+            }
+            _ if arg.starts_with("--max-errors=") => match arg["--max-errors=".len()..].parse() {
+                Ok(max) => lox.max_errors = max,
+                Err(_) => {
+                    eprintln!("Expected a number in `{arg}`.");
+                    std::process::exit(64);
+                }
+            },
+            _ if arg.starts_with("--filter=") => {
+                lox.test_filter = Some(String::from(&arg["--filter=".len()..]))
+            }
+            _ if arg.starts_with("--jobs=") => match arg["--jobs=".len()..].parse() {
+                Ok(jobs) if jobs > 0 => lox.test_jobs = jobs,
+                _ => {
+                    eprintln!("Expected a positive number in `{arg}`.");
+                    std::process::exit(64);
+                }
+            },
+            _ if arg.starts_with("--memory-limit=") => {
+                match arg["--memory-limit=".len()..].parse() {
+                    Ok(limit) => lox.options.memory_limit = Some(limit),
+                    Err(_) => {
+                        eprintln!("Expected a number of bytes in `{arg}`.");
+                        std::process::exit(64);
+                    }
+                }
+            }
+            _ if Permission::from_flag(&arg).is_some() => lox
+                .options
+                .permissions
+                .grant(Permission::from_flag(&arg).unwrap()),
+            "-e" => match args.next() {
+                Some((_, source)) => code = Some(source),
+                None => {
+                    eprintln!("Expected code after `-e`.");
+                    std::process::exit(64);
+                }
+            },
+            "--filter" => match args.next() {
+                Some((_, filter)) => lox.test_filter = Some(filter),
+                None => {
+                    eprintln!("Expected a test name after `--filter`.");
+                    std::process::exit(64);
+                }
+            },
+            "test" if i == 0 => test = true,
+            "emit-js" if i == 0 => emit_js = true,
+            _ => paths.push(arg),
+        }
+    }
 
-        let mut interpreter = Interpreter::new();
+    // Several scripts are run one after another in a shared session.
+    let more = paths.len() > 1 && code.is_none() && !check && !test && !emit_js && !watch;
+    // The other modes take one script, and would ignore the rest.
+    if paths.len() > 1 && !more {
+        let mode = match () {
+            _ if code.is_some() => "-e",
+            _ if check => "--check",
+            _ if test => "test",
+            _ if emit_js => "emit-js",
+            _ => "--watch",
+        };
+        eprintln!("`{mode}` takes one script, but {} were given.", paths.len());
+        std::process::exit(64);
+    }
+    let path = if more { None } else { paths.pop() };
+    if json {
+        let file = path.clone().filter(|path| code.is_none() && path != "-");
+        lox.set_diagnostic_sink(Box::new(JsonSink::new(file)));
+    }
 
-        {
-            let mut resolver = Resolver::new(&mut interpreter);
-            resolver.resolve(&statements)?;
+    for prelude in preludes {
+        if let Err(e) = lox.add_prelude(&prelude) {
+            lox.report(&e);
+            e.exit_with(codes);
         }
+    }
 
-        interpreter.interpret(&statements)?;
+    // The number of failed tests, which is always 0 when running or checking a script.
+    let result = match (code, path) {
+        (None, None) if more => lox.run_files(&paths).map(|_| 0),
+        (Some(code), _) if check => lox.check(code).map(|_| 0),
+        (Some(code), _) if test => lox.run_tests(code),
+        (Some(code), _) if emit_js => lox.emit_js(code).map(print_js),
+        (Some(code), _) => eval(&lox, code),
+        (None, Some(path)) if check => lox.check_file(&path).map(|_| 0),
+        (None, Some(path)) if test && Path::new(&path).is_dir() => lox.test_dir(&path),
+        (None, Some(path)) if test => lox.test_file(&path),
+        (None, Some(path)) if emit_js => lox.emit_js_file(&path).map(print_js),
+        (None, Some(path)) if watch && path != "-" => {
+            let mut watcher = Watcher::new(&lox, &path);
+            watcher.keep_globals = keep_globals;
+            watcher.run().map(|_| 0)
+        }
+        (None, Some(path)) => lox.run_file(&path).map(|_| 0),
+        (None, None) => {
+            Repl::new(&lox).run();
+            return;
+        }
+    };
 
-        Ok(())
+    match result {
+        Ok(0) => (),
+        Ok(_) => std::process::exit(1),
+        Err(e) => {
+            lox.report(&e);
+            e.exit_with(codes);
+        }
     }
 }
 
-fn main() {
-    let lox = Lox::new();
-    if let Err(e) = lox.run_file("sample.lox") {
-        e.report();
-        e.exit();
+/// Runs code passed with `-e`, printing its value unless it's nil, so
+/// `loxrt -e '1 + 2'` prints 3. The final `;` can be left out.
+fn eval(lox: &Lox, code: String) -> Result<usize, LoxError> {
+    let value = match lox.run(code.clone()) {
+        Err(e) if e.is_incomplete_input() && lox.check(format!("{code};")).is_ok() => {
+            lox.run(format!("{code};"))?
+        }
+        result => result?,
+    };
+    if value != Types::Nil {
+        println!("{value}");
     }
+    Ok(0)
+}
+
+/// Prints JavaScript emitted for `loxrt emit-js`, returning no failed tests.
+fn print_js(js: String) -> usize {
+    print!("{js}");
+    0
 }
@@ -0,0 +1,123 @@
+use crate::environment::Environment;
+use crate::interpreter::Types;
+use crate::sync::{Rc, RefCell};
+use std::collections::HashSet;
+use std::mem::size_of;
+
+/// Rough sizes, in bytes, of the values scripts allocate. They only need to be
+/// close enough for [`crate::interpreter::InterpreterOptions::memory_limit`]
+/// to stop a script well before it exhausts the host's memory.
+pub(crate) const VALUE: usize = size_of::<Types>();
+pub(crate) const STRING: usize = 16;
+pub(crate) const LIST: usize = 32;
+pub(crate) const INSTANCE: usize = 80;
+pub(crate) const FIELD: usize = 32 + VALUE;
+pub(crate) const ENVIRONMENT: usize = 96;
+pub(crate) const FUNCTION: usize = 96;
+pub(crate) const CLASS: usize = 128;
+
+pub(crate) fn string_size(len: usize) -> usize {
+    STRING + len
+}
+
+pub(crate) fn list_size(len: usize) -> usize {
+    LIST + len * VALUE
+}
+
+/// Adds up the approximate size of the values reachable from a set of roots.
+/// Values shared between several roots are only counted once.
+#[derive(Default)]
+pub(crate) struct LiveSize {
+    seen: HashSet<*const ()>,
+    pub bytes: usize,
+    /// Set if a host object couldn't be measured because one of its methods
+    /// is running, e.g. one that allocates.
+    pub incomplete: bool,
+}
+
+impl LiveSize {
+    /// The number of distinct strings, lists, instances, classes, environments
+    /// and host objects counted.
+    pub fn objects(&self) -> usize {
+        self.seen.len()
+    }
+
+    /// Whether `ptr` hasn't been counted yet, marking it as counted.
+    fn first_visit<T: ?Sized>(&mut self, ptr: *const T) -> bool {
+        self.seen.insert(ptr as *const ())
+    }
+
+    pub fn environment(&mut self, environment: &Rc<RefCell<Environment>>) {
+        if !self.first_visit(Rc::as_ptr(environment)) {
+            return;
+        }
+        let environment = environment.borrow();
+        self.bytes += ENVIRONMENT;
+        for (name, value) in &environment.values {
+            self.bytes += name.len() + VALUE;
+            self.value(value);
+        }
+        for value in environment.slots() {
+            self.bytes += VALUE;
+            self.value(value);
+        }
+        if let Some(parent) = &environment.parent {
+            self.environment(parent);
+        }
+    }
+
+    pub fn value(&mut self, value: &Types) {
+        match value {
+            Types::String(s) if self.first_visit(Rc::as_ptr(s)) => {
+                self.bytes += string_size(s.len());
+            }
+            Types::List(list) if self.first_visit(Rc::as_ptr(list)) => {
+                let list = list.borrow();
+                self.bytes += list_size(list.len());
+                list.iter().for_each(|element| self.value(element));
+            }
+            Types::ClassInstance(instance) if self.first_visit(Rc::as_ptr(instance)) => {
+                let instance = instance.borrow();
+                self.bytes += INSTANCE;
+                for (name, value) in instance.fields() {
+                    self.bytes += FIELD + name.len();
+                    self.value(value);
+                }
+                self.value(&Types::Class(instance.class().clone()));
+            }
+            Types::Class(class) if self.first_visit(Rc::as_ptr(class)) => {
+                self.bytes += CLASS;
+                class
+                    .methods()
+                    .values()
+                    .for_each(|method| self.value(method));
+                for value in class.static_values() {
+                    self.bytes += FIELD;
+                    self.value(&value);
+                }
+                if let Some(superclass) = class.superclass() {
+                    self.value(&Types::Class(superclass.clone()));
+                }
+            }
+            Types::Callable(function) => {
+                self.bytes += FUNCTION;
+                self.environment(function.closure());
+            }
+            Types::Generator(generator) if self.first_visit(Rc::as_ptr(generator)) => {
+                match generator.try_borrow() {
+                    Ok(generator) => generator
+                        .environments()
+                        .for_each(|environment| self.environment(environment)),
+                    Err(_) => self.incomplete = true,
+                }
+            }
+            Types::Host(object) if self.first_visit(Rc::as_ptr(&object.object)) => {
+                match object.try_borrow() {
+                    Ok(object) => self.bytes += object.size(),
+                    Err(_) => self.incomplete = true,
+                }
+            }
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,891 @@
+use crate::environment::Environment;
+use crate::error::LoxError;
+use crate::generator::{Generator, Task};
+use crate::host::LoxObject;
+use crate::inspect;
+use crate::interpreter::{Callable, Interpreter, LoxClass, LoxClassInstance, Mutability, Types};
+use crate::memory;
+use crate::permissions::Permission;
+use crate::sync::{Rc, RefCell, Shareable, Weak};
+use crate::trace::TraceValue;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::process::Command;
+use std::time::SystemTime;
+
+/// The Rust code a [`NativeFunction`] runs.
+#[cfg(not(feature = "send"))]
+pub type NativeFn = dyn Fn(&mut Interpreter, Vec<Types>) -> Result<Types, LoxError>;
+
+#[cfg(feature = "send")]
+pub type NativeFn = dyn Fn(&mut Interpreter, Vec<Types>) -> Result<Types, LoxError> + Send + Sync;
+
+/// A function implemented in Rust and exposed to Lox code, e.g. as a global
+/// or as the method of a host object.
+pub struct NativeFunction {
+    name: String,
+    airity: usize,
+    doc: String,
+    func: Box<NativeFn>,
+}
+
+impl NativeFunction {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(
+        name: &str,
+        airity: usize,
+        doc: &str,
+        func: impl Fn(&mut Interpreter, Vec<Types>) -> Result<Types, LoxError> + Shareable + 'static,
+    ) -> Types {
+        Types::NativeFunc(Rc::new(NativeFunction {
+            name: String::from(name),
+            airity,
+            doc: String::from(doc),
+            func: Box::new(func),
+        }))
+    }
+
+    /// What the function does, as shown to Lox users. Empty if it has no docs.
+    pub fn doc(&self) -> &str {
+        &self.doc
+    }
+}
+
+impl Callable for Rc<NativeFunction> {
+    fn airity(&self) -> usize {
+        self.airity
+    }
+
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Types>,
+    ) -> Result<Types, LoxError> {
+        (self.func)(interpreter, arguments)
+    }
+
+    fn to_string(&self) -> String {
+        format!("<native fn {}>", self.name)
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+}
+
+pub(crate) type NativeBody = fn(&mut Interpreter, Vec<Types>) -> Result<Types, LoxError>;
+
+pub fn define_globals(env: &mut Environment) {
+    let natives: &[(&str, usize, NativeBody, &str)] = &[
+        ("clock", 0, clock, "clock() returns the number of seconds since the Unix epoch."),
+        ("random", 0, random, "random() returns a number from 0 up to but not including 1."),
+        ("len", 1, len, "len(value) returns the length of a string or list."),
+        ("exec", 2, exec, "exec(cmd, args) runs cmd with the list of args and returns a ProcessResult with stdout, stderr and code fields."),
+        ("assert", 2, assert, "assert(condition, message) raises an error with message when condition is falsey."),
+        ("assertEq", 2, assert_eq, "assertEq(actual, expected) raises an error showing both values and where they differ unless they're deeply equal."),
+        ("fields", 1, fields, "fields(instance) lists the names of the fields set on instance."),
+        ("hasField", 2, has_field, "hasField(instance, name) is true if instance has a field called name."),
+        ("removeField", 2, remove_field, "removeField(instance, name) deletes the field name from instance and returns its value."),
+        ("freeze", 1, freeze, "freeze(instance) stops any field of instance from being added, removed or updated, and returns instance."),
+        ("seal", 1, seal, "seal(instance) stops fields from being added to or removed from instance, and returns instance."),
+        ("classOf", 1, class_of, "classOf(instance) returns the class instance was created from."),
+        ("className", 1, class_name, "className(class) returns the name class was declared with."),
+        ("superclassOf", 1, superclass_of, "superclassOf(class) returns the class class inherits from, or nil."),
+        ("methodsOf", 1, methods_of, "methodsOf(class) lists the names of the methods callable on instances of class."),
+        ("next", 1, next, "next(generator) runs generator to its next yield and returns the value yielded, or nil once it has finished."),
+        ("done", 1, done, "done(generator) is true once generator has finished."),
+        ("spawn", 1, spawn, "spawn(task) queues a function taking no arguments, or a generator, to run on the next runTasks()."),
+        ("runTasks", 0, run_tasks, "runTasks() runs spawned tasks until all of them have finished."),
+        ("inspect", 1, inspect, "inspect(value) renders value with its contents, e.g. the fields of an instance."),
+        ("deepEquals", 2, deep_equals, "deepEquals(a, b) is true if a and b are equal, comparing the fields of instances."),
+        ("sort", 1, sort, "sort(list) sorts a list of numbers or of strings in place, and returns it."),
+        ("sortBy", 2, sort_by, "sortBy(list, comparator) sorts list in place using comparator(a, b), and returns it."),
+        ("map", 2, map, "map(list, function) returns a new list of function(element) for each element of list."),
+        ("filter", 2, filter, "filter(list, predicate) returns a new list of the elements of list for which predicate(element) is truthy."),
+        ("reduce", 3, reduce, "reduce(list, function, initial) combines the elements of list into one value, calling function(accumulator, element) for each, starting from initial."),
+        ("forEach", 2, for_each, "forEach(list, function) calls function(element) for each element of list."),
+        ("any", 2, any, "any(list, predicate) is true if predicate(element) is truthy for some element of list."),
+        ("all", 2, all, "all(list, predicate) is true if predicate(element) is truthy for every element of list."),
+        ("toFixed", 2, to_fixed, "toFixed(n, digits) renders n with exactly digits digits after the decimal point."),
+        ("parseNumber", 1, parse_number, "parseNumber(string) returns the number string spells out, or nil if it isn't one."),
+        ("isNan", 1, is_nan, "isNan(n) is true if n is not a number, e.g. the result of 0 / 0."),
+        ("isFinite", 1, is_finite, "isFinite(n) is true unless n is infinite or not a number."),
+        ("StringBuilder", 0, string_builder, "StringBuilder() makes a builder with append(value) and build() methods."),
+        ("weakRef", 1, weak_ref, "weakRef(value) returns a WeakRef whose get() gives value, an instance, list or class, while anything else refers to it, and nil once nothing does."),
+        ("gcStats", 0, gc_stats, "gcStats() returns a GcStats with the collections, bytesLive, objectsLive and pauseTime of memory accounting."),
+        ("help", 1, help, "help(value) prints the signature and docs of a function or class."),
+        ("platform", 0, platform, "platform() returns the operating system, e.g. \"linux\", \"macos\" or \"windows\"."),
+        ("cwd", 0, cwd, "cwd() returns the directory relative paths are relative to."),
+        ("pid", 0, pid, "pid() returns the id of the interpreter's process."),
+        ("hostname", 0, hostname, "hostname() returns the name of the host, or nil if it can't be found."),
+    ];
+    for (name, airity, func, doc) in natives {
+        env.define(
+            String::from(*name),
+            NativeFunction::new(name, *airity, doc, *func),
+        );
+    }
+    crate::files::define_globals(env);
+    #[cfg(feature = "threads")]
+    crate::threads::define_globals(env);
+}
+
+fn instance_arg(line: usize, value: &Types) -> Result<Rc<RefCell<LoxClassInstance>>, LoxError> {
+    match value {
+        Types::ClassInstance(instance) => Ok(instance.clone()),
+        other => LoxError::new_runtime(line, format!("Expected an instance but found {other}")),
+    }
+}
+
+fn class_arg(line: usize, value: &Types) -> Result<Rc<LoxClass>, LoxError> {
+    match value {
+        Types::Class(class) => Ok(class.clone()),
+        other => LoxError::new_runtime(line, format!("Expected a class but found {other}")),
+    }
+}
+
+fn list_arg(line: usize, value: &Types) -> Result<Rc<RefCell<Vec<Types>>>, LoxError> {
+    match value {
+        Types::List(list) => Ok(list.clone()),
+        other => LoxError::new_runtime(line, format!("Expected a List but found {other}")),
+    }
+}
+
+fn number_arg(line: usize, value: &Types) -> Result<f64, LoxError> {
+    match value {
+        Types::Number(n) => Ok(*n),
+        other => LoxError::new_runtime(line, format!("Expected a Number but found {other}")),
+    }
+}
+
+fn generator_arg(line: usize, value: &Types) -> Result<Rc<RefCell<Generator>>, LoxError> {
+    match value {
+        Types::Generator(generator) => Ok(generator.clone()),
+        other => LoxError::new_runtime(line, format!("Expected a generator but found {other}")),
+    }
+}
+
+pub(crate) fn string_arg(line: usize, value: &Types) -> Result<Rc<String>, LoxError> {
+    match value {
+        Types::String(s) => Ok(s.clone()),
+        other => LoxError::new_runtime(line, format!("Expected a String but found {other}")),
+    }
+}
+
+/// `clock()` is the number of seconds since the Unix epoch.
+fn clock(interpreter: &mut Interpreter, _arguments: Vec<Types>) -> Result<Types, LoxError> {
+    if let Some(time) = interpreter.virtual_clock() {
+        return Ok(Types::Number(time));
+    }
+    let line = interpreter.line();
+    let values = interpreter.input("clock", || {
+        match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+            Ok(n) => Ok(vec![TraceValue::Number(n.as_millis() as f64 / 1000.0)]),
+            Err(_) => LoxError::new_runtime(line, String::from("SystemTime before UNIX EPOCH!")),
+        }
+    })?;
+    match values.as_slice() {
+        [TraceValue::Number(n)] => Ok(Types::Number(*n)),
+        _ => invalid_input(line, "clock"),
+    }
+}
+
+/// `random()` is a number from 0 up to but not including 1.
+fn random(interpreter: &mut Interpreter, _arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let line = interpreter.line();
+    let n = interpreter.next_random();
+    let values = interpreter.input("random", || Ok(vec![TraceValue::Number(n)]))?;
+    match values.as_slice() {
+        [TraceValue::Number(n)] if (0.0..1.0).contains(n) => Ok(Types::Number(*n)),
+        _ => invalid_input(line, "random"),
+    }
+}
+
+/// The error for a replayed input that the native couldn't have returned.
+pub(crate) fn invalid_input(line: usize, native: &str) -> Result<Types, LoxError> {
+    LoxError::new_runtime(
+        line,
+        format!("The trace's input for `{native}` is invalid."),
+    )
+}
+
+fn len(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    match &arguments[0] {
+        Types::String(s) => Ok(Types::Number(s.chars().count() as f64)),
+        Types::List(list) => Ok(Types::Number(list.borrow().len() as f64)),
+        other => LoxError::new_runtime(
+            interpreter.line(),
+            format!("Can't take the length of {other}."),
+        ),
+    }
+}
+
+/// `toFixed(n, digits)` rounds `n` to `digits` decimal places, which must be
+/// a whole number from 0 to 100.
+fn to_fixed(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let line = interpreter.line();
+    let n = number_arg(line, &arguments[0])?;
+    let digits = number_arg(line, &arguments[1])?;
+    if digits.fract() != 0.0 || !(0.0..=100.0).contains(&digits) {
+        return LoxError::new_runtime(
+            line,
+            format!("Expected digits to be a whole number from 0 to 100 but found {digits}"),
+        );
+    }
+    let rendered = format!("{n:.*}", digits as usize);
+    interpreter.allocate(memory::string_size(rendered.len()))?;
+    Ok(Types::String(Rc::from(rendered)))
+}
+
+/// `parseNumber(string)` accepts what Lox number literals look like, with an
+/// optional sign, exponent and surrounding whitespace.
+fn parse_number(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let s = string_arg(interpreter.line(), &arguments[0])?;
+    let s = s.trim();
+    // Rust also parses words like "inf" and "NaN", and numbers like ".5" and
+    // "5.", none of which the Lox scanner accepts.
+    let digits = s.strip_prefix(['-', '+']).unwrap_or(s);
+    let digit_first = |part: &str| part.starts_with(|c: char| c.is_ascii_digit());
+    if !digit_first(digits) || !digits.split('.').skip(1).all(digit_first) {
+        return Ok(Types::Nil);
+    }
+    match s.parse::<f64>() {
+        Ok(n) if n.is_finite() => Ok(Types::Number(n)),
+        _ => Ok(Types::Nil),
+    }
+}
+
+fn is_nan(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let n = number_arg(interpreter.line(), &arguments[0])?;
+    Ok(Types::Bool(n.is_nan()))
+}
+
+fn is_finite(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let n = number_arg(interpreter.line(), &arguments[0])?;
+    Ok(Types::Bool(n.is_finite()))
+}
+
+/// `fields(instance)` lists the names of the fields set on `instance`.
+fn fields(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let instance = instance_arg(interpreter.line(), &arguments[0])?;
+    let names = instance
+        .borrow()
+        .field_names()
+        .into_iter()
+        .map(|name| Types::String(Rc::from(name)))
+        .collect();
+    Ok(Types::List(Rc::new(RefCell::new(names))))
+}
+
+/// `hasField(instance, name)` is true if `instance` has a field called `name`.
+fn has_field(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let instance = instance_arg(interpreter.line(), &arguments[0])?;
+    let name = string_arg(interpreter.line(), &arguments[1])?;
+    let has_field = instance.borrow().has_field(&name);
+    Ok(Types::Bool(has_field))
+}
+
+/// `removeField(instance, name)` deletes the field `name` from `instance` and
+/// returns its value.
+fn remove_field(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let line = interpreter.line();
+    let instance = instance_arg(line, &arguments[0])?;
+    let name = string_arg(line, &arguments[1])?;
+    if instance.borrow().mutability() != Mutability::Open {
+        return LoxError::new_runtime(
+            line,
+            format!("Can't remove field `{name}` from a sealed or frozen instance."),
+        );
+    }
+    let removed = instance.borrow_mut().remove_field(&name);
+    match removed {
+        Some(value) => Ok(value),
+        None => LoxError::new_runtime(
+            line,
+            format!(
+                "Can't remove undefined field `{name}` from {}.",
+                arguments[0]
+            ),
+        ),
+    }
+}
+
+/// `freeze(instance)` stops any field of `instance` from being added, removed
+/// or updated, and returns `instance`.
+fn freeze(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let instance = instance_arg(interpreter.line(), &arguments[0])?;
+    instance.borrow_mut().restrict(Mutability::Frozen);
+    Ok(arguments[0].clone())
+}
+
+/// `seal(instance)` stops fields from being added to or removed from
+/// `instance` while still allowing updates, and returns `instance`.
+fn seal(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let instance = instance_arg(interpreter.line(), &arguments[0])?;
+    instance.borrow_mut().restrict(Mutability::Sealed);
+    Ok(arguments[0].clone())
+}
+
+/// `classOf(instance)` returns the class `instance` was created from.
+fn class_of(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let instance = instance_arg(interpreter.line(), &arguments[0])?;
+    let class = instance.borrow().class().clone();
+    Ok(Types::Class(class))
+}
+
+/// `className(class)` returns the name `class` was declared with.
+fn class_name(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let class = class_arg(interpreter.line(), &arguments[0])?;
+    Ok(Types::String(Rc::from(class.name())))
+}
+
+/// `superclassOf(class)` returns the class `class` inherits from, or nil.
+fn superclass_of(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let class = class_arg(interpreter.line(), &arguments[0])?;
+    match class.superclass() {
+        Some(superclass) => Ok(Types::Class(superclass.clone())),
+        None => Ok(Types::Nil),
+    }
+}
+
+/// `methodsOf(class)` lists the names of the methods callable on instances
+/// of `class`, including inherited ones.
+fn methods_of(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let class = class_arg(interpreter.line(), &arguments[0])?;
+    let names = class
+        .method_names()
+        .into_iter()
+        .map(|name| Types::String(Rc::from(name)))
+        .collect();
+    Ok(Types::List(Rc::new(RefCell::new(names))))
+}
+
+/// `next(generator)` runs `generator` to its next `yield` and returns the
+/// yielded value, or nil once it has finished.
+fn next(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let generator = generator_arg(interpreter.line(), &arguments[0])?;
+    Generator::resume(&generator, interpreter)
+}
+
+/// `done(generator)` is true once `generator` has finished, i.e. after a call
+/// to `next` found no more values.
+fn done(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let generator = generator_arg(interpreter.line(), &arguments[0])?;
+    let done = generator.borrow().is_done();
+    Ok(Types::Bool(done))
+}
+
+/// `spawn(task)` queues a function taking no arguments, or a generator, to run
+/// as a cooperative task. Tasks start when `runTasks` is called and hand over
+/// to the next task at each `yield`.
+fn spawn(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let line = interpreter.line();
+    let func = match &arguments[0] {
+        Types::Generator(generator) => {
+            interpreter.spawn(Task::Resume(generator.clone()));
+            return Ok(Types::Nil);
+        }
+        value => match value.as_callable() {
+            Some(func) => func,
+            None => {
+                return LoxError::new_runtime(
+                    line,
+                    format!("Expected a function or generator but found {value}"),
+                )
+            }
+        },
+    };
+    if func.airity() != 0 {
+        return LoxError::new_runtime(
+            line,
+            format!(
+                "Can only spawn functions that take no arguments but {} takes {}.",
+                arguments[0],
+                func.airity()
+            ),
+        );
+    }
+    let task = Task::Start(arguments[0].clone());
+    interpreter.spawn(task);
+    Ok(Types::Nil)
+}
+
+/// `runTasks()` runs spawned tasks until all of them have finished.
+fn run_tasks(interpreter: &mut Interpreter, _arguments: Vec<Types>) -> Result<Types, LoxError> {
+    interpreter.run_tasks()?;
+    Ok(Types::Nil)
+}
+
+/// `inspect(value)` renders `value` with its contents, e.g. the fields of an
+/// instance, see [`inspect::inspect`].
+fn inspect(_interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let rendered = inspect::inspect(&arguments[0], inspect::DEFAULT_DEPTH);
+    Ok(Types::String(Rc::from(rendered)))
+}
+
+/// `deepEquals(a, b)` is true if `a` and `b` are equal, comparing the contents
+/// of lists and the fields of instances of the same class.
+fn deep_equals(_interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    Ok(Types::Bool(arguments[0].deep_eq(&arguments[1])))
+}
+
+/// `sort(list)` sorts a list of numbers or of strings in place, and returns it.
+fn sort(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let line = interpreter.line();
+    let list = list_arg(line, &arguments[0])?;
+    let values = list.borrow().clone();
+    let sorted = merge_sort(values, &mut |a, b| match (a, b) {
+        (Types::Number(a), Types::Number(b)) => match a.partial_cmp(b) {
+            Some(ordering) => Ok(ordering),
+            None => LoxError::new_runtime(line, String::from("Can't sort a list containing NaN.")),
+        },
+        (Types::String(a), Types::String(b)) => Ok(a.cmp(b)),
+        _ => LoxError::new_runtime(line, format!("Can't compare {a} with {b}.")),
+    })?;
+    *list.borrow_mut() = sorted;
+    Ok(arguments[0].clone())
+}
+
+/// `sortBy(list, comparator)` sorts `list` in place, and returns it.
+/// `comparator(a, b)` returns a negative number if `a` goes before `b`, a
+/// positive one if it goes after, and 0 to keep their order.
+fn sort_by(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let line = interpreter.line();
+    let list = list_arg(line, &arguments[0])?;
+    let comparator = &arguments[1];
+    let values = list.borrow().clone();
+    let sorted = merge_sort(values, &mut |a, b| match interpreter
+        .call(comparator, vec![a.clone(), b.clone()])?
+    {
+        Types::Number(n) if n < 0.0 => Ok(Ordering::Less),
+        Types::Number(n) if n > 0.0 => Ok(Ordering::Greater),
+        Types::Number(_) => Ok(Ordering::Equal),
+        other => LoxError::new_runtime(
+            line,
+            format!("Expected the comparator to return a Number but it returned {other}"),
+        ),
+    })?;
+    *list.borrow_mut() = sorted;
+    Ok(arguments[0].clone())
+}
+
+/// The elements of the list `value`, copied so callbacks can change the list
+/// without affecting which elements they're called with.
+fn list_elements(line: usize, value: &Types) -> Result<Vec<Types>, LoxError> {
+    Ok(list_arg(line, value)?.borrow().clone())
+}
+
+fn map(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let elements = list_elements(interpreter.line(), &arguments[0])?;
+    let mapped = elements
+        .into_iter()
+        .map(|element| interpreter.call(&arguments[1], vec![element]))
+        .collect::<Result<Vec<_>, _>>()?;
+    interpreter.allocate(memory::list_size(mapped.len()))?;
+    Ok(Types::List(Rc::new(RefCell::new(mapped))))
+}
+
+fn filter(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let elements = list_elements(interpreter.line(), &arguments[0])?;
+    let mut kept = vec![];
+    for element in elements {
+        if interpreter
+            .call(&arguments[1], vec![element.clone()])?
+            .is_truty()
+        {
+            kept.push(element);
+        }
+    }
+    interpreter.allocate(memory::list_size(kept.len()))?;
+    Ok(Types::List(Rc::new(RefCell::new(kept))))
+}
+
+fn reduce(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let elements = list_elements(interpreter.line(), &arguments[0])?;
+    elements
+        .into_iter()
+        .try_fold(arguments[2].clone(), |accumulator, element| {
+            interpreter.call(&arguments[1], vec![accumulator, element])
+        })
+}
+
+fn for_each(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    for element in list_elements(interpreter.line(), &arguments[0])? {
+        interpreter.call(&arguments[1], vec![element])?;
+    }
+    Ok(Types::Nil)
+}
+
+/// `any(list, predicate)` stops at the first element `predicate` accepts.
+fn any(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    for element in list_elements(interpreter.line(), &arguments[0])? {
+        if interpreter.call(&arguments[1], vec![element])?.is_truty() {
+            return Ok(Types::Bool(true));
+        }
+    }
+    Ok(Types::Bool(false))
+}
+
+/// `all(list, predicate)` stops at the first element `predicate` rejects.
+fn all(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    for element in list_elements(interpreter.line(), &arguments[0])? {
+        if !interpreter.call(&arguments[1], vec![element])?.is_truty() {
+            return Ok(Types::Bool(false));
+        }
+    }
+    Ok(Types::Bool(true))
+}
+
+/// Sorts `values` stably, stopping at the first comparison that fails. Unlike
+/// `slice::sort_by` it copes with comparisons that aren't consistent, which a
+/// comparator written in Lox may well be.
+fn merge_sort(
+    mut values: Vec<Types>,
+    compare: &mut impl FnMut(&Types, &Types) -> Result<Ordering, LoxError>,
+) -> Result<Vec<Types>, LoxError> {
+    if values.len() <= 1 {
+        return Ok(values);
+    }
+    let right = values.split_off(values.len() / 2);
+    let mut left = merge_sort(values, compare)?.into_iter().peekable();
+    let mut right = merge_sort(right, compare)?.into_iter().peekable();
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        // Ties keep the element from the left half first, so the sort is stable.
+        let next = if compare(l, r)? == Ordering::Greater {
+            right.next()
+        } else {
+            left.next()
+        };
+        merged.extend(next);
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+/// `StringBuilder()` makes a builder for a string made of many pieces, which
+/// unlike `s = s + piece` doesn't copy what's been built so far for each piece.
+/// `builder.append(value)` adds `value`, converted to a string, to the end, and
+/// `builder.build()` returns the string built so far.
+fn string_builder(
+    _interpreter: &mut Interpreter,
+    _arguments: Vec<Types>,
+) -> Result<Types, LoxError> {
+    Ok(Types::host(Rc::new(RefCell::new(StringBuilder {
+        contents: String::new(),
+    }))))
+}
+
+struct StringBuilder {
+    contents: String,
+}
+
+impl LoxObject for StringBuilder {
+    fn class_name(&self) -> &str {
+        "StringBuilder"
+    }
+
+    /// `length` is the number of characters built so far.
+    fn get(&self, name: &str) -> Option<Types> {
+        match name {
+            "length" => Some(Types::Number(self.contents.chars().count() as f64)),
+            _ => None,
+        }
+    }
+
+    fn method_airity(&self, name: &str) -> Option<usize> {
+        match name {
+            "append" => Some(1),
+            "build" => Some(0),
+            _ => None,
+        }
+    }
+
+    fn call_method(
+        &mut self,
+        interpreter: &mut Interpreter,
+        name: &str,
+        arguments: Vec<Types>,
+    ) -> Result<Types, LoxError> {
+        if name == "build" {
+            interpreter.allocate(memory::string_size(self.contents.len()))?;
+            return Ok(Types::String(Rc::new(self.contents.clone())));
+        }
+        let len = self.contents.len();
+        match &arguments[0] {
+            Types::String(s) => self.contents.push_str(s),
+            other => self.contents.push_str(&other.to_string()),
+        }
+        interpreter.allocate(self.contents.len() - len)?;
+        Ok(Types::Nil)
+    }
+
+    fn size(&self) -> usize {
+        memory::string_size(self.contents.capacity())
+    }
+}
+
+/// `weakRef(value)` makes a reference to an instance, list or class that
+/// doesn't keep it alive, so e.g. a child can point back at its parent without
+/// the two keeping each other alive forever. `ref.get()` returns the value, or
+/// nil once nothing else refers to it.
+fn weak_ref(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let target = match &arguments[0] {
+        Types::ClassInstance(instance) => WeakTarget::Instance(Rc::downgrade(instance)),
+        Types::List(list) => WeakTarget::List(Rc::downgrade(list)),
+        Types::Class(class) => WeakTarget::Class(Rc::downgrade(class)),
+        other => {
+            return LoxError::new_runtime(
+                interpreter.line(),
+                format!("Expected an instance, list or class but found {other}"),
+            )
+        }
+    };
+    interpreter.allocate(WeakRef::SIZE)?;
+    Ok(Types::host(Rc::new(RefCell::new(WeakRef { target }))))
+}
+
+struct WeakRef {
+    target: WeakTarget,
+}
+
+enum WeakTarget {
+    Instance(Weak<RefCell<LoxClassInstance>>),
+    List(Weak<RefCell<Vec<Types>>>),
+    Class(Weak<LoxClass>),
+}
+
+impl WeakRef {
+    const SIZE: usize = 16;
+}
+
+impl LoxObject for WeakRef {
+    fn class_name(&self) -> &str {
+        "WeakRef"
+    }
+
+    fn method_airity(&self, name: &str) -> Option<usize> {
+        match name {
+            "get" => Some(0),
+            _ => None,
+        }
+    }
+
+    fn call_method(
+        &mut self,
+        _interpreter: &mut Interpreter,
+        _name: &str,
+        _arguments: Vec<Types>,
+    ) -> Result<Types, LoxError> {
+        let value = match &self.target {
+            WeakTarget::Instance(instance) => instance.upgrade().map(Types::ClassInstance),
+            WeakTarget::List(list) => list.upgrade().map(Types::List),
+            WeakTarget::Class(class) => class.upgrade().map(Types::Class),
+        };
+        Ok(value.unwrap_or(Types::Nil))
+    }
+
+    fn size(&self) -> usize {
+        WeakRef::SIZE
+    }
+}
+
+/// `help(value)` prints the signature of a function or class followed by its
+/// docs: the `///` comments before its declaration, or a native's description.
+fn help(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let (signature, doc) = match &arguments[0] {
+        Types::Callable(function) => (function.signature(), function.doc()),
+        Types::Class(class) => {
+            let signature = match class.superclass() {
+                Some(superclass) => format!("class {} < {}", class.name(), superclass.name()),
+                None => format!("class {}", class.name()),
+            };
+            (signature, class.doc())
+        }
+        Types::NativeFunc(function) => (function.to_string(), Some(function.doc())),
+        other => (other.to_string(), None),
+    };
+    interpreter.write_line(&signature)?;
+    match doc.filter(|doc| !doc.is_empty()) {
+        Some(doc) => {
+            for line in doc.lines() {
+                interpreter.write_line(&format!("  {line}"))?;
+            }
+        }
+        None => interpreter.write_line("  No documentation.")?,
+    }
+    Ok(Types::Nil)
+}
+
+/// `gcStats()` returns a `GcStats` instance with the `collections`,
+/// `bytesLive`, `objectsLive` and `pauseTime` (in seconds) of memory
+/// accounting, see [`Interpreter::gc_stats`].
+fn gc_stats(interpreter: &mut Interpreter, _arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let stats = interpreter.gc_stats();
+    let class = LoxClass::new(String::from("GcStats"), HashMap::new(), None, None);
+    let mut result = LoxClassInstance::new(Rc::new(class));
+    result.set_field("collections", Types::Number(stats.collections as f64));
+    result.set_field("bytesLive", Types::Number(stats.bytes_live as f64));
+    result.set_field("objectsLive", Types::Number(stats.objects_live as f64));
+    result.set_field("pauseTime", Types::Number(stats.pause.as_secs_f64()));
+    Ok(Types::ClassInstance(Rc::new(RefCell::new(result))))
+}
+
+/// `assert(condition, message)` raises a runtime error with `message` when
+/// `condition` is falsey.
+fn assert(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    if arguments[0].is_truty() {
+        Ok(Types::Nil)
+    } else {
+        LoxError::new_runtime(
+            interpreter.line(),
+            format!("Assertion failed: {}", arguments[1]),
+        )
+    }
+}
+
+/// `assertEq(actual, expected)` fails unless the values are equal as
+/// `deepEquals` compares them. The error shows both, as `inspect` renders
+/// them, and the path to the first element or field that differs.
+fn assert_eq(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let (actual, expected) = (&arguments[0], &arguments[1]);
+    let Some(path) = inspect::difference(actual, expected) else {
+        return Ok(Types::Nil);
+    };
+    let at = if path.is_empty() {
+        String::new()
+    } else {
+        format!(" at `{path}`")
+    };
+    // Nested lines of the rendered values line up under the first.
+    let render =
+        |value| inspect::inspect(value, inspect::DEFAULT_DEPTH).replace('\n', "\n            ");
+    LoxError::new_runtime(
+        interpreter.line(),
+        format!(
+            "Values differ{at}.\n  actual:   {}\n  expected: {}",
+            render(actual),
+            render(expected)
+        ),
+    )
+}
+
+/// `exec(cmd, args)` runs `cmd` with the list of `args` and returns a
+/// `ProcessResult` instance with `stdout`, `stderr` and `code` fields.
+fn exec(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let line = interpreter.line();
+    interpreter.require(Permission::Process)?;
+
+    let cmd = string_arg(line, &arguments[0])?;
+    let args = match &arguments[1] {
+        Types::List(list) => list.borrow().iter().map(|arg| arg.to_string()).collect(),
+        Types::Nil => vec![],
+        other => {
+            return LoxError::new_runtime(
+                line,
+                format!("Expected arguments to be a List but found {other}"),
+            )
+        }
+    };
+
+    let output = interpreter.input("exec", || match Command::new(&*cmd).args(args).output() {
+        Ok(output) => Ok(vec![
+            TraceValue::String(String::from_utf8_lossy(&output.stdout).into_owned()),
+            TraceValue::String(String::from_utf8_lossy(&output.stderr).into_owned()),
+            match output.status.code() {
+                Some(code) => TraceValue::Number(code as f64),
+                None => TraceValue::Nil,
+            },
+        ]),
+        Err(e) => LoxError::new_runtime(line, format!("Failed to run `{cmd}`: {e}")),
+    })?;
+    let (stdout, stderr, code) = match output.as_slice() {
+        [TraceValue::String(stdout), TraceValue::String(stderr), code] => (stdout, stderr, code),
+        _ => return invalid_input(line, "exec"),
+    };
+
+    let class = LoxClass::new(String::from("ProcessResult"), HashMap::new(), None, None);
+    let mut result = LoxClassInstance::new(Rc::new(class));
+    result.set_field("stdout", Types::String(Rc::new(stdout.clone())));
+    result.set_field("stderr", Types::String(Rc::new(stderr.clone())));
+    result.set_field(
+        "code",
+        match code {
+            TraceValue::Number(code) => Types::Number(*code),
+            _ => Types::Nil,
+        },
+    );
+    Ok(Types::ClassInstance(Rc::new(RefCell::new(result))))
+}
+
+/// `platform()` is the operating system the interpreter was built for, as
+/// Rust names it, e.g. `"linux"`, `"macos"` or `"windows"`.
+fn platform(interpreter: &mut Interpreter, _arguments: Vec<Types>) -> Result<Types, LoxError> {
+    string_input(interpreter, "platform", || {
+        Ok(Some(String::from(std::env::consts::OS)))
+    })
+}
+
+/// `cwd()` is the current working directory, which relative paths given to
+/// natives such as `open` are relative to.
+fn cwd(interpreter: &mut Interpreter, _arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let line = interpreter.line();
+    interpreter.require(Permission::Read)?;
+    string_input(interpreter, "cwd", || match std::env::current_dir() {
+        Ok(dir) => Ok(Some(dir.to_string_lossy().into_owned())),
+        Err(e) => LoxError::new_runtime(line, format!("Failed to get the working directory: {e}")),
+    })
+}
+
+/// `pid()` is the id of the interpreter's process.
+fn pid(interpreter: &mut Interpreter, _arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let line = interpreter.line();
+    let values = interpreter.input("pid", || {
+        Ok(vec![TraceValue::Number(std::process::id() as f64)])
+    })?;
+    match values.as_slice() {
+        [TraceValue::Number(n)] => Ok(Types::Number(*n)),
+        _ => invalid_input(line, "pid"),
+    }
+}
+
+/// `hostname()` is the name of the host, or nil if it can't be found. It's
+/// read from the environment on Windows and from the kernel elsewhere.
+fn hostname(interpreter: &mut Interpreter, _arguments: Vec<Types>) -> Result<Types, LoxError> {
+    interpreter.require(Permission::Env)?;
+    string_input(interpreter, "hostname", || {
+        let name = if cfg!(windows) {
+            std::env::var("COMPUTERNAME").ok()
+        } else {
+            ["/proc/sys/kernel/hostname", "/etc/hostname"]
+                .iter()
+                .find_map(|path| std::fs::read_to_string(path).ok())
+                .or_else(|| std::env::var("HOSTNAME").ok())
+        };
+        Ok(name
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty()))
+    })
+}
+
+/// Gets a string, or nil for `None`, for the native `native` through
+/// [`Interpreter::input`].
+fn string_input(
+    interpreter: &mut Interpreter,
+    native: &str,
+    read: impl FnOnce() -> Result<Option<String>, LoxError>,
+) -> Result<Types, LoxError> {
+    let line = interpreter.line();
+    let values = interpreter.input(native, || {
+        Ok(vec![read()?.map_or(TraceValue::Nil, TraceValue::String)])
+    })?;
+    match values.as_slice() {
+        [TraceValue::String(s)] => {
+            interpreter.allocate(memory::string_size(s.len()))?;
+            Ok(Types::String(Rc::new(s.clone())))
+        }
+        [TraceValue::Nil] => Ok(Types::Nil),
+        _ => invalid_input(line, native),
+    }
+}
@@ -0,0 +1,329 @@
+use crate::ast::{next_node_id, Expr, MatchArm, Pattern, Stmt};
+use crate::generator;
+use crate::interpreter::{Interpreter, Types};
+use crate::tokens::{Token, TokenType};
+
+/// Folds constant expressions and removes code that can never run. Must run
+/// after the resolver, so errors in removed code are still reported; it keeps
+/// the ids of every expression it doesn't remove, so resolutions stay valid.
+pub fn optimize(statements: Vec<Box<Stmt>>) -> Vec<Box<Stmt>> {
+    let mut optimizer = Optimizer {
+        interpreter: Interpreter::new(),
+    };
+    optimizer.stmts(statements)
+}
+
+struct Optimizer {
+    /// Evaluates constant expressions, so folding matches the interpreter
+    /// exactly. Expressions it fails on are left for the runtime to report.
+    interpreter: Interpreter,
+}
+
+fn literal(expr: &Expr) -> Option<&Token> {
+    match expr {
+        Expr::Literal { value, .. } => Some(value),
+        _ => None,
+    }
+}
+
+fn is_truthy(literal: &Token) -> bool {
+    !matches!(literal.tok_typ, TokenType::False | TokenType::Nil)
+}
+
+fn empty_block() -> Box<Stmt> {
+    Box::new(Stmt::Block {
+        id: next_node_id(),
+        stmts: vec![],
+    })
+}
+
+impl Optimizer {
+    fn stmts(&mut self, stmts: Vec<Box<Stmt>>) -> Vec<Box<Stmt>> {
+        stmts
+            .into_iter()
+            .map(|stmt| self.stmt(*stmt))
+            .filter(|stmt| !matches!(&**stmt, Stmt::Block { stmts, .. } if stmts.is_empty()))
+            .collect()
+    }
+
+    fn stmt(&mut self, stmt: Stmt) -> Box<Stmt> {
+        let stmt = match stmt {
+            Stmt::Expr { id, expr } => Stmt::Expr {
+                id,
+                expr: self.expr(*expr),
+            },
+            Stmt::Print { id, expr } => Stmt::Print {
+                id,
+                expr: self.expr(*expr),
+            },
+            Stmt::Var { id, name, ty, expr } => Stmt::Var {
+                id,
+                name,
+                ty,
+                expr: expr.map(|expr| self.expr(*expr)),
+            },
+            Stmt::Block { id, stmts } => Stmt::Block {
+                id,
+                stmts: self.stmts(stmts),
+            },
+            Stmt::If {
+                id,
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                let condition = self.expr(*condition);
+                let then_branch = self.stmt(*then_branch);
+                let else_branch = else_branch.map(|branch| self.stmt(*branch));
+                // Removing a `yield` would stop its function being a generator.
+                let dead_yield = match literal(&condition) {
+                    Some(value) if is_truthy(value) => else_branch
+                        .as_deref()
+                        .is_some_and(generator::contains_yield),
+                    Some(_) => generator::contains_yield(&then_branch),
+                    None => true,
+                };
+                match literal(&condition) {
+                    Some(value) if !dead_yield && is_truthy(value) => return then_branch,
+                    Some(_) if !dead_yield => return else_branch.unwrap_or_else(empty_block),
+                    _ => Stmt::If {
+                        id,
+                        condition,
+                        then_branch,
+                        else_branch,
+                    },
+                }
+            }
+            Stmt::While {
+                id,
+                condition,
+                body,
+            } => {
+                let condition = self.expr(*condition);
+                let body = self.stmt(*body);
+                match literal(&condition) {
+                    Some(value) if !is_truthy(value) && !generator::contains_yield(&body) => {
+                        return empty_block()
+                    }
+                    _ => Stmt::While {
+                        id,
+                        condition,
+                        body,
+                    },
+                }
+            }
+            Stmt::For {
+                id,
+                initializer,
+                condition,
+                increment,
+                body,
+            } => {
+                let initializer = initializer.map(|stmt| self.stmt(*stmt));
+                let condition = condition.map(|expr| self.expr(*expr));
+                let increment = increment.map(|expr| self.expr(*expr));
+                let body = self.stmt(*body);
+                match condition.as_deref().and_then(literal) {
+                    // The initializer still runs, in a scope of its own.
+                    Some(value) if !is_truthy(value) && !generator::contains_yield(&body) => {
+                        Stmt::Block {
+                            id,
+                            stmts: initializer.into_iter().collect(),
+                        }
+                    }
+                    _ => Stmt::For {
+                        id,
+                        initializer,
+                        condition,
+                        increment,
+                        body,
+                    },
+                }
+            }
+            Stmt::Function {
+                id,
+                name,
+                params,
+                param_types,
+                return_type,
+                body,
+                doc,
+            } => Stmt::Function {
+                id,
+                name,
+                params,
+                param_types,
+                return_type,
+                body: self.stmts(body),
+                doc,
+            },
+            Stmt::Return { id, keyword, value } => Stmt::Return {
+                id,
+                keyword,
+                value: value.map(|expr| self.expr(*expr)),
+            },
+            Stmt::Yield { id, keyword, value } => Stmt::Yield {
+                id,
+                keyword,
+                value: value.map(|expr| self.expr(*expr)),
+            },
+            Stmt::Class {
+                id,
+                name,
+                methods,
+                statics,
+                superclass,
+                doc,
+            } => Stmt::Class {
+                id,
+                name,
+                methods: self.stmts(methods),
+                statics: self.stmts(statics),
+                superclass,
+                doc,
+            },
+            Stmt::Test { id, name, body } => Stmt::Test {
+                id,
+                name,
+                body: self.stmts(body),
+            },
+        };
+        Box::new(stmt)
+    }
+
+    fn expr(&mut self, mut expr: Expr) -> Box<Expr> {
+        match &mut expr {
+            Expr::Binary { left, right, .. } => {
+                self.optimize(left);
+                self.optimize(right);
+                if literal(left).is_some() && literal(right).is_some() {
+                    return Box::new(self.fold(expr));
+                }
+            }
+            Expr::Unary { right, .. } => {
+                self.optimize(right);
+                if literal(right).is_some() {
+                    return Box::new(self.fold(expr));
+                }
+            }
+            Expr::Grouping { expr: inner, .. } => {
+                self.optimize(inner);
+                if literal(inner).is_some() {
+                    return Expr::take(inner);
+                }
+            }
+            Expr::Logical {
+                left,
+                operator,
+                right,
+                ..
+            } => {
+                self.optimize(left);
+                self.optimize(right);
+                let short_circuits = |value: &Token| match operator.tok_typ {
+                    TokenType::Or => is_truthy(value),
+                    _ => !is_truthy(value),
+                };
+                match literal(left).map(short_circuits) {
+                    Some(true) => return Expr::take(left),
+                    Some(false) => return Expr::take(right),
+                    None => {}
+                }
+            }
+            Expr::Assignment { value, .. } => self.optimize(value),
+            Expr::Call {
+                callee, arguments, ..
+            } => {
+                self.optimize(callee);
+                arguments
+                    .iter_mut()
+                    .for_each(|argument| self.optimize(argument));
+            }
+            Expr::Get { object, .. } => self.optimize(object),
+            Expr::Set { object, value, .. } => {
+                self.optimize(object);
+                self.optimize(value);
+            }
+            Expr::List { elements, .. } => {
+                elements
+                    .iter_mut()
+                    .for_each(|element| self.optimize(element));
+            }
+            Expr::Index { object, index, .. } => {
+                self.optimize(object);
+                self.optimize(index);
+            }
+            Expr::IndexSet {
+                object,
+                index,
+                value,
+                ..
+            } => {
+                self.optimize(object);
+                self.optimize(index);
+                self.optimize(value);
+            }
+            Expr::Match { subject, arms, .. } => {
+                self.optimize(subject);
+                *arms = std::mem::take(arms)
+                    .into_iter()
+                    .map(|arm| MatchArm {
+                        pattern: self.pattern(arm.pattern),
+                        body: self.expr(*arm.body),
+                    })
+                    .collect();
+            }
+            Expr::Literal { .. }
+            | Expr::Variable { .. }
+            | Expr::This { .. }
+            | Expr::Super { .. } => {}
+        }
+        Box::new(expr)
+    }
+
+    /// Optimizes a child expression where it is.
+    fn optimize(&mut self, expr: &mut Box<Expr>) {
+        let optimized = self.expr(*Expr::take(expr));
+        *expr = optimized;
+    }
+
+    fn pattern(&mut self, pattern: Pattern) -> Pattern {
+        match pattern {
+            Pattern::Class {
+                class,
+                paren,
+                fields,
+            } => Pattern::Class {
+                class,
+                paren,
+                fields: fields
+                    .into_iter()
+                    .map(|field| self.pattern(field))
+                    .collect(),
+            },
+            pattern => pattern,
+        }
+    }
+
+    /// Replaces an expression whose operands are all literals with its value.
+    fn fold(&mut self, expr: Expr) -> Expr {
+        let (id, line) = match &expr {
+            Expr::Binary { id, operator, .. } | Expr::Unary { id, operator, .. } => {
+                (*id, operator.line)
+            }
+            _ => (next_node_id(), 0),
+        };
+        let (tok_typ, lexeme) = match self.interpreter.evaulate(&expr) {
+            Ok(Types::Number(n)) => (TokenType::Number(n), n.to_string()),
+            Ok(Types::String(s)) => (TokenType::Str(s.to_string()), format!("\"{s}\"")),
+            Ok(Types::Bool(true)) => (TokenType::True, String::from("true")),
+            Ok(Types::Bool(false)) => (TokenType::False, String::from("false")),
+            Ok(Types::Nil) => (TokenType::Nil, String::from("nil")),
+            _ => return expr,
+        };
+        Expr::Literal {
+            id,
+            value: Token::new(tok_typ, lexeme, line),
+        }
+    }
+}
@@ -1,15 +1,72 @@
-use crate::ast::{Expr, Stmt};
-use crate::error::LoxError;
+use crate::ast::{next_node_id, Expr, MatchArm, NodeId, Pattern, Spans, Stmt};
+use crate::diagnostics::{Span, Suggestion};
+use crate::error::{LoxError, LoxErrorContainer};
 use crate::tokens::{Token, TokenType};
+use std::collections::HashMap;
+
+/// How many errors the parser reports by default before giving up.
+pub const DEFAULT_MAX_ERRORS: usize = 20;
+
+/// How deeply statements and expressions may nest by default: deeper code
+/// could overflow the parser's stack, or that of the passes that walk the AST
+/// after it, on a main thread's 8 MiB stack even in a debug build.
+pub const DEFAULT_MAX_DEPTH: usize = 128;
 
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
+    max_errors: usize,
+    max_depth: usize,
+    /// How many nested statements and expressions enclose the current token.
+    depth: usize,
+    /// The text of doc comments, by the index of the token following them.
+    docs: HashMap<usize, String>,
+    spans: Spans,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Parser { tokens, current: 0 }
+        let mut docs = HashMap::new();
+        let mut doc: Vec<String> = vec![];
+        let mut kept = Vec::with_capacity(tokens.len());
+        for token in tokens {
+            match token.tok_typ {
+                TokenType::DocComment(line) => doc.push(line),
+                _ => {
+                    if !doc.is_empty() {
+                        docs.insert(kept.len(), doc.join("\n"));
+                        doc.clear();
+                    }
+                    kept.push(token);
+                }
+            }
+        }
+        Parser {
+            tokens: kept,
+            current: 0,
+            max_errors: DEFAULT_MAX_ERRORS,
+            max_depth: DEFAULT_MAX_DEPTH,
+            depth: 0,
+            docs,
+            spans: Spans::new(),
+        }
+    }
+
+    /// Where each node parsed so far starts: at its keyword, name or operator.
+    pub fn spans(&self) -> &Spans {
+        &self.spans
+    }
+
+    /// Stops parsing once `max` errors have been found, since later ones are
+    /// often caused by earlier ones. `0` means no limit.
+    pub fn max_errors(&mut self, max: usize) {
+        self.max_errors = max;
+    }
+
+    /// Reports code nested more than `max` levels deep, e.g. a
+    /// parenthesized expression inside `max` others, as an error.
+    pub fn max_depth(&mut self, max: usize) {
+        self.max_depth = max;
     }
 
     pub fn parse(&mut self) -> Result<Vec<Box<Stmt>>, LoxError> {
@@ -19,6 +76,15 @@ impl Parser {
             match self.declaration() {
                 Err(LoxError::ParserErrors(mut e)) => {
                     errors.append(&mut e);
+                    if self.max_errors > 0 && errors.len() >= self.max_errors {
+                        errors.truncate(self.max_errors);
+                        let line = errors[errors.len() - 1].line();
+                        errors.push(LoxErrorContainer::new(
+                            line,
+                            format!("Too many errors, stopping after {}.", self.max_errors),
+                        ));
+                        break;
+                    }
                     self.syncronize();
                 }
                 Err(e) => {
@@ -28,7 +94,7 @@ impl Parser {
             }
         }
 
-        if errors.len() > 0 {
+        if !errors.is_empty() {
             Err(LoxError::ParserErrors(errors))
         } else {
             Ok(stmts)
@@ -36,23 +102,44 @@ impl Parser {
     }
 
     fn declaration(&mut self) -> Result<Box<Stmt>, LoxError> {
+        let doc = self.docs.remove(&self.current);
         if self.matches(vec![TokenType::Var]) {
             return self.var_declaration();
         }
         if self.matches(vec![TokenType::Fun]) {
-            return self.function("function");
+            return self.function("function", doc);
         }
         if self.matches(vec![TokenType::Class]) {
-            return self.class_declaration();
+            return self.class_declaration(doc);
+        }
+        if self.peek().lexeme == "test" && self.peek_next().tok_typ == TokenType::Str(String::new())
+        {
+            self.advance();
+            return self.test_declaration();
         }
         self.statement()
     }
 
-    fn function(&mut self, kind: &str) -> Result<Box<Stmt>, LoxError> {
+    fn test_declaration(&mut self) -> Result<Box<Stmt>, LoxError> {
         let name = self.consume(
-            TokenType::Identifier(String::new()),
-            format!("Expected {kind} name."),
+            TokenType::Str(String::new()),
+            String::from("Expected test name."),
         )?;
+        self.consume(
+            TokenType::LeftBrace,
+            String::from("Expected `{` before test body."),
+        )?;
+        let body = self.block()?;
+
+        Ok(Box::new(Stmt::Test {
+            id: self.node_id(&name),
+            name,
+            body,
+        }))
+    }
+
+    fn function(&mut self, kind: &str, doc: Option<String>) -> Result<Box<Stmt>, LoxError> {
+        let name = self.consume(TokenType::Identifier, format!("Expected {kind} name."))?;
 
         self.consume(
             TokenType::LeftParen,
@@ -60,6 +147,7 @@ impl Parser {
         )?;
 
         let mut params = vec![];
+        let mut param_types = vec![];
         if !self.check(TokenType::RightParen) {
             while {
                 if params.len() >= 255 {
@@ -70,9 +158,10 @@ impl Parser {
                 }
 
                 params.push(self.consume(
-                    TokenType::Identifier(String::new()),
+                    TokenType::Identifier,
                     format!("Expected parameter name. Found {}", self.peek()),
                 )?);
+                param_types.push(self.type_annotation()?);
                 self.matches(vec![TokenType::Comma])
             } {}
         }
@@ -81,6 +170,7 @@ impl Parser {
             TokenType::RightParen,
             String::from("Expected `)` after parameters."),
         )?;
+        let return_type = self.type_annotation()?;
 
         self.consume(
             TokenType::LeftBrace,
@@ -89,23 +179,30 @@ impl Parser {
 
         let body = self.block()?;
 
-        Ok(Box::new(Stmt::Function { name, params, body }))
+        Ok(Box::new(Stmt::Function {
+            id: self.node_id(&name),
+            name,
+            params,
+            param_types,
+            return_type,
+            body,
+            doc,
+        }))
     }
 
-    fn class_declaration(&mut self) -> Result<Box<Stmt>, LoxError> {
-        let name = self.consume(
-            TokenType::Identifier(String::new()),
-            String::from("Expected class name."),
-        )?;
+    fn class_declaration(&mut self, doc: Option<String>) -> Result<Box<Stmt>, LoxError> {
+        let name = self.consume(TokenType::Identifier, String::from("Expected class name."))?;
 
         let mut superclass = None;
         if self.matches(vec![TokenType::Less]) {
             self.consume(
-                TokenType::Identifier(String::new()),
+                TokenType::Identifier,
                 String::from("Expected superclass name"),
             )?;
+            let name = self.previous();
             superclass = Some(Box::new(Expr::Variable {
-                name: self.previous(),
+                id: self.node_id(&name),
+                name,
             }));
         }
 
@@ -115,8 +212,18 @@ impl Parser {
         )?;
 
         let mut methods = vec![];
+        let mut statics = vec![];
         while !self.check(TokenType::RightBrace) && !self.is_at_end() {
-            methods.push(self.function("method")?);
+            if self.matches(vec![TokenType::Class]) {
+                self.consume(
+                    TokenType::Var,
+                    String::from("Expected `var` after `class` in class body."),
+                )?;
+                statics.push(self.var_declaration()?);
+            } else {
+                let doc = self.docs.remove(&self.current);
+                methods.push(self.function("method", doc)?);
+            }
         }
         self.consume(
             TokenType::RightBrace,
@@ -124,37 +231,68 @@ impl Parser {
         )?;
 
         Ok(Box::new(Stmt::Class {
+            id: self.node_id(&name),
             name,
             methods,
+            statics,
             superclass,
+            doc,
         }))
     }
 
     fn var_declaration(&mut self) -> Result<Box<Stmt>, LoxError> {
         let name = self.consume(
-            TokenType::Identifier(String::new()),
+            TokenType::Identifier,
             String::from("Expected variable name"),
         )?;
+        let ty = self.type_annotation()?;
 
         let mut expr = None;
         if self.matches(vec![TokenType::Equal]) {
             expr = Some(self.expression()?);
         }
 
+        self.consume_semicolon(String::from("Expected `;` after variable declaration"))?;
+        Ok(Box::new(Stmt::Var {
+            id: self.node_id(&name),
+            name,
+            ty,
+            expr,
+        }))
+    }
+
+    /// Parses the `: Type` that can follow a variable, parameter or parameter
+    /// list, if there is one.
+    fn type_annotation(&mut self) -> Result<Option<Token>, LoxError> {
+        if !self.matches(vec![TokenType::Colon]) {
+            return Ok(None);
+        }
         self.consume(
-            TokenType::Semicolon,
-            String::from("Expected `;` after variable declaration"),
-        )?;
-        Ok(Box::new(Stmt::Var { name, expr }))
+            TokenType::Identifier,
+            String::from("Expected type name after `:`."),
+        )
+        .map(Some)
     }
 
     fn statement(&mut self) -> Result<Box<Stmt>, LoxError> {
+        self.nested("Statement", Self::nested_statement)
+    }
+
+    fn nested_statement(&mut self) -> Result<Box<Stmt>, LoxError> {
+        if self.is_at_end() {
+            return self.unexpected_eof();
+        }
         match self.advance().tok_typ {
             TokenType::Print => self.print_statement(),
             TokenType::Return => self.return_statement(),
-            TokenType::LeftBrace => Ok(Box::new(Stmt::Block {
-                stmts: self.block()?,
-            })),
+            TokenType::Yield => self.yield_statement(),
+            TokenType::LeftBrace => {
+                let id = self.node_id(&self.previous());
+                Ok(Box::new(Stmt::Block {
+                    id,
+                    stmts: self.block()?,
+                }))
+            }
             TokenType::If => self.if_statement(),
             TokenType::While => self.while_statement(),
             TokenType::For => self.for_statement(),
@@ -172,19 +310,40 @@ impl Parser {
             value = Some(self.expression()?);
         }
 
-        self.consume(
-            TokenType::Semicolon,
-            String::from("Expect `;` after return value."),
-        )?;
+        self.consume_semicolon(String::from("Expect `;` after return value."))?;
 
-        Ok(Box::new(Stmt::Return { keyword, value }))
+        Ok(Box::new(Stmt::Return {
+            id: self.node_id(&keyword),
+            keyword,
+            value,
+        }))
+    }
+
+    fn yield_statement(&mut self) -> Result<Box<Stmt>, LoxError> {
+        let keyword = self.previous();
+        let mut value = None;
+        if !self.check(TokenType::Semicolon) {
+            value = Some(self.expression()?);
+        }
+
+        self.consume_semicolon(String::from("Expect `;` after yield value."))?;
+
+        Ok(Box::new(Stmt::Yield {
+            id: self.node_id(&keyword),
+            keyword,
+            value,
+        }))
     }
 
     fn for_statement(&mut self) -> Result<Box<Stmt>, LoxError> {
+        let keyword = self.previous();
         self.consume(
             TokenType::LeftParen,
             String::from("Expected `(` after `for`."),
         )?;
+        if self.is_at_end() {
+            return self.unexpected_eof();
+        }
         let initializer = match self.advance().tok_typ {
             TokenType::Semicolon => None,
             TokenType::Var => Some(self.var_declaration()?),
@@ -200,12 +359,9 @@ impl Parser {
             Some(self.expression()?)
         };
 
-        self.consume(
-            TokenType::Semicolon,
-            String::from("Expect `;` after loop condition."),
-        )?;
+        self.consume_semicolon(String::from("Expect `;` after loop condition."))?;
 
-        let increment = if self.check(TokenType::Semicolon) {
+        let increment = if self.check(TokenType::RightParen) {
             None
         } else {
             Some(self.expression()?)
@@ -216,28 +372,19 @@ impl Parser {
             String::from("Expect `)` after for clauses."),
         )?;
 
-        let mut body = self.statement()?;
-
-        if let Some(increment) = increment {
-            body = Box::new(Stmt::Block {
-                stmts: vec![body, Box::new(Stmt::Expr { expr: increment })],
-            });
-        }
-
-        if let Some(condition) = condition {
-            body = Box::new(Stmt::While { condition, body });
-        }
-
-        if let Some(initializer) = initializer {
-            body = Box::new(Stmt::Block {
-                stmts: vec![initializer, body],
-            });
-        }
+        let body = self.statement()?;
 
-        Ok(body)
+        Ok(Box::new(Stmt::For {
+            id: self.node_id(&keyword),
+            initializer,
+            condition,
+            increment,
+            body,
+        }))
     }
 
     fn while_statement(&mut self) -> Result<Box<Stmt>, LoxError> {
+        let keyword = self.previous();
         self.consume(
             TokenType::LeftParen,
             String::from("Expected `(` after `while`."),
@@ -249,10 +396,15 @@ impl Parser {
         )?;
 
         let body = self.statement()?;
-        Ok(Box::new(Stmt::While { condition, body }))
+        Ok(Box::new(Stmt::While {
+            id: self.node_id(&keyword),
+            condition,
+            body,
+        }))
     }
 
     fn if_statement(&mut self) -> Result<Box<Stmt>, LoxError> {
+        let keyword = self.previous();
         self.consume(
             TokenType::LeftParen,
             String::from("Expected `(` after `if`."),
@@ -270,6 +422,7 @@ impl Parser {
         }
 
         Ok(Box::new(Stmt::If {
+            id: self.node_id(&keyword),
             condition,
             then_branch,
             else_branch,
@@ -277,12 +430,13 @@ impl Parser {
     }
 
     fn print_statement(&mut self) -> Result<Box<Stmt>, LoxError> {
+        let keyword = self.previous();
         let expr = self.expression()?;
-        self.consume(
-            TokenType::Semicolon,
-            String::from("Expected `;` after value."),
-        )?;
-        Ok(Box::new(Stmt::Print { expr }))
+        self.consume_semicolon(String::from("Expected `;` after value."))?;
+        Ok(Box::new(Stmt::Print {
+            id: self.node_id(&keyword),
+            expr,
+        }))
     }
 
     fn block(&mut self) -> Result<Vec<Box<Stmt>>, LoxError> {
@@ -299,48 +453,65 @@ impl Parser {
     }
 
     fn expression_statement(&mut self) -> Result<Box<Stmt>, LoxError> {
+        let start = self.peek().clone();
         let expr = self.expression()?;
-        self.consume(
-            TokenType::Semicolon,
-            String::from("Expected `;` after expression."),
-        )?;
-        Ok(Box::new(Stmt::Expr { expr }))
+        self.consume_semicolon(String::from("Expected `;` after expression."))?;
+        Ok(Box::new(Stmt::Expr {
+            id: self.node_id(&start),
+            expr,
+        }))
     }
 
     fn expression(&mut self) -> Result<Box<Expr>, LoxError> {
-        self.assignment()
+        self.nested("Expression", Self::assignment)
     }
 
     fn assignment(&mut self) -> Result<Box<Expr>, LoxError> {
-        let expr = self.or()?;
+        let mut expr = self.or()?;
         if self.matches(vec![TokenType::Equal]) {
             let equals = self.previous();
-            let assignment = self.assignment()?;
+            let assignment = self.nested("Expression", Self::assignment)?;
 
-            match *expr {
+            match &mut *expr {
                 Expr::Variable { name, .. } => {
                     return Ok(Box::new(Expr::Assignment {
+                        id: self.node_id(name),
                         name: name.clone(),
                         value: assignment,
                     }));
                 }
-                Expr::Get { object, name } => {
+                Expr::Get { object, name, .. } => {
                     return Ok(Box::new(Expr::Set {
+                        id: self.node_id(name),
                         name: name.clone(),
                         value: assignment,
-                        object,
+                        object: Expr::take(object),
+                    }))
+                }
+                Expr::Index {
+                    object,
+                    bracket,
+                    index,
+                    ..
+                } => {
+                    return Ok(Box::new(Expr::IndexSet {
+                        id: self.node_id(bracket),
+                        object: Expr::take(object),
+                        bracket: bracket.clone(),
+                        index: Expr::take(index),
+                        value: assignment,
                     }))
                 }
                 _ => {
                     return LoxError::new_parser(
                         equals.line,
-                        format!("Invalid assignment target: {}", expr.to_string()),
+                        format!("Invalid assignment target: {}", expr),
                     )
                 }
             }
         }
 
-        return Ok(expr);
+        Ok(expr)
     }
 
     fn or(&mut self) -> Result<Box<Expr>, LoxError> {
@@ -350,6 +521,7 @@ impl Parser {
             let operator = self.previous();
             let right = self.and()?;
             expr = Box::new(Expr::Logical {
+                id: self.node_id(&operator),
                 left: expr,
                 operator,
                 right,
@@ -367,6 +539,7 @@ impl Parser {
             let right = self.equality()?;
 
             expr = Box::new(Expr::Logical {
+                id: self.node_id(&operator),
                 left: expr,
                 operator,
                 right,
@@ -382,6 +555,7 @@ impl Parser {
             let operator = self.previous();
             let right = self.comparison()?;
             expr = Box::new(Expr::Binary {
+                id: self.node_id(&operator),
                 left: expr,
                 operator,
                 right,
@@ -403,6 +577,7 @@ impl Parser {
             let operator = self.previous();
             let right = self.term()?;
             expr = Box::new(Expr::Binary {
+                id: self.node_id(&operator),
                 left: expr,
                 operator,
                 right,
@@ -419,6 +594,7 @@ impl Parser {
             let operator = self.previous();
             let right = self.factor()?;
             expr = Box::new(Expr::Binary {
+                id: self.node_id(&operator),
                 left: expr,
                 operator,
                 right,
@@ -435,6 +611,7 @@ impl Parser {
             let operator = self.previous();
             let right = self.unary()?;
             expr = Box::new(Expr::Binary {
+                id: self.node_id(&operator),
                 left: expr,
                 operator,
                 right,
@@ -447,8 +624,12 @@ impl Parser {
     fn unary(&mut self) -> Result<Box<Expr>, LoxError> {
         if self.matches(vec![TokenType::Bang, TokenType::Minus]) {
             let operator = self.previous();
-            let right = self.unary()?;
-            Ok(Box::new(Expr::Unary { operator, right }))
+            let right = self.nested("Expression", Self::unary)?;
+            Ok(Box::new(Expr::Unary {
+                id: self.node_id(&operator),
+                operator,
+                right,
+            }))
         } else {
             self.call()
         }
@@ -461,10 +642,26 @@ impl Parser {
                 expr = self.finish_call(expr)?;
             } else if self.matches(vec![TokenType::Dot]) {
                 let name = self.consume(
-                    TokenType::Identifier(String::new()),
+                    TokenType::Identifier,
                     String::from("Expected property name after `.`."),
                 )?;
-                expr = Box::new(Expr::Get { object: expr, name });
+                expr = Box::new(Expr::Get {
+                    id: self.node_id(&name),
+                    object: expr,
+                    name,
+                });
+            } else if self.matches(vec![TokenType::LeftBracket]) {
+                let index = self.expression()?;
+                let bracket = self.consume(
+                    TokenType::RightBracket,
+                    String::from("Expected `]` after index."),
+                )?;
+                expr = Box::new(Expr::Index {
+                    id: self.node_id(&bracket),
+                    object: expr,
+                    bracket,
+                    index,
+                });
             } else {
                 break;
             }
@@ -494,6 +691,7 @@ impl Parser {
         )?;
 
         Ok(Box::new(Expr::Call {
+            id: self.node_id(&paren),
             callee,
             paren,
             arguments,
@@ -501,36 +699,189 @@ impl Parser {
     }
 
     fn primary(&mut self) -> Result<Box<Expr>, LoxError> {
+        if self.is_at_end() {
+            return self.unexpected_eof();
+        }
         let tok = self.advance();
         match &tok.tok_typ {
             TokenType::False
             | TokenType::True
             | TokenType::Nil
             | TokenType::Number(_)
-            | TokenType::Str(_) => Ok(Box::new(Expr::Literal { value: tok })),
+            | TokenType::Str(_) => Ok(Box::new(Expr::Literal {
+                id: self.node_id(&tok),
+                value: tok,
+            })),
             TokenType::LeftParen => {
                 let expr = self.expression()?;
                 self.consume(
                     TokenType::RightParen,
                     String::from("Expected `)` after expression"),
                 )?;
-                Ok(Box::new(Expr::Grouping { expr }))
+                Ok(Box::new(Expr::Grouping {
+                    id: self.node_id(&tok),
+                    expr,
+                }))
+            }
+            TokenType::This => Ok(Box::new(Expr::This {
+                id: self.node_id(&tok),
+                keyword: tok,
+            })),
+            TokenType::LeftBracket => {
+                let mut elements = vec![];
+                if !self.check(TokenType::RightBracket) {
+                    while {
+                        elements.push(self.expression()?);
+                        self.matches(vec![TokenType::Comma])
+                    } {}
+                }
+                let bracket = self.consume(
+                    TokenType::RightBracket,
+                    String::from("Expected `]` after list elements."),
+                )?;
+                Ok(Box::new(Expr::List {
+                    id: self.node_id(&tok),
+                    bracket,
+                    elements,
+                }))
             }
-            TokenType::This => Ok(Box::new(Expr::This { keyword: tok })),
             TokenType::Super => {
                 let keyword = self.previous();
                 self.consume(TokenType::Dot, String::from("Expect `.` after `super`."))?;
                 let method = self.consume(
-                    TokenType::Identifier(String::new()),
+                    TokenType::Identifier,
                     String::from("Expected superclass method name"),
                 )?;
-                Ok(Box::new(Expr::Super { keyword, method }))
+                Ok(Box::new(Expr::Super {
+                    id: self.node_id(&keyword),
+                    keyword,
+                    method,
+                }))
             }
-            TokenType::Identifier(_) => Ok(Box::new(Expr::Variable { name: tok })),
+            TokenType::Identifier => Ok(Box::new(Expr::Variable {
+                id: self.node_id(&tok),
+                name: tok,
+            })),
+            TokenType::Match => self.match_expression(tok),
             _ => LoxError::new_parser(tok.line, format!("Unexpected Token: {}", tok)),
         }
     }
 
+    fn match_expression(&mut self, keyword: Token) -> Result<Box<Expr>, LoxError> {
+        let subject = self.expression()?;
+        self.consume(
+            TokenType::LeftBrace,
+            String::from("Expected `{` after match value."),
+        )?;
+
+        let mut arms = vec![];
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            let pattern = self.pattern()?;
+            self.consume(
+                TokenType::FatArrow,
+                String::from("Expected `=>` after pattern."),
+            )?;
+            let body = self.expression()?;
+            arms.push(MatchArm { pattern, body });
+            if !self.matches(vec![TokenType::Comma]) {
+                break;
+            }
+        }
+
+        self.consume(
+            TokenType::RightBrace,
+            String::from("Expected `}` after match arms."),
+        )?;
+
+        Ok(Box::new(Expr::Match {
+            id: self.node_id(&keyword),
+            keyword,
+            subject,
+            arms,
+        }))
+    }
+
+    fn pattern(&mut self) -> Result<Pattern, LoxError> {
+        if self.is_at_end() {
+            return self.unexpected_eof();
+        }
+        let tok = self.advance();
+        match &tok.tok_typ {
+            TokenType::False
+            | TokenType::True
+            | TokenType::Nil
+            | TokenType::Number(_)
+            | TokenType::Str(_) => Ok(Pattern::Literal { value: tok }),
+            TokenType::Minus => {
+                let number = self.consume(
+                    TokenType::Number(0.0),
+                    String::from("Expected a number after `-` in pattern."),
+                )?;
+                let TokenType::Number(n) = number.tok_typ else {
+                    unreachable!()
+                };
+                Ok(Pattern::Literal {
+                    value: Token::new(
+                        TokenType::Number(-n),
+                        format!("-{}", number.lexeme),
+                        tok.line,
+                    ),
+                })
+            }
+            TokenType::Identifier if tok.lexeme == "_" => Ok(Pattern::Wildcard),
+            TokenType::Identifier if self.check(TokenType::LeftParen) => {
+                self.advance();
+                let mut fields = vec![];
+                if !self.check(TokenType::RightParen) {
+                    while {
+                        fields.push(self.nested("Pattern", Self::pattern)?);
+                        self.matches(vec![TokenType::Comma])
+                    } {}
+                }
+                let paren = self.consume(
+                    TokenType::RightParen,
+                    String::from("Expected `)` after field patterns."),
+                )?;
+                Ok(Pattern::Class {
+                    class: Box::new(Expr::Variable {
+                        id: self.node_id(&tok),
+                        name: tok,
+                    }),
+                    paren,
+                    fields,
+                })
+            }
+            TokenType::Identifier => Ok(Pattern::Binding { name: tok }),
+            _ => LoxError::new_parser(tok.line, format!("Expected a pattern but found {tok}.")),
+        }
+    }
+
+    /// Runs `parse` one level deeper, failing if that's deeper than allowed.
+    fn nested<T>(
+        &mut self,
+        what: &str,
+        parse: impl FnOnce(&mut Self) -> Result<T, LoxError>,
+    ) -> Result<T, LoxError> {
+        if self.depth >= self.max_depth {
+            return LoxError::new_parser(self.peek().line, format!("{what} too deeply nested."));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// A new node id, recording that the node starts at `token`.
+    fn node_id(&mut self, token: &Token) -> NodeId {
+        let id = next_node_id();
+        let span = Span {
+            line: token.line,
+            column: (token.column > 0).then_some(token.column),
+        };
+        self.spans.insert(id, span);
+        id
+    }
+
     fn matches(&mut self, types: Vec<TokenType>) -> bool {
         for typ in types {
             if self.check(typ) {
@@ -563,11 +914,33 @@ impl Parser {
     fn consume(&mut self, typ: TokenType, message: String) -> Result<Token, LoxError> {
         if self.check(typ) {
             Ok(self.advance())
+        } else if self.is_at_end() {
+            LoxError::new_parser_at_eof(self.previous().line, message)
         } else {
             LoxError::new_parser(self.previous().line, message)
         }
     }
 
+    /// Consumes a `;`, suggesting where to insert one if it's missing.
+    fn consume_semicolon(&mut self, message: String) -> Result<Token, LoxError> {
+        let previous = self.previous();
+        // Just past the end of the previous token, which may span lines if it's a string.
+        let column = match previous.lexeme.rsplit_once('\n') {
+            Some((_, last_line)) => Some(last_line.chars().count() + 1),
+            None if previous.column > 0 => Some(previous.column + previous.lexeme.chars().count()),
+            None => None,
+        };
+        self.consume(TokenType::Semicolon, message).map_err(|e| {
+            e.with_suggestion(Suggestion {
+                span: Span {
+                    line: previous.line,
+                    column,
+                },
+                insert: String::from(";"),
+            })
+        })
+    }
+
     fn is_at_end(&self) -> bool {
         matches!(self.tokens[self.current].tok_typ, TokenType::EoF)
     }
@@ -576,8 +949,20 @@ impl Parser {
         self.tokens[self.current].clone()
     }
 
+    fn peek_next(&self) -> Token {
+        if self.is_at_end() {
+            self.peek()
+        } else {
+            self.tokens[self.current + 1].clone()
+        }
+    }
+
     fn previous(&self) -> Token {
-        self.tokens[self.current - 1].clone()
+        self.tokens[self.current.saturating_sub(1)].clone()
+    }
+
+    fn unexpected_eof<T>(&self) -> Result<T, LoxError> {
+        LoxError::new_parser_at_eof(self.peek().line, String::from("Unexpected end of file."))
     }
 
     fn syncronize(&mut self) {
@@ -601,7 +986,8 @@ impl Parser {
                 | TokenType::If
                 | TokenType::While
                 | TokenType::Print
-                | TokenType::Return => {
+                | TokenType::Return
+                | TokenType::Yield => {
                     return;
                 }
                 _ => {
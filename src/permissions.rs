@@ -0,0 +1,110 @@
+//! What natives with effects outside the interpreter are allowed to do, so an
+//! untrusted script can be run without giving it the host's files, network,
+//! processes or environment. Everything is denied by default.
+
+use std::fmt;
+
+/// Something a native can only do once it's granted, see [`Permissions`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    /// Reading files and listing directories.
+    Read,
+    /// Creating, writing and deleting files and directories.
+    Write,
+    /// Opening network connections.
+    Net,
+    /// Spawning host processes, e.g. through `exec`.
+    Process,
+    /// Reading and setting environment variables.
+    Env,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 5] = [
+        Permission::Read,
+        Permission::Write,
+        Permission::Net,
+        Permission::Process,
+        Permission::Env,
+    ];
+
+    /// The command line flag that grants this permission.
+    pub fn flag(self) -> &'static str {
+        match self {
+            Permission::Read => "--allow-read",
+            Permission::Write => "--allow-write",
+            Permission::Net => "--allow-net",
+            Permission::Process => "--allow-run",
+            Permission::Env => "--allow-env",
+        }
+    }
+
+    /// The permission granted by `flag`, e.g. [`Permission::Read`] for `--allow-read`.
+    pub fn from_flag(flag: &str) -> Option<Permission> {
+        Permission::ALL.into_iter().find(|p| p.flag() == flag)
+    }
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Permission::Read => "Reading files",
+            Permission::Write => "Writing files",
+            Permission::Net => "Network access",
+            Permission::Process => "Process execution",
+            Permission::Env => "Environment access",
+        })
+    }
+}
+
+/// The permissions granted to a script, see
+/// [`crate::interpreter::InterpreterOptions::permissions`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Permissions {
+    pub read: bool,
+    pub write: bool,
+    pub net: bool,
+    pub process: bool,
+    pub env: bool,
+}
+
+impl Permissions {
+    /// Every permission, for trusted scripts.
+    pub fn all() -> Self {
+        Permissions {
+            read: true,
+            write: true,
+            net: true,
+            process: true,
+            env: true,
+        }
+    }
+
+    pub fn allows(&self, permission: Permission) -> bool {
+        match permission {
+            Permission::Read => self.read,
+            Permission::Write => self.write,
+            Permission::Net => self.net,
+            Permission::Process => self.process,
+            Permission::Env => self.env,
+        }
+    }
+
+    pub fn grant(&mut self, permission: Permission) {
+        *self.field(permission) = true;
+    }
+
+    pub fn revoke(&mut self, permission: Permission) {
+        *self.field(permission) = false;
+    }
+
+    fn field(&mut self, permission: Permission) -> &mut bool {
+        match permission {
+            Permission::Read => &mut self.read,
+            Permission::Write => &mut self.write,
+            Permission::Net => &mut self.net,
+            Permission::Process => &mut self.process,
+            Permission::Env => &mut self.env,
+        }
+    }
+}
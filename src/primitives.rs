@@ -0,0 +1,212 @@
+//! Methods called on strings and numbers, e.g. `"a,b".split(",")` or
+//! `(3.7).floor()`. Reading one gives a native function bound to the value it
+//! was read from, like a method read from an instance.
+
+use crate::error::LoxError;
+use crate::interpreter::{Interpreter, Types};
+use crate::memory;
+use crate::natives::{string_arg, NativeFunction};
+use crate::sync::{Rc, RefCell, Shareable};
+use crate::tokens::Token;
+
+type Method<T> = fn(&mut Interpreter, &T, Vec<Types>) -> Result<Types, LoxError>;
+
+const STRING_METHODS: &[(&str, usize, Method<Rc<String>>, &str)] = &[
+    (
+        "len",
+        0,
+        len,
+        "s.len() returns the number of characters in s.",
+    ),
+    ("upper", 0, upper, "s.upper() returns s in upper case."),
+    ("lower", 0, lower, "s.lower() returns s in lower case."),
+    (
+        "trim",
+        0,
+        trim,
+        "s.trim() returns s without leading and trailing whitespace.",
+    ),
+    (
+        "split",
+        1,
+        split,
+        "s.split(separator) returns the list of the parts of s between each separator.",
+    ),
+    (
+        "contains",
+        1,
+        contains,
+        "s.contains(other) is true if other appears in s.",
+    ),
+    (
+        "startsWith",
+        1,
+        starts_with,
+        "s.startsWith(prefix) is true if s begins with prefix.",
+    ),
+    (
+        "endsWith",
+        1,
+        ends_with,
+        "s.endsWith(suffix) is true if s ends with suffix.",
+    ),
+    (
+        "replace",
+        2,
+        replace,
+        "s.replace(from, to) returns s with every from replaced by to.",
+    ),
+];
+
+const NUMBER_METHODS: &[(&str, usize, Method<f64>, &str)] = &[
+    (
+        "floor",
+        0,
+        floor,
+        "n.floor() returns the largest whole number no greater than n.",
+    ),
+    (
+        "ceil",
+        0,
+        ceil,
+        "n.ceil() returns the smallest whole number no less than n.",
+    ),
+    (
+        "round",
+        0,
+        round,
+        "n.round() returns the whole number nearest n, rounding halves away from zero.",
+    ),
+    ("abs", 0, abs, "n.abs() returns n without its sign."),
+];
+
+/// Reads the method `name` of `value`, a string or a number.
+pub(crate) fn get(value: &Types, name: &Token) -> Result<Types, LoxError> {
+    match value {
+        Types::String(s) => bind(STRING_METHODS, s, "Strings", name),
+        Types::Number(n) => bind(NUMBER_METHODS, n, "Numbers", name),
+        other => LoxError::new_runtime(name.line, format!("{other} has no methods.")),
+    }
+}
+
+fn bind<T: Clone + Shareable + 'static>(
+    methods: &[(&str, usize, Method<T>, &str)],
+    receiver: &T,
+    kind: &str,
+    name: &Token,
+) -> Result<Types, LoxError> {
+    let Some((_, airity, method, doc)) = methods.iter().find(|(n, ..)| name.lexeme == *n) else {
+        return LoxError::new_runtime(
+            name.line,
+            format!("{kind} have no method `{}`.", name.lexeme),
+        );
+    };
+    let receiver = receiver.clone();
+    let method = *method;
+    Ok(NativeFunction::new(
+        &name.lexeme,
+        *airity,
+        doc,
+        move |interpreter, arguments| method(interpreter, &receiver, arguments),
+    ))
+}
+
+fn new_string(interpreter: &mut Interpreter, s: String) -> Result<Types, LoxError> {
+    interpreter.allocate(memory::string_size(s.len()))?;
+    Ok(Types::String(Rc::from(s)))
+}
+
+fn len(_interpreter: &mut Interpreter, s: &Rc<String>, _: Vec<Types>) -> Result<Types, LoxError> {
+    Ok(Types::Number(s.chars().count() as f64))
+}
+
+fn upper(interpreter: &mut Interpreter, s: &Rc<String>, _: Vec<Types>) -> Result<Types, LoxError> {
+    new_string(interpreter, s.to_uppercase())
+}
+
+fn lower(interpreter: &mut Interpreter, s: &Rc<String>, _: Vec<Types>) -> Result<Types, LoxError> {
+    new_string(interpreter, s.to_lowercase())
+}
+
+fn trim(interpreter: &mut Interpreter, s: &Rc<String>, _: Vec<Types>) -> Result<Types, LoxError> {
+    new_string(interpreter, s.trim().to_string())
+}
+
+fn split(
+    interpreter: &mut Interpreter,
+    s: &Rc<String>,
+    arguments: Vec<Types>,
+) -> Result<Types, LoxError> {
+    let separator = string_arg(interpreter.line(), &arguments[0])?;
+    if separator.is_empty() {
+        return LoxError::new_runtime(
+            interpreter.line(),
+            String::from("Can't split on an empty separator."),
+        );
+    }
+    let parts: Vec<Types> = s
+        .split(&*separator)
+        .map(|part| Types::String(Rc::new(String::from(part))))
+        .collect();
+    interpreter.allocate(memory::list_size(parts.len()) + memory::string_size(s.len()))?;
+    Ok(Types::List(Rc::new(RefCell::new(parts))))
+}
+
+fn contains(
+    interpreter: &mut Interpreter,
+    s: &Rc<String>,
+    arguments: Vec<Types>,
+) -> Result<Types, LoxError> {
+    let other = string_arg(interpreter.line(), &arguments[0])?;
+    Ok(Types::Bool(s.contains(&*other)))
+}
+
+fn starts_with(
+    interpreter: &mut Interpreter,
+    s: &Rc<String>,
+    arguments: Vec<Types>,
+) -> Result<Types, LoxError> {
+    let prefix = string_arg(interpreter.line(), &arguments[0])?;
+    Ok(Types::Bool(s.starts_with(&*prefix)))
+}
+
+fn ends_with(
+    interpreter: &mut Interpreter,
+    s: &Rc<String>,
+    arguments: Vec<Types>,
+) -> Result<Types, LoxError> {
+    let suffix = string_arg(interpreter.line(), &arguments[0])?;
+    Ok(Types::Bool(s.ends_with(&*suffix)))
+}
+
+fn replace(
+    interpreter: &mut Interpreter,
+    s: &Rc<String>,
+    arguments: Vec<Types>,
+) -> Result<Types, LoxError> {
+    let from = string_arg(interpreter.line(), &arguments[0])?;
+    let to = string_arg(interpreter.line(), &arguments[1])?;
+    if from.is_empty() {
+        return LoxError::new_runtime(
+            interpreter.line(),
+            String::from("Can't replace an empty string."),
+        );
+    }
+    new_string(interpreter, s.replace(&*from, &to))
+}
+
+fn floor(_interpreter: &mut Interpreter, n: &f64, _: Vec<Types>) -> Result<Types, LoxError> {
+    Ok(Types::Number(n.floor()))
+}
+
+fn ceil(_interpreter: &mut Interpreter, n: &f64, _: Vec<Types>) -> Result<Types, LoxError> {
+    Ok(Types::Number(n.ceil()))
+}
+
+fn round(_interpreter: &mut Interpreter, n: &f64, _: Vec<Types>) -> Result<Types, LoxError> {
+    Ok(Types::Number(n.round()))
+}
+
+fn abs(_interpreter: &mut Interpreter, n: &f64, _: Vec<Types>) -> Result<Types, LoxError> {
+    Ok(Types::Number(n.abs()))
+}
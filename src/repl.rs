@@ -0,0 +1,182 @@
+use crate::inspect;
+use crate::interpreter::{Interpreter, Types};
+use crate::Lox;
+use std::io::{BufRead, Write};
+
+enum Input {
+    Line(String),
+    #[cfg_attr(not(feature = "rustyline"), allow(dead_code))]
+    Interrupted,
+    Eof,
+}
+
+/// An interactive prompt that runs each line against a single interpreter, so
+/// globals defined on one line are visible on the next.
+pub struct Repl<'a> {
+    lox: &'a Lox,
+    interpreter: Interpreter,
+    editor: Editor,
+}
+
+impl<'a> Repl<'a> {
+    pub fn new(lox: &'a Lox) -> Self {
+        Repl {
+            lox,
+            interpreter: lox.new_interpreter(),
+            editor: Editor::new(),
+        }
+    }
+
+    pub fn run(&mut self) {
+        // Lines of a statement that isn't finished yet, e.g. an open `{`.
+        let mut pending = String::new();
+        loop {
+            let prompt = if pending.is_empty() { "> " } else { "... " };
+            match self.editor.read_line(prompt) {
+                Input::Line(line) if pending.is_empty() && line.starts_with(':') => {
+                    self.editor.add_history(&line);
+                    self.command(&line);
+                }
+                Input::Line(line) => {
+                    let blank = line.trim().is_empty();
+                    if blank && pending.is_empty() {
+                        continue;
+                    }
+                    if !blank {
+                        self.editor.add_history(&line);
+                    }
+                    pending.push_str(&line);
+                    pending.push('\n');
+                    // Keep doc comments for the declaration on the next line.
+                    if line.trim_start().starts_with("///") {
+                        continue;
+                    }
+
+                    match self.lox.eval_in(&mut self.interpreter, pending.clone()) {
+                        // A blank line submits the input as is, so a mistake
+                        // can't trap the user in continuation mode.
+                        Err(e) if e.is_incomplete_input() && !blank => continue,
+                        Err(e) => self.lox.report(&e),
+                        // Show the value of an expression, as if it were printed.
+                        Ok(Some(value)) if value != Types::Nil => {
+                            println!("{}", inspect::inspect(&value, inspect::DEFAULT_DEPTH));
+                        }
+                        Ok(_) => (),
+                    }
+                    pending.clear();
+                }
+                // Ctrl-C abandons the current input rather than the session.
+                Input::Interrupted => pending.clear(),
+                Input::Eof => break,
+            }
+        }
+        self.editor.save_history();
+    }
+
+    /// Runs a line starting with `:`, which controls the REPL rather than
+    /// being Lox code, e.g. `:doc add` to show the docs of `add`, `:type p` to
+    /// show what kind of value `p` is or `:load lib.lox` to run `lib.lox` in
+    /// the session.
+    fn command(&mut self, line: &str) {
+        let (command, argument) = line.split_once(' ').unwrap_or((line, ""));
+        let argument = argument.trim();
+        match command {
+            ":doc" if argument.is_empty() => eprintln!("Expected a value after `:doc`."),
+            ":doc" => {
+                let source = format!("help({argument});");
+                if let Err(e) = self.lox.run_in(&mut self.interpreter, source) {
+                    self.lox.report(&e);
+                }
+            }
+            ":type" if argument.is_empty() => eprintln!("Expected an expression after `:type`."),
+            ":type" => {
+                // The parentheses make anything but an expression a syntax error.
+                let source = format!("({});", argument.trim_end_matches(';'));
+                match self.lox.eval_in(&mut self.interpreter, source) {
+                    Ok(value) => println!("{}", inspect::type_of(&value.unwrap_or(Types::Nil))),
+                    Err(e) => self.lox.report(&e),
+                }
+            }
+            ":load" if argument.is_empty() => eprintln!("Expected a path after `:load`."),
+            ":load" => {
+                if let Err(e) = self.lox.run_file_in(&mut self.interpreter, argument) {
+                    self.lox.report(&e);
+                }
+            }
+            _ => eprintln!("Unknown command `{command}`."),
+        }
+    }
+}
+
+fn read_stdin_line(prompt: &str) -> Input {
+    print!("{prompt}");
+    let _ = std::io::stdout().flush();
+    let mut line = String::new();
+    match std::io::stdin().lock().read_line(&mut line) {
+        Ok(0) | Err(_) => Input::Eof,
+        Ok(_) => Input::Line(String::from(line.trim_end_matches(['\n', '\r']))),
+    }
+}
+
+#[cfg(feature = "rustyline")]
+struct Editor {
+    /// `None` when stdin isn't a terminal, in which case lines are read plainly.
+    editor: Option<rustyline::DefaultEditor>,
+    history: Option<std::path::PathBuf>,
+}
+
+#[cfg(feature = "rustyline")]
+impl Editor {
+    fn new() -> Self {
+        let history =
+            std::env::var_os("HOME").map(|home| std::path::Path::new(&home).join(".loxrt_history"));
+        let mut editor = rustyline::DefaultEditor::new().ok();
+        if let (Some(editor), Some(history)) = (editor.as_mut(), history.as_ref()) {
+            let _ = editor.load_history(history);
+        }
+        Editor { editor, history }
+    }
+
+    fn read_line(&mut self, prompt: &str) -> Input {
+        use rustyline::error::ReadlineError;
+
+        match self.editor.as_mut() {
+            Some(editor) => match editor.readline(prompt) {
+                Ok(line) => Input::Line(line),
+                Err(ReadlineError::Interrupted) => Input::Interrupted,
+                Err(_) => Input::Eof,
+            },
+            None => read_stdin_line(prompt),
+        }
+    }
+
+    fn add_history(&mut self, line: &str) {
+        if let Some(editor) = self.editor.as_mut() {
+            let _ = editor.add_history_entry(line);
+        }
+    }
+
+    fn save_history(&mut self) {
+        if let (Some(editor), Some(history)) = (self.editor.as_mut(), self.history.as_ref()) {
+            let _ = editor.save_history(history);
+        }
+    }
+}
+
+#[cfg(not(feature = "rustyline"))]
+struct Editor;
+
+#[cfg(not(feature = "rustyline"))]
+impl Editor {
+    fn new() -> Self {
+        Editor
+    }
+
+    fn read_line(&mut self, prompt: &str) -> Input {
+        read_stdin_line(prompt)
+    }
+
+    fn add_history(&mut self, _line: &str) {}
+
+    fn save_history(&mut self) {}
+}
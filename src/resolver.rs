@@ -1,8 +1,10 @@
-use crate::ast::{Expr, Stmt};
-use crate::error::LoxError;
-use crate::interpreter::Interpreter;
+use crate::ast::{Expr, NodeId, Pattern, Stmt};
+use crate::diagnostics::{self, Diagnostic, Severity, Span};
+use crate::error::{LoxError, Phase};
+use crate::interpreter::{self, Interpreter, Local};
 use crate::tokens::Token;
-use std::collections::HashMap;
+use crate::visit::{walk_expr, walk_operands, walk_pattern, walk_stmt, ExprVisitor, StmtVisitor};
+use std::collections::{HashMap, HashSet};
 
 #[derive(Debug, Clone)]
 enum FunctionKind {
@@ -19,11 +21,49 @@ enum ClassKind {
     SubClass,
 }
 
+/// A local variable in a scope being resolved.
+#[derive(Debug, Clone, Copy)]
+struct Variable {
+    defined: bool,
+    /// Where the interpreter keeps the variable: its index in the scope's
+    /// environment, or in the call's frame for scopes on the frame. Locals are
+    /// numbered in the order they're declared, which is the order they're defined at runtime.
+    slot: usize,
+    /// The line of the `var` or function parameter that declared the variable,
+    /// to warn if it's never read. `None` for other kinds of locals.
+    declared_at: Option<usize>,
+    parameter: bool,
+    read: bool,
+    /// Unset for `var x;` until something is assigned to `x`.
+    initialized: bool,
+}
+
+#[derive(Debug, Default)]
+struct Scope {
+    variables: HashMap<String, Variable>,
+    /// Set when the scope's locals live on the interpreter's frame stack
+    /// instead of in an environment of their own.
+    on_frame: bool,
+    /// The frame index of the scope's first local.
+    base: usize,
+}
+
 pub struct Resolver<'a> {
     interpreter: &'a mut Interpreter,
-    scopes: Vec<HashMap<String, bool>>,
+    scopes: Vec<Scope>,
     function_kind: FunctionKind,
     class_kind: ClassKind,
+    /// Globals declared so far, to tell assignments to undeclared ones apart.
+    globals: HashSet<String>,
+    /// Assignments to names that aren't locals, checked once every global is declared.
+    global_assignments: Vec<Token>,
+    /// Functions declared at the top level, to warn about those never used.
+    global_functions: Vec<Token>,
+    /// Globals read anywhere except in the body of the function they name.
+    global_reads: HashSet<String>,
+    /// The top-level function being resolved.
+    top_level_function: Option<String>,
+    warnings: Vec<Diagnostic>,
 }
 
 impl<'a> Resolver<'a> {
@@ -33,36 +73,363 @@ impl<'a> Resolver<'a> {
             scopes: vec![],
             function_kind: FunctionKind::None,
             class_kind: ClassKind::None,
+            globals: HashSet::new(),
+            global_assignments: vec![],
+            global_functions: vec![],
+            global_reads: HashSet::new(),
+            top_level_function: None,
+            warnings: vec![],
         }
     }
-    pub fn resolve(&mut self, statements: &Vec<Box<Stmt>>) -> Result<(), LoxError> {
-        for stmt in statements {
-            self.resolve_stmt(&*stmt)?;
+
+    pub fn resolve(&mut self, statements: &[Box<Stmt>]) -> Result<(), LoxError> {
+        self.visit_stmts(statements)?;
+        for name in std::mem::take(&mut self.global_assignments) {
+            let declared = self.globals.contains(name.lexeme.as_str())
+                || self
+                    .interpreter
+                    .global_env
+                    .borrow()
+                    .contains(name.lexeme.as_str());
+            if !declared {
+                self.warn(
+                    name.line,
+                    format!("`{}` is assigned but never declared.", name.lexeme),
+                )?;
+            }
         }
         Ok(())
     }
 
-    fn resolve_stmt(&mut self, stmt: &Stmt) -> Result<(), LoxError> {
-        match stmt {
-            Stmt::Function { name, params, body } => {
-                self.declare(&name)?;
-                self.define(&name);
-                self.resolve_function(&params, &body, FunctionKind::Function)?;
+    /// Resolves a whole program, which is [`Resolver::resolve`] plus warnings
+    /// about top-level functions it never uses. Those aren't reported for code
+    /// run a piece at a time, as in the REPL, since later pieces may use them.
+    pub fn resolve_program(&mut self, statements: &[Box<Stmt>]) -> Result<(), LoxError> {
+        self.resolve(statements)?;
+        for name in std::mem::take(&mut self.global_functions) {
+            if !self.global_reads.contains(name.lexeme.as_str()) && !name.lexeme.starts_with('_') {
+                self.warn(
+                    name.line,
+                    format!("Function `{}` is never used.", name.lexeme),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The warnings found so far: unused and shadowing locals, unused
+    /// parameters and functions, reads of locals that haven't been assigned
+    /// yet and assignments to undeclared globals.
+    pub fn take_warnings(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Records a warning, or returns it as an error in strict mode.
+    fn warn(&mut self, line: usize, message: String) -> Result<(), LoxError> {
+        if self.interpreter.options().strict {
+            return LoxError::new_resolution(line, message);
+        }
+        self.warnings.push(Diagnostic {
+            severity: Severity::Warning,
+            phase: Phase::Resolve,
+            file: None,
+            span: Some(Span { line, column: None }),
+            message,
+            suggestion: None,
+        });
+        Ok(())
+    }
+
+    /// Opens a block's scope, which lives wherever its enclosing scope does.
+    fn begin_scope(&mut self) {
+        let scope = match self.scopes.last() {
+            Some(outer) if outer.on_frame => Scope {
+                on_frame: true,
+                base: outer.base + outer.variables.len(),
+                ..Scope::default()
+            },
+            _ => Scope::default(),
+        };
+        self.scopes.push(scope);
+    }
+
+    /// Closes the innermost scope, warning about its `var`s that were never read.
+    fn end_scope(&mut self) -> Result<(), LoxError> {
+        let Some(scope) = self.scopes.pop() else {
+            return Ok(());
+        };
+        let mut unused: Vec<(&String, &Variable)> = scope
+            .variables
+            .iter()
+            .filter(|(name, variable)| {
+                variable.declared_at.is_some() && !variable.read && !name.starts_with('_')
+            })
+            .collect();
+        unused.sort_by_key(|(_, variable)| variable.slot);
+        for (name, variable) in unused {
+            let kind = if variable.parameter {
+                "Parameter"
+            } else {
+                "Local variable"
+            };
+            self.warn(
+                variable.declared_at.unwrap_or_default(),
+                format!("{kind} `{name}` is never read."),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// The innermost local called `name`, if there is one.
+    fn variable_mut(&mut self, name: &str) -> Option<&mut Variable> {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.variables.get_mut(name))
+    }
+
+    fn declare(&mut self, name: &Token) -> Result<(), LoxError> {
+        let Some((scope, enclosing)) = self.scopes.split_last() else {
+            self.globals.insert(name.lexeme.to_string());
+            return Ok(());
+        };
+        let shadows = !scope.variables.contains_key(name.lexeme.as_str())
+            && enclosing
+                .iter()
+                .any(|scope| scope.variables.contains_key(name.lexeme.as_str()));
+        if shadows {
+            self.warn(
+                name.line,
+                format!(
+                    "`{}` shadows a variable of an enclosing scope.",
+                    name.lexeme
+                ),
+            )?;
+        }
+
+        if let Some(scope) = self.scopes.last_mut() {
+            if scope.variables.contains_key(name.lexeme.as_str()) {
+                return LoxError::new_resolution(
+                    name.line,
+                    format!(
+                        "A variable with name `{}` already exists within this scope",
+                        name.lexeme
+                    ),
+                );
+            }
+            let slot = scope.base + scope.variables.len();
+            scope.variables.insert(
+                name.lexeme.to_string(),
+                Variable {
+                    defined: false,
+                    slot,
+                    declared_at: None,
+                    parameter: false,
+                    read: false,
+                    initialized: true,
+                },
+            );
+        }
+
+        Ok(())
+    }
+
+    fn define(&mut self, name: &Token) {
+        if let Some(scope) = self.scopes.last_mut() {
+            if let Some(variable) = scope.variables.get_mut(name.lexeme.as_str()) {
+                variable.defined = true;
+            }
+        }
+    }
+
+    /// Declares and defines a variable the interpreter binds itself, such as `this`.
+    fn define_implicit(&mut self, name: &str) {
+        let scope = self.scopes.last_mut().unwrap();
+        let slot = scope.base + scope.variables.len();
+        scope.variables.insert(
+            String::from(name),
+            Variable {
+                defined: true,
+                slot,
+                declared_at: None,
+                parameter: false,
+                read: false,
+                initialized: true,
+            },
+        );
+    }
+
+    fn resolve_local(&mut self, id: NodeId, name: &Token) {
+        // Scopes on the frame don't get an environment, so they don't add to the depth.
+        let mut depth = 0;
+        for scope in self.scopes.iter().rev() {
+            if let Some(variable) = scope.variables.get(name.lexeme.as_str()) {
+                let local = if scope.on_frame {
+                    Local::Frame(variable.slot)
+                } else {
+                    Local::Env {
+                        depth,
+                        slot: variable.slot,
+                    }
+                };
+                self.interpreter.resolve(id, local);
+                return;
+            }
+            if !scope.on_frame {
+                depth += 1;
+            }
+        }
+
+        // It's a global, which may not exist. Keep the closest local name in
+        // case it's a typo, since locals' names are gone at runtime.
+        let locals = self
+            .scopes
+            .iter()
+            .flat_map(|scope| scope.variables.keys().map(String::as_str));
+        if let Some(similar) = diagnostics::closest_name(&name.lexeme, locals) {
+            self.interpreter
+                .note_similar_local(id, String::from(similar));
+        }
+    }
+
+    fn resolve_function(
+        &mut self,
+        params: &Vec<Token>,
+        body: &[Box<Stmt>],
+        kind: FunctionKind,
+    ) -> Result<(), LoxError> {
+        let prev_kind = self.function_kind.clone();
+        self.function_kind = kind;
+        self.scopes.push(Scope {
+            on_frame: interpreter::runs_on_frame(body),
+            ..Scope::default()
+        });
+        let warn_unused = matches!(self.function_kind, FunctionKind::Function);
+        for param in params {
+            self.declare(param)?;
+            self.define(param);
+            // Methods may ignore parameters they take to match the methods they override.
+            if let (true, Some(variable)) = (warn_unused, self.variable_mut(&param.lexeme)) {
+                variable.declared_at = Some(param.line);
+                variable.parameter = true;
+            }
+        }
+        self.visit_stmts(body)?;
+        self.end_scope()?;
+
+        self.function_kind = prev_kind;
+
+        Ok(())
+    }
+}
+
+impl ExprVisitor for Resolver<'_> {
+    fn visit_expr(&mut self, expr: &Expr) -> Result<(), LoxError> {
+        match expr {
+            Expr::Variable { id, name } => {
+                if let Some(scope) = self.scopes.last() {
+                    if let Some(variable) = scope.variables.get(name.lexeme.as_str()) {
+                        if !variable.defined {
+                            return LoxError::new_resolution(
+                                name.line,
+                                String::from("Can't read local var in it's own initializer"),
+                            );
+                        }
+                    }
+                }
+
+                self.resolve_local(*id, name);
+                if let Some(variable) = self.variable_mut(&name.lexeme) {
+                    variable.read = true;
+                    if !variable.initialized {
+                        // Only warn about the first read.
+                        variable.initialized = true;
+                        self.warn(
+                            name.line,
+                            format!("`{}` is read before it's assigned a value.", name.lexeme),
+                        )?;
+                    }
+                } else if self.top_level_function.as_deref() != Some(name.lexeme.as_str()) {
+                    self.global_reads.insert(name.lexeme.to_string());
+                }
+            }
+            Expr::Assignment { id, name, .. } => {
+                walk_expr(self, expr)?;
+                self.resolve_local(*id, name);
+                match self.variable_mut(&name.lexeme) {
+                    Some(variable) => variable.initialized = true,
+                    None => self.global_assignments.push(name.clone()),
+                }
+            }
+            Expr::Match { subject, arms, .. } => {
+                self.visit_expr(subject)?;
+                for arm in arms {
+                    self.begin_scope();
+                    self.visit_pattern(&arm.pattern)?;
+                    self.visit_expr(&arm.body)?;
+                    self.end_scope()?;
+                }
+            }
+            Expr::This { id, keyword } => {
+                if let ClassKind::None = self.class_kind {
+                    return LoxError::new_resolution(
+                        keyword.line,
+                        String::from("Cannot use `this` outside of a class."),
+                    );
+                }
+                self.resolve_local(*id, keyword)
+            }
+            Expr::Super { id, keyword, .. } => match self.class_kind {
+                ClassKind::None => {
+                    return LoxError::new_resolution(
+                        keyword.line,
+                        String::from("Can't use `super` outside of a class."),
+                    )
+                }
+                ClassKind::Class => {
+                    return LoxError::new_resolution(
+                        keyword.line,
+                        String::from("Can't use `super` in a class with no superclass."),
+                    )
+                }
+                ClassKind::SubClass => self.resolve_local(*id, keyword),
+            },
+            Expr::Binary { .. } | Expr::Logical { .. } => walk_operands(self, expr)?,
+            _ => walk_expr(self, expr)?,
+        }
+        Ok(())
+    }
+
+    fn visit_pattern(&mut self, pattern: &Pattern) -> Result<(), LoxError> {
+        match pattern {
+            Pattern::Binding { name } => {
+                self.declare(name)?;
+                self.define(name);
             }
-            Stmt::Expr { expr } => self.resolve_expr(&*expr)?,
-            Stmt::If {
-                condition,
-                then_branch,
-                else_branch,
+            _ => walk_pattern(self, pattern)?,
+        }
+        Ok(())
+    }
+}
+
+impl StmtVisitor for Resolver<'_> {
+    fn visit_stmt(&mut self, stmt: &Stmt) -> Result<(), LoxError> {
+        match stmt {
+            Stmt::Function {
+                name, params, body, ..
             } => {
-                self.resolve_expr(&*condition)?;
-                self.resolve_stmt(&*then_branch)?;
-                if let Some(branch) = else_branch {
-                    self.resolve_stmt(&*branch)?;
+                self.declare(name)?;
+                self.define(name);
+                if self.scopes.is_empty() {
+                    self.global_functions.push(name.clone());
+                    self.top_level_function = Some(name.lexeme.to_string());
+                    self.resolve_function(params, body, FunctionKind::Function)?;
+                    self.top_level_function = None;
+                } else {
+                    self.resolve_function(params, body, FunctionKind::Function)?;
                 }
             }
-            Stmt::Print { expr } => self.resolve_expr(&*expr)?,
-            Stmt::Return { keyword, value } => match self.function_kind {
+            Stmt::Return { keyword, value, .. } => match self.function_kind {
                 FunctionKind::None => {
                     return LoxError::new_resolution(
                         keyword.line,
@@ -77,67 +444,104 @@ impl<'a> Resolver<'a> {
                         );
                     }
                 }
-                FunctionKind::Method | FunctionKind::Function => {
-                    if let Some(value) = value {
-                        self.resolve_expr(&*value)?;
-                    }
+                FunctionKind::Method | FunctionKind::Function => walk_stmt(self, stmt)?,
+            },
+            Stmt::Yield { keyword, .. } => match self.function_kind {
+                FunctionKind::None => {
+                    return LoxError::new_resolution(
+                        keyword.line,
+                        String::from("Can't yield from top-level code."),
+                    )
+                }
+                FunctionKind::Initializer => {
+                    return LoxError::new_resolution(
+                        keyword.line,
+                        String::from("Can't yield from an initializer."),
+                    )
                 }
+                FunctionKind::Method | FunctionKind::Function => walk_stmt(self, stmt)?,
             },
-            Stmt::While { condition, body } => {
-                self.resolve_expr(&*condition)?;
-                self.resolve_stmt(&*body)?;
+            Stmt::For { .. } => {
+                self.begin_scope();
+                walk_stmt(self, stmt)?;
+                self.end_scope()?;
             }
-            Stmt::Var { name, expr } => {
+            Stmt::Var { name, expr, .. } => {
                 self.declare(name)?;
                 if let Some(init) = expr {
-                    self.resolve_expr(&*init)?;
+                    self.visit_expr(init)?;
                 }
                 self.define(name);
+                if let Some(variable) = self.variable_mut(&name.lexeme) {
+                    variable.declared_at = Some(name.line);
+                    variable.initialized = expr.is_some();
+                }
             }
-            Stmt::Block { stmts } => {
+            Stmt::Block { .. } | Stmt::Test { .. } => {
                 self.begin_scope();
-                self.resolve(stmts)?;
-                self.end_scope();
+                walk_stmt(self, stmt)?;
+                self.end_scope()?;
             }
             Stmt::Class {
                 name,
                 methods,
+                statics,
                 superclass,
+                ..
             } => {
+                // Static fields are initialized where the class is declared,
+                // before `this` or `super` exist.
+                let mut static_names = HashSet::new();
+                for stmt in statics {
+                    if let Stmt::Var {
+                        name: field, expr, ..
+                    } = &**stmt
+                    {
+                        if !static_names.insert(&field.lexeme) {
+                            return LoxError::new_resolution(
+                                field.line,
+                                format!(
+                                    "Class `{}` already has a static field `{}`.",
+                                    name.lexeme, field.lexeme
+                                ),
+                            );
+                        }
+                        if let Some(init) = expr {
+                            self.visit_expr(init)?;
+                        }
+                    }
+                }
+
                 let enclosing_class = self.class_kind.clone();
                 self.class_kind = ClassKind::Class;
                 self.declare(name)?;
                 self.define(name);
 
                 if let Some(superclass) = superclass {
-                    match &**superclass {
-                        Expr::Variable { name: superclass } => {
-                            if name.lexeme == superclass.lexeme {
-                                return LoxError::new_resolution(
-                                    name.line,
-                                    String::from("A class can't inherit from itself."),
-                                );
-                            }
+                    if let Expr::Variable {
+                        name: superclass, ..
+                    } = &**superclass
+                    {
+                        if name.lexeme == superclass.lexeme {
+                            return LoxError::new_resolution(
+                                name.line,
+                                String::from("A class can't inherit from itself."),
+                            );
                         }
-                        _ => unreachable!(),
                     }
                     self.class_kind = ClassKind::SubClass;
-                    self.resolve_expr(superclass)?;
+                    self.visit_expr(superclass)?;
                     self.begin_scope();
-                    self.scopes
-                        .last_mut()
-                        .unwrap()
-                        .insert(String::from("super"), true);
+                    self.define_implicit("super");
                 }
 
                 self.begin_scope();
-                self.scopes
-                    .last_mut()
-                    .unwrap()
-                    .insert(String::from("this"), true);
+                self.define_implicit("this");
                 for method in methods {
                     match &**method {
-                        Stmt::Function { params, body, name } => {
+                        Stmt::Function {
+                            params, body, name, ..
+                        } => {
                             let kind = if name.lexeme == "init" {
                                 FunctionKind::Initializer
                             } else {
@@ -145,146 +549,24 @@ impl<'a> Resolver<'a> {
                             };
                             self.resolve_function(params, body, kind)?;
                         }
-                        _ => unreachable!(),
-                    }
-                }
-
-                self.end_scope();
-                if superclass.is_some() {
-                    self.end_scope()
-                }
-
-                self.class_kind = enclosing_class;
-            }
-        }
-
-        Ok(())
-    }
-
-    fn resolve_expr(&mut self, expr: &Expr) -> Result<(), LoxError> {
-        match expr {
-            Expr::Variable { name } => {
-                if let Some(scope) = self.scopes.last() {
-                    if let Some(init) = scope.get(&name.lexeme) {
-                        if !init {
+                        _ => {
                             return LoxError::new_resolution(
                                 name.line,
-                                String::from("Can't read local var in it's own initializer"),
-                            );
+                                format!("Class `{}` contains a non-method member.", name.lexeme),
+                            )
                         }
                     }
                 }
 
-                self.resolve_local(expr, name);
-            }
-            Expr::Assignment { name, value } => {
-                self.resolve_expr(&*value)?;
-                self.resolve_local(&*value, name);
-            }
-            Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
-                self.resolve_expr(&*left)?;
-                self.resolve_expr(&*right)?;
-            }
-            Expr::Call {
-                callee, arguments, ..
-            } => {
-                self.resolve_expr(&*callee)?;
-                for arg in arguments {
-                    self.resolve_expr(&*arg)?;
-                }
-            }
-            Expr::Grouping { expr } => self.resolve_expr(&*expr)?,
-            Expr::Literal { .. } => (),
-            Expr::Unary { right, .. } => self.resolve_expr(&*right)?,
-            Expr::Get { object, .. } => self.resolve_expr(&*object)?,
-            Expr::Set { object, value, .. } => {
-                self.resolve_expr(object)?;
-                self.resolve_expr(value)?;
-            }
-            Expr::This { keyword } => {
-                if let ClassKind::None = self.class_kind {
-                    return LoxError::new_resolution(
-                        keyword.line,
-                        String::from("Cannot use `this` outside of a class."),
-                    );
-                }
-                self.resolve_local(expr, keyword)
-            }
-            Expr::Super { keyword, .. } => match self.class_kind {
-                ClassKind::None => {
-                    return LoxError::new_resolution(
-                        keyword.line,
-                        String::from("Can't use `super` outside of a class."),
-                    )
-                }
-                ClassKind::Class => {
-                    return LoxError::new_resolution(
-                        keyword.line,
-                        String::from("Can't use `super` in a class with no superclass."),
-                    )
+                self.end_scope()?;
+                if superclass.is_some() {
+                    self.end_scope()?;
                 }
-                ClassKind::SubClass => self.resolve_local(expr, keyword),
-            },
-        }
-        Ok(())
-    }
 
-    fn begin_scope(&mut self) {
-        self.scopes.push(HashMap::new());
-    }
-
-    fn end_scope(&mut self) {
-        self.scopes.pop();
-    }
-
-    fn declare(&mut self, name: &Token) -> Result<(), LoxError> {
-        if let Some(scope) = self.scopes.last_mut() {
-            if scope.contains_key(&name.lexeme) {
-                return LoxError::new_resolution(
-                    name.line,
-                    format!(
-                        "A variable with name `{}` already exists within this scope",
-                        name.lexeme
-                    ),
-                );
-            }
-            scope.insert(name.lexeme.clone(), false);
-        }
-
-        Ok(())
-    }
-
-    fn define(&mut self, name: &Token) {
-        if let Some(scope) = self.scopes.last_mut() {
-            scope.insert(name.lexeme.clone(), true);
-        }
-    }
-
-    fn resolve_local(&mut self, expr: &Expr, name: &Token) {
-        for (i, scope) in self.scopes.iter().rev().enumerate() {
-            if scope.contains_key(&name.lexeme) {
-                self.interpreter.resolve(expr, i);
+                self.class_kind = enclosing_class;
             }
+            _ => walk_stmt(self, stmt)?,
         }
-    }
-
-    fn resolve_function(
-        &mut self,
-        params: &Vec<Token>,
-        body: &Vec<Box<Stmt>>,
-        kind: FunctionKind,
-    ) -> Result<(), LoxError> {
-        let prev_kind = self.function_kind.clone();
-        self.function_kind = kind;
-        self.begin_scope();
-        for param in params {
-            self.declare(param)?;
-            self.define(param);
-        }
-        self.resolve(body)?;
-        self.end_scope();
-
-        self.function_kind = prev_kind;
 
         Ok(())
     }
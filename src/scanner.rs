@@ -1,35 +1,44 @@
 use crate::error::LoxError;
-use crate::tokens::{Token, TokenType};
-use substring::Substring;
+use crate::tokens::{Lexeme, Token, TokenType};
+use std::sync::Arc;
 
+/// Turns source code into tokens. It's an iterator, so tokens can be read
+/// one at a time without holding them all, ending with an `EoF` token; or
+/// all at once with [`Scanner::scan_tokens`]. It stops after an error.
 pub struct Scanner {
-    source: String,
-    tokens: Vec<Token>,
+    /// Shared with the tokens, whose lexemes are ranges of it.
+    source: Arc<str>,
+    /// The token the last call to `scan_token` found, if any.
+    token: Option<Token>,
+    /// Set once the `EoF` token or an error has been returned.
+    finished: bool,
+    /// Byte offsets in `source` of the token being scanned and of the next
+    /// character.
     start: usize,
     current: usize,
     line: usize,
+    /// How many characters of the current line have been consumed.
+    line_column: usize,
+    /// Column of the token being scanned.
+    column: usize,
 }
 
 impl Scanner {
     pub fn new(source: String) -> Self {
         Scanner {
-            source,
-            tokens: vec![],
+            source: Arc::from(source),
+            token: None,
+            finished: false,
             start: 0,
             current: 0,
             line: 1,
+            line_column: 0,
+            column: 1,
         }
     }
 
-    pub fn scan_tokens(mut self) -> Result<Vec<Token>, LoxError> {
-        while !self.is_at_end() {
-            self.start = self.current;
-            self.scan_token()?;
-        }
-
-        self.tokens
-            .push(Token::new(TokenType::EoF, String::from(""), self.line));
-        Ok(self.tokens)
+    pub fn scan_tokens(self) -> Result<Vec<Token>, LoxError> {
+        self.collect()
     }
 
     fn is_at_end(&self) -> bool {
@@ -43,7 +52,10 @@ impl Scanner {
             ')' => self.add_token(TokenType::RightParen),
             '{' => self.add_token(TokenType::LeftBrace),
             '}' => self.add_token(TokenType::RightBrace),
+            '[' => self.add_token(TokenType::LeftBracket),
+            ']' => self.add_token(TokenType::RightBracket),
             ',' => self.add_token(TokenType::Comma),
+            ':' => self.add_token(TokenType::Colon),
             '.' => self.add_token(TokenType::Dot),
             '-' => self.add_token(TokenType::Minus),
             '+' => self.add_token(TokenType::Plus),
@@ -59,6 +71,8 @@ impl Scanner {
             '=' => {
                 if self.matches('=') {
                     self.add_token(TokenType::EqualEqual)
+                } else if self.matches('>') {
+                    self.add_token(TokenType::FatArrow)
                 } else {
                     self.add_token(TokenType::Equal)
                 }
@@ -79,20 +93,27 @@ impl Scanner {
             }
             '/' => {
                 if self.matches('/') {
+                    // `///` starts a doc comment, but `////` is an ordinary one.
+                    let doc = self.matches('/') && self.peek(0) != '/';
                     while self.peek(0) != '\n' && !self.is_at_end() {
                         self.advance();
                     }
+                    if doc {
+                        let text = &self.source[self.start + 3..self.current];
+                        let text = String::from(text.strip_prefix(' ').unwrap_or(text));
+                        self.add_token(TokenType::DocComment(text));
+                    }
                 } else {
                     self.add_token(TokenType::Slash)
                 }
             }
             ' ' | '\t' | '\r' => (),
-            '\n' => self.line += 1,
+            '\n' => self.new_line(),
             '"' => self.string()?,
             c => {
                 if c.is_ascii_digit() {
                     self.number()?
-                } else if c.is_alphabetic() {
+                } else if c.is_alphabetic() || c == '_' {
                     self.identifier()
                 } else {
                     return LoxError::new_scanner(self.line, format!("Unexpected character `{c}`"));
@@ -102,93 +123,219 @@ impl Scanner {
         Ok(())
     }
 
+    /// Records that a newline was just consumed.
+    fn new_line(&mut self) {
+        self.line += 1;
+        self.line_column = 0;
+    }
+
     fn advance(&mut self) -> char {
-        let c = self.source.chars().nth(self.current).unwrap();
-        self.current += 1;
+        let c = self.peek(0);
+        if !self.is_at_end() {
+            self.current += c.len_utf8();
+            self.line_column += 1;
+        }
         c
     }
 
     fn add_token(&mut self, tok_typ: TokenType) {
-        self.tokens.push(Token::new(
+        self.token = Some(Token {
             tok_typ,
-            String::from(self.source.substring(self.start, self.current)),
-            self.line,
-        ));
+            lexeme: Lexeme::new(&self.source, self.start, self.current),
+            line: self.line,
+            column: self.column,
+        });
     }
 
     fn matches(&mut self, expected: char) -> bool {
-        if self.is_at_end() {
-            return false;
-        }
-        if self.source.chars().nth(self.current).unwrap() != expected {
+        if self.is_at_end() || self.peek(0) != expected {
             false
         } else {
-            self.current += 1;
+            self.advance();
             true
         }
     }
 
     fn peek(&self, offset: usize) -> char {
-        self.source
+        self.source[self.current..]
             .chars()
-            .nth(self.current + offset)
+            .nth(offset)
             .unwrap_or('\0')
     }
     fn string(&mut self) -> Result<(), LoxError> {
         while self.peek(0) != '"' && !self.is_at_end() {
-            if self.peek(0) == '\n' {
-                self.line += 1;
+            if self.advance() == '\n' {
+                self.new_line();
             }
-            self.advance();
         }
 
         if self.is_at_end() {
-            return LoxError::new_scanner(self.line, String::from("Unterminated String"));
+            return LoxError::new_scanner_at_eof(self.line, String::from("Unterminated String"));
         }
 
         self.advance();
 
         self.add_token(TokenType::Str(String::from(
-            self.source.substring(self.start + 1, self.current - 1),
+            &self.source[self.start + 1..self.current - 1],
         )));
 
         Ok(())
     }
 
     fn number(&mut self) -> Result<(), LoxError> {
-        while self.peek(0).is_ascii_digit() {
-            self.advance();
-        }
-        if self.peek(0) == '.' && self.peek(1).is_ascii_digit() {
+        let radix = match self.peek(0) {
+            'x' | 'X' if &self.source[self.start..self.current] == "0" => Some((16, "hex")),
+            'b' | 'B' if &self.source[self.start..self.current] == "0" => Some((2, "binary")),
+            _ => None,
+        };
+
+        let value = if let Some((radix, kind)) = radix {
             self.advance();
-            while self.peek(0).is_ascii_digit() {
+            let digits = self.digits(radix)?;
+            if digits.is_empty() {
+                return LoxError::new_scanner(
+                    self.line,
+                    format!(
+                        "Expected {kind} digits after `{}`.",
+                        &self.source[self.start..self.current]
+                    ),
+                );
+            }
+            self.end_of_number(kind)?;
+            match u64::from_str_radix(&digits, radix) {
+                Ok(n) => n as f64,
+                Err(_) => {
+                    return LoxError::new_scanner(
+                        self.line,
+                        format!(
+                            "The {kind} literal `{}` is too large.",
+                            &self.source[self.start..self.current]
+                        ),
+                    )
+                }
+            }
+        } else {
+            // Rescan the first digit, so a separator right after it is accepted.
+            self.current = self.start;
+            self.line_column = self.column - 1;
+            let mut literal = self.digits(10)?;
+            if self.peek(0) == '.' && self.peek(1).is_ascii_digit() {
                 self.advance();
+                literal.push('.');
+                literal.push_str(&self.digits(10)?);
+            }
+            if matches!(self.peek(0), 'e' | 'E') {
+                self.advance();
+                literal.push('e');
+                if matches!(self.peek(0), '+' | '-') {
+                    literal.push(self.advance());
+                }
+                let exponent = self.digits(10)?;
+                if exponent.is_empty() {
+                    return LoxError::new_scanner(
+                        self.line,
+                        format!(
+                            "Expected digits in the exponent of `{}`.",
+                            &self.source[self.start..self.current]
+                        ),
+                    );
+                }
+                literal.push_str(&exponent);
+            }
+            self.end_of_number("number")?;
+            match literal.parse::<f64>() {
+                Ok(n) => n,
+                Err(_) => {
+                    return LoxError::new_scanner(self.line, String::from("Failed to parse number"))
+                }
+            }
+        };
+
+        self.add_token(TokenType::Number(value));
+        Ok(())
+    }
+
+    /// Consumes digits in `radix`, which may be separated by single `_`s, and
+    /// returns them without the separators.
+    fn digits(&mut self, radix: u32) -> Result<String, LoxError> {
+        let mut digits = String::new();
+        loop {
+            let c = self.peek(0);
+            if c.is_digit(radix) {
+                digits.push(c);
+                self.advance();
+            } else if c == '_' {
+                if digits.is_empty() || !self.peek(1).is_digit(radix) {
+                    return LoxError::new_scanner(
+                        self.line,
+                        String::from("A digit separator `_` must be between two digits."),
+                    );
+                }
+                self.advance();
+            } else {
+                return Ok(digits);
             }
         }
+    }
 
-        if let Ok(n) = self
-            .source
-            .substring(self.start, self.current)
-            .parse::<f64>()
-        {
-            self.add_token(TokenType::Number(n));
-            Ok(())
-        } else {
-            LoxError::new_scanner(self.line, String::from("Failed to parse number"))
+    /// Rejects letters and digits running on from a number, e.g. the `2` in `0b12`.
+    fn end_of_number(&mut self, kind: &str) -> Result<(), LoxError> {
+        let c = self.peek(0);
+        if c.is_alphanumeric() {
+            return LoxError::new_scanner(
+                self.line,
+                format!(
+                    "Unexpected character `{c}` in {kind} literal `{}`.",
+                    &self.source[self.start..self.current + c.len_utf8()]
+                ),
+            );
         }
+        Ok(())
     }
 
     fn identifier(&mut self) {
-        let keywords = Token::keywords();
         while self.peek(0).is_alphanumeric() || self.peek(0) == '_' {
             self.advance();
         }
-        let ident = String::from(self.source.substring(self.start, self.current));
+        match Token::keyword(&self.source[self.start..self.current]) {
+            Some(keyword) => self.add_token(keyword),
+            None => self.add_token(TokenType::Identifier),
+        }
+    }
+}
 
-        if let Some(kw) = keywords.get(ident.as_str()) {
-            self.add_token(kw.clone());
-        } else {
-            self.add_token(TokenType::Identifier(String::from(ident)));
+impl Iterator for Scanner {
+    type Item = Result<Token, LoxError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        // Skip a `#!` line so scripts can be executable on Unix.
+        if self.current == 0 && self.source.starts_with("#!") {
+            while self.peek(0) != '\n' && !self.is_at_end() {
+                self.advance();
+            }
+        }
+
+        while !self.is_at_end() {
+            self.start = self.current;
+            self.column = self.line_column + 1;
+            if let Err(e) = self.scan_token() {
+                self.finished = true;
+                return Some(Err(e));
+            }
+            if let Some(token) = self.token.take() {
+                return Some(Ok(token));
+            }
         }
+
+        self.finished = true;
+        Some(Ok(Token {
+            tok_typ: TokenType::EoF,
+            lexeme: Lexeme::from(""),
+            line: self.line,
+            column: self.line_column + 1,
+        }))
     }
 }
@@ -0,0 +1,87 @@
+//! The containers values are shared through. By default they're `Rc` and
+//! `RefCell`; with the `send` feature they're `Arc` and a read-write lock, so an
+//! [`Interpreter`](crate::interpreter::Interpreter) can be moved to another
+//! thread, e.g. a worker of an async host. Either way an interpreter and its
+//! values are only used from one thread at a time.
+//!
+//! Code that shares values, such as host objects, should use these rather than
+//! `std`'s so it builds with and without the feature.
+
+#[cfg(not(feature = "send"))]
+pub use std::{
+    cell::RefCell,
+    rc::{Rc, Weak},
+};
+
+#[cfg(feature = "send")]
+pub use std::sync::{Arc as Rc, Weak};
+
+#[cfg(feature = "send")]
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError};
+
+/// A lock with `RefCell`'s interface. Borrowing a value that's already
+/// borrowed mutably blocks rather than panicking.
+#[cfg(feature = "send")]
+#[derive(Default)]
+pub struct RefCell<T: ?Sized>(RwLock<T>);
+
+#[cfg(feature = "send")]
+impl<T> RefCell<T> {
+    pub fn new(value: T) -> Self {
+        RefCell(RwLock::new(value))
+    }
+}
+
+#[cfg(feature = "send")]
+impl<T: ?Sized> RefCell<T> {
+    pub fn borrow(&self) -> RwLockReadGuard<'_, T> {
+        self.0.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn borrow_mut(&self) -> RwLockWriteGuard<'_, T> {
+        self.0.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Borrows the value unless it's borrowed mutably, rather than blocking.
+    pub fn try_borrow(
+        &self,
+    ) -> Result<RwLockReadGuard<'_, T>, TryLockError<RwLockReadGuard<'_, T>>> {
+        self.0.try_read()
+    }
+
+    /// Borrows the value mutably unless it's borrowed at all, rather than blocking.
+    pub fn try_borrow_mut(
+        &self,
+    ) -> Result<RwLockWriteGuard<'_, T>, TryLockError<RwLockWriteGuard<'_, T>>> {
+        self.0.try_write()
+    }
+}
+
+#[cfg(feature = "send")]
+impl<T: Clone> Clone for RefCell<T> {
+    fn clone(&self) -> Self {
+        RefCell::new(self.borrow().clone())
+    }
+}
+
+#[cfg(feature = "send")]
+impl<T: ?Sized + std::fmt::Debug> std::fmt::Debug for RefCell<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Implemented by every type, or with the `send` feature by every type that
+/// can be shared between threads. Traits whose implementations values hold,
+/// such as [`crate::interpreter::Callable`], require it.
+#[cfg(not(feature = "send"))]
+pub trait Shareable {}
+
+#[cfg(not(feature = "send"))]
+impl<T: ?Sized> Shareable for T {}
+
+#[cfg(feature = "send")]
+pub trait Shareable: Send + Sync {}
+
+#[cfg(feature = "send")]
+impl<T: ?Sized + Send + Sync> Shareable for T {}
@@ -0,0 +1,141 @@
+//! The results of running `test "name" { ... }` blocks, for `loxrt test`. A
+//! directory is tested by running every `*_test.lox` file under it, see
+//! [`crate::Lox::test_dir`].
+
+use crate::diagnostics::Diagnostic;
+use crate::sync::{Rc, RefCell};
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+/// How one `test` block went.
+pub struct TestResult {
+    pub name: String,
+    /// What the test printed, including while re-running the top-level code.
+    pub output: Vec<u8>,
+    /// The errors that failed the test, if it did.
+    pub failure: Option<Vec<Diagnostic>>,
+    pub elapsed: Duration,
+}
+
+/// The tests run from one script.
+pub struct TestRun {
+    pub tests: Vec<TestResult>,
+    /// The number of tests skipped because their names didn't match
+    /// [`crate::Lox::test_filter`].
+    pub filtered_out: usize,
+}
+
+/// The results of testing one file.
+pub struct FileResults {
+    /// Diagnostics reported while testing the file on another thread, e.g.
+    /// resolver warnings, to be reported once its results are shown.
+    pub reported: Vec<Diagnostic>,
+    /// The file's tests, or the errors that stopped any of them running.
+    pub run: Result<TestRun, Vec<Diagnostic>>,
+    pub elapsed: Duration,
+}
+
+/// The line ending a test run, e.g.
+/// `test result: ok. 3 passed; 0 failed; finished in 1.20ms`.
+pub struct Summary {
+    pub passed: usize,
+    pub failed: usize,
+    pub filtered_out: usize,
+    pub elapsed: Duration,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "test result: {}. {} passed; {} failed",
+            if self.failed == 0 { "ok" } else { "FAILED" },
+            self.passed,
+            self.failed
+        )?;
+        if self.filtered_out > 0 {
+            write!(f, "; {} filtered out", self.filtered_out)?;
+        }
+        write!(f, "; finished in {:.2?}", self.elapsed)
+    }
+}
+
+/// Collects what a test prints, see [`crate::interpreter::Interpreter::set_output`].
+#[derive(Clone, Default)]
+pub(crate) struct Captured(Rc<RefCell<Vec<u8>>>);
+
+impl Captured {
+    pub(crate) fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.borrow_mut())
+    }
+}
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Finds the `*_test.lox` files under `dir`, in the order they're run.
+pub fn test_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    find_test_files(dir, &mut files)?;
+    Ok(files)
+}
+
+fn find_test_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<PathBuf>>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            find_test_files(&path, files)?;
+        } else if path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.ends_with("_test.lox"))
+        {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Calls `test` on each of `files` from `jobs` threads, returning the results
+/// in the same order as the files.
+pub(crate) fn run_parallel<T: Send>(
+    files: &[PathBuf],
+    jobs: usize,
+    test: impl Fn(&Path) -> T + Sync,
+) -> Vec<T> {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new((0..files.len()).map(|_| None).collect::<Vec<Option<T>>>());
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(files.len()) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(path) = files.get(i) else { break };
+                let result = test(path);
+                results.lock().unwrap()[i] = Some(result);
+            });
+        }
+    });
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.expect("every file is tested"))
+        .collect()
+}
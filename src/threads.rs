@@ -0,0 +1,290 @@
+use crate::ast::{next_node_id, Expr, Stmt};
+use crate::diagnostics::Diagnostic;
+use crate::environment::Environment;
+use crate::error::LoxError;
+use crate::interpreter::{Callable, Interpreter, Types};
+use crate::natives::NativeFunction;
+use crate::resolver::Resolver;
+use crate::sync::{Rc, RefCell};
+use crate::tokens::{Token, TokenType};
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+
+/// A copy of a value that can cross to another thread. Lists are copied deeply;
+/// channels stay shared.
+enum SendValue {
+    Number(f64),
+    String(String),
+    Bool(bool),
+    List(Vec<SendValue>),
+    Channel(Channel),
+    Nil,
+}
+
+impl SendValue {
+    fn from_types(line: usize, value: &Types) -> Result<Self, LoxError> {
+        match value {
+            Types::Number(n) => Ok(SendValue::Number(*n)),
+            Types::String(s) => Ok(SendValue::String(s.to_string())),
+            Types::Bool(b) => Ok(SendValue::Bool(*b)),
+            Types::Nil => Ok(SendValue::Nil),
+            Types::Channel(channel) => Ok(SendValue::Channel(channel.clone())),
+            Types::List(list) => {
+                let elements = list
+                    .borrow()
+                    .iter()
+                    .map(|element| SendValue::from_types(line, element))
+                    .collect::<Result<_, _>>()?;
+                Ok(SendValue::List(elements))
+            }
+            other => LoxError::new_runtime(line, format!("Can't send {other} to another thread.")),
+        }
+    }
+
+    /// Adds the channels in this value to `channels`.
+    fn channels(&self, channels: &mut Vec<Channel>) {
+        match self {
+            SendValue::Channel(channel) => channels.push(channel.clone()),
+            SendValue::List(elements) => elements
+                .iter()
+                .for_each(|element| element.channels(channels)),
+            SendValue::Number(_) | SendValue::String(_) | SendValue::Bool(_) | SendValue::Nil => {}
+        }
+    }
+
+    fn into_types(self) -> Types {
+        match self {
+            SendValue::Number(n) => Types::Number(n),
+            SendValue::String(s) => Types::String(Rc::from(s)),
+            SendValue::Bool(b) => Types::Bool(b),
+            SendValue::Nil => Types::Nil,
+            SendValue::Channel(channel) => Types::Channel(channel),
+            SendValue::List(elements) => Types::List(Rc::new(RefCell::new(
+                elements.into_iter().map(SendValue::into_types).collect(),
+            ))),
+        }
+    }
+}
+
+/// Where threads started by `spawnThread` leave the diagnostics of the errors
+/// they fail with, shared with the interpreter that started them.
+pub(crate) type ThreadErrors = Arc<Mutex<Vec<Diagnostic>>>;
+
+/// A queue of values shared between threads. Every copy of a channel refers to
+/// the same queue.
+#[derive(Clone)]
+pub struct Channel {
+    queue: Arc<(Mutex<Queue>, Condvar)>,
+}
+
+#[derive(Default)]
+struct Queue {
+    values: VecDeque<SendValue>,
+    /// Set when a thread that could send on the channel fails, so receivers
+    /// don't wait forever for a value it won't send.
+    poisoned: bool,
+}
+
+impl Channel {
+    fn new() -> Self {
+        Channel {
+            queue: Arc::new((Mutex::new(Queue::default()), Condvar::new())),
+        }
+    }
+
+    fn send(&self, value: SendValue) {
+        let (queue, ready) = &*self.queue;
+        queue
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .values
+            .push_back(value);
+        ready.notify_one();
+    }
+
+    /// Waits until a value has been sent, then takes it. `None` once the
+    /// channel is poisoned and every value sent before has been taken.
+    fn recv(&self) -> Option<SendValue> {
+        let (queue, ready) = &*self.queue;
+        let mut queue = queue.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            if let Some(value) = queue.values.pop_front() {
+                return Some(value);
+            }
+            if queue.poisoned {
+                return None;
+            }
+            queue = ready.wait(queue).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Wakes every receiver waiting on the channel, see [`Queue::poisoned`].
+    fn poison(&self) {
+        let (queue, ready) = &*self.queue;
+        queue.lock().unwrap_or_else(|e| e.into_inner()).poisoned = true;
+        ready.notify_all();
+    }
+
+    pub fn same_as(&self, other: &Channel) -> bool {
+        Arc::ptr_eq(&self.queue, &other.queue)
+    }
+}
+
+pub fn define_globals(env: &mut Environment) {
+    env.define(
+        String::from("channel"),
+        NativeFunction::new(
+            "channel",
+            0,
+            "channel() creates a new, empty channel.",
+            channel,
+        ),
+    );
+    env.define(
+        String::from("send"),
+        NativeFunction::new(
+            "send",
+            2,
+            "send(channel, value) queues a copy of value on channel.",
+            send,
+        ),
+    );
+    env.define(
+        String::from("recv"),
+        NativeFunction::new(
+            "recv",
+            1,
+            "recv(channel) waits for a value on channel and returns it.",
+            recv,
+        ),
+    );
+    env.define(
+        String::from("spawnThread"),
+        NativeFunction::new(
+            "spawnThread",
+            1,
+            "spawnThread(fn) calls fn on a fresh interpreter in a new OS thread.",
+            spawn_thread,
+        ),
+    );
+}
+
+fn channel_arg(line: usize, value: &Types) -> Result<Channel, LoxError> {
+    match value {
+        Types::Channel(channel) => Ok(channel.clone()),
+        other => LoxError::new_runtime(line, format!("Expected a channel but found {other}")),
+    }
+}
+
+/// `channel()` creates a new, empty channel.
+fn channel(_interpreter: &mut Interpreter, _arguments: Vec<Types>) -> Result<Types, LoxError> {
+    Ok(Types::Channel(Channel::new()))
+}
+
+/// `send(channel, value)` queues a copy of `value` on `channel`.
+fn send(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let line = interpreter.line();
+    let channel = channel_arg(line, &arguments[0])?;
+    channel.send(SendValue::from_types(line, &arguments[1])?);
+    Ok(Types::Nil)
+}
+
+/// `recv(channel)` waits for a value on `channel` and returns it. It fails
+/// instead once a thread that could send on the channel has failed.
+fn recv(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let line = interpreter.line();
+    match channel_arg(line, &arguments[0])?.recv() {
+        Some(value) => Ok(value.into_types()),
+        None => LoxError::new_runtime(
+            line,
+            String::from("A thread that could send on this channel failed."),
+        ),
+    }
+}
+
+/// `spawnThread(fn)` calls `fn` on a fresh interpreter in a new OS thread.
+/// Nothing is shared with the thread: `fn` sees only the natives, the
+/// top-level functions, and copies of the globals that could be sent over a
+/// channel, such as channels themselves. The run that started the thread
+/// waits for it to finish. If the thread fails, the channels it was given are
+/// poisoned, and the run reports its error and fails too.
+fn spawn_thread(interpreter: &mut Interpreter, arguments: Vec<Types>) -> Result<Types, LoxError> {
+    let line = interpreter.line();
+    let declaration = match &arguments[0] {
+        Types::Callable(func) if func.airity() == 0 => func.declaration(),
+        other => {
+            return LoxError::new_runtime(
+                line,
+                format!("Expected a function that takes no arguments but found {other}"),
+            )
+        }
+    };
+    let name = match &declaration {
+        Stmt::Function { name, .. } => name.clone(),
+        _ => unreachable!(),
+    };
+    let mut globals: Vec<(String, SendValue)> = vec![];
+    let mut functions: Vec<Box<Stmt>> = vec![];
+    for (global, value) in interpreter.globals() {
+        match &value {
+            Types::Callable(func)
+                if interpreter.is_top_level(func)
+                    && func.name() == global
+                    && name.lexeme != *global =>
+            {
+                functions.push(Box::new(func.declaration()));
+            }
+            value => {
+                if let Ok(value) = SendValue::from_types(line, value) {
+                    globals.push((global, value));
+                }
+            }
+        }
+    }
+    let mut channels = vec![];
+    globals
+        .iter()
+        .for_each(|(_, value)| value.channels(&mut channels));
+    let options = interpreter.options();
+    let errors = interpreter.thread_errors();
+
+    let spawned = std::thread::Builder::new().spawn(move || {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_options(options);
+        interpreter.set_thread_errors(errors.clone());
+        for (name, value) in globals {
+            interpreter.set_global(&name, value.into_types());
+        }
+        let call = Stmt::Expr {
+            id: next_node_id(),
+            expr: Box::new(Expr::Call {
+                id: next_node_id(),
+                callee: Box::new(Expr::Variable {
+                    id: next_node_id(),
+                    name: name.clone(),
+                }),
+                paren: Token::new(TokenType::RightParen, String::from(")"), name.line),
+                arguments: vec![],
+            }),
+        };
+        let mut statements = functions;
+        statements.extend([Box::new(declaration), Box::new(call)]);
+        let result = Resolver::new(&mut interpreter)
+            .resolve(&statements)
+            .and_then(|_| interpreter.interpret(&statements));
+        // Its own threads report to the same place, so they only need waiting for.
+        interpreter.join_threads();
+        if let Err(e) = result {
+            errors
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .extend(e.diagnostics());
+            channels.iter().for_each(Channel::poison);
+        }
+    });
+    match spawned {
+        Ok(thread) => interpreter.add_thread(thread),
+        Err(e) => return LoxError::new_runtime(line, format!("Failed to spawn thread: {e}")),
+    }
+    Ok(Types::Nil)
+}
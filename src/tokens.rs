@@ -1,4 +1,5 @@
-use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::Arc;
 
 #[derive(Debug, Clone)]
 pub enum TokenType {
@@ -6,7 +7,10 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
+    Colon,
     Dot,
     Minus,
     Plus,
@@ -16,12 +20,13 @@ pub enum TokenType {
     Bang,
     BangEqual,
     Equal,
+    FatArrow,
     EqualEqual,
     Greater,
     GreaterEqual,
     Less,
     LessEqual,
-    Identifier(String),
+    Identifier,
     Str(String),
     Number(f64),
     And,
@@ -31,6 +36,7 @@ pub enum TokenType {
     Fun,
     For,
     If,
+    Match,
     Nil,
     Or,
     Print,
@@ -40,91 +46,151 @@ pub enum TokenType {
     True,
     Var,
     While,
+    Yield,
+    /// A `///` comment, documenting the declaration that follows it. Holds
+    /// the comment's text. The parser sets these aside rather than parsing them.
+    DocComment(String),
     EoF,
 }
 
 impl PartialEq for TokenType {
     fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (TokenType::LeftParen, TokenType::LeftParen)
-            | (TokenType::RightParen, TokenType::RightParen)
-            | (TokenType::LeftBrace, TokenType::LeftBrace)
-            | (TokenType::RightBrace, TokenType::RightBrace)
-            | (TokenType::Comma, TokenType::Comma)
-            | (TokenType::Dot, TokenType::Dot)
-            | (TokenType::Minus, TokenType::Minus)
-            | (TokenType::Plus, TokenType::Plus)
-            | (TokenType::Semicolon, TokenType::Semicolon)
-            | (TokenType::Slash, TokenType::Slash)
-            | (TokenType::Star, TokenType::Star)
-            | (TokenType::Bang, TokenType::Bang)
-            | (TokenType::BangEqual, TokenType::BangEqual)
-            | (TokenType::Equal, TokenType::Equal)
-            | (TokenType::EqualEqual, TokenType::EqualEqual)
-            | (TokenType::Greater, TokenType::Greater)
-            | (TokenType::GreaterEqual, TokenType::GreaterEqual)
-            | (TokenType::Less, TokenType::Less)
-            | (TokenType::LessEqual, TokenType::LessEqual)
-            | (TokenType::Identifier(_), TokenType::Identifier(_))
-            | (TokenType::Str(_), TokenType::Str(_))
-            | (TokenType::Number(_), TokenType::Number(_))
-            | (TokenType::And, TokenType::And)
-            | (TokenType::Class, TokenType::Class)
-            | (TokenType::Else, TokenType::Else)
-            | (TokenType::False, TokenType::False)
-            | (TokenType::Fun, TokenType::Fun)
-            | (TokenType::For, TokenType::For)
-            | (TokenType::If, TokenType::If)
-            | (TokenType::Nil, TokenType::Nil)
-            | (TokenType::Or, TokenType::Or)
-            | (TokenType::Print, TokenType::Print)
-            | (TokenType::Return, TokenType::Return)
-            | (TokenType::Super, TokenType::Super)
-            | (TokenType::This, TokenType::This)
-            | (TokenType::True, TokenType::True)
-            | (TokenType::Var, TokenType::Var)
-            | (TokenType::While, TokenType::While)
-            | (TokenType::EoF, TokenType::EoF) => true,
-            _ => false,
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+}
+
+/// The text of a token. Tokens scanned from the same source share it, and
+/// each one refers to its range of it, so scanning doesn't copy any text.
+#[derive(Clone)]
+pub struct Lexeme {
+    /// An `Arc` even without the `send` feature, so `spawnThread` can move a
+    /// function's declaration to another thread.
+    source: Arc<str>,
+    start: usize,
+    end: usize,
+}
+
+impl Lexeme {
+    /// The text between the byte offsets `start` and `end` of `source`.
+    pub fn new(source: &Arc<str>, start: usize, end: usize) -> Self {
+        Lexeme {
+            source: source.clone(),
+            start,
+            end,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source[self.start..self.end]
+    }
+}
+
+impl From<&str> for Lexeme {
+    fn from(text: &str) -> Self {
+        Lexeme {
+            source: Arc::from(text),
+            start: 0,
+            end: text.len(),
         }
     }
 }
 
+impl From<String> for Lexeme {
+    fn from(text: String) -> Self {
+        Lexeme::from(text.as_str())
+    }
+}
+
+impl Deref for Lexeme {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl PartialEq for Lexeme {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for Lexeme {}
+
+impl PartialEq<str> for Lexeme {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Lexeme {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl std::hash::Hash for Lexeme {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+
+impl std::fmt::Display for Lexeme {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self)
+    }
+}
+
+impl std::fmt::Debug for Lexeme {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Token {
     pub tok_typ: TokenType,
-    pub lexeme: String,
+    pub lexeme: Lexeme,
     pub line: usize,
+    /// 1-based column of the token's first character, or 0 for tokens that
+    /// weren't scanned from source.
+    pub column: usize,
 }
 
 impl Token {
     pub fn new(tok_typ: TokenType, lexeme: String, line: usize) -> Self {
         Token {
             tok_typ,
-            lexeme: lexeme,
+            lexeme: Lexeme::from(lexeme),
             line,
+            column: 0,
         }
     }
 
-    pub fn keywords() -> HashMap<&'static str, TokenType> {
-        let mut map = HashMap::new();
-        map.insert("and", TokenType::And);
-        map.insert("class", TokenType::Class);
-        map.insert("else", TokenType::Else);
-        map.insert("false", TokenType::False);
-        map.insert("fun", TokenType::Fun);
-        map.insert("for", TokenType::For);
-        map.insert("if", TokenType::If);
-        map.insert("nil", TokenType::Nil);
-        map.insert("or", TokenType::Or);
-        map.insert("print", TokenType::Print);
-        map.insert("return", TokenType::Return);
-        map.insert("super", TokenType::Super);
-        map.insert("this", TokenType::This);
-        map.insert("true", TokenType::True);
-        map.insert("var", TokenType::Var);
-        map.insert("while", TokenType::While);
-        map
+    /// The keyword spelled `text`, if it is one.
+    pub fn keyword(text: &str) -> Option<TokenType> {
+        let keyword = match text {
+            "and" => TokenType::And,
+            "class" => TokenType::Class,
+            "else" => TokenType::Else,
+            "false" => TokenType::False,
+            "fun" => TokenType::Fun,
+            "for" => TokenType::For,
+            "if" => TokenType::If,
+            "match" => TokenType::Match,
+            "nil" => TokenType::Nil,
+            "or" => TokenType::Or,
+            "print" => TokenType::Print,
+            "return" => TokenType::Return,
+            "super" => TokenType::Super,
+            "this" => TokenType::This,
+            "true" => TokenType::True,
+            "var" => TokenType::Var,
+            "while" => TokenType::While,
+            "yield" => TokenType::Yield,
+            _ => return None,
+        };
+        Some(keyword)
     }
 }
 
@@ -0,0 +1,157 @@
+use std::collections::VecDeque;
+
+/// A value a native got from outside the program, e.g. the time `clock`
+/// returned.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceValue {
+    Number(f64),
+    String(String),
+    Nil,
+    /// The native failed to get its input, with this message. It's an entry's
+    /// only value, and replaying it fails the same way.
+    Error(String),
+}
+
+/// The values one call to a non-deterministic native received.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEntry {
+    pub native: String,
+    pub values: Vec<TraceValue>,
+}
+
+/// The inputs a run received, in order, so a later run can be fed the same
+/// ones and behave the same way. See [`crate::interpreter::Interpreter::record`].
+///
+/// Traces are saved as text with an entry per line: the native's name
+/// followed by its values, which are numbers, `nil`, strings quoted and
+/// escaped like Rust's, or `error` followed by such a string. Blank lines and
+/// lines starting with `#` are ignored.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Trace {
+    entries: VecDeque<TraceEntry>,
+}
+
+impl Trace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, entry: TraceEntry) {
+        self.entries.push_back(entry);
+    }
+
+    /// Takes the oldest entry that hasn't been replayed yet.
+    pub fn pop(&mut self) -> Option<TraceEntry> {
+        self.entries.pop_front()
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &TraceEntry> {
+        self.entries.iter()
+    }
+
+    pub fn parse(text: &str) -> Result<Trace, String> {
+        let mut trace = Trace::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let entry = parse_entry(line).map_err(|e| format!("line {}: {e}", i + 1))?;
+            trace.push(entry);
+        }
+        Ok(trace)
+    }
+}
+
+impl std::fmt::Display for Trace {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for entry in &self.entries {
+            f.write_str(&entry.native)?;
+            for value in &entry.values {
+                match value {
+                    TraceValue::Number(n) => write!(f, " {n}")?,
+                    TraceValue::String(s) => write!(f, " {s:?}")?,
+                    TraceValue::Nil => f.write_str(" nil")?,
+                    TraceValue::Error(message) => write!(f, " error {message:?}")?,
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_entry(line: &str) -> Result<TraceEntry, String> {
+    let (native, mut rest) = line.split_once(' ').unwrap_or((line, ""));
+    let mut values = vec![];
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+        let (value, remaining) = if let Some(quoted) = rest.strip_prefix('"') {
+            let (s, remaining) = parse_string(quoted)?;
+            (TraceValue::String(s), remaining)
+        } else if let Some(quoted) = rest.strip_prefix("error \"") {
+            let (message, remaining) = parse_string(quoted)?;
+            (TraceValue::Error(message), remaining)
+        } else {
+            let end = rest.find(' ').unwrap_or(rest.len());
+            let value = match &rest[..end] {
+                "nil" => TraceValue::Nil,
+                word => match word.parse() {
+                    Ok(n) => TraceValue::Number(n),
+                    Err(_) => return Err(format!("Unexpected value `{word}`.")),
+                },
+            };
+            (value, &rest[end..])
+        };
+        values.push(value);
+        rest = remaining;
+    }
+    Ok(TraceEntry {
+        native: String::from(native),
+        values,
+    })
+}
+
+/// Parses the rest of a string after its opening quote, returning it and what
+/// follows the closing quote.
+fn parse_string(text: &str) -> Result<(String, &str), String> {
+    let mut s = String::new();
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((s, &text[i + 1..])),
+            '\\' => match chars.next().map(|(_, c)| c) {
+                Some('n') => s.push('\n'),
+                Some('r') => s.push('\r'),
+                Some('t') => s.push('\t'),
+                Some('0') => s.push('\0'),
+                Some('\\') => s.push('\\'),
+                Some('"') => s.push('"'),
+                Some('\'') => s.push('\''),
+                Some('u') => {
+                    let rest = &text[i + 2..];
+                    let code = rest
+                        .strip_prefix('{')
+                        .and_then(|rest| rest.split_once('}'))
+                        .and_then(|(hex, _)| u32::from_str_radix(hex, 16).ok())
+                        .and_then(char::from_u32)
+                        .ok_or_else(|| String::from("Invalid `\\u` escape."))?;
+                    s.push(code);
+                    // Skip past the closing brace.
+                    for (_, c) in chars.by_ref() {
+                        if c == '}' {
+                            break;
+                        }
+                    }
+                }
+                Some(c) => return Err(format!("Unknown escape `\\{c}`.")),
+                None => break,
+            },
+            c => s.push(c),
+        }
+    }
+    Err(String::from("Unterminated string."))
+}
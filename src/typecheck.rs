@@ -0,0 +1,1031 @@
+//! Checks programs against their type annotations before they run, e.g.
+//! `fun add(a: Number, b: Number): Number` or `var s: String = "";`.
+//! Annotations are optional: anything without one has the type `Any`, which
+//! fits every annotation, so unannotated code is as dynamic as ever.
+//!
+//! A type is either built in (`Number`, `String`, `Bool`, `Nil`, `List`,
+//! `Function` or `Any`) or the name of a class, which instances of the class
+//! and its subclasses fit.
+//!
+//! Unannotated variables have the type of the value last assigned to them on
+//! every path to where they're read, when that's known. Operations that would
+//! certainly fail with those types, such as calling a number or passing the
+//! wrong number of arguments to a known function, are reported as warnings.
+
+use crate::ast::{Expr, Pattern, Stmt};
+use crate::diagnostics::{Diagnostic, Severity, Span};
+use crate::error::{LoxError, LoxErrorContainer, Phase};
+use crate::interpreter::{Callable, Interpreter, Types};
+use crate::tokens::{Token, TokenType};
+use crate::visit::{walk_expr, walk_operands, walk_pattern, walk_stmt, ExprVisitor, StmtVisitor};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+
+/// The type of a value as far as the checker can tell before running.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    /// Not known until the program runs, e.g. an unannotated parameter.
+    Any,
+    Number,
+    String,
+    Bool,
+    Nil,
+    List,
+    /// A function, with its signature if it's known which one.
+    Function(Option<Rc<Signature>>),
+    /// The class with this name itself, rather than one of its instances.
+    Class(String),
+    /// An instance of the class with this name or one of its subclasses.
+    Instance(String),
+}
+
+impl Type {
+    /// The type in a sentence, e.g. "a number".
+    fn describe(&self) -> String {
+        match self {
+            Type::Any => String::from("anything"),
+            Type::Number => String::from("a number"),
+            Type::String => String::from("a string"),
+            Type::Bool => String::from("a boolean"),
+            Type::Nil => String::from("nil"),
+            Type::List => String::from("a list"),
+            Type::Function(_) => String::from("a function"),
+            Type::Class(name) => format!("the class {name}"),
+            Type::Instance(name) => format!("an instance of {name}"),
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Any => write!(f, "Any"),
+            Type::Number => write!(f, "Number"),
+            Type::String => write!(f, "String"),
+            Type::Bool => write!(f, "Bool"),
+            Type::Nil => write!(f, "Nil"),
+            Type::List => write!(f, "List"),
+            Type::Function(_) => write!(f, "Function"),
+            Type::Class(name) => write!(f, "class {name}"),
+            Type::Instance(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+/// The types a function's parameters and result are annotated with, `Any`
+/// for those without annotations.
+#[derive(Debug, PartialEq)]
+pub struct Signature {
+    pub name: String,
+    pub params: Vec<Type>,
+    pub returns: Type,
+}
+
+/// Checks `statements` against their annotations, returning every mismatch
+/// as one error, or otherwise the warnings about operations that will fail.
+/// In strict mode the warnings are errors too. The classes and functions
+/// already defined in `interpreter`, e.g. by earlier REPL lines, are known.
+pub fn check(
+    interpreter: &Interpreter,
+    statements: &[Box<Stmt>],
+) -> Result<Vec<Diagnostic>, LoxError> {
+    let mut assignments = Assignments::default();
+    let _ = assignments.visit_stmts(statements);
+    let options = interpreter.options();
+    let mut checker = Checker {
+        scopes: vec![HashMap::new()],
+        classes: HashMap::new(),
+        returns: vec![],
+        class: None,
+        depth: 0,
+        reachable: true,
+        assigned: assignments.assigned,
+        captured_assignments: assignments.captured,
+        stringify_operands: options.stringifies_operands(),
+        errors: vec![],
+        warnings: vec![],
+    };
+    for (name, value) in interpreter.globals() {
+        checker.declare_global(name, value);
+    }
+    checker.declare_classes(statements);
+    checker.hoist(statements);
+    // The checker never fails part way, it collects errors instead.
+    let _ = checker.visit_stmts(statements);
+
+    if options.strict {
+        for warning in checker.warnings.drain(..) {
+            let line = warning.span.map_or(0, |span| span.line);
+            checker
+                .errors
+                .push(LoxErrorContainer::new(line, warning.message));
+        }
+    }
+    if checker.errors.is_empty() {
+        Ok(checker.warnings)
+    } else {
+        LoxError::new_types(checker.errors)
+    }
+}
+
+struct Class {
+    superclass: Option<String>,
+    methods: HashMap<String, Rc<Signature>>,
+}
+
+/// A variable in scope, with the type it's annotated with or holds.
+struct Binding {
+    ty: Type,
+    /// Whether `ty` comes from an annotation, so assignments have to fit it.
+    annotated: bool,
+    /// How many functions the variable's declaration is nested in.
+    depth: usize,
+}
+
+/// The type of every variable in scope at some point, to go back to or merge
+/// with after a branch.
+struct State {
+    types: Vec<HashMap<String, Type>>,
+    reachable: bool,
+}
+
+struct Checker {
+    scopes: Vec<HashMap<String, Binding>>,
+    classes: HashMap<String, Class>,
+    /// The name and return type of each function being checked, innermost last.
+    returns: Vec<(String, Type)>,
+    /// The class whose methods are being checked, for `this` and `super`.
+    class: Option<String>,
+    /// How many functions the code being checked is nested in.
+    depth: usize,
+    /// Unset after a `return`, until a path that doesn't return joins it.
+    reachable: bool,
+    /// The names of every variable assigned anywhere in the program.
+    assigned: HashSet<String>,
+    /// The names of variables assigned by functions other than the one
+    /// declaring them, which may run at any time, so their types are unknown.
+    captured_assignments: HashSet<String>,
+    stringify_operands: bool,
+    errors: Vec<LoxErrorContainer>,
+    warnings: Vec<Diagnostic>,
+}
+
+impl Checker {
+    fn error(&mut self, line: usize, message: String) {
+        self.errors.push(LoxErrorContainer::new(line, message));
+    }
+
+    fn warn(&mut self, line: usize, message: String) {
+        self.warnings.push(Diagnostic {
+            severity: Severity::Warning,
+            phase: Phase::Type,
+            file: None,
+            span: Some(Span { line, column: None }),
+            message,
+            suggestion: None,
+        });
+    }
+
+    fn declare(&mut self, name: &str, ty: Type, annotated: bool) {
+        let depth = self.depth;
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(
+                name.to_string(),
+                Binding {
+                    ty,
+                    annotated,
+                    depth,
+                },
+            );
+        }
+    }
+
+    /// Declares a global the interpreter already has. Only classes and
+    /// functions are given types, since only their arity is worth checking.
+    fn declare_global(&mut self, name: String, value: Types) {
+        let signature = |callable: &dyn Callable| {
+            Rc::new(Signature {
+                name: name.clone(),
+                params: vec![Type::Any; callable.airity()],
+                returns: Type::Any,
+            })
+        };
+        let ty = match &value {
+            Types::Class(class) => {
+                self.classes.insert(
+                    name.clone(),
+                    Class {
+                        superclass: class.superclass().map(|class| class.name().to_string()),
+                        methods: HashMap::from([(String::from("init"), signature(class))]),
+                    },
+                );
+                Type::Class(name.clone())
+            }
+            Types::Callable(function) => Type::Function(Some(signature(&**function))),
+            Types::NativeFunc(function) => Type::Function(Some(signature(function))),
+            _ => return,
+        };
+        self.declare(&name, ty, false);
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Binding> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    /// The type of the variable `name` where it's read.
+    fn read(&self, name: &str) -> Type {
+        let Some(binding) = self.lookup(name) else {
+            return Type::Any;
+        };
+        if binding.annotated {
+            return binding.ty.clone();
+        }
+        // A function declared inside the variable's scope may be called after
+        // the variable has been assigned anything.
+        let captured = binding.depth < self.depth && self.assigned.contains(name);
+        if captured || self.captured_assignments.contains(name) {
+            return Type::Any;
+        }
+        binding.ty.clone()
+    }
+
+    /// Records that the variable `name` now holds a value of type `ty`,
+    /// unless it's annotated.
+    fn assign(&mut self, name: &str, ty: Type) {
+        let binding = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name));
+        if let Some(binding) = binding.filter(|binding| !binding.annotated) {
+            binding.ty = ty;
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn state(&self) -> State {
+        State {
+            types: self
+                .scopes
+                .iter()
+                .map(|scope| {
+                    scope
+                        .iter()
+                        .map(|(name, binding)| (name.clone(), binding.ty.clone()))
+                        .collect()
+                })
+                .collect(),
+            reachable: self.reachable,
+        }
+    }
+
+    fn restore(&mut self, state: &State) {
+        for (scope, types) in self.scopes.iter_mut().zip(&state.types) {
+            for (name, binding) in scope.iter_mut() {
+                if let Some(ty) = types.get(name) {
+                    binding.ty = ty.clone();
+                }
+            }
+        }
+        self.reachable = state.reachable;
+    }
+
+    /// Continues from where the path that led to `other` and the current one
+    /// meet. Variables with different types on the two paths could have either.
+    fn join(&mut self, other: State) {
+        if !other.reachable {
+            return;
+        }
+        if !self.reachable {
+            self.restore(&other);
+            return;
+        }
+        for (scope, types) in self.scopes.iter_mut().zip(&other.types) {
+            for (name, binding) in scope.iter_mut() {
+                if types.get(name).is_some_and(|ty| *ty != binding.ty) {
+                    binding.ty = Type::Any;
+                }
+            }
+        }
+    }
+
+    /// Narrows the variables `condition` checks aren't nil, for the code it
+    /// guards. They're known to be nil otherwise, so that code never runs,
+    /// which is returned. It's still checked, but as if they could be anything.
+    fn narrow(&mut self, condition: &Expr) -> bool {
+        let variable = match condition {
+            Expr::Variable { name, .. } => name,
+            Expr::Binary {
+                left,
+                operator,
+                right,
+                ..
+            } if operator.tok_typ == TokenType::BangEqual => match (&**left, &**right) {
+                (Expr::Variable { name, .. }, Expr::Literal { value, .. })
+                | (Expr::Literal { value, .. }, Expr::Variable { name, .. })
+                    if value.tok_typ == TokenType::Nil =>
+                {
+                    name
+                }
+                _ => return false,
+            },
+            Expr::Logical {
+                left,
+                operator,
+                right,
+                ..
+            } if operator.tok_typ == TokenType::And => {
+                let left = self.narrow(left);
+                return self.narrow(right) || left;
+            }
+            Expr::Grouping { expr, .. } => return self.narrow(expr),
+            _ => return false,
+        };
+        if self.read(&variable.lexeme) != Type::Nil {
+            return false;
+        }
+        self.assign(&variable.lexeme, Type::Any);
+        true
+    }
+
+    /// Forgets the types of the variables a loop assigns, since it may run
+    /// any number of times.
+    fn forget_assigned(&mut self, body: &Stmt, increment: Option<&Expr>) {
+        let mut assignments = Assignments::default();
+        let _ = assignments.visit_stmt(body);
+        if let Some(increment) = increment {
+            let _ = assignments.visit_expr(increment);
+        }
+        for name in assignments.assigned {
+            self.assign(&name, Type::Any);
+        }
+    }
+
+    /// The type an annotation names, reporting names that aren't types when
+    /// `report` is set. Unknown types are `Any`, so they're only reported once.
+    fn annotation(&mut self, annotation: &Option<Token>, report: bool) -> Type {
+        let Some(token) = annotation else {
+            return Type::Any;
+        };
+        match token.lexeme.as_str() {
+            "Any" => Type::Any,
+            "Number" => Type::Number,
+            "String" => Type::String,
+            "Bool" => Type::Bool,
+            "Nil" => Type::Nil,
+            "List" => Type::List,
+            "Function" => Type::Function(None),
+            name if self.classes.contains_key(name) => Type::Instance(name.to_string()),
+            name => {
+                if report {
+                    self.error(token.line, format!("Unknown type `{name}`."));
+                }
+                Type::Any
+            }
+        }
+    }
+
+    fn signature(&mut self, annotations: &Annotations, report: bool) -> Rc<Signature> {
+        Rc::new(Signature {
+            name: annotations.name.lexeme.to_string(),
+            params: annotations
+                .param_types
+                .iter()
+                .map(|ty| self.annotation(ty, report))
+                .collect(),
+            returns: self.annotation(&annotations.return_type, report),
+        })
+    }
+
+    /// Finds every class declared in `statements`, however deeply nested, so
+    /// their names can be used as types anywhere, including before them.
+    fn declare_classes(&mut self, statements: &[Box<Stmt>]) {
+        let mut collector = ClassCollector { classes: vec![] };
+        let _ = collector.visit_stmts(statements);
+        for (name, superclass, _) in &collector.classes {
+            self.classes.insert(
+                name.clone(),
+                Class {
+                    superclass: superclass.clone(),
+                    methods: HashMap::new(),
+                },
+            );
+        }
+        for (name, _, methods) in collector.classes {
+            let signatures = methods
+                .iter()
+                .map(|method| {
+                    (
+                        method.name.lexeme.to_string(),
+                        self.signature(method, false),
+                    )
+                })
+                .collect();
+            if let Some(class) = self.classes.get_mut(&name) {
+                class.methods = signatures;
+            }
+        }
+    }
+
+    /// Declares the functions and classes of a block before checking it, so
+    /// code in the block can call them before they're declared.
+    fn hoist(&mut self, statements: &[Box<Stmt>]) {
+        for stmt in statements {
+            if let Some(annotations) = Annotations::of(stmt) {
+                let signature = self.signature(&annotations, false);
+                let name = annotations.name.lexeme.as_str();
+                self.declare(name, Type::Function(Some(signature)), false);
+            } else if let Stmt::Class { name, .. } = &**stmt {
+                self.declare(&name.lexeme, Type::Class(name.lexeme.to_string()), false);
+            }
+        }
+    }
+
+    /// Whether `class` is `ancestor` or inherits from it.
+    fn inherits(&self, class: &str, ancestor: &str) -> bool {
+        let mut class = Some(class);
+        // Bounded, in case a class inherits from itself.
+        for _ in 0..=self.classes.len() {
+            match class {
+                Some(name) if name == ancestor => return true,
+                Some(name) => {
+                    class = self
+                        .classes
+                        .get(name)
+                        .and_then(|class| class.superclass.as_deref())
+                }
+                None => return false,
+            }
+        }
+        false
+    }
+
+    /// The signature of the method `name` of `class` or its superclasses.
+    fn method(&self, class: &str, name: &str) -> Option<Rc<Signature>> {
+        let mut class = self.classes.get(class);
+        for _ in 0..=self.classes.len() {
+            let current = class?;
+            if let Some(signature) = current.methods.get(name) {
+                return Some(signature.clone());
+            }
+            class = self.classes.get(current.superclass.as_deref()?);
+        }
+        None
+    }
+
+    /// Whether a value of type `actual` can be used where `expected` is.
+    fn accepts(&self, expected: &Type, actual: &Type) -> bool {
+        match (expected, actual) {
+            (Type::Any, _) | (_, Type::Any) => true,
+            (Type::Function(_), Type::Function(_) | Type::Class(_)) => true,
+            (Type::Instance(expected), Type::Instance(actual))
+            | (Type::Class(expected), Type::Class(actual)) => self.inherits(actual, expected),
+            (expected, actual) => expected == actual,
+        }
+    }
+
+    /// Checks the arguments of a call to `name`, whose parameters are `params`.
+    fn check_call(&mut self, name: &str, params: &[Type], arguments: &[Type], line: usize) {
+        if params.len() != arguments.len() {
+            self.warn(
+                line,
+                format!(
+                    "`{name}` expects {} arguments but is called with {}.",
+                    params.len(),
+                    arguments.len()
+                ),
+            );
+        }
+        for (i, (param, argument)) in params.iter().zip(arguments).enumerate() {
+            if !self.accepts(param, argument) {
+                self.error(
+                    line,
+                    format!(
+                        "Argument {} of `{name}` should be {param} but is {argument}.",
+                        i + 1
+                    ),
+                );
+            }
+        }
+    }
+
+    fn function(&mut self, stmt: &Stmt) {
+        let (Stmt::Function { params, body, .. }, Some(annotations)) =
+            (stmt, Annotations::of(stmt))
+        else {
+            return;
+        };
+        let signature = self.signature(&annotations, true);
+        self.returns
+            .push((signature.name.clone(), signature.returns.clone()));
+        let reachable = self.reachable;
+        self.depth += 1;
+        self.begin_scope();
+        let annotated = annotations.param_types.iter().map(Option::is_some);
+        for ((param, ty), annotated) in params.iter().zip(&signature.params).zip(annotated) {
+            self.declare(&param.lexeme, ty.clone(), annotated);
+        }
+        self.hoist(body);
+        let _ = self.visit_stmts(body);
+        self.end_scope();
+        self.depth -= 1;
+        self.reachable = reachable;
+        self.returns.pop();
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Type {
+        match expr {
+            Expr::Literal { value, .. } => match value.tok_typ {
+                TokenType::Number(_) => Type::Number,
+                TokenType::Str(_) => Type::String,
+                TokenType::True | TokenType::False => Type::Bool,
+                TokenType::Nil => Type::Nil,
+                _ => Type::Any,
+            },
+            Expr::Grouping { expr, .. } => self.expr(expr),
+            Expr::Unary {
+                operator, right, ..
+            } => {
+                let right = self.expr(right);
+                match operator.tok_typ {
+                    TokenType::Minus => {
+                        if !self.accepts(&Type::Number, &right) {
+                            let message = format!("Can't negate {}.", right.describe());
+                            self.warn(operator.line, message);
+                        }
+                        Type::Number
+                    }
+                    TokenType::Bang => Type::Bool,
+                    _ => Type::Any,
+                }
+            }
+            Expr::Binary { .. } | Expr::Logical { .. } => self.operator_chain(expr),
+            Expr::Variable { name, .. } => self.read(&name.lexeme),
+            Expr::Assignment { name, value, .. } => {
+                let value = self.expr(value);
+                let declared = self
+                    .lookup(&name.lexeme)
+                    .filter(|binding| binding.annotated)
+                    .map(|binding| binding.ty.clone());
+                if let Some(declared) = declared {
+                    if !self.accepts(&declared, &value) {
+                        self.error(
+                            name.line,
+                            format!(
+                                "Can't assign {value} to `{}`, which is {declared}.",
+                                name.lexeme
+                            ),
+                        );
+                    }
+                }
+                self.assign(&name.lexeme, value.clone());
+                value
+            }
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+                ..
+            } => {
+                let callee_type = self.expr(callee);
+                let arguments: Vec<Type> = arguments.iter().map(|a| self.expr(a)).collect();
+                match callee_type {
+                    Type::Function(Some(signature)) => {
+                        self.check_call(&signature.name, &signature.params, &arguments, paren.line);
+                        signature.returns.clone()
+                    }
+                    Type::Class(class) => {
+                        // A class without `init` takes no arguments.
+                        let params = self
+                            .method(&class, "init")
+                            .map_or(vec![], |init| init.params.clone());
+                        self.check_call(&class, &params, &arguments, paren.line);
+                        Type::Instance(class)
+                    }
+                    Type::Any | Type::Function(None) => Type::Any,
+                    ty => {
+                        let message = format!(
+                            "`{}` is {}, which can't be called.",
+                            callee.source(),
+                            ty.describe()
+                        );
+                        self.warn(paren.line, message);
+                        Type::Any
+                    }
+                }
+            }
+            Expr::Get { object, name, .. } => match self.expr(object) {
+                Type::Instance(class) => self
+                    .method(&class, &name.lexeme)
+                    .map_or(Type::Any, |signature| Type::Function(Some(signature))),
+                _ => Type::Any,
+            },
+            Expr::Super { method, .. } => {
+                let superclass = self
+                    .class
+                    .as_ref()
+                    .and_then(|class| self.classes.get(class))
+                    .and_then(|class| class.superclass.clone());
+                superclass
+                    .and_then(|superclass| self.method(&superclass, &method.lexeme))
+                    .map_or(Type::Any, |signature| Type::Function(Some(signature)))
+            }
+            Expr::This { .. } => self.class.clone().map_or(Type::Any, Type::Instance),
+            Expr::List { elements, .. } => {
+                for element in elements {
+                    self.expr(element);
+                }
+                Type::List
+            }
+            Expr::Match { subject, arms, .. } => {
+                self.expr(subject);
+                // No arm may match, in which case the match gives nil.
+                let before = self.state();
+                let mut after = self.state();
+                for arm in arms {
+                    self.restore(&before);
+                    self.begin_scope();
+                    self.pattern(&arm.pattern);
+                    self.expr(&arm.body);
+                    self.end_scope();
+                    self.join(after);
+                    after = self.state();
+                }
+                Type::Any
+            }
+            _ => {
+                let _ = walk_expr(self, expr);
+                Type::Any
+            }
+        }
+    }
+
+    /// The type of a chain of operators such as `a + b + c`, worked out
+    /// without recursing, as [`crate::visit::walk_operands`] walks them.
+    fn operator_chain(&mut self, expr: &Expr) -> Type {
+        let mut operators = vec![];
+        let mut leftmost = expr;
+        while let Expr::Binary {
+            left,
+            operator,
+            right,
+            ..
+        }
+        | Expr::Logical {
+            left,
+            operator,
+            right,
+            ..
+        } = leftmost
+        {
+            operators.push((operator, right));
+            leftmost = left;
+        }
+        let mut ty = self.expr(leftmost);
+        for (operator, right) in operators.into_iter().rev() {
+            if let TokenType::And | TokenType::Or = operator.tok_typ {
+                // The right operand doesn't always run.
+                let before = self.state();
+                let right = self.expr(right);
+                self.join(before);
+                ty = if ty == right { ty } else { Type::Any };
+            } else {
+                let right = self.expr(right);
+                ty = self.binary(operator, ty, right);
+            }
+        }
+        ty
+    }
+
+    /// The type of a binary operation, warning if it can't succeed.
+    fn binary(&mut self, operator: &Token, left: Type, right: Type) -> Type {
+        let number = |ty: &Type| matches!(ty, Type::Number | Type::Any);
+        let known = left != Type::Any && right != Type::Any;
+        let (valid, result) = match operator.tok_typ {
+            TokenType::Minus | TokenType::Star | TokenType::Slash => {
+                (number(&left) && number(&right), Type::Number)
+            }
+            TokenType::Plus => match (&left, &right) {
+                (Type::Number, Type::Number) => (true, Type::Number),
+                (Type::String, Type::String) => (true, Type::String),
+                (Type::String, _) | (_, Type::String) if self.stringify_operands => {
+                    (true, Type::String)
+                }
+                _ => (!known, Type::Any),
+            },
+            TokenType::Greater
+            | TokenType::GreaterEqual
+            | TokenType::Less
+            | TokenType::LessEqual => {
+                let comparable = matches!(
+                    (&left, &right),
+                    (Type::Number, Type::Number) | (Type::String, Type::String)
+                );
+                (comparable || !known, Type::Bool)
+            }
+            TokenType::EqualEqual | TokenType::BangEqual => (true, Type::Bool),
+            _ => (true, Type::Any),
+        };
+        if !valid {
+            let message = format!(
+                "Can't use `{}` on {} and {}.",
+                operator.lexeme,
+                left.describe(),
+                right.describe()
+            );
+            self.warn(operator.line, message);
+        }
+        result
+    }
+
+    /// Declares the variables a `match` pattern binds, and checks the classes
+    /// it names.
+    fn pattern(&mut self, pattern: &Pattern) {
+        match pattern {
+            Pattern::Binding { name } => self.declare(&name.lexeme, Type::Any, false),
+            Pattern::Class { class, fields, .. } => {
+                self.expr(class);
+                for field in fields {
+                    self.pattern(field);
+                }
+            }
+            Pattern::Wildcard | Pattern::Literal { .. } => (),
+        }
+    }
+}
+
+impl ExprVisitor for Checker {
+    fn visit_expr(&mut self, expr: &Expr) -> Result<(), LoxError> {
+        self.expr(expr);
+        Ok(())
+    }
+}
+
+impl StmtVisitor for Checker {
+    fn visit_stmt(&mut self, stmt: &Stmt) -> Result<(), LoxError> {
+        match stmt {
+            Stmt::Var { name, ty, expr, .. } => {
+                let declared = self.annotation(ty, true);
+                let value = expr.as_ref().map_or(Type::Nil, |expr| self.expr(expr));
+                if expr.is_some() && !self.accepts(&declared, &value) {
+                    self.error(
+                        name.line,
+                        format!(
+                            "Can't initialize `{}`, which is {declared}, with {value}.",
+                            name.lexeme
+                        ),
+                    );
+                }
+                match ty {
+                    Some(_) => self.declare(&name.lexeme, declared, true),
+                    None => self.declare(&name.lexeme, value, false),
+                }
+            }
+            Stmt::Function { .. } => self.function(stmt),
+            Stmt::Class {
+                name,
+                methods,
+                statics,
+                superclass,
+                ..
+            } => {
+                if let Some(superclass) = superclass {
+                    self.expr(superclass);
+                }
+                // Static fields are properties of the class, not variables.
+                for stmt in statics {
+                    if let Stmt::Var {
+                        expr: Some(expr), ..
+                    } = &**stmt
+                    {
+                        self.expr(expr);
+                    }
+                }
+                let enclosing = self.class.replace(name.lexeme.to_string());
+                for method in methods {
+                    self.function(method);
+                }
+                self.class = enclosing;
+            }
+            Stmt::Return { keyword, value, .. } => {
+                let ty = value.as_ref().map_or(Type::Nil, |value| self.expr(value));
+                if let Some((function, expected)) = self.returns.last().cloned() {
+                    if !self.accepts(&expected, &ty) {
+                        self.error(
+                            keyword.line,
+                            format!("`{function}` should return {expected} but returns {ty}."),
+                        );
+                    }
+                }
+                self.reachable = false;
+            }
+            Stmt::Block { stmts, .. } => {
+                self.begin_scope();
+                self.hoist(stmts);
+                self.visit_stmts(stmts)?;
+                self.end_scope();
+            }
+            Stmt::Test { body, .. } => {
+                // A failing test stops at the failure but the next one runs.
+                let reachable = self.reachable;
+                self.begin_scope();
+                self.hoist(body);
+                self.visit_stmts(body)?;
+                self.end_scope();
+                self.reachable = reachable;
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.expr(condition);
+                let before = self.state();
+                let never_runs = self.narrow(condition);
+                self.visit_stmt(then_branch)?;
+                self.reachable &= !never_runs;
+                let after_then = self.state();
+                self.restore(&before);
+                if let Some(else_branch) = else_branch {
+                    self.visit_stmt(else_branch)?;
+                }
+                self.join(after_then);
+            }
+            Stmt::While {
+                condition, body, ..
+            } => {
+                self.forget_assigned(body, None);
+                self.expr(condition);
+                let before = self.state();
+                let never_runs = self.narrow(condition);
+                self.visit_stmt(body)?;
+                self.reachable &= !never_runs;
+                self.join(before);
+            }
+            Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+                ..
+            } => {
+                self.begin_scope();
+                if let Some(initializer) = initializer {
+                    self.visit_stmt(initializer)?;
+                }
+                self.forget_assigned(body, increment.as_deref());
+                if let Some(condition) = condition {
+                    self.expr(condition);
+                }
+                let before = self.state();
+                self.visit_stmt(body)?;
+                if let Some(increment) = increment {
+                    self.expr(increment);
+                }
+                self.join(before);
+                self.end_scope();
+            }
+            _ => walk_stmt(self, stmt)?,
+        }
+        Ok(())
+    }
+}
+
+/// The annotations of a function's declaration.
+struct Annotations {
+    name: Token,
+    param_types: Vec<Option<Token>>,
+    return_type: Option<Token>,
+}
+
+impl Annotations {
+    fn of(stmt: &Stmt) -> Option<Annotations> {
+        match stmt {
+            Stmt::Function {
+                name,
+                param_types,
+                return_type,
+                ..
+            } => Some(Annotations {
+                name: name.clone(),
+                param_types: param_types.clone(),
+                return_type: return_type.clone(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Collects the classes declared anywhere in a program, with the names of
+/// their superclasses and the annotations of their methods.
+struct ClassCollector {
+    classes: Vec<(String, Option<String>, Vec<Annotations>)>,
+}
+
+impl ExprVisitor for ClassCollector {
+    // Classes are only declared by statements, so expressions can be skipped.
+    fn visit_expr(&mut self, _expr: &Expr) -> Result<(), LoxError> {
+        Ok(())
+    }
+}
+
+impl StmtVisitor for ClassCollector {
+    fn visit_stmt(&mut self, stmt: &Stmt) -> Result<(), LoxError> {
+        if let Stmt::Class {
+            name,
+            methods,
+            superclass,
+            ..
+        } = stmt
+        {
+            let superclass = match superclass.as_deref() {
+                Some(Expr::Variable { name, .. }) => Some(name.lexeme.to_string()),
+                _ => None,
+            };
+            let methods = methods
+                .iter()
+                .filter_map(|method| Annotations::of(method))
+                .collect();
+            self.classes
+                .push((name.lexeme.to_string(), superclass, methods));
+        }
+        walk_stmt(self, stmt)
+    }
+}
+
+/// Collects the names of the variables some code assigns.
+#[derive(Default)]
+struct Assignments {
+    assigned: HashSet<String>,
+    /// See [`Checker::captured_assignments`].
+    captured: HashSet<String>,
+    /// The names declared by each function being walked, innermost last.
+    functions: Vec<HashSet<String>>,
+}
+
+impl Assignments {
+    fn declare(&mut self, name: &Token) {
+        if let Some(declared) = self.functions.last_mut() {
+            declared.insert(name.lexeme.to_string());
+        }
+    }
+}
+
+impl ExprVisitor for Assignments {
+    fn visit_expr(&mut self, expr: &Expr) -> Result<(), LoxError> {
+        match expr {
+            Expr::Binary { .. } | Expr::Logical { .. } => return walk_operands(self, expr),
+            Expr::Assignment { name, .. } => {
+                let name = name.lexeme.as_str();
+                self.assigned.insert(name.to_string());
+                if self
+                    .functions
+                    .last()
+                    .is_some_and(|declared| !declared.contains(name))
+                {
+                    self.captured.insert(name.to_string());
+                }
+            }
+            _ => (),
+        }
+        walk_expr(self, expr)
+    }
+
+    fn visit_pattern(&mut self, pattern: &Pattern) -> Result<(), LoxError> {
+        if let Pattern::Binding { name } = pattern {
+            self.declare(name);
+        }
+        walk_pattern(self, pattern)
+    }
+}
+
+impl StmtVisitor for Assignments {
+    fn visit_stmt(&mut self, stmt: &Stmt) -> Result<(), LoxError> {
+        match stmt {
+            Stmt::Var { name, .. } | Stmt::Class { name, .. } => self.declare(name),
+            Stmt::Function { name, params, .. } => {
+                self.declare(name);
+                self.functions.push(
+                    params
+                        .iter()
+                        .map(|param| param.lexeme.to_string())
+                        .collect(),
+                );
+                walk_stmt(self, stmt)?;
+                self.functions.pop();
+                return Ok(());
+            }
+            _ => (),
+        }
+        walk_stmt(self, stmt)
+    }
+}
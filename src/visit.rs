@@ -0,0 +1,394 @@
+//! Walking and rewriting the AST. A pass that only reads the AST implements [`ExprVisitor`] and [`StmtVisitor`],
+//! overriding the methods for the nodes it cares about and calling the
+//! matching `walk_*` function to carry on into their children. Every other
+//! node is walked by the default methods, so passes don't each need an
+//! exhaustive `match`. Passes that change it implement [`Transformer`].
+
+use crate::ast::{Expr, MatchArm, Pattern, Stmt};
+use crate::error::LoxError;
+
+pub trait ExprVisitor {
+    fn visit_expr(&mut self, expr: &Expr) -> Result<(), LoxError> {
+        walk_expr(self, expr)
+    }
+
+    fn visit_pattern(&mut self, pattern: &Pattern) -> Result<(), LoxError> {
+        walk_pattern(self, pattern)
+    }
+}
+
+pub trait StmtVisitor: ExprVisitor {
+    fn visit_stmt(&mut self, stmt: &Stmt) -> Result<(), LoxError> {
+        walk_stmt(self, stmt)
+    }
+
+    fn visit_stmts(&mut self, stmts: &[Box<Stmt>]) -> Result<(), LoxError> {
+        stmts.iter().try_for_each(|stmt| self.visit_stmt(stmt))
+    }
+}
+
+/// Visits the expressions directly inside `expr`, and the patterns of a
+/// `match`, in the order they're evaluated.
+pub fn walk_expr<V: ExprVisitor + ?Sized>(visitor: &mut V, expr: &Expr) -> Result<(), LoxError> {
+    match expr {
+        Expr::Literal { .. } | Expr::Variable { .. } | Expr::This { .. } | Expr::Super { .. } => {}
+        Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+            visitor.visit_expr(left)?;
+            visitor.visit_expr(right)?;
+        }
+        Expr::Unary { right: expr, .. }
+        | Expr::Grouping { expr, .. }
+        | Expr::Assignment { value: expr, .. }
+        | Expr::Get { object: expr, .. } => visitor.visit_expr(expr)?,
+        Expr::Call {
+            callee, arguments, ..
+        } => {
+            visitor.visit_expr(callee)?;
+            for argument in arguments {
+                visitor.visit_expr(argument)?;
+            }
+        }
+        Expr::Set { object, value, .. } => {
+            visitor.visit_expr(object)?;
+            visitor.visit_expr(value)?;
+        }
+        Expr::List { elements, .. } => {
+            for element in elements {
+                visitor.visit_expr(element)?;
+            }
+        }
+        Expr::Index { object, index, .. } => {
+            visitor.visit_expr(object)?;
+            visitor.visit_expr(index)?;
+        }
+        Expr::IndexSet {
+            object,
+            index,
+            value,
+            ..
+        } => {
+            visitor.visit_expr(object)?;
+            visitor.visit_expr(index)?;
+            visitor.visit_expr(value)?;
+        }
+        Expr::Match { subject, arms, .. } => {
+            visitor.visit_expr(subject)?;
+            for arm in arms {
+                visitor.visit_pattern(&arm.pattern)?;
+                visitor.visit_expr(&arm.body)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Visits the operands of a chain of binary and logical operators such as
+/// `a + b + c` in the order they're evaluated, without visiting the operators
+/// in between. The parser nests such chains to the left, so a visitor that has
+/// nothing to do at the operators can use this rather than [`walk_expr`] to
+/// walk long generated chains without overflowing the stack.
+pub fn walk_operands<V: ExprVisitor + ?Sized>(
+    visitor: &mut V,
+    expr: &Expr,
+) -> Result<(), LoxError> {
+    let mut rights = vec![];
+    let mut leftmost = expr;
+    while let Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } = leftmost {
+        rights.push(right);
+        leftmost = left;
+    }
+    visitor.visit_expr(leftmost)?;
+    for right in rights.into_iter().rev() {
+        visitor.visit_expr(right)?;
+    }
+    Ok(())
+}
+
+/// Visits the class and field patterns of a class pattern.
+pub fn walk_pattern<V: ExprVisitor + ?Sized>(
+    visitor: &mut V,
+    pattern: &Pattern,
+) -> Result<(), LoxError> {
+    if let Pattern::Class { class, fields, .. } = pattern {
+        visitor.visit_expr(class)?;
+        for field in fields {
+            visitor.visit_pattern(field)?;
+        }
+    }
+    Ok(())
+}
+
+/// Visits the statements and expressions directly inside `stmt`. A class's
+/// superclass and static fields come before its methods.
+pub fn walk_stmt<V: StmtVisitor + ?Sized>(visitor: &mut V, stmt: &Stmt) -> Result<(), LoxError> {
+    match stmt {
+        Stmt::Expr { expr, .. } | Stmt::Print { expr, .. } => visitor.visit_expr(expr)?,
+        Stmt::Var { expr, .. } => {
+            if let Some(expr) = expr {
+                visitor.visit_expr(expr)?;
+            }
+        }
+        Stmt::Return { value, .. } | Stmt::Yield { value, .. } => {
+            if let Some(value) = value {
+                visitor.visit_expr(value)?;
+            }
+        }
+        Stmt::Block { stmts, .. }
+        | Stmt::Function { body: stmts, .. }
+        | Stmt::Test { body: stmts, .. } => visitor.visit_stmts(stmts)?,
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            visitor.visit_expr(condition)?;
+            visitor.visit_stmt(then_branch)?;
+            if let Some(branch) = else_branch {
+                visitor.visit_stmt(branch)?;
+            }
+        }
+        Stmt::While {
+            condition, body, ..
+        } => {
+            visitor.visit_expr(condition)?;
+            visitor.visit_stmt(body)?;
+        }
+        Stmt::For {
+            initializer,
+            condition,
+            increment,
+            body,
+            ..
+        } => {
+            if let Some(initializer) = initializer {
+                visitor.visit_stmt(initializer)?;
+            }
+            if let Some(condition) = condition {
+                visitor.visit_expr(condition)?;
+            }
+            if let Some(increment) = increment {
+                visitor.visit_expr(increment)?;
+            }
+            visitor.visit_stmt(body)?;
+        }
+        Stmt::Class {
+            methods,
+            statics,
+            superclass,
+            ..
+        } => {
+            if let Some(superclass) = superclass {
+                visitor.visit_expr(superclass)?;
+            }
+            visitor.visit_stmts(statics)?;
+            visitor.visit_stmts(methods)?;
+        }
+    }
+    Ok(())
+}
+
+/// Rewrites the AST bottom-up, for desugarings and instrumentation such as
+/// injecting calls to a tracing function. Each hook gets a node whose children
+/// have already been rewritten and returns the node to put in its place,
+/// which may be a new one made with [`crate::ast::next_node_id`]. Hosts can
+/// have [`crate::Lox`] apply one to every program before it's resolved, see
+/// [`crate::Lox::add_transformer`].
+pub trait Transformer {
+    fn transform_expr(&mut self, expr: Expr) -> Expr {
+        expr
+    }
+
+    fn transform_stmt(&mut self, stmt: Stmt) -> Stmt {
+        stmt
+    }
+}
+
+pub fn rewrite_stmts<T: Transformer + ?Sized>(
+    transformer: &mut T,
+    stmts: Vec<Box<Stmt>>,
+) -> Vec<Box<Stmt>> {
+    stmts
+        .into_iter()
+        .map(|stmt| Box::new(rewrite_stmt(transformer, *stmt)))
+        .collect()
+}
+
+/// Rewrites the children of `stmt`, then `stmt` itself.
+pub fn rewrite_stmt<T: Transformer + ?Sized>(transformer: &mut T, stmt: Stmt) -> Stmt {
+    let mut expr = |expr: Box<Expr>| Box::new(rewrite_expr(transformer, *expr));
+    let stmt = match stmt {
+        Stmt::Expr { id, expr: e } => Stmt::Expr { id, expr: expr(e) },
+        Stmt::Print { id, expr: e } => Stmt::Print { id, expr: expr(e) },
+        Stmt::Var {
+            id,
+            name,
+            ty,
+            expr: e,
+        } => Stmt::Var {
+            id,
+            name,
+            ty,
+            expr: e.map(expr),
+        },
+        Stmt::Return { id, keyword, value } => Stmt::Return {
+            id,
+            keyword,
+            value: value.map(expr),
+        },
+        Stmt::Yield { id, keyword, value } => Stmt::Yield {
+            id,
+            keyword,
+            value: value.map(expr),
+        },
+        Stmt::Block { id, stmts } => Stmt::Block {
+            id,
+            stmts: rewrite_stmts(transformer, stmts),
+        },
+        Stmt::Test { id, name, body } => Stmt::Test {
+            id,
+            name,
+            body: rewrite_stmts(transformer, body),
+        },
+        Stmt::Function {
+            id,
+            name,
+            params,
+            param_types,
+            return_type,
+            body,
+            doc,
+        } => Stmt::Function {
+            id,
+            name,
+            params,
+            param_types,
+            return_type,
+            body: rewrite_stmts(transformer, body),
+            doc,
+        },
+        Stmt::If {
+            id,
+            condition,
+            then_branch,
+            else_branch,
+        } => Stmt::If {
+            id,
+            condition: expr(condition),
+            then_branch: Box::new(rewrite_stmt(transformer, *then_branch)),
+            else_branch: else_branch.map(|branch| Box::new(rewrite_stmt(transformer, *branch))),
+        },
+        Stmt::While {
+            id,
+            condition,
+            body,
+        } => Stmt::While {
+            id,
+            condition: expr(condition),
+            body: Box::new(rewrite_stmt(transformer, *body)),
+        },
+        Stmt::For {
+            id,
+            initializer,
+            condition,
+            increment,
+            body,
+        } => Stmt::For {
+            id,
+            initializer: initializer.map(|stmt| Box::new(rewrite_stmt(transformer, *stmt))),
+            condition: condition.map(|e| Box::new(rewrite_expr(transformer, *e))),
+            increment: increment.map(|e| Box::new(rewrite_expr(transformer, *e))),
+            body: Box::new(rewrite_stmt(transformer, *body)),
+        },
+        Stmt::Class {
+            id,
+            name,
+            methods,
+            statics,
+            superclass,
+            doc,
+        } => Stmt::Class {
+            id,
+            name,
+            superclass: superclass.map(expr),
+            statics: rewrite_stmts(transformer, statics),
+            methods: rewrite_stmts(transformer, methods),
+            doc,
+        },
+    };
+    transformer.transform_stmt(stmt)
+}
+
+/// Rewrites the children of `expr`, then `expr` itself.
+pub fn rewrite_expr<T: Transformer + ?Sized>(transformer: &mut T, mut expr: Expr) -> Expr {
+    let mut rewrite = |expr: &mut Box<Expr>| {
+        let rewritten = rewrite_expr(transformer, *Expr::take(expr));
+        **expr = rewritten;
+    };
+    match &mut expr {
+        Expr::Literal { .. } | Expr::Variable { .. } | Expr::This { .. } | Expr::Super { .. } => {}
+        Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+            rewrite(left);
+            rewrite(right);
+        }
+        Expr::Unary { right: expr, .. }
+        | Expr::Grouping { expr, .. }
+        | Expr::Assignment { value: expr, .. } => rewrite(expr),
+        Expr::Call {
+            callee, arguments, ..
+        } => {
+            rewrite(callee);
+            arguments.iter_mut().for_each(rewrite);
+        }
+        Expr::Get { object, .. } => rewrite(object),
+        Expr::Set { object, value, .. } => {
+            rewrite(object);
+            rewrite(value);
+        }
+        Expr::List { elements, .. } => elements.iter_mut().for_each(rewrite),
+        Expr::Index { object, index, .. } => {
+            rewrite(object);
+            rewrite(index);
+        }
+        Expr::IndexSet {
+            object,
+            index,
+            value,
+            ..
+        } => {
+            rewrite(object);
+            rewrite(index);
+            rewrite(value);
+        }
+        Expr::Match { subject, arms, .. } => {
+            rewrite(subject);
+            *arms = std::mem::take(arms)
+                .into_iter()
+                .map(|arm| MatchArm {
+                    pattern: rewrite_pattern(transformer, arm.pattern),
+                    body: Box::new(rewrite_expr(transformer, *arm.body)),
+                })
+                .collect();
+        }
+    }
+    transformer.transform_expr(expr)
+}
+
+/// Rewrites the class expressions in class patterns.
+fn rewrite_pattern<T: Transformer + ?Sized>(transformer: &mut T, pattern: Pattern) -> Pattern {
+    match pattern {
+        Pattern::Class {
+            class,
+            paren,
+            fields,
+        } => Pattern::Class {
+            class: Box::new(rewrite_expr(transformer, *class)),
+            paren,
+            fields: fields
+                .into_iter()
+                .map(|field| rewrite_pattern(transformer, field))
+                .collect(),
+        },
+        other => other,
+    }
+}
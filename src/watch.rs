@@ -0,0 +1,74 @@
+use crate::error::LoxError;
+use crate::interpreter::Interpreter;
+use crate::Lox;
+use std::fs;
+use std::time::Duration;
+
+/// How often the script is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Runs a script, then runs it again every time its file changes, for
+/// iterating on small scripts without restarting the process.
+pub struct Watcher<'a> {
+    lox: &'a Lox,
+    path: String,
+    /// Keeps the globals of one run for the next instead of starting each run
+    /// with a fresh interpreter.
+    pub keep_globals: bool,
+    interpreter: Interpreter,
+    /// The source of the last run, `None` before the first one.
+    source: Option<String>,
+}
+
+impl<'a> Watcher<'a> {
+    pub fn new(lox: &'a Lox, path: &str) -> Self {
+        Watcher {
+            lox,
+            path: String::from(path),
+            keep_globals: false,
+            interpreter: lox.new_interpreter(),
+            source: None,
+        }
+    }
+
+    /// The interpreter of the last run.
+    pub fn interpreter(&self) -> &Interpreter {
+        &self.interpreter
+    }
+
+    /// Runs the script if its source changed since the last run, reporting any
+    /// error. Returns whether it ran.
+    pub fn run_if_changed(&mut self) -> bool {
+        // Editors may replace the file rather than write to it, so it can be
+        // missing for a moment. Try again on the next poll.
+        let Ok(source) = fs::read_to_string(&self.path) else {
+            return false;
+        };
+        if self.source.as_ref() == Some(&source) {
+            return false;
+        }
+        self.source = Some(source.clone());
+
+        if !self.keep_globals {
+            self.interpreter = self.lox.new_interpreter();
+        }
+        if let Err(e) = self.lox.run_in(&mut self.interpreter, source) {
+            self.lox.report(&e);
+        }
+        true
+    }
+
+    /// Runs the script and keeps re-running it on changes until the process
+    /// is killed. Only fails if the file can't be found to begin with.
+    pub fn run(&mut self) -> Result<(), LoxError> {
+        if let Err(e) = fs::metadata(&self.path) {
+            return LoxError::new_io(&self.path, e);
+        }
+        loop {
+            if self.run_if_changed() {
+                eprintln!("[watch] Waiting for `{}` to change.", self.path);
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
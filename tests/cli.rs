@@ -0,0 +1,503 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn loxrt(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_lox"))
+        .args(args)
+        .output()
+        .expect("failed to run loxrt")
+}
+
+/// Runs the REPL with `input` typed into it.
+fn repl(input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run loxrt");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().expect("failed to run loxrt")
+}
+
+#[test]
+fn evaluates_code_from_the_command_line() {
+    let output = loxrt(&["-e", "print 1 + 2;"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "3\n");
+}
+
+#[test]
+fn prints_the_value_of_code_from_the_command_line() {
+    let output = loxrt(&["-e", "1 + 2"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "3\n");
+
+    let output = loxrt(&["-e", "var a = \"b\"; a + \"c\";"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "bc\n");
+
+    let output = loxrt(&["-e", "1 +"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn reports_errors_as_json() {
+    let output = loxrt(&[
+        "--error-format=json",
+        "-e",
+        "var a = len(\"b\");\nprint a + \"b\";",
+    ]);
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr).lines().next(),
+        Some(
+            r#"{"severity":"error","file":null,"line":2,"column":null,"code":"runtime","message":"Invalid operands for operator `+`.\n\tCannot add `1` with `b`","suggestion":null}"#
+        )
+    );
+}
+
+#[test]
+fn check_reports_errors_without_running() {
+    let output = loxrt(&["--check", "-e", "print \"ran\";"]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    let output = loxrt(&["--check", "-e", "print \"ran\"; return 1;"]);
+    assert_eq!(output.status.code(), Some(4));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn stringify_operands_converts_the_other_operand() {
+    let source = "print \"count: \" + 3; print 2.5 + \"!\"; print \"\" + nil;";
+    let output = loxrt(&["--stringify-operands", "-e", source]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "count: 3\n2.5!\nNil\n"
+    );
+
+    let output = loxrt(&["-e", "print \"count: \" + 3;"]);
+    assert_eq!(output.status.code(), Some(3));
+}
+
+#[test]
+fn ieee_division_allows_dividing_by_zero() {
+    let output = loxrt(&["--ieee-division", "-e", "print 1 / 0; print -1 / 0;"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "inf\n-inf\n");
+
+    let output = loxrt(&["-e", "print 1 / 0;"]);
+    assert_eq!(output.status.code(), Some(3));
+}
+
+#[test]
+fn ieee_division_results_can_be_checked() {
+    let source =
+        "print isNan(0 / 0); print isFinite(1 / 0); print isFinite(0 / 0); print isFinite(2);";
+    let output = loxrt(&["--ieee-division", "-e", source]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "true\nfalse\nfalse\ntrue\n"
+    );
+}
+
+#[test]
+fn nil_properties_read_as_nil() {
+    let source = "class A {} var a = A(); a.b = nil; print a.b.c.d;";
+    let output = loxrt(&["--nil-properties", "-e", source]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Nil\n");
+
+    let output = loxrt(&["--nil-properties", "-e", "var a; a.b = 1;"]);
+    assert_eq!(output.status.code(), Some(3));
+}
+
+#[test]
+fn uninitialized_errors_reject_reading_unassigned_variables() {
+    let source = "var a; a = 1; print a; var b; print b;";
+    let output = loxrt(&["--uninitialized-errors", "-e", source]);
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n");
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("Variable `b` used before assignment.")
+    );
+
+    let source = "fun f() { var c; return c; } f();";
+    let output = loxrt(&["--uninitialized-errors", "-e", source]);
+    assert_eq!(output.status.code(), Some(3));
+}
+
+#[test]
+fn strict_turns_warnings_into_errors() {
+    let source = "fun f() {\n  var unused = 1;\n}\nf();\nprint \"ran\";";
+    let output = loxrt(&["-e", source]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "ran\n");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "[line 2] Warning: Local variable `unused` is never read.\n"
+    );
+
+    let output = loxrt(&["--strict", "-e", source]);
+    assert_eq!(output.status.code(), Some(4));
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "[line 2] Error: Local variable `unused` is never read.\n"
+    );
+}
+
+#[test]
+fn strict_disables_lenient_coercions() {
+    let output = loxrt(&["--strict", "--stringify-operands", "-e", "print \"a\" + 1;"]);
+    // Known to fail before running, so it's a type error.
+    assert_eq!(output.status.code(), Some(6));
+
+    let output = loxrt(&["--strict", "--ieee-division", "-e", "print 1 / 0;"]);
+    assert_eq!(output.status.code(), Some(3));
+}
+
+#[test]
+fn emits_javascript() {
+    let source = "class Point {\n  init(x, y) {\n    this.x = x;\n    this.y = y;\n  }\n}\nvar p = Point(1, 2);\nprint match p { Point(x, 0) => \"axis\", Point(x, y) => x + y };\nprint 0 and \"zero is truthy\";";
+    let output = loxrt(&["emit-js", "-e", source]);
+    assert!(output.status.success());
+    let js = String::from_utf8_lossy(&output.stdout);
+    assert!(js.contains("var Point = class Point extends $Object {"));
+
+    // Errors are reported like they are for a run, and nothing is emitted.
+    let output = loxrt(&["emit-js", "-e", "return 1;"]);
+    assert_eq!(output.status.code(), Some(4));
+    assert!(output.stdout.is_empty());
+
+    // Run the output if Node is around.
+    let path = std::env::temp_dir().join(format!("emit-js-{}.js", std::process::id()));
+    std::fs::write(&path, js.as_bytes()).unwrap();
+    let node = Command::new("node").arg(&path).output();
+    std::fs::remove_file(&path).unwrap();
+    if let Ok(node) = node {
+        assert_eq!(String::from_utf8_lossy(&node.stdout), "3\nzero is truthy\n");
+    }
+}
+
+#[test]
+fn memory_limit_stops_runaway_scripts() {
+    let source = "var s = \"\"; while (true) s = s + \"0123456789\";";
+    let output = loxrt(&["--memory-limit=100000", "-e", source]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("Memory limit of 100000 bytes exceeded.")
+    );
+}
+
+#[test]
+fn gc_stress_measures_before_every_allocation() {
+    let source = "var l = [1, 2]; var s = \"a\" + \"b\"; print gcStats().collections;";
+    let output = loxrt(&["--gc-stress", "-e", source]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "4\n");
+
+    let output = loxrt(&["-e", source]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "0\n");
+}
+
+#[test]
+fn repl_doc_shows_the_docs_of_a_value() {
+    let output = repl("/// Says hi.\nfun greet() {}\n:doc greet\n:nope\n");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("fun greet()\n  Says hi.\n"), "{stdout}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unknown command `:nope`."));
+}
+
+#[test]
+fn repl_type_describes_values_without_printing_them() {
+    let input = "class A { init(x) { this.x = x; } area() {} }\nclass B < A {}\n\
+                 :type B(1)\n:type [1, 2]\n:type \"hi\";\n:type var a = 1\n";
+    let output = repl(input);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("instance of B\n  fields: x\n  methods: area, init\n"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("list of 2 elements\n"), "{stdout}");
+    assert!(stdout.contains("string\n"), "{stdout}");
+    // Only expressions can be described.
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Error: Unexpected Token: Var"));
+}
+
+#[test]
+fn repl_load_runs_a_file_in_the_session() {
+    let path = std::env::temp_dir().join(format!("load-{}.lox", std::process::id()));
+    std::fs::write(
+        &path,
+        "var base = 10;\nfun add(n) { return base + n; }\nprint \"loaded\";",
+    )
+    .unwrap();
+    let input = format!(
+        "var base = 1;\n:load {}\nadd(5);\nbase = 20;\nadd(5);\n:load missing.lox\n",
+        path.display()
+    );
+    let output = repl(&input);
+    std::fs::remove_file(&path).unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout
+        .lines()
+        .map(|line| line.trim_start_matches("> "))
+        .filter(|line| !line.is_empty())
+        .collect();
+    // The file's globals replace the session's, and later lines can use them.
+    assert_eq!(lines, ["loaded", "15", "20", "25"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("missing.lox"));
+}
+
+#[test]
+fn several_scripts_run_in_one_session() {
+    let dir = std::env::temp_dir().join(format!("session-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (a, b, c) = (dir.join("a.lox"), dir.join("b.lox"), dir.join("c.lox"));
+    std::fs::write(&a, "var base = 10;\nfun add(n) { return base + n; }").unwrap();
+    std::fs::write(&b, "print add(5);\nbase = 20;\nprint missing;").unwrap();
+    std::fs::write(&c, "print \"not run\";").unwrap();
+    let paths: Vec<&str> = [&a, &b, &c].map(|path| path.to_str().unwrap()).to_vec();
+    let output = loxrt(&paths);
+    let json = loxrt(&["--error-format=json", paths[0], paths[1]]);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "15\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with(&format!("[{} line 3] Error:", paths[1])));
+    let stderr = String::from_utf8_lossy(&json.stderr);
+    assert!(stderr.contains(&format!("\"file\":\"{}\",\"line\":3", paths[1])));
+}
+
+#[test]
+fn modes_that_take_one_script_reject_several() {
+    let dir = std::env::temp_dir().join(format!("one-script-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (broken, ok) = (dir.join("broken.lox"), dir.join("ok.lox"));
+    std::fs::write(&broken, "var = ;").unwrap();
+    std::fs::write(&ok, "print 1;").unwrap();
+    let (broken, ok) = (broken.to_str().unwrap(), ok.to_str().unwrap());
+    let outputs = [
+        loxrt(&["--check", broken, ok]),
+        loxrt(&["test", broken, ok]),
+        loxrt(&["emit-js", broken, ok]),
+        loxrt(&["--watch", broken, ok]),
+        loxrt(&["-e", "print 2;", broken, ok]),
+    ];
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    for output in outputs {
+        assert_eq!(output.status.code(), Some(64));
+        assert!(output.stdout.is_empty());
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains("takes one script, but 2 were given.")
+        );
+    }
+}
+
+#[test]
+fn repl_shows_the_values_of_expressions() {
+    let output = repl("1 + 2;\nvar a = [1, \"x\"];\na;\nnil;\nprint \"done\";\n");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout
+        .lines()
+        .map(|line| line.trim_start_matches("> "))
+        .filter(|line| !line.is_empty())
+        .collect();
+    assert_eq!(lines, ["3", "[1, \"x\"]", "done"]);
+}
+
+#[test]
+fn preludes_define_globals_for_the_script() {
+    let path = std::env::temp_dir().join(format!("prelude-{}.lox", std::process::id()));
+    std::fs::write(&path, "fun twice(n) { return max(n, 0) * 2; }").unwrap();
+    let prelude = format!("--prelude={}", path.display());
+    let output = loxrt(&[&prelude, "-e", "print twice(3);"]);
+    std::fs::write(&path, "fun broken( {").unwrap();
+    let broken = loxrt(&[&prelude, "-e", "print 1;"]);
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "6\n");
+    // The script doesn't run if its prelude has errors.
+    assert_eq!(broken.status.code(), Some(2));
+    assert!(broken.stdout.is_empty());
+}
+
+#[test]
+fn natives_need_permission_for_side_effects() {
+    let source = "print exec(\"echo\", [\"hi\"]).stdout;";
+    let output = loxrt(&["-e", source]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("Process execution is disabled. Run with `--allow-run` to enable it."));
+
+    for flag in ["--allow-run", "--allow-exec", "--allow-all"] {
+        let output = loxrt(&[flag, "-e", source]);
+        assert!(output.status.success(), "{flag}");
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "hi\n\n");
+    }
+    // Other permissions don't allow running processes.
+    let output = loxrt(&["--allow-read", "--allow-env", "-e", source]);
+    assert_eq!(output.status.code(), Some(3));
+}
+
+#[test]
+fn files_are_read_and_written_a_line_at_a_time() {
+    let path = std::env::temp_dir().join(format!("handle-{}.txt", std::process::id()));
+    let path = path.to_str().unwrap();
+    std::fs::write(path, "one\r\ntwo\n").unwrap();
+    let source = format!(
+        "var f = open(\"{path}\", \"a+\");
+        f.write(3);
+        f.seek(0);
+        print f.readLine();
+        print f.readLine();
+        f.seek(5);
+        print f.readLine();
+        print f.readLine();
+        print f.readLine();
+        f.close();
+        f.readLine();"
+    );
+    let output = loxrt(&["--allow-read", "--allow-write", "-e", &source]);
+    let written = std::fs::read_to_string(path);
+    let read_only = loxrt(&["--allow-read", "-e", &source]);
+    std::fs::remove_file(path).unwrap();
+
+    assert_eq!(written.unwrap(), "one\r\ntwo\n3");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "one\ntwo\ntwo\n3\nNil\n"
+    );
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("The file is closed."));
+    assert!(String::from_utf8_lossy(&read_only.stderr)
+        .contains("Writing files is disabled. Run with `--allow-write` to enable it."));
+}
+
+#[test]
+fn files_are_written_where_reading_left_off() {
+    let path = std::env::temp_dir().join(format!("read-write-{}.txt", std::process::id()));
+    let path = path.to_str().unwrap();
+    std::fs::write(path, "one\ntwo\nthree\n").unwrap();
+    let source = format!(
+        "var f = open(\"{path}\", \"r+\");
+        print f.readLine();
+        f.write(\"TWO\");
+        print f.readLine();
+        print f.readLine();
+        f.close();"
+    );
+    let output = loxrt(&["--allow-read", "--allow-write", "-e", &source]);
+    let written = std::fs::read_to_string(path);
+    std::fs::remove_file(path).unwrap();
+
+    assert_eq!(written.unwrap(), "one\nTWO\nthree\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "one\n\nthree\n");
+}
+
+#[test]
+fn directories_are_listed_and_paths_worked_out() {
+    let dir = std::env::temp_dir().join(format!("list-dir-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("sub")).unwrap();
+    std::fs::write(dir.join("b.lox"), "").unwrap();
+    let dir = dir.to_str().unwrap();
+    let source = format!(
+        "var dir = \"{dir}\";
+        print listDir(dir);
+        print isDir(joinPath(dir, \"sub\"));
+        print isDir(joinPath(dir, \"b.lox\"));
+        print exists(joinPath(dir, \"b.lox\"));
+        print exists(joinPath(dir, \"c.lox\"));"
+    );
+    let output = loxrt(&["--allow-read", "-e", &source]);
+    let denied = loxrt(&["-e", &source]);
+    std::fs::remove_dir_all(dir).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "[b.lox, sub]\ntrue\nfalse\ntrue\nfalse\n"
+    );
+    assert!(String::from_utf8_lossy(&denied.stderr)
+        .contains("Reading files is disabled. Run with `--allow-read` to enable it."));
+
+    // Working out paths doesn't touch the filesystem.
+    let source = "print joinPath(\"a\", \"b.lox\"); print basename(\"a/b.lox\"); \
+        print dirname(\"a/b.lox\"); print basename(\"/\"); print joinPath(\"a\", \"/b\");";
+    let output = loxrt(&["-e", source]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "a/b.lox\nb.lox\na\nNil\n/b\n"
+    );
+}
+
+#[test]
+fn scripts_can_find_out_about_their_environment() {
+    let source = "print platform(); print pid(); print cwd(); print hostname() != nil;";
+    let output = loxrt(&["--allow-read", "--allow-env", "-e", source]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines[0], std::env::consts::OS);
+    assert!(lines[1].parse::<u32>().is_ok());
+    let cwd = std::env::current_dir().unwrap();
+    assert_eq!(lines[2..], [cwd.to_str().unwrap(), "true"]);
+
+    let output = loxrt(&["--allow-read", "-e", source]);
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("Environment access is disabled. Run with `--allow-env` to enable it."));
+}
+
+#[test]
+fn tests_every_test_file_in_a_directory() {
+    let dir = std::env::temp_dir().join(format!("test-dir-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("nested")).unwrap();
+    std::fs::write(
+        dir.join("math_test.lox"),
+        "test \"adds\" { assertEq(1 + 2, 3); }\ntest \"subtracts\" { assertEq(2 - 1, 0); }",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("nested/strings_test.lox"),
+        "test \"joins\" { assertEq(\"a\" + \"b\", \"ab\"); }",
+    )
+    .unwrap();
+    // Only files ending in `_test.lox` are run.
+    std::fs::write(dir.join("helper.lox"), "print \"not a test\";").unwrap();
+    let path = dir.to_str().unwrap();
+    let all = loxrt(&["test", path]);
+    let parallel = loxrt(&["test", "--jobs=4", path]);
+    let filtered = loxrt(&["test", "--filter", "add", path]);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    for output in [&all, &parallel] {
+        assert_eq!(output.status.code(), Some(1));
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(!stdout.contains("not a test"));
+        let lines: Vec<&str> = stdout.lines().filter(|l| l.starts_with("test ")).collect();
+        assert_eq!(
+            lines[..3],
+            [
+                "test adds ... ok",
+                "test subtracts ... FAILED",
+                "test joins ... ok"
+            ]
+        );
+        assert!(lines[3].starts_with("test result: FAILED. 2 passed; 1 failed; finished in "));
+    }
+    assert!(filtered.status.success());
+    assert!(String::from_utf8_lossy(&filtered.stdout)
+        .contains("test result: ok. 1 passed; 0 failed; 2 filtered out;"));
+}
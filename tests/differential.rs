@@ -0,0 +1,99 @@
+//! Runs every `.lox` file under `tests/lox` (or the directory named by
+//! `LOX_TEST_DIR`) through both loxrt and a reference implementation such as
+//! jlox or clox, and fails if their output or exit code differ.
+//!
+//! The reference is only run when `LOX_REFERENCE` names it, e.g.
+//! `LOX_REFERENCE=/path/to/clox` or `LOX_REFERENCE="java -cp build com.craftinginterpreters.lox.Lox"`;
+//! the words after the first are passed as arguments before the script's path.
+//! loxrt runs with `--sysexits` so its exit codes match the book's.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn lox_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap_or_else(|e| panic!("Failed to read {}: {e}", dir.display()))
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            lox_files(&path, files);
+        } else if path.extension().is_some_and(|ext| ext == "lox") {
+            files.push(path);
+        }
+    }
+}
+
+fn run(program: &str, args: &[&str], path: &Path) -> Output {
+    Command::new(program)
+        .args(args)
+        .arg(path)
+        .output()
+        .unwrap_or_else(|e| panic!("Failed to run {program}: {e}"))
+}
+
+/// Describes how the two runs of `path` differ, if they do.
+fn compare(path: &Path, loxrt: &Output, reference: &Output) -> Option<String> {
+    let mut problems = vec![];
+    if loxrt.status.code() != reference.status.code() {
+        problems.push(format!(
+            "    exit code: loxrt {:?}, reference {:?}",
+            loxrt.status.code(),
+            reference.status.code()
+        ));
+    }
+    let ours = String::from_utf8_lossy(&loxrt.stdout);
+    let theirs = String::from_utf8_lossy(&reference.stdout);
+    let (ours, theirs): (Vec<&str>, Vec<&str>) = (ours.lines().collect(), theirs.lines().collect());
+    // The first differing line is usually enough to find the divergence.
+    let line = (0..ours.len().max(theirs.len())).find(|&i| ours.get(i) != theirs.get(i));
+    if let Some(i) = line {
+        problems.push(format!(
+            "    line {} of output: loxrt {:?}, reference {:?}",
+            i + 1,
+            ours.get(i).copied().unwrap_or("<none>"),
+            theirs.get(i).copied().unwrap_or("<none>")
+        ));
+    }
+    if problems.is_empty() {
+        None
+    } else {
+        Some(format!("{}:\n{}", path.display(), problems.join("\n")))
+    }
+}
+
+#[test]
+fn output_matches_the_reference_implementation() {
+    let Ok(reference) = std::env::var("LOX_REFERENCE") else {
+        eprintln!("LOX_REFERENCE isn't set, skipping differential tests");
+        return;
+    };
+    let mut reference = reference.split_whitespace();
+    let program = reference.next().expect("LOX_REFERENCE is empty");
+    let reference_args: Vec<&str> = reference.collect();
+
+    let dir = std::env::var("LOX_TEST_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/lox"));
+    let mut files = vec![];
+    lox_files(&dir, &mut files);
+
+    let divergences: Vec<String> = files
+        .iter()
+        .filter_map(|path| {
+            let loxrt = run(env!("CARGO_BIN_EXE_lox"), &["--sysexits"], path);
+            let reference = run(program, &reference_args, path);
+            compare(path, &loxrt, &reference)
+        })
+        .collect();
+
+    assert!(
+        divergences.is_empty(),
+        "{} of {} files diverge from the reference:\n{}",
+        divergences.len(),
+        files.len(),
+        divergences.join("\n")
+    );
+}
@@ -0,0 +1,965 @@
+use lox::ast::{next_node_id, Expr, Stmt};
+use lox::diagnostics::{Diagnostic, Severity, Span, Suggestion};
+use lox::error::{LoxError, Phase};
+use lox::hooks::InterpreterHooks;
+use lox::host::LoxObject;
+use lox::interpreter::{Callable, Interpreter, Metrics, Types};
+use lox::permissions::Permission;
+use lox::sync::{Rc, RefCell};
+use lox::tokens::{Token, TokenType};
+use lox::trace::{Trace, TraceEntry, TraceValue};
+use lox::visit::Transformer;
+use lox::watch::Watcher;
+use lox::Lox;
+
+#[test]
+fn globals_round_trip_between_host_and_script() {
+    let lox = Lox::new();
+    let mut interpreter = lox.new_interpreter();
+    interpreter.set_global("base", Types::Number(40.0));
+
+    lox.run_in(&mut interpreter, String::from("var answer = base + 2;"))
+        .unwrap();
+
+    assert_eq!(interpreter.get_global("answer"), Some(Types::Number(42.0)));
+    assert_eq!(interpreter.get_global("missing"), None);
+    assert!(interpreter.globals().any(|(name, _)| name == "answer"));
+}
+
+#[test]
+fn restoring_a_snapshot_undoes_global_changes() {
+    let lox = Lox::new();
+    let mut interpreter = lox.new_interpreter();
+    lox.run_in(&mut interpreter, String::from("var a = 1;"))
+        .unwrap();
+
+    let snapshot = interpreter.snapshot(false);
+    lox.run_in(&mut interpreter, String::from("a = 2; var b = 3;"))
+        .unwrap();
+    interpreter.restore(&snapshot);
+
+    assert_eq!(interpreter.get_global("a"), Some(Types::Number(1.0)));
+    assert_eq!(interpreter.get_global("b"), None);
+}
+
+#[test]
+fn data_globals_are_saved_and_restored_as_json() {
+    let lox = Lox::new();
+    let mut interpreter = lox.new_interpreter();
+    let source = "var count = 2.5; var name = \"a b\"; var items = [1, [true, nil]];
+        fun f() {} class C {} var c = C(); var zero = 0;";
+    lox.run_in(&mut interpreter, String::from(source)).unwrap();
+
+    let (json, warnings) = interpreter.export_globals_json();
+    assert_eq!(
+        json,
+        r#"{"count":2.5,"items":[1,[true,null]],"name":"a b","zero":0}"#
+    );
+    // Natives and the prelude's functions are left out without a warning.
+    assert_eq!(
+        warnings,
+        [
+            "`C` is a class, so it wasn't exported.",
+            "`c` is an instance of C, so it wasn't exported.",
+            "`f` is a function, so it wasn't exported.",
+        ]
+    );
+
+    let mut restored = lox.new_interpreter();
+    restored.import_globals_json(&json).unwrap();
+    lox.run_in(&mut restored, String::from("var total = count + items[0];"))
+        .unwrap();
+    assert_eq!(restored.get_global("total"), Some(Types::Number(3.5)));
+    assert_eq!(
+        restored.export_globals_json().0,
+        r#"{"count":2.5,"items":[1,[true,null]],"name":"a b","total":3.5,"zero":0}"#
+    );
+
+    let error = restored
+        .import_globals_json(r#"{"late": 1, "nested": {"a": 1}}"#)
+        .unwrap_err();
+    assert_eq!(
+        error,
+        "Objects are only supported at the top level, found one at byte 22."
+    );
+    assert_eq!(restored.get_global("late"), None);
+    assert!(restored
+        .import_globals_json("{\"x\": \"\\ud83d\\ude00\"}")
+        .is_ok());
+    assert_eq!(restored.get_global("x"), Some(Types::from("😀")));
+
+    for number in [
+        "-inf",
+        "-infinity",
+        "-nan",
+        "01",
+        "-",
+        "1.",
+        ".5",
+        "1e",
+        "+1",
+    ] {
+        let json = format!("{{\"n\": {number}}}");
+        assert!(
+            restored.import_globals_json(&json).is_err(),
+            "{number} isn't a JSON number"
+        );
+    }
+    restored
+        .import_globals_json(r#"{"a": -0, "b": 0.5, "c": -1.25e+2, "d": 10E-1}"#)
+        .unwrap();
+    assert_eq!(restored.get_global("c"), Some(Types::Number(-125.0)));
+    assert_eq!(restored.get_global("d"), Some(Types::Number(1.0)));
+}
+
+#[test]
+fn errors_expose_their_phase_line_and_message() {
+    let lox = Lox::new();
+    let error = lox
+        .run_in(&mut lox.new_interpreter(), String::from("\nprint missing;"))
+        .unwrap_err();
+
+    assert_eq!(error.phase(), Phase::Runtime);
+    assert_eq!(error.line(), Some(2));
+    assert_eq!(
+        error.message(),
+        "Failed to get undefined variable `missing`."
+    );
+    assert_eq!(
+        error.to_string(),
+        "[line 2] Error: Failed to get undefined variable `missing`."
+    );
+
+    let boxed: Box<dyn std::error::Error> = Box::new(error);
+    assert!(boxed.source().is_none());
+}
+
+#[test]
+fn diagnostics_can_be_collected_instead_of_printed() {
+    let diagnostics = Rc::new(RefCell::new(Vec::<Diagnostic>::new()));
+    let mut lox = Lox::new();
+    lox.set_diagnostic_sink(Box::new(diagnostics.clone()));
+
+    let error = lox
+        .run_in(
+            &mut lox.new_interpreter(),
+            String::from("var a = ;\nvar b = ;"),
+        )
+        .unwrap_err();
+    lox.report(&error);
+
+    let diagnostics = diagnostics.borrow();
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(diagnostics[1].severity, Severity::Error);
+    assert_eq!(diagnostics[1].phase, Phase::Parse);
+    assert_eq!(
+        diagnostics[1].span,
+        Some(Span {
+            line: 2,
+            column: None
+        })
+    );
+}
+
+#[test]
+fn parsing_stops_after_the_maximum_number_of_errors() {
+    let mut lox = Lox::new();
+    lox.max_errors = 2;
+    let error = lox
+        .run_in(
+            &mut lox.new_interpreter(),
+            String::from("var a = ;\nvar b = ;\nvar c = ;"),
+        )
+        .unwrap_err();
+
+    let diagnostics = error.diagnostics();
+    assert_eq!(diagnostics.len(), 3);
+    assert_eq!(diagnostics[2].message, "Too many errors, stopping after 2.");
+}
+
+#[test]
+fn missing_semicolons_suggest_where_to_insert_one() {
+    let lox = Lox::new();
+    let error = lox
+        .run_in(
+            &mut lox.new_interpreter(),
+            String::from("var a = 1;\nprint  a + 2\nprint a;"),
+        )
+        .unwrap_err();
+
+    let diagnostics = error.diagnostics();
+    assert_eq!(
+        diagnostics[0].suggestion,
+        Some(Suggestion {
+            span: Span {
+                line: 2,
+                column: Some(13)
+            },
+            insert: String::from(";"),
+        })
+    );
+    assert!(diagnostics[0]
+        .to_string()
+        .ends_with("help: insert `;` at line 2, column 13"));
+}
+
+#[test]
+fn resolver_warnings_are_reported_to_the_sink() {
+    let diagnostics = Rc::new(RefCell::new(Vec::<Diagnostic>::new()));
+    let mut lox = Lox::new();
+    lox.set_diagnostic_sink(Box::new(diagnostics.clone()));
+
+    let source =
+        "fun f(a) {\n  var b;\n  print b;\n  {\n    var a = 1;\n    print a;\n  }\n  c = 2;\n}";
+    lox.run_in(&mut lox.new_interpreter(), String::from(source))
+        .unwrap();
+
+    let diagnostics = diagnostics.borrow();
+    let messages: Vec<(usize, &str)> = diagnostics
+        .iter()
+        .map(|d| (d.span.unwrap().line, d.message.as_str()))
+        .collect();
+    assert_eq!(
+        messages,
+        [
+            (3, "`b` is read before it's assigned a value."),
+            (5, "`a` shadows a variable of an enclosing scope."),
+            (1, "Parameter `a` is never read."),
+            (8, "`c` is assigned but never declared."),
+        ]
+    );
+    assert!(diagnostics
+        .iter()
+        .all(|d| d.severity == Severity::Warning && d.phase == Phase::Resolve));
+}
+
+#[test]
+fn unused_parameters_and_functions_are_reported() {
+    let diagnostics = Rc::new(RefCell::new(Vec::<Diagnostic>::new()));
+    let mut lox = Lox::new();
+    lox.set_diagnostic_sink(Box::new(diagnostics.clone()));
+
+    let source = "fun used(a, _b) {\n  return used(a, 1);\n}\nfun unused(c) {}\n\
+                  fun _ignored() {}\nclass A {\n  m(d) {}\n}\nprint used;";
+    lox.check(String::from(source)).unwrap();
+
+    let messages: Vec<(usize, String)> = diagnostics
+        .borrow()
+        .iter()
+        .map(|d| (d.span.unwrap().line, d.message.clone()))
+        .collect();
+    assert_eq!(
+        messages,
+        [
+            (4, String::from("Parameter `c` is never read.")),
+            (4, String::from("Function `unused` is never used.")),
+        ]
+    );
+}
+
+#[test]
+fn strict_mode_rejects_programs_with_warnings() {
+    let mut lox = Lox::new();
+    lox.options.strict = true;
+
+    let error = lox
+        .run_in(&mut lox.new_interpreter(), String::from("x = 1;"))
+        .unwrap_err();
+    assert_eq!(error.phase(), Phase::Resolve);
+    assert_eq!(error.message(), "`x` is assigned but never declared.");
+
+    lox.run_in(&mut lox.new_interpreter(), String::from("var x; x = 1;"))
+        .unwrap();
+    // Calls guarded by checking the variable isn't nil can't fail.
+    lox.run_in(
+        &mut lox.new_interpreter(),
+        String::from("var x = nil; if (x) { x(); }"),
+    )
+    .unwrap();
+}
+
+#[test]
+fn type_annotations_are_checked_before_running() {
+    let lox = Lox::new();
+    let source = "
+        class Animal {}
+        class Dog < Animal {}
+        fun name(animal: Animal): String { return 1; }
+        var count: Number = \"none\";
+        name(Dog());
+        name(Animal);
+        var label: Label;
+        count = count + 1;
+        print \"unreachable\";";
+    let error = lox.check(String::from(source)).unwrap_err();
+    assert_eq!(error.phase(), Phase::Type);
+    let messages: Vec<String> = error
+        .diagnostics()
+        .into_iter()
+        .map(|d| d.to_string())
+        .collect();
+    assert_eq!(
+        messages,
+        [
+            "[line 4] Error: `name` should return String but returns Number.",
+            "[line 5] Error: Can't initialize `count`, which is Number, with String.",
+            "[line 7] Error: Argument 1 of `name` should be Animal but is class Animal.",
+            "[line 8] Error: Unknown type `Label`.",
+        ]
+    );
+
+    // Code without annotations is as dynamic as ever.
+    lox.check(String::from(
+        "var a = 1; a = \"one\"; fun f(x) { return x; } f(nil);",
+    ))
+    .unwrap();
+}
+
+#[test]
+fn operations_that_will_fail_are_warned_about() {
+    let diagnostics = Rc::new(RefCell::new(Vec::<Diagnostic>::new()));
+    let mut lox = Lox::new();
+    lox.set_diagnostic_sink(Box::new(diagnostics.clone()));
+
+    let source = "
+        var n = 1;
+        n();
+        fun add(a, b) { return a + b; }
+        add(1, 2, 3);
+        print \"a\" + true;
+        var s = \"a\";
+        if (n > 0) s = 2;
+        print s + true;
+        len(\"a\", \"b\");
+        var callback = nil;
+        if (callback) callback();
+        while (callback != nil and n) callback();
+        callback();";
+    lox.check(String::from(source)).unwrap();
+
+    let diagnostics = diagnostics.borrow();
+    let messages: Vec<(usize, &str)> = diagnostics
+        .iter()
+        .filter(|d| d.phase == Phase::Type)
+        .map(|d| (d.span.unwrap().line, d.message.as_str()))
+        .collect();
+    assert_eq!(
+        messages,
+        [
+            (3, "`n` is a number, which can't be called."),
+            (5, "`add` expects 2 arguments but is called with 3."),
+            (6, "Can't use `+` on a string and a boolean."),
+            (10, "`len` expects 1 arguments but is called with 2."),
+            (14, "`callback` is nil, which can't be called."),
+        ]
+    );
+}
+
+#[test]
+fn watcher_reruns_changed_scripts() {
+    let path = std::env::temp_dir().join(format!("watch-{}.lox", std::process::id()));
+    let path_str = path.to_str().unwrap();
+    let lox = Lox::new();
+    let mut watcher = Watcher::new(&lox, path_str);
+    watcher.keep_globals = true;
+
+    std::fs::write(&path, "var count = 1;").unwrap();
+    assert!(watcher.run_if_changed());
+    assert!(!watcher.run_if_changed());
+
+    std::fs::write(&path, "count = count + 1;").unwrap();
+    assert!(watcher.run_if_changed());
+    assert_eq!(
+        watcher.interpreter().get_global("count"),
+        Some(Types::Number(2.0))
+    );
+
+    // Without keeping globals each run starts over.
+    watcher.keep_globals = false;
+    std::fs::write(&path, "var fresh = true;").unwrap();
+    assert!(watcher.run_if_changed());
+    assert_eq!(watcher.interpreter().get_global("count"), None);
+    assert_eq!(
+        watcher.interpreter().get_global("fresh"),
+        Some(Types::Bool(true))
+    );
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn recorded_inputs_can_be_replayed() {
+    let lox = Lox::new();
+    let mut interpreter = lox.new_interpreter();
+    interpreter.record();
+    lox.run_in(&mut interpreter, String::from("var t = clock();"))
+        .unwrap();
+    let trace = interpreter.take_trace().unwrap();
+    let recorded = interpreter.get_global("t");
+
+    let mut replayed = lox.new_interpreter();
+    replayed.replay(Trace::parse(&trace.to_string()).unwrap());
+    lox.run_in(&mut replayed, String::from("var t = clock();"))
+        .unwrap();
+    assert_eq!(replayed.get_global("t"), recorded);
+
+    // Running out of inputs is an error rather than a silent fallback.
+    let error = lox
+        .run_in(&mut replayed, String::from("clock();"))
+        .unwrap_err();
+    assert_eq!(error.message(), "The trace has no more inputs for `clock`.");
+}
+
+#[test]
+fn failed_inputs_are_replayed_as_failures() {
+    let mut lox = Lox::new();
+    lox.options.permissions.grant(Permission::Read);
+    let source = "var f = open(\"/nonexistent/x\", \"r\");";
+    let mut interpreter = lox.new_interpreter();
+    interpreter.record();
+    let recorded = lox
+        .run_in(&mut interpreter, String::from(source))
+        .unwrap_err();
+    let trace = interpreter.take_trace().unwrap();
+    assert!(recorded.message().starts_with("Failed to open"));
+
+    let mut replayed = lox.new_interpreter();
+    replayed.replay(Trace::parse(&trace.to_string()).unwrap());
+    let error = lox.run_in(&mut replayed, String::from(source)).unwrap_err();
+    assert_eq!(error.phase(), Phase::Runtime);
+    assert_eq!(error.message(), recorded.message());
+}
+
+#[test]
+fn failing_to_save_a_trace_keeps_the_scripts_result() {
+    let diagnostics = Rc::new(RefCell::new(Vec::<Diagnostic>::new()));
+    let mut lox = Lox::new();
+    lox.set_diagnostic_sink(Box::new(diagnostics.clone()));
+    let dir = std::env::temp_dir().join(format!("no-such-dir-{}", std::process::id()));
+    let path = dir.join("trace.txt").display().to_string();
+    lox.record = Some(path.clone());
+
+    assert_eq!(lox.run(String::from("1 + 2;")).unwrap(), Types::Number(3.0));
+    let diagnostics = diagnostics.borrow();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].phase, Phase::Io);
+    assert!(
+        diagnostics[0]
+            .message
+            .starts_with(&format!("Failed to write `{path}`:")),
+        "{}",
+        diagnostics[0].message
+    );
+}
+
+#[test]
+fn traces_round_trip_through_text() {
+    let mut trace = Trace::new();
+    trace.push(TraceEntry {
+        native: String::from("exec"),
+        values: vec![
+            TraceValue::String(String::from("line \"one\"\n\ttwo\u{1b}")),
+            TraceValue::String(String::new()),
+            TraceValue::Nil,
+        ],
+    });
+    trace.push(TraceEntry {
+        native: String::from("clock"),
+        values: vec![TraceValue::Number(1.5)],
+    });
+    trace.push(TraceEntry {
+        native: String::from("open"),
+        values: vec![TraceValue::Error(String::from("Failed to open `x`."))],
+    });
+    assert_eq!(Trace::parse(&trace.to_string()), Ok(trace));
+
+    assert_eq!(
+        Trace::parse("# comment\n\nclock what"),
+        Err(String::from("line 3: Unexpected value `what`."))
+    );
+}
+
+struct Player {
+    name: String,
+    health: f64,
+}
+
+impl LoxObject for Player {
+    fn class_name(&self) -> &str {
+        "Player"
+    }
+
+    fn get(&self, name: &str) -> Option<Types> {
+        match name {
+            "name" => Some(Types::from(self.name.as_str())),
+            "health" => Some(Types::Number(self.health)),
+            _ => None,
+        }
+    }
+
+    fn set(&mut self, name: &str, value: Types) -> Result<(), String> {
+        match (name, value) {
+            ("health", Types::Number(health)) => {
+                self.health = health;
+                Ok(())
+            }
+            (name, _) => Err(format!("Can't set `{name}` on a player.")),
+        }
+    }
+
+    fn method_airity(&self, name: &str) -> Option<usize> {
+        match name {
+            "damage" => Some(1),
+            _ => None,
+        }
+    }
+
+    fn call_method(
+        &mut self,
+        interpreter: &mut Interpreter,
+        _name: &str,
+        arguments: Vec<Types>,
+    ) -> Result<Types, LoxError> {
+        match arguments[0] {
+            Types::Number(amount) => {
+                self.health -= amount;
+                Ok(Types::Number(self.health))
+            }
+            _ => LoxError::new_runtime(interpreter.line(), String::from("Expected a number.")),
+        }
+    }
+}
+
+#[test]
+fn scripts_can_use_host_objects() {
+    let lox = Lox::new();
+    let player = Rc::new(RefCell::new(Player {
+        name: String::from("ferris"),
+        health: 10.0,
+    }));
+    let mut interpreter = lox.new_interpreter();
+    interpreter.set_global("player", Types::host(player.clone()));
+
+    let source = "var hit = player.damage;\nhit(3);\nplayer.health = player.health * 2;\nvar name = player.name;\nvar same = player == player;";
+    lox.run_in(&mut interpreter, String::from(source)).unwrap();
+    assert_eq!(player.borrow().health, 14.0);
+    assert_eq!(interpreter.get_global("name"), Some(Types::from("ferris")));
+    assert_eq!(interpreter.get_global("same"), Some(Types::Bool(true)));
+
+    let error = lox
+        .run_in(&mut interpreter, String::from("player.name = \"crab\";"))
+        .unwrap_err();
+    assert_eq!(error.message(), "Can't set `name` on a player.");
+    let error = lox
+        .run_in(&mut interpreter, String::from("player.mana;"))
+        .unwrap_err();
+    assert_eq!(
+        error.message(),
+        "Instance of <class Player> doesn't have a field `mana`"
+    );
+}
+
+/// Calls the function it's given, which can use it again.
+struct Callback;
+
+impl LoxObject for Callback {
+    fn class_name(&self) -> &str {
+        "Callback"
+    }
+
+    fn method_airity(&self, name: &str) -> Option<usize> {
+        (name == "call").then_some(1)
+    }
+
+    fn call_method(
+        &mut self,
+        interpreter: &mut Interpreter,
+        _name: &str,
+        arguments: Vec<Types>,
+    ) -> Result<Types, LoxError> {
+        interpreter.call(&arguments[0], vec![])
+    }
+}
+
+#[test]
+fn host_objects_used_while_their_methods_run_are_errors() {
+    let lox = Lox::new();
+    let mut interpreter = lox.new_interpreter();
+    interpreter.set_global("callback", Types::host(Rc::new(RefCell::new(Callback))));
+
+    let source = "fun again() { callback.call(again); }\ncallback.call(again);";
+    let error = lox
+        .run_in(&mut interpreter, String::from(source))
+        .unwrap_err();
+    assert_eq!(
+        error.message(),
+        "Can't use instance of <class Callback> while one of its methods is running."
+    );
+
+    // Printing the object doesn't need to borrow it.
+    let source = "var sb = StringBuilder();\nsb.append(sb);\nvar built = sb.build();";
+    lox.run_in(&mut interpreter, String::from(source)).unwrap();
+    assert_eq!(
+        interpreter.get_global("built"),
+        Some(Types::from("instance of <class StringBuilder>"))
+    );
+}
+
+#[test]
+fn values_can_be_inspected_without_tokens() {
+    let lox = Lox::new();
+    let mut interpreter = lox.new_interpreter();
+    let source = "class Shape {}\nclass Point < Shape {\n  init(x, y) {\n    this.x = x;\n    this.y = y;\n  }\n  norm() {}\n}\nvar p = Point(3, \"four\");\nvar list = [1, nil];";
+    lox.run_in(&mut interpreter, String::from(source)).unwrap();
+
+    let p = interpreter.get_global("p").unwrap();
+    assert!(p.as_instance_of("Point").is_some());
+    assert!(p.as_instance_of("Shape").is_some());
+    assert!(p.as_instance_of("Other").is_none());
+    assert_eq!(p.get_field("x").and_then(|x| x.as_number()), Some(3.0));
+    assert_eq!(
+        p.get_field("y").as_ref().and_then(Types::as_str),
+        Some("four")
+    );
+    assert_eq!(p.get_field("norm"), None);
+
+    let list = interpreter.get_global("list").unwrap();
+    let list = list.as_list().unwrap().borrow();
+    assert_eq!(list[0].as_number(), Some(1.0));
+    assert!(list[1].is_nil());
+    assert_eq!(list[0].as_str(), None);
+}
+
+#[derive(Default)]
+struct Events {
+    statements: usize,
+    calls: Vec<(String, usize)>,
+    errors: Vec<String>,
+}
+
+struct Recorder(Rc<RefCell<Events>>);
+
+impl InterpreterHooks for Recorder {
+    fn before_statement(&mut self, _stmt: &Stmt) {
+        self.0.borrow_mut().statements += 1;
+    }
+
+    fn before_call(&mut self, name: &str, arguments: &[Types]) {
+        self.0
+            .borrow_mut()
+            .calls
+            .push((String::from(name), arguments.len()));
+    }
+
+    fn on_error(&mut self, error: &LoxError) {
+        self.0.borrow_mut().errors.push(error.message());
+    }
+}
+
+#[test]
+fn hooks_see_statements_calls_and_errors() {
+    let lox = Lox::new();
+    let events = Rc::new(RefCell::new(Events::default()));
+    let mut interpreter = lox.new_interpreter();
+    interpreter.set_hooks(Box::new(Recorder(events.clone())));
+
+    let source = "fun add(a, b) {\n  return a + b;\n}\nclass Point {}\nvar sum = add(1, 2);\nPoint();\nlen(\"abc\");";
+    lox.run_in(&mut interpreter, String::from(source)).unwrap();
+    // Five top-level statements and the `return` in `add`.
+    assert_eq!(events.borrow().statements, 6);
+    assert_eq!(
+        events.borrow().calls,
+        [
+            (String::from("add"), 2),
+            (String::from("Point"), 0),
+            (String::from("len"), 1)
+        ]
+    );
+
+    lox.run_in(&mut interpreter, String::from("add(nil, 1);"))
+        .unwrap_err();
+    assert_eq!(
+        events.borrow().errors,
+        ["Invalid operands for operator `+`.\n\tCannot add `Nil` with `1`"]
+    );
+}
+
+#[test]
+fn metrics_count_the_work_done() {
+    let lox = Lox::new();
+    let mut interpreter = lox.new_interpreter();
+    let source = "var a = [1, 2];\nfun f(x) {\n  return x;\n}\nf(a);\n{\n  var b = \"s\";\n}";
+    lox.run_in(&mut interpreter, String::from(source)).unwrap();
+
+    assert_eq!(
+        interpreter.metrics(),
+        Metrics {
+            statements: 6,
+            expressions: 8,
+            calls: 1,
+            // Only the block needs one: `f` keeps its locals on the frame.
+            environments: 1,
+            allocations: 3,
+        }
+    );
+    interpreter.reset_metrics();
+    assert_eq!(interpreter.metrics(), Metrics::default());
+}
+
+#[test]
+fn scripts_are_stopped_at_the_memory_limit() {
+    let mut lox = Lox::new();
+    lox.options.memory_limit = Some(100_000);
+
+    // Garbage is freed as it's dropped, so it doesn't count towards the limit.
+    let source = "for (var i = 0; i < 2000; i = i + 1) {\n  var s = \"abc\" + \"def\";\n  var l = [s, s];\n}";
+    lox.run_in(&mut lox.new_interpreter(), String::from(source))
+        .unwrap();
+
+    let source = "var s = \"\";\nwhile (true) {\n  s = s + \"0123456789\";\n}";
+    let mut interpreter = lox.new_interpreter();
+    let error = lox
+        .run_in(&mut interpreter, String::from(source))
+        .unwrap_err();
+    assert_eq!(error.message(), "Memory limit of 100000 bytes exceeded.");
+    assert!(interpreter.live_bytes() > 90_000);
+
+    // Host objects count what they report keeping alive.
+    let source = "var b = StringBuilder();\nwhile (true) {\n  b.append(\"0123456789\");\n}";
+    let error = lox
+        .run_in(&mut lox.new_interpreter(), String::from(source))
+        .unwrap_err();
+    assert_eq!(error.message(), "Memory limit of 100000 bytes exceeded.");
+}
+
+#[test]
+fn memory_held_by_callers_counts_towards_the_limit() {
+    let mut lox = Lox::new();
+    lox.options.memory_limit = Some(1_000_000);
+    let build = "fun build() {\n  var s = \"x\";\n  for (var i = 0; i < 15; i = i + 1) s = s + s;\n  return s;\n}\n";
+
+    // Each level of the recursion keeps a 32KB string until it returns.
+    let source = format!("{build}fun deep(n) {{\n  var s = build();\n  if (n > 0) deep(n - 1);\n  return s;\n}}\ndeep(100);");
+    let error = lox.run_in(&mut lox.new_interpreter(), source).unwrap_err();
+    assert_eq!(error.message(), "Memory limit of 1000000 bytes exceeded.");
+
+    // So do suspended generators and queued tasks.
+    let source = format!("{build}class Link {{}}\nfun hold() {{\n  var s = build();\n  yield 1;\n  return s;\n}}\nvar held = nil;\nfor (var i = 0; i < 50; i = i + 1) {{\n  var link = Link();\n  link.generator = hold();\n  next(link.generator);\n  link.next = held;\n  held = link;\n}}");
+    let error = lox.run_in(&mut lox.new_interpreter(), source).unwrap_err();
+    assert_eq!(error.message(), "Memory limit of 1000000 bytes exceeded.");
+    let source = format!("{build}fun queue() {{\n  var s = build();\n  fun task() {{\n    return s;\n  }}\n  spawn(task);\n}}\nfor (var i = 0; i < 50; i = i + 1) queue();");
+    let error = lox.run_in(&mut lox.new_interpreter(), source).unwrap_err();
+    assert_eq!(error.message(), "Memory limit of 1000000 bytes exceeded.");
+}
+
+#[test]
+fn cancelling_interrupts_a_running_script() {
+    let lox = Lox::new();
+    let mut interpreter = lox.new_interpreter();
+    let handle = interpreter.cancel_handle();
+    let canceller = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        handle.cancel();
+    });
+
+    let error = lox
+        .run_in(
+            &mut interpreter,
+            String::from("var i = 0;\nwhile (true) i = i + 1;"),
+        )
+        .unwrap_err();
+    canceller.join().unwrap();
+    assert!(matches!(error, LoxError::Interrupted));
+    assert_eq!(error.phase(), Phase::Runtime);
+
+    // The interpreter can carry on once the interrupted script has stopped.
+    assert!(!interpreter.cancel_handle().is_cancelled());
+    lox.run_in(&mut interpreter, String::from("i = -1;"))
+        .unwrap();
+    assert_eq!(interpreter.get_global("i"), Some(Types::Number(-1.0)));
+}
+
+#[test]
+fn debug_formatting_survives_cycles() {
+    let lox = Lox::new();
+    let mut interpreter = lox.new_interpreter();
+    let source = "class Node {}\nvar node = Node();\nnode.next = node;\nnode.tags = [node];";
+    lox.run_in(&mut interpreter, String::from(source)).unwrap();
+
+    let node = interpreter.get_global("node").unwrap();
+    assert_eq!(
+        format!("{node:?}"),
+        "ClassInstance(Node { next: ClassInstance(<cycle>), tags: [ClassInstance(<cycle>)] })"
+    );
+}
+
+#[test]
+fn gc_stats_count_live_values() {
+    let lox = Lox::new();
+    let mut interpreter = lox.new_interpreter();
+    let before = interpreter.gc_stats();
+    lox.run_in(
+        &mut interpreter,
+        String::from("var list = [\"a\", \"b\"];\nvar alias = list;"),
+    )
+    .unwrap();
+
+    let after = interpreter.gc_stats();
+    assert_eq!(after.collections, 0);
+    // The list and its two strings, counted once even though two globals share it.
+    assert_eq!(after.objects_live, before.objects_live + 3);
+    assert!(after.bytes_live > before.bytes_live);
+}
+
+#[test]
+fn natives_carry_their_name_airity_and_docs() {
+    let lox = Lox::new();
+    let interpreter = lox.new_interpreter();
+
+    let Some(Types::NativeFunc(clock)) = interpreter.get_global("clock") else {
+        panic!("clock should be a native function");
+    };
+    assert_eq!(clock.name(), "clock");
+    assert_eq!(clock.airity(), 0);
+    assert!(clock.doc().starts_with("clock()"));
+    assert_eq!(Types::NativeFunc(clock).to_string(), "<native fn clock>");
+}
+
+#[test]
+fn eval_returns_the_value_of_a_final_expression() {
+    let lox = Lox::new();
+    let mut interpreter = lox.new_interpreter();
+
+    let value = lox.eval_in(&mut interpreter, String::from("var a = 2; a * 21;"));
+    assert_eq!(value.unwrap(), Some(Types::Number(42.0)));
+    let value = lox.eval_in(&mut interpreter, String::from("a * 21; var b;"));
+    assert_eq!(value.unwrap(), None);
+}
+
+/// Starts every function with `calls = calls + 1;`.
+struct CountCalls;
+
+impl Transformer for CountCalls {
+    fn transform_stmt(&mut self, stmt: Stmt) -> Stmt {
+        let Stmt::Function {
+            id,
+            name,
+            params,
+            param_types,
+            return_type,
+            mut body,
+            doc,
+        } = stmt
+        else {
+            return stmt;
+        };
+        let calls = Token::new(TokenType::Identifier, String::from("calls"), name.line);
+        let increment = Expr::Assignment {
+            id: next_node_id(),
+            name: calls.clone(),
+            value: Box::new(Expr::Binary {
+                id: next_node_id(),
+                left: Box::new(Expr::Variable {
+                    id: next_node_id(),
+                    name: calls,
+                }),
+                operator: Token::new(TokenType::Plus, String::from("+"), name.line),
+                right: Box::new(Expr::Literal {
+                    id: next_node_id(),
+                    value: Token::new(TokenType::Number(1.0), String::from("1"), name.line),
+                }),
+            }),
+        };
+        body.insert(
+            0,
+            Box::new(Stmt::Expr {
+                id: next_node_id(),
+                expr: Box::new(increment),
+            }),
+        );
+        Stmt::Function {
+            id,
+            name,
+            params,
+            param_types,
+            return_type,
+            body,
+            doc,
+        }
+    }
+}
+
+#[test]
+fn transformers_rewrite_programs_before_they_run() {
+    let mut lox = Lox::new();
+    lox.add_transformer(Box::new(CountCalls));
+    let mut interpreter = lox.new_interpreter();
+    lox.run_in(
+        &mut interpreter,
+        String::from("var calls = 0;\nfun f() {}\nfun g() { f(); }\ng();\ng();"),
+    )
+    .unwrap();
+
+    assert_eq!(interpreter.get_global("calls"), Some(Types::Number(4.0)));
+}
+
+#[test]
+fn long_operator_chains_dont_overflow_the_stack() {
+    let lox = Lox::new();
+    let mut interpreter = lox.new_interpreter();
+    let sum = format!("{};", vec!["1"; 200_000].join(" + "));
+    assert_eq!(
+        lox.eval_in(&mut interpreter, sum).unwrap(),
+        Some(Types::Number(200_000.0))
+    );
+
+    let source = format!("{} or \"last\";", vec!["false"; 200_000].join(" or "));
+    assert_eq!(
+        lox.eval_in(&mut interpreter, source).unwrap(),
+        Some(Types::from("last"))
+    );
+}
+
+#[test]
+fn running_a_script_returns_its_last_value() {
+    let lox = Lox::new();
+    assert_eq!(
+        lox.run(String::from("var a = 1; a + 2;")).unwrap(),
+        Types::Number(3.0)
+    );
+    assert_eq!(lox.run(String::from("var a = 1;")).unwrap(), Types::Nil);
+    assert_eq!(
+        lox.run(String::from("fun f() { return 1; } f();")).unwrap(),
+        Types::Number(1.0)
+    );
+}
+
+#[test]
+fn values_are_two_words() {
+    assert!(std::mem::size_of::<Types>() <= 2 * std::mem::size_of::<usize>());
+}
+
+#[test]
+fn assert_eq_shows_where_values_differ() {
+    let lox = Lox::new();
+    let mut interpreter = lox.new_interpreter();
+    let mut failure = |source: &str| {
+        lox.run_in(&mut interpreter, String::from(source))
+            .unwrap_err()
+            .message()
+    };
+
+    assert_eq!(
+        failure("assertEq([1, 2], [1, 2, 3]);"),
+        "Values differ at `[2]`.\n  actual:   [1, 2]\n  expected: [1, 2, 3]"
+    );
+    assert_eq!(
+        failure("assertEq(1, \"1\");"),
+        "Values differ.\n  actual:   1\n  expected: \"1\""
+    );
+    let source = "class Box {}\nvar a = Box();\na.items = [1, [2]];\nvar b = Box();\nb.items = [1, [3]];\nassertEq(a, b);";
+    assert_eq!(
+        failure(source),
+        "Values differ at `.items[1][0]`.\n  actual:   Box {\n              items: [\n                1,\n                [2],\n              ],\n            }\n  expected: Box {\n              items: [\n                1,\n                [3],\n              ],\n            }"
+    );
+}
@@ -0,0 +1,159 @@
+//! Runs every `.lox` file under `tests/lox` (or the directory named by
+//! `LOX_TEST_DIR`, e.g. a checkout of the Crafting Interpreters test suite)
+//! and checks its output against the `// expect: ...` comments it contains.
+//!
+//! A file may also contain `// expect runtime error: ...` to require that it
+//! stops with a runtime error, or `// Error ...` / `// [line N] Error ...` to
+//! require that it fails to compile.
+
+use lox::error::LoxError;
+use lox::sync::{Rc, RefCell};
+use lox::Lox;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum ExpectedError {
+    None,
+    Runtime(String),
+    Compile,
+}
+
+struct Expectations {
+    output: Vec<String>,
+    error: ExpectedError,
+}
+
+fn expectations(source: &str) -> Expectations {
+    let mut output = vec![];
+    let mut error = ExpectedError::None;
+    for line in source.lines() {
+        if let Some((_, comment)) = line.split_once("// ") {
+            if let Some(value) = comment.strip_prefix("expect: ") {
+                output.push(String::from(value));
+            } else if let Some(message) = comment.strip_prefix("expect runtime error: ") {
+                error = ExpectedError::Runtime(String::from(message));
+            } else if comment.starts_with("Error") || comment.starts_with("[line ") {
+                error = ExpectedError::Compile;
+            }
+        }
+    }
+
+    Expectations { output, error }
+}
+
+fn lox_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap_or_else(|e| panic!("Failed to read {}: {e}", dir.display()))
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            lox_files(&path, files);
+        } else if path.extension().is_some_and(|ext| ext == "lox") {
+            files.push(path);
+        }
+    }
+}
+
+fn run_golden_file(path: &Path, optimize: bool) -> Result<(), String> {
+    let source = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let expected = expectations(&source);
+
+    let output = SharedBuffer::default();
+    let mut lox = Lox::new();
+    lox.optimize = optimize;
+    lox.options.deterministic = true;
+    let result = lox.run_with_output(source, Box::new(output.clone()));
+    let actual = String::from_utf8_lossy(&output.0.borrow()).into_owned();
+    let actual: Vec<&str> = actual.lines().collect();
+
+    let error = match result {
+        Ok(_) => ExpectedError::None,
+        // Only the first line of a message is compared, since some add details below.
+        Err(e @ LoxError::RuntimeError(_)) => {
+            ExpectedError::Runtime(e.message().lines().next().unwrap_or("").to_string())
+        }
+        Err(_) => ExpectedError::Compile,
+    };
+
+    let mut problems = vec![];
+    if error != expected.error {
+        problems.push(format!(
+            "expected error {:?} but got {:?}",
+            expected.error, error
+        ));
+    }
+    for i in 0..actual.len().max(expected.output.len()) {
+        match (expected.output.get(i), actual.get(i)) {
+            (Some(e), Some(a)) if e == a => (),
+            (Some(e), Some(a)) => {
+                problems.push(format!("line {}: expected `{e}`, got `{a}`", i + 1))
+            }
+            (Some(e), None) => {
+                problems.push(format!("line {}: expected `{e}`, got nothing", i + 1))
+            }
+            (None, Some(a)) => problems.push(format!("line {}: unexpected output `{a}`", i + 1)),
+            (None, None) => unreachable!(),
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(problems.join("\n    "))
+    }
+}
+
+#[test]
+fn golden_files() {
+    let dir = match std::env::var("LOX_TEST_DIR") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("lox"),
+    };
+
+    let mut files = vec![];
+    lox_files(&dir, &mut files);
+    assert!(
+        !files.is_empty(),
+        "No .lox files found in {}",
+        dir.display()
+    );
+
+    let failures: Vec<String> = files
+        .iter()
+        .flat_map(|path| [(path, false), (path, true)])
+        .filter_map(|(path, optimize)| {
+            run_golden_file(path, optimize).err().map(|problems| {
+                let mode = if optimize { " (optimized)" } else { "" };
+                format!("{}{mode}:\n    {problems}", path.display())
+            })
+        })
+        .collect();
+
+    assert!(
+        failures.is_empty(),
+        "{} of {} golden files failed:\n{}",
+        failures.len(),
+        files.len(),
+        failures.join("\n")
+    );
+}
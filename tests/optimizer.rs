@@ -0,0 +1,33 @@
+#![allow(clippy::vec_box)]
+
+use lox::ast::{Expr, Stmt};
+use lox::optimizer::optimize;
+use lox::parser::Parser;
+use lox::scanner::Scanner;
+use lox::tokens::TokenType;
+
+fn optimized(source: &str) -> Vec<Box<Stmt>> {
+    let tokens = Scanner::new(String::from(source)).scan_tokens().unwrap();
+    optimize(Parser::new(tokens).parse().unwrap())
+}
+
+#[test]
+fn folds_constant_arithmetic() {
+    let statements = optimized("print (1 + 2) * 3;");
+    match &*statements[0] {
+        Stmt::Print { expr, .. } => match &**expr {
+            Expr::Literal { value, .. } => {
+                assert!(matches!(value.tok_typ, TokenType::Number(n) if n == 9.0))
+            }
+            other => panic!("expected a literal but found {other}"),
+        },
+        other => panic!("expected a print statement but found {other:?}"),
+    }
+}
+
+#[test]
+fn removes_dead_branches_and_loops() {
+    let statements = optimized("if (false) print 1; while (false) print 2; if (true) print 3;");
+    assert_eq!(statements.len(), 1);
+    assert!(matches!(&*statements[0], Stmt::Print { .. }));
+}
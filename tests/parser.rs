@@ -0,0 +1,191 @@
+#![allow(clippy::vec_box)]
+
+use lox::ast::{Expr, Stmt};
+use lox::diagnostics::Span;
+use lox::error::LoxError;
+use lox::parser::{self, Parser};
+use lox::scanner::Scanner;
+use lox::visit::{walk_expr, ExprVisitor, StmtVisitor};
+use lox::Lox;
+
+fn parse(source: &str) -> Vec<Box<Stmt>> {
+    let tokens = Scanner::new(String::from(source)).scan_tokens().unwrap();
+    Parser::new(tokens).parse().unwrap()
+}
+
+fn expression(source: &str) -> Expr {
+    match *parse(source).remove(0) {
+        Stmt::Expr { expr, .. } => *expr,
+        other => panic!("expected an expression statement but found {other:?}"),
+    }
+}
+
+#[test]
+fn chained_assignment_sets_the_last_property() {
+    match &expression("a.b.c = 1;") {
+        Expr::Set {
+            object,
+            name,
+            value,
+            ..
+        } => {
+            assert_eq!(name.lexeme, "c");
+            assert!(matches!(**value, Expr::Literal { .. }));
+            match &**object {
+                Expr::Get { object, name, .. } => {
+                    assert_eq!(name.lexeme, "b");
+                    assert!(matches!(&**object, Expr::Variable { name, .. } if name.lexeme == "a"));
+                }
+                other => panic!("expected a get but found {other}"),
+            }
+        }
+        other => panic!("expected a set but found {other}"),
+    }
+}
+
+#[test]
+fn properties_of_call_results_can_be_set() {
+    match &expression("make().b = 1;") {
+        Expr::Set { object, .. } => assert!(matches!(**object, Expr::Call { .. })),
+        other => panic!("expected a set but found {other}"),
+    }
+}
+
+#[test]
+fn assignment_to_a_call_is_an_error() {
+    let tokens = Scanner::new(String::from("a.b() = 1;"))
+        .scan_tokens()
+        .unwrap();
+    assert!(Parser::new(tokens).parse().is_err());
+}
+
+#[test]
+fn nodes_have_ids_and_spans() {
+    let tokens = Scanner::new(String::from("var a = 1;\nprint a +\n  -a;"))
+        .scan_tokens()
+        .unwrap();
+    let mut parser = Parser::new(tokens);
+    let stmts = parser.parse().unwrap();
+    let span = |id| parser.spans()[&id];
+    assert_ne!(stmts[0].id(), stmts[1].id());
+    assert_eq!(
+        span(stmts[0].id()),
+        Span {
+            line: 1,
+            column: Some(5)
+        }
+    );
+    assert_eq!(
+        span(stmts[1].id()),
+        Span {
+            line: 2,
+            column: Some(1)
+        }
+    );
+    let Stmt::Print { expr, .. } = &*stmts[1] else {
+        panic!("expected a print statement");
+    };
+    assert_eq!(
+        span(expr.id()),
+        Span {
+            line: 2,
+            column: Some(9)
+        }
+    );
+    let Expr::Binary { right, .. } = &**expr else {
+        panic!("expected a binary expression");
+    };
+    assert_eq!(
+        span(right.id()),
+        Span {
+            line: 3,
+            column: Some(3)
+        }
+    );
+}
+
+/// Collects the names of the variables read, in order.
+#[derive(Default)]
+struct Reads(Vec<String>);
+
+impl ExprVisitor for Reads {
+    fn visit_expr(&mut self, expr: &Expr) -> Result<(), LoxError> {
+        if let Expr::Variable { name, .. } = expr {
+            self.0.push(name.lexeme.to_string());
+        }
+        walk_expr(self, expr)
+    }
+}
+
+impl StmtVisitor for Reads {}
+
+#[test]
+fn visitors_walk_every_node() {
+    let stmts = parse(
+        "class A < B { m() { return c[d]; } }\n\
+         fun f() { for (;e;) if (g) print match h { P(i) => j }; }\n\
+         k.l = -m(n, [o]);",
+    );
+    let mut reads = Reads::default();
+    reads.visit_stmts(&stmts).unwrap();
+    assert_eq!(
+        reads.0,
+        ["B", "c", "d", "e", "g", "h", "P", "j", "k", "m", "n", "o"]
+    );
+}
+
+/// Runs `test` on a thread with as much stack as a main thread usually has,
+/// which is what the default nesting limit allows for.
+fn with_main_thread_stack(test: impl FnOnce() + Send + 'static) {
+    std::thread::Builder::new()
+        .stack_size(8 << 20)
+        .spawn(test)
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn deeply_nested_code_is_an_error() {
+    with_main_thread_stack(|| {
+        let source = format!("print {}1{};", "(".repeat(10_000), ")".repeat(10_000));
+        let tokens = Scanner::new(source).scan_tokens().unwrap();
+        match Parser::new(tokens).parse() {
+            Err(e) => assert!(
+                e.to_string().contains("Expression too deeply nested."),
+                "{e}"
+            ),
+            Ok(_) => panic!("expected an error"),
+        }
+
+        let source = format!("var a;{}1;", "a = ".repeat(100_000));
+        let tokens = Scanner::new(source).scan_tokens().unwrap();
+        match Parser::new(tokens).parse() {
+            Err(e) => assert!(
+                e.to_string().contains("Expression too deeply nested."),
+                "{e}"
+            ),
+            Ok(_) => panic!("expected an error"),
+        }
+
+        let source = format!("{}print 1;{}", "{".repeat(10_000), "}".repeat(10_000));
+        let tokens = Scanner::new(source).scan_tokens().unwrap();
+        assert!(Parser::new(tokens).parse().is_err());
+    });
+
+    let source = format!("print {}1{};", "(".repeat(10), ")".repeat(10));
+    let tokens = Scanner::new(source).scan_tokens().unwrap();
+    let mut parser = Parser::new(tokens);
+    parser.max_depth(10);
+    assert!(parser.parse().is_err());
+}
+
+#[test]
+fn code_nested_up_to_the_limit_can_be_checked() {
+    with_main_thread_stack(|| {
+        // The statement and the expression around the parentheses count too.
+        let depth = parser::DEFAULT_MAX_DEPTH - 2;
+        let source = format!("print {}1{};", "(".repeat(depth), ")".repeat(depth));
+        Lox::new().check(source).unwrap();
+    });
+}
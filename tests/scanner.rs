@@ -0,0 +1,103 @@
+use lox::scanner::Scanner;
+use lox::tokens::TokenType;
+
+fn number(source: &str) -> f64 {
+    let tokens = Scanner::new(String::from(source)).scan_tokens().unwrap();
+    match tokens[0].tok_typ {
+        TokenType::Number(n) => n,
+        ref other => panic!("expected a number but found {other:?}"),
+    }
+}
+
+fn error(source: &str) -> String {
+    match Scanner::new(String::from(source)).scan_tokens() {
+        Ok(tokens) => panic!("expected an error but scanned {tokens:?}"),
+        Err(e) => e.message(),
+    }
+}
+
+#[test]
+fn scans_extended_number_literals() {
+    assert_eq!(number("0xFF"), 255.0);
+    assert_eq!(number("0Xff"), 255.0);
+    assert_eq!(number("0b1010"), 10.0);
+    assert_eq!(number("1.5e3"), 1500.0);
+    assert_eq!(number("2E-2"), 0.02);
+    assert_eq!(number("1e+2"), 100.0);
+    assert_eq!(number("1_000_000"), 1_000_000.0);
+    assert_eq!(number("0xff_ff"), 65535.0);
+    assert_eq!(number("0b1111_0000"), 240.0);
+    assert_eq!(number("12.345_6"), 12.3456);
+}
+
+#[test]
+fn reports_malformed_number_literals() {
+    assert_eq!(error("0x"), "Expected hex digits after `0x`.");
+    assert_eq!(error("0b"), "Expected binary digits after `0b`.");
+    assert_eq!(
+        error("0b102"),
+        "Unexpected character `2` in binary literal `0b102`."
+    );
+    assert_eq!(
+        error("0xFG"),
+        "Unexpected character `G` in hex literal `0xFG`."
+    );
+    assert_eq!(error("1e"), "Expected digits in the exponent of `1e`.");
+    assert_eq!(error("1e+"), "Expected digits in the exponent of `1e+`.");
+    assert_eq!(
+        error("12abc"),
+        "Unexpected character `a` in number literal `12a`."
+    );
+    for source in ["1__0", "1_", "0x_1", "1_.5", "1.5_"] {
+        assert_eq!(
+            error(source),
+            "A digit separator `_` must be between two digits.",
+            "scanning {source}"
+        );
+    }
+    assert_eq!(
+        error("0xFFFFFFFFFFFFFFFFF"),
+        "The hex literal `0xFFFFFFFFFFFFFFFFF` is too large."
+    );
+}
+
+#[test]
+fn scans_doc_comments() {
+    let source = "/// Adds.\n///\n//// Not a doc.\n// Nor this.\nfun";
+    let tokens = Scanner::new(String::from(source)).scan_tokens().unwrap();
+    let docs: Vec<&str> = tokens
+        .iter()
+        .filter_map(|token| match &token.tok_typ {
+            TokenType::DocComment(text) => Some(text.as_str()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(docs, ["Adds.", ""]);
+    assert_eq!(tokens.len(), 4);
+}
+
+#[test]
+fn scans_tokens_one_at_a_time() {
+    let mut scanner = Scanner::new(String::from("var a = 1; @ print a;"));
+    let first = scanner.next().unwrap().unwrap();
+    assert_eq!(first.tok_typ, TokenType::Var);
+
+    let rest: Vec<_> = scanner.collect();
+    assert_eq!(rest.len(), 5);
+    assert!(rest[..4].iter().all(Result::is_ok));
+    assert_eq!(
+        rest[4].as_ref().unwrap_err().message(),
+        "Unexpected character `@`"
+    );
+}
+
+#[test]
+fn lexemes_are_slices_of_the_source() {
+    let tokens = Scanner::new(String::from("var café = \"λ\";"))
+        .scan_tokens()
+        .unwrap();
+    let lexemes: Vec<&str> = tokens.iter().map(|token| token.lexeme.as_str()).collect();
+    assert_eq!(lexemes, ["var", "café", "=", "\"λ\"", ";", ""]);
+    let columns: Vec<usize> = tokens.iter().map(|token| token.column).collect();
+    assert_eq!(columns, [1, 5, 10, 12, 15, 16]);
+}
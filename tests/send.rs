@@ -0,0 +1,38 @@
+#![cfg(feature = "send")]
+
+use lox::interpreter::{Interpreter, Types};
+use lox::Lox;
+
+fn assert_send<T: Send>() {}
+
+#[test]
+fn interpreters_can_move_between_threads() {
+    assert_send::<Interpreter>();
+    assert_send::<Types>();
+
+    let lox = Lox::new();
+    let mut interpreter = lox.new_interpreter();
+    lox.run_in(
+        &mut interpreter,
+        String::from(
+            "class Counter {\n  init() {\n    this.count = 0;\n  }\n}\nvar counter = Counter();",
+        ),
+    )
+    .unwrap();
+
+    let interpreter = std::thread::spawn(move || {
+        Lox::new()
+            .run_in(
+                &mut interpreter,
+                String::from(
+                    "fun bump() {\n  counter.count = counter.count + 1;\n}\nbump();\nbump();",
+                ),
+            )
+            .unwrap();
+        interpreter
+    })
+    .join()
+    .unwrap();
+    let counter = interpreter.get_global("counter").unwrap();
+    assert_eq!(counter.get_field("count"), Some(Types::Number(2.0)));
+}
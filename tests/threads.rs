@@ -0,0 +1,120 @@
+#![cfg(feature = "threads")]
+
+use lox::diagnostics::Diagnostic;
+use lox::error::LoxError;
+use lox::interpreter::{Interpreter, Types};
+use lox::sync::{Rc, RefCell};
+use lox::Lox;
+
+fn run(source: &str) -> Interpreter {
+    let lox = Lox::new();
+    let mut interpreter = lox.new_interpreter();
+    lox.run_in(&mut interpreter, String::from(source)).unwrap();
+    interpreter
+}
+
+#[test]
+fn threads_communicate_over_channels() {
+    let interpreter = run(r#"
+        var requests = channel();
+        var replies = channel();
+
+        fun worker() {
+            var n = recv(requests);
+            while (n != nil) {
+                send(replies, [n, n * n]);
+                n = recv(requests);
+            }
+            send(replies, "bye");
+        }
+
+        spawnThread(worker);
+        send(requests, 3);
+        send(requests, 4);
+        send(requests, nil);
+
+        var first = recv(replies);
+        var second = recv(replies);
+        var last = recv(replies);
+    "#);
+
+    let element = |name: &str, i: usize| match interpreter.get_global(name) {
+        Some(Types::List(items)) => items.borrow()[i].clone(),
+        other => panic!("expected a list but found {other:?}"),
+    };
+    assert!(matches!(
+        interpreter.get_global("first"),
+        Some(Types::List(_))
+    ));
+    assert_eq!(element("first", 0), Types::Number(3.0));
+    assert_eq!(element("first", 1), Types::Number(9.0));
+    assert_eq!(element("second", 1), Types::Number(16.0));
+    assert_eq!(interpreter.get_global("last"), Some(Types::from("bye")));
+}
+
+#[test]
+fn only_copyable_values_can_be_sent() {
+    let lox = Lox::new();
+    let result = lox.run_in(
+        &mut lox.new_interpreter(),
+        String::from("class Point {} send(channel(), Point());"),
+    );
+    assert!(matches!(result, Err(LoxError::RuntimeError(_))));
+}
+
+#[test]
+fn threads_can_call_other_top_level_functions() {
+    let interpreter = run(r#"
+        var results = channel();
+        fun square(n) { return n * n; }
+        fun worker() { send(results, square(5)); }
+        spawnThread(worker);
+        var result = recv(results);
+    "#);
+    assert_eq!(interpreter.get_global("result"), Some(Types::Number(25.0)));
+}
+
+#[test]
+fn failed_threads_are_reported_and_poison_their_channels() {
+    let diagnostics = Rc::new(RefCell::new(Vec::<Diagnostic>::new()));
+    let mut lox = Lox::new();
+    lox.set_diagnostic_sink(Box::new(diagnostics.clone()));
+    let result = lox.run_in(
+        &mut lox.new_interpreter(),
+        String::from(
+            r#"
+            var results = channel();
+            fun worker() { send(results, [1][5]); }
+            spawnThread(worker);
+            recv(results);
+            "#,
+        ),
+    );
+
+    let error = result.unwrap_err();
+    assert!(matches!(error, LoxError::RuntimeError(_)));
+    assert!(error
+        .to_string()
+        .contains("A thread that could send on this channel failed."));
+    let diagnostics = diagnostics.borrow();
+    assert_eq!(diagnostics.len(), 1, "{diagnostics:?}");
+    assert!(diagnostics[0].message.contains("out of"));
+}
+
+#[test]
+fn runs_wait_for_their_threads_and_fail_with_their_errors() {
+    let lox = Lox::new();
+    let result = lox.run_in(
+        &mut lox.new_interpreter(),
+        String::from(
+            "fun worker() {
+                for (var i = 0; i < 100000; i = i + 1) {}
+                [1][5];
+            }
+            spawnThread(worker);",
+        ),
+    );
+    let error = result.unwrap_err();
+    assert!(matches!(error, LoxError::RuntimeError(_)));
+    assert!(error.message().contains("out of bounds"), "{error}");
+}